@@ -0,0 +1,722 @@
+//! Bitstream-level verification of the concatenated output. Every chunk is
+//! a separate encode, so chunks that were encoded with different parameters
+//! (e.g. by zones) can be merged into a stream whose sequence headers change
+//! at scene boundaries, which many hardware decoders do not play back
+//! correctly.
+
+use std::{
+    fmt,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use itertools::Itertools;
+use tracing::{debug, info, warn};
+
+use crate::{children, concat::sort_files_by_filename, encoder::Encoder, util::read_in_dir};
+
+/// Parameters of a sequence header (AV1) or sequence parameter set (HEVC)
+/// that must match for the whole stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StreamParameters {
+    profile:    u8,
+    tier:       u8,
+    /// (major, minor)
+    level:      (u8, u8),
+    bit_depth:  u8,
+    chroma:     &'static str,
+    full_range: Option<bool>,
+    /// (color primaries, transfer characteristics, matrix coefficients)
+    color:      Option<(u8, u8, u8)>,
+    /// (width, height)
+    max_size:   (u32, u32),
+}
+
+impl fmt::Display for StreamParameters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "profile {profile}, level {major}.{minor}{tier}, {bit_depth}-bit {chroma}",
+            profile = self.profile,
+            major = self.level.0,
+            minor = self.level.1,
+            tier = if self.tier == 0 { "" } else { " (high tier)" },
+            bit_depth = self.bit_depth,
+            chroma = self.chroma,
+        )?;
+        if let Some(full_range) = self.full_range {
+            write!(
+                f,
+                " {}",
+                if full_range {
+                    "full range"
+                } else {
+                    "limited range"
+                }
+            )?;
+        }
+        if let Some((primaries, transfer, matrix)) = self.color {
+            write!(
+                f,
+                ", primaries {primaries}, transfer {transfer}, matrix {matrix}"
+            )?;
+        }
+        write!(f, ", {}x{}", self.max_size.0, self.max_size.1)
+    }
+}
+
+/// Reads the bits of a header, most significant bit first
+struct BitReader<'a> {
+    data:     &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    const fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: 0,
+        }
+    }
+
+    fn bits(&mut self, count: usize) -> anyhow::Result<u32> {
+        debug_assert!(count <= 32);
+        let mut value = 0u64;
+        for _ in 0..count {
+            let byte =
+                self.data.get(self.position / 8).ok_or_else(|| anyhow!("header ends early"))?;
+            value = (value << 1) | u64::from((byte >> (7 - self.position % 8)) & 1);
+            self.position += 1;
+        }
+        Ok(value as u32)
+    }
+
+    fn flag(&mut self) -> anyhow::Result<bool> {
+        Ok(self.bits(1)? == 1)
+    }
+
+    fn skip(&mut self, count: usize) -> anyhow::Result<()> {
+        ensure!(
+            self.position + count <= self.data.len() * 8,
+            "header ends early"
+        );
+        self.position += count;
+        Ok(())
+    }
+
+    /// Exp-Golomb code used by HEVC, `ue(v)`
+    fn unsigned_exp_golomb(&mut self) -> anyhow::Result<u32> {
+        let mut leading_zeros = 0;
+        while !self.flag()? {
+            leading_zeros += 1;
+            ensure!(leading_zeros < 32, "invalid exp-golomb code");
+        }
+        Ok((1 << leading_zeros) - 1 + self.bits(leading_zeros)?)
+    }
+
+    /// Variable length code used by AV1, `uvlc()`
+    fn uvlc(&mut self) -> anyhow::Result<u32> {
+        let mut leading_zeros = 0;
+        while !self.flag()? {
+            leading_zeros += 1;
+        }
+        if leading_zeros >= 32 {
+            return Ok(u32::MAX);
+        }
+        Ok(self.bits(leading_zeros)? + ((1 << leading_zeros) - 1))
+    }
+}
+
+/// Splits a low overhead AV1 bitstream into its sequence header OBUs
+fn av1_sequence_headers(data: &[u8]) -> anyhow::Result<Vec<&[u8]>> {
+    const OBU_SEQUENCE_HEADER: u8 = 1;
+
+    let mut headers = Vec::new();
+    let mut rest = data;
+    while let [header, tail @ ..] = rest {
+        let obu_type = (header >> 3) & 0xF;
+        let has_extension = header & 0b100 != 0;
+        ensure!(header & 0b10 != 0, "OBU without a size field");
+        let tail = if has_extension {
+            tail.get(1..).ok_or_else(|| anyhow!("OBU ends early"))?
+        } else {
+            tail
+        };
+
+        // leb128
+        let mut size = 0usize;
+        let mut length = 0;
+        loop {
+            let byte = *tail.get(length).ok_or_else(|| anyhow!("OBU ends early"))?;
+            size |= usize::from(byte & 0x7F) << (7 * length);
+            length += 1;
+            if byte & 0x80 == 0 || length == 8 {
+                break;
+            }
+        }
+
+        let payload = tail.get(length..length + size).ok_or_else(|| anyhow!("OBU ends early"))?;
+        if obu_type == OBU_SEQUENCE_HEADER {
+            headers.push(payload);
+        }
+        rest = &tail[length + size..];
+    }
+
+    Ok(headers)
+}
+
+fn parse_av1_sequence_header(payload: &[u8]) -> anyhow::Result<StreamParameters> {
+    let mut r = BitReader::new(payload);
+    let profile = r.bits(3)? as u8;
+    let _still_picture = r.flag()?;
+    let reduced_still_picture_header = r.flag()?;
+
+    let (level, tier) = if reduced_still_picture_header {
+        (r.bits(5)? as u8, 0)
+    } else {
+        let timing_info_present = r.flag()?;
+        let mut buffer_delay_length = 0;
+        let decoder_model_info_present = if timing_info_present {
+            r.skip(64)?;
+            if r.flag()? {
+                r.uvlc()?;
+            }
+            let present = r.flag()?;
+            if present {
+                buffer_delay_length = r.bits(5)? as usize + 1;
+                r.skip(32 + 5 + 5)?;
+            }
+            present
+        } else {
+            false
+        };
+        let initial_display_delay_present = r.flag()?;
+
+        let operating_points = r.bits(5)? + 1;
+        let mut first = None;
+        for _ in 0..operating_points {
+            r.skip(12)?;
+            let level = r.bits(5)? as u8;
+            let tier = if level > 7 { r.bits(1)? as u8 } else { 0 };
+            if decoder_model_info_present && r.flag()? {
+                r.skip(2 * buffer_delay_length + 1)?;
+            }
+            if initial_display_delay_present && r.flag()? {
+                r.skip(4)?;
+            }
+            first.get_or_insert((level, tier));
+        }
+        first.unwrap_or_default()
+    };
+
+    let width_bits = r.bits(4)? as usize + 1;
+    let height_bits = r.bits(4)? as usize + 1;
+    let max_size = (r.bits(width_bits)? + 1, r.bits(height_bits)? + 1);
+
+    if !reduced_still_picture_header && r.flag()? {
+        // delta_frame_id_length_minus_2, additional_frame_id_length_minus_1
+        r.skip(4 + 3)?;
+    }
+    // use_128x128_superblock, enable_filter_intra, enable_intra_edge_filter
+    r.skip(3)?;
+    if !reduced_still_picture_header {
+        // enable_interintra_compound, enable_masked_compound,
+        // enable_warped_motion, enable_dual_filter
+        r.skip(4)?;
+        let enable_order_hint = r.flag()?;
+        if enable_order_hint {
+            // enable_jnt_comp, enable_ref_frame_mvs
+            r.skip(2)?;
+        }
+        let force_screen_content_tools = if r.flag()? { 2 } else { r.bits(1)? };
+        if force_screen_content_tools > 0 && !r.flag()? {
+            r.skip(1)?;
+        }
+        if enable_order_hint {
+            r.skip(3)?;
+        }
+    }
+    // enable_superres, enable_cdef, enable_restoration
+    r.skip(3)?;
+
+    let high_bitdepth = r.flag()?;
+    let bit_depth = if profile == 2 && high_bitdepth {
+        if r.flag()? {
+            12
+        } else {
+            10
+        }
+    } else if high_bitdepth {
+        10
+    } else {
+        8
+    };
+    let monochrome = profile != 1 && r.flag()?;
+    let (primaries, transfer, matrix) = if r.flag()? {
+        (r.bits(8)?, r.bits(8)?, r.bits(8)?)
+    } else {
+        (2, 2, 2)
+    };
+
+    let (full_range, subsampling) = if monochrome {
+        (r.flag()?, (1, 1))
+    } else if primaries == 1 && transfer == 13 && matrix == 0 {
+        // sRGB
+        (true, (0, 0))
+    } else {
+        let full_range = r.flag()?;
+        let subsampling = match profile {
+            0 => (1, 1),
+            1 => (0, 0),
+            _ if bit_depth == 12 => {
+                let x = r.bits(1)?;
+                (x, if x == 1 { r.bits(1)? } else { 0 })
+            },
+            _ => (1, 0),
+        };
+        (full_range, subsampling)
+    };
+
+    Ok(StreamParameters {
+        profile,
+        tier,
+        level: (2 + (level >> 2), level & 3),
+        bit_depth,
+        chroma: if monochrome {
+            "4:0:0"
+        } else {
+            match subsampling {
+                (1, 1) => "4:2:0",
+                (1, 0) => "4:2:2",
+                _ => "4:4:4",
+            }
+        },
+        full_range: Some(full_range),
+        color: Some((primaries as u8, transfer as u8, matrix as u8)),
+        max_size,
+    })
+}
+
+/// Splits an Annex B HEVC bitstream into the payloads of its sequence
+/// parameter sets, without emulation prevention bytes
+fn hevc_sequence_parameter_sets(data: &[u8]) -> Vec<Vec<u8>> {
+    const NAL_SPS: u8 = 33;
+
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    starts
+        .iter()
+        .enumerate()
+        .filter_map(|(index, &start)| {
+            let end = starts.get(index + 1).map_or(data.len(), |&next| next - 3);
+            let nal = &data[start..end];
+            ((nal.first()? >> 1) & 0x3F == NAL_SPS).then(|| {
+                // Skip the NAL unit header and remove emulation prevention bytes
+                let mut payload = Vec::with_capacity(nal.len());
+                let mut zeros = 0;
+                for &byte in nal.get(2..).unwrap_or_default() {
+                    if byte == 3 && zeros >= 2 {
+                        zeros = 0;
+                        continue;
+                    }
+                    zeros = if byte == 0 { zeros + 1 } else { 0 };
+                    payload.push(byte);
+                }
+                payload
+            })
+        })
+        .collect()
+}
+
+fn parse_hevc_sequence_parameter_set(payload: &[u8]) -> anyhow::Result<StreamParameters> {
+    let mut r = BitReader::new(payload);
+    // sps_video_parameter_set_id
+    r.skip(4)?;
+    let max_sub_layers = r.bits(3)? as usize;
+    // sps_temporal_id_nesting_flag
+    r.skip(1)?;
+
+    // profile_tier_level
+    // general_profile_space
+    r.skip(2)?;
+    let tier = r.bits(1)? as u8;
+    let profile = r.bits(5)? as u8;
+    // compatibility and constraint flags
+    r.skip(32 + 48)?;
+    let level = r.bits(8)?;
+    let mut sub_layers = Vec::with_capacity(max_sub_layers);
+    for _ in 0..max_sub_layers {
+        sub_layers.push((r.flag()?, r.flag()?));
+    }
+    if max_sub_layers > 0 {
+        r.skip(2 * (8 - max_sub_layers))?;
+    }
+    for (profile_present, level_present) in sub_layers {
+        if profile_present {
+            r.skip(88)?;
+        }
+        if level_present {
+            r.skip(8)?;
+        }
+    }
+
+    // sps_seq_parameter_set_id
+    r.unsigned_exp_golomb()?;
+    let chroma_format = r.unsigned_exp_golomb()?;
+    if chroma_format == 3 {
+        // separate_colour_plane_flag
+        r.skip(1)?;
+    }
+    let max_size = (r.unsigned_exp_golomb()?, r.unsigned_exp_golomb()?);
+    if r.flag()? {
+        for _ in 0..4 {
+            r.unsigned_exp_golomb()?;
+        }
+    }
+    let bit_depth = r.unsigned_exp_golomb()? + 8;
+
+    Ok(StreamParameters {
+        profile,
+        tier,
+        level: ((level / 30) as u8, (level % 30 / 3) as u8),
+        bit_depth: bit_depth as u8,
+        chroma: match chroma_format {
+            0 => "4:0:0",
+            1 => "4:2:0",
+            2 => "4:2:2",
+            _ => "4:4:4",
+        },
+        full_range: None,
+        color: None,
+        max_size,
+    })
+}
+
+/// Extracts the stream headers of the first video stream of `output` with
+/// ffmpeg
+fn extract_headers(output: &Path, bsf: &str, format: &str) -> anyhow::Result<Vec<u8>> {
+    let out = children::output(
+        Command::new("ffmpeg")
+            .args(["-hide_banner", "-v", "error", "-i"])
+            .arg(output)
+            .args(["-map", "0:V:0", "-c", "copy", "-bsf:v", bsf, "-f", format, "-"])
+            .stdin(Stdio::null()),
+    )
+    .context("Failed to run ffmpeg to extract the stream headers")?;
+    ensure!(
+        out.status.success(),
+        "ffmpeg failed to extract the stream headers: {}",
+        String::from_utf8_lossy(&out.stderr).trim()
+    );
+    Ok(out.stdout)
+}
+
+/// Checks that the stream headers of the concatenated `output` describe the
+/// same stream throughout
+#[tracing::instrument(level = "debug")]
+pub(crate) fn verify_output(output: &Path, encoder: Encoder) -> anyhow::Result<()> {
+    let headers = match encoder.format() {
+        "av1" => {
+            let data = extract_headers(output, "filter_units=pass_types=1", "obu")?;
+            av1_sequence_headers(&data)?
+                .into_iter()
+                .map(parse_av1_sequence_header)
+                .collect::<anyhow::Result<Vec<_>>>()
+        },
+        "h265" => {
+            let data = extract_headers(
+                output,
+                "hevc_mp4toannexb,filter_units=pass_types=33",
+                "hevc",
+            )?;
+            hevc_sequence_parameter_sets(&data)
+                .iter()
+                .map(|payload| parse_hevc_sequence_parameter_set(payload))
+                .collect::<anyhow::Result<Vec<_>>>()
+        },
+        format => {
+            warn!("bitstream verification is not supported for {format}, skipping it");
+            return Ok(());
+        },
+    }
+    .context("Failed to parse the stream headers of the output")?;
+
+    check_consistent(&headers)
+}
+
+/// Reads the stream header at the start of an encoded chunk, or `None` if the
+/// chunks of `encoder` are not read
+fn chunk_header(chunk: &Path, encoder: Encoder) -> anyhow::Result<Option<StreamParameters>> {
+    /// Part of an HEVC chunk that is searched for the sequence parameter set,
+    /// which follows the video parameter set at the start of the stream
+    const HEVC_PREFIX: u64 = 64 * 1024;
+
+    let mut file = File::open(chunk)?;
+    match (encoder.format(), encoder.output_extension()) {
+        ("av1", "ivf") => {
+            // The file header, then the size and timestamp of the first frame
+            let mut headers = [0; 32 + 12];
+            file.read_exact(&mut headers)?;
+            let size = u32::from_le_bytes(headers[32..36].try_into().expect("slice is 4 bytes"));
+            let mut frame = vec![0; size as usize];
+            file.read_exact(&mut frame)?;
+            av1_sequence_headers(&frame)?
+                .first()
+                .map(|header| parse_av1_sequence_header(header))
+                .transpose()
+        },
+        ("h265", "hevc") => {
+            let mut data = Vec::new();
+            file.take(HEVC_PREFIX).read_to_end(&mut data)?;
+            hevc_sequence_parameter_sets(&data)
+                .first()
+                .map(|payload| parse_hevc_sequence_parameter_set(payload))
+                .transpose()
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Checks that every encoded chunk in `encode_dir` starts with the same
+/// stream parameters, so that concatenating them gives a playable output.
+/// Chunks that were encoded differently, such as by zones, are listed by
+/// index.
+#[tracing::instrument(level = "debug")]
+pub(crate) fn verify_chunks(encode_dir: &Path, encoder: Encoder) -> anyhow::Result<()> {
+    let mut chunks = read_in_dir(encode_dir)?
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == encoder.output_extension())
+        })
+        .collect::<Vec<PathBuf>>();
+    sort_files_by_filename(&mut chunks);
+
+    let mut groups: Vec<(StreamParameters, Vec<String>)> = Vec::new();
+    for chunk in &chunks {
+        let Some(header) = chunk_header(chunk, encoder)
+            .with_context(|| format!("Failed to read the stream header of {}", chunk.display()))?
+        else {
+            debug!("chunk consistency is not checked for {encoder}");
+            return Ok(());
+        };
+        // Chunks are named after their zero-padded index
+        let stem = chunk.file_stem().unwrap_or_default().to_string_lossy();
+        let index = stem
+            .parse::<usize>()
+            .map_or_else(|_| stem.to_string(), |index| index.to_string());
+        match groups.iter_mut().find(|(parameters, _)| *parameters == header) {
+            Some((_, indices)) => indices.push(index),
+            None => groups.push((header, vec![index])),
+        }
+    }
+
+    if groups.len() > 1 {
+        groups.sort_by_key(|(_, indices)| std::cmp::Reverse(indices.len()));
+        bail!(
+            "the encoded chunks have different stream parameters, which cannot be concatenated \
+             into a playable output. Check the zones and filters of these chunks:\n{}",
+            groups
+                .iter()
+                .map(|(parameters, indices)| format!(
+                    "{parameters}: chunk(s) {}{}",
+                    indices.iter().take(20).join(", "),
+                    if indices.len() > 20 { ", ..." } else { "" }
+                ))
+                .join("\n")
+        );
+    }
+
+    debug!(
+        "verified the stream parameters of {} chunk(s)",
+        chunks.len()
+    );
+    Ok(())
+}
+
+fn check_consistent(headers: &[StreamParameters]) -> anyhow::Result<()> {
+    let Some(first) = headers.first() else {
+        warn!("no stream headers found in the output, skipping bitstream verification");
+        return Ok(());
+    };
+
+    let mismatches = headers
+        .iter()
+        .enumerate()
+        .filter(|(_, header)| *header != first)
+        .map(|(index, header)| format!("header {index}: {header}"))
+        .collect::<Vec<_>>();
+    if !mismatches.is_empty() {
+        bail!(
+            "the output mixes stream parameters, which hardware decoders may not play back \
+             correctly. Header 0: {first}, but\n{}",
+            mismatches.iter().take(10).join("\n")
+        );
+    }
+
+    info!(
+        "verified {count} stream header(s) of the output: {first}",
+        count = headers.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        av1_sequence_headers,
+        check_consistent,
+        hevc_sequence_parameter_sets,
+        parse_av1_sequence_header,
+        verify_chunks,
+        StreamParameters,
+    };
+    use crate::Encoder;
+
+    /// Packs `(value, bit count)` pairs, most significant bit first
+    fn pack(fields: &[(u32, usize)]) -> Vec<u8> {
+        let bits = fields
+            .iter()
+            .flat_map(|&(value, count)| (0..count).rev().map(move |bit| (value >> bit) & 1))
+            .collect::<Vec<_>>();
+        bits.chunks(8)
+            .map(|byte| {
+                byte.iter()
+                    .enumerate()
+                    .fold(0u8, |acc, (i, &bit)| acc | ((bit as u8) << (7 - i)))
+            })
+            .collect()
+    }
+
+    fn av1_sequence_header(high_bitdepth: u32) -> Vec<u8> {
+        pack(&[
+            // profile, still_picture, reduced_still_picture_header
+            (0, 3),
+            (0, 1),
+            (0, 1),
+            // timing_info_present_flag, initial_display_delay_present_flag
+            (0, 1),
+            (0, 1),
+            // one operating point at level 4.1 (seq_level_idx 9), main tier
+            (0, 5),
+            (0, 12),
+            (9, 5),
+            (0, 1),
+            // frame size bits and maximum frame size
+            (10, 4),
+            (10, 4),
+            (1919, 11),
+            (1079, 11),
+            // frame_id_numbers_present_flag
+            (0, 1),
+            // superblock size to enable_dual_filter
+            (0b011_1111, 7),
+            // enable_order_hint, enable_jnt_comp, enable_ref_frame_mvs
+            (0b111, 3),
+            // seq_choose_screen_content_tools, seq_choose_integer_mv
+            (1, 1),
+            (1, 1),
+            // order_hint_bits_minus_1
+            (6, 3),
+            // enable_superres, enable_cdef, enable_restoration
+            (0b011, 3),
+            // high_bitdepth, mono_chrome, color_description_present_flag,
+            // color_range
+            (high_bitdepth, 1),
+            (0, 1),
+            (0, 1),
+            (0, 1),
+            // chroma_sample_position, separate_uv_delta_q,
+            // film_grain_params_present
+            (0, 4),
+        ])
+    }
+
+    #[test]
+    fn av1_sequence_headers_are_parsed_and_compared() {
+        let header = av1_sequence_header(1);
+        let mut stream = Vec::new();
+        // temporal delimiter
+        stream.extend([0x12, 0x00]);
+        stream.extend([0x0A, header.len() as u8]);
+        stream.extend(&header);
+        stream.extend([0x0A, header.len() as u8]);
+        stream.extend(av1_sequence_header(0));
+
+        let headers = av1_sequence_headers(&stream)
+            .expect("stream should split")
+            .into_iter()
+            .map(parse_av1_sequence_header)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .expect("headers should parse");
+        assert_eq!(headers[0], StreamParameters {
+            profile:    0,
+            tier:       0,
+            level:      (4, 1),
+            bit_depth:  10,
+            chroma:     "4:2:0",
+            full_range: Some(false),
+            color:      Some((2, 2, 2)),
+            max_size:   (1920, 1080),
+        });
+        assert_eq!(headers[1].bit_depth, 8);
+
+        assert!(check_consistent(&headers[..1]).is_ok());
+        assert!(check_consistent(&headers).is_err());
+        assert!(check_consistent(&[]).is_ok());
+    }
+
+    #[test]
+    fn chunks_with_other_parameters_are_listed() {
+        let encode_dir = tempfile::tempdir().expect("temp dir should be created");
+        for (index, high_bitdepth) in [(0, 1), (1, 1), (2, 0)] {
+            let header = av1_sequence_header(high_bitdepth);
+            let mut frame = vec![0x0A, header.len() as u8];
+            frame.extend(&header);
+
+            let mut ivf = b"DKIF".to_vec();
+            ivf.resize(32, 0);
+            ivf.extend((frame.len() as u32).to_le_bytes());
+            ivf.extend([0; 8]);
+            ivf.extend(&frame);
+            std::fs::write(encode_dir.path().join(format!("{index:05}.ivf")), ivf)
+                .expect("chunk should be written");
+        }
+
+        let err = verify_chunks(encode_dir.path(), Encoder::svt_av1)
+            .expect_err("chunks should be inconsistent")
+            .to_string();
+        assert!(err.contains(
+            "10-bit 4:2:0 limited range, primaries 2, transfer 2, matrix 2, 1920x1080: chunk(s) \
+             0, 1"
+        ));
+        assert!(err.contains(
+            "8-bit 4:2:0 limited range, primaries 2, transfer 2, matrix 2, 1920x1080: chunk(s) 2"
+        ));
+
+        std::fs::remove_file(encode_dir.path().join("00002.ivf")).expect("chunk should be removed");
+        assert!(verify_chunks(encode_dir.path(), Encoder::svt_av1).is_ok());
+    }
+
+    #[test]
+    fn hevc_emulation_prevention_is_removed() {
+        let stream = [
+            0, 0, 0, 1, 0x40, 0x01, 0xAA, // VPS
+            0, 0, 1, 0x42, 0x01, 0x01, 0, 0, 3, 1, 0xFF, // SPS
+        ];
+        assert_eq!(hevc_sequence_parameter_sets(&stream), vec![vec![
+            0x01, 0, 0, 1, 0xFF
+        ]]);
+    }
+}
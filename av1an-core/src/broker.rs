@@ -1,44 +1,212 @@
 use std::{
     fmt::{Debug, Display},
-    fs::File,
-    io::Write,
-    path::Path,
+    fs,
+    path::{Path, PathBuf},
     process::ExitStatus,
     sync::{
-        atomic::{AtomicU8, Ordering},
+        atomic::{AtomicBool, AtomicU8, Ordering},
         mpsc::Sender,
         Arc,
+        Mutex,
     },
-    thread::available_parallelism,
+    thread::{self, available_parallelism},
+    time::{Duration, SystemTime},
 };
 
 use anyhow::bail;
 use cfg_if::cfg_if;
+use once_cell::sync::Lazy;
 use smallvec::SmallVec;
 use thiserror::Error;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 
 use crate::{
     context::Av1anContext,
+    encoder::{probe_file_name, Encoder},
+    ffmpeg::get_video_format,
     finish_progress_bar,
     get_done,
+    parse::{valid_params, EncoderStats},
     progress_bar::{
         dec_bar,
+        inc_bar,
         inc_mp_bar,
         update_mp_chunk,
         update_mp_msg,
         update_progress_bar_estimates,
+        ACTIVE_WORKERS,
+        RETRIES,
     },
-    util::printable_base10_digits,
+    resources::{take_chunk_peak_rss, MemoryGovernor},
+    settings::{invalid_params, merge_video_params, option_names},
+    util::{printable_base10_digits, write_atomic},
+    vapoursynth::parse_failed_frame,
     Chunk,
+    DecodeErrorPolicy,
     DoneChunk,
     Instant,
 };
 
+/// Number of times Ctrl-C was pressed while encoding. The handler can only be
+/// set once per process, so every encoding loop shares it.
+static TERMINATIONS_REQUESTED: Lazy<Arc<AtomicU8>> = Lazy::new(|| {
+    let terminations_requested = Arc::new(AtomicU8::new(0));
+    let terminations_requested_clone = Arc::clone(&terminations_requested);
+    ctrlc::set_handler(move || {
+        let count = terminations_requested_clone.fetch_add(1, Ordering::SeqCst) + 1;
+        if count == 1 {
+            error!("Shutting down. Waiting for current workers to finish...");
+        } else {
+            error!("Shutting down all workers...");
+        }
+    })
+    .expect("should set ctrlc handler");
+    terminations_requested
+});
+
+/// Set while new chunks are held back by [`set_encode_state`]
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the encoding loop starts new chunks. Chunks that are already
+/// encoding run to completion in every state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeState {
+    Running,
+    /// No new chunks are started until the encode is resumed
+    Paused,
+    /// No new chunks are started, as after pressing Ctrl-C
+    Cancelled,
+}
+
+/// Current state of the encoding loop, ignoring `--pause-file`
+#[inline]
+pub fn encode_state() -> EncodeState {
+    if Lazy::get(&TERMINATIONS_REQUESTED)
+        .is_some_and(|terminations_requested| terminations_requested.load(Ordering::SeqCst) > 0)
+    {
+        EncodeState::Cancelled
+    } else if PAUSED.load(Ordering::SeqCst) {
+        EncodeState::Paused
+    } else {
+        EncodeState::Running
+    }
+}
+
+/// Pauses, resumes or cancels the encoding loop. Cancelling is the same as
+/// pressing Ctrl-C, so cancelling twice also stops the chunks that are
+/// encoding, and a cancelled encode cannot be resumed.
+#[inline]
+pub fn set_encode_state(state: EncodeState) {
+    match state {
+        EncodeState::Running => PAUSED.store(false, Ordering::SeqCst),
+        EncodeState::Paused => PAUSED.store(true, Ordering::SeqCst),
+        EncodeState::Cancelled => {
+            TERMINATIONS_REQUESTED.fetch_add(1, Ordering::SeqCst);
+        },
+    }
+}
+
 #[derive(Debug)]
 pub struct Broker<'a> {
-    pub chunk_queue: Vec<Chunk>,
-    pub project:     &'a Av1anContext,
+    pub chunk_queue:            Vec<Chunk>,
+    pub project:                &'a Av1anContext,
+    /// Set once the output of a finished chunk has been checked against the
+    /// requested output pixel format
+    pub output_verified:        AtomicBool,
+    /// No new chunks are started after this point in time
+    pub deadline:               Option<Instant>,
+    /// Encoder parameters reloaded from `--live-params` while encoding
+    pub live_params:            Option<LiveParams>,
+    /// No new chunks are started while this file exists
+    pub pause_file:             Option<PathBuf>,
+    /// No new chunks are started while memory is close to `--memory-ceiling`
+    pub(crate) memory_governor: Option<MemoryGovernor>,
+}
+
+/// Encoder parameters read from a file that may be edited while encoding.
+/// Every change that passes validation starts a new generation, which is
+/// applied to the chunks started after it.
+#[derive(Debug)]
+pub struct LiveParams {
+    path:    PathBuf,
+    encoder: Encoder,
+    state:   Mutex<LiveParamsState>,
+}
+
+#[derive(Debug, Default)]
+struct LiveParamsState {
+    modified:   Option<SystemTime>,
+    generation: usize,
+    params:     Vec<String>,
+}
+
+impl LiveParams {
+    /// Changes made to `path` after this call are picked up; its current
+    /// contents are not applied.
+    pub fn new(path: PathBuf, encoder: Encoder) -> Self {
+        let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+        Self {
+            path,
+            encoder,
+            state: Mutex::new(LiveParamsState {
+                modified,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Returns the current generation and its parameters, reloading the file
+    /// first if it changed. Generation 0 means the file has not changed
+    /// since encoding started.
+    pub fn current(&self) -> (usize, Vec<String>) {
+        let mut state = self.state.lock().expect("mutex should acquire lock");
+        let modified = fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok();
+        if modified.is_some() && modified != state.modified {
+            state.modified = modified;
+            match self.load() {
+                Ok(params) => {
+                    state.generation += 1;
+                    info!(
+                        "loaded video params generation {generation} from {path}: {params}",
+                        generation = state.generation,
+                        path = self.path.display(),
+                        params = params.join(" ")
+                    );
+                    state.params = params;
+                },
+                Err(e) => warn!(
+                    "Ignoring changed video params in {path}: {e}",
+                    path = self.path.display()
+                ),
+            }
+        }
+
+        (state.generation, state.params.clone())
+    }
+
+    fn load(&self) -> anyhow::Result<Vec<String>> {
+        let contents = fs::read_to_string(&self.path)?;
+        let Some(params) = shlex::split(&contents) else {
+            bail!("failed to split video params");
+        };
+        if params.is_empty() {
+            bail!("no video params found");
+        }
+
+        let help_text = self.encoder.help_text()?;
+        let valid_params = valid_params(&help_text, self.encoder);
+        let option_names = option_names(self.encoder, &params);
+        let invalid_params = invalid_params(&option_names, &valid_params);
+        if !invalid_params.is_empty() {
+            bail!(
+                "invalid parameter(s) for {encoder}: {invalid}",
+                encoder = self.encoder,
+                invalid = invalid_params.join(", ")
+            );
+        }
+
+        Ok(params)
+    }
 }
 
 #[derive(Clone)]
@@ -129,17 +297,7 @@ impl Broker<'_> {
             drop(sender);
 
             crossbeam_utils::thread::scope(|s| {
-                let terminations_requested = Arc::new(AtomicU8::new(0));
-                let terminations_requested_clone = Arc::clone(&terminations_requested);
-                ctrlc::set_handler(move || {
-                    let count = terminations_requested_clone.fetch_add(1, Ordering::SeqCst) + 1;
-                    if count == 1 {
-                        error!("Shutting down. Waiting for current workers to finish...");
-                    } else {
-                        error!("Shutting down all workers...");
-                    }
-                })
-                .expect("should set ctrlc handler");
+                let terminations_requested = Arc::clone(&TERMINATIONS_REQUESTED);
 
                 let consumers: Vec<_> = (0..self.project.args.workers)
                     .map(|idx| (receiver.clone(), &self, idx, Arc::clone(&terminations_requested)))
@@ -172,14 +330,24 @@ impl Broker<'_> {
                             }
 
                             while let Ok(mut chunk) = rx.recv() {
-                                if terminations_requested.load(Ordering::SeqCst) == 0
-                                    && let Err(e) = queue.encode_chunk(
-                                        &mut chunk,
-                                        worker_id,
-                                        &terminations_requested,
-                                        total_chunks,
-                                    )
+                                queue.wait_while_paused(worker_id, &terminations_requested);
+                                queue.wait_for_memory(worker_id, &terminations_requested);
+                                if terminations_requested.load(Ordering::SeqCst) > 0
+                                    || queue.deadline_passed()
                                 {
+                                    continue;
+                                }
+
+                                ACTIVE_WORKERS.fetch_add(1, Ordering::SeqCst);
+                                let res = queue.encode_chunk(
+                                    &mut chunk,
+                                    worker_id,
+                                    &terminations_requested,
+                                    total_chunks,
+                                );
+                                ACTIVE_WORKERS.fetch_sub(1, Ordering::SeqCst);
+
+                                if let Err(e) = res {
                                     error!("[chunk {index}] {e}", index = chunk.index);
                                     tx.send(()).expect("should send successfully");
                                     return Err(());
@@ -205,8 +373,52 @@ impl Broker<'_> {
         Ok(())
     }
 
+    /// Whether the `--time-budget` has run out
+    pub fn deadline_passed(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    fn paused(&self) -> bool {
+        PAUSED.load(Ordering::SeqCst) || self.pause_file.as_ref().is_some_and(|path| path.exists())
+    }
+
+    /// Blocks the worker until the encode is resumed or cancelled
+    fn wait_while_paused(&self, worker_id: usize, terminations_requested: &AtomicU8) {
+        if !self.paused() {
+            return;
+        }
+
+        info!("worker {worker_id} paused");
+        while self.paused() && terminations_requested.load(Ordering::SeqCst) == 0 {
+            thread::sleep(Duration::from_millis(500));
+        }
+        info!("worker {worker_id} resumed");
+    }
+
+    /// Blocks the worker until the chunk it is about to start fits under
+    /// `--memory-ceiling`
+    fn wait_for_memory(&self, worker_id: usize, terminations_requested: &AtomicU8) {
+        let Some(governor) = &self.memory_governor else {
+            return;
+        };
+
+        let mut waiting = false;
+        while !governor.try_start(ACTIVE_WORKERS.load(Ordering::SeqCst))
+            && terminations_requested.load(Ordering::SeqCst) == 0
+        {
+            if !waiting {
+                info!("worker {worker_id} waiting for memory");
+                waiting = true;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+        if waiting {
+            info!("worker {worker_id} resumed");
+        }
+    }
+
     #[tracing::instrument(skip(self, chunk, terminations_requested), fields(chunk_index = format!("{:>05}", chunk.index)))]
-    fn encode_chunk(
+    pub(crate) fn encode_chunk(
         &self,
         chunk: &mut Chunk,
         worker_id: usize,
@@ -217,7 +429,7 @@ impl Broker<'_> {
 
         // we display the index, so we need to subtract 1 to get the max index
         let padding = printable_base10_digits(self.chunk_queue.len() - 1) as usize;
-        update_mp_chunk(worker_id, chunk.index, padding);
+        update_mp_chunk(worker_id, &chunk.label(), padding);
 
         if let Some((min, max)) = chunk.target_quality.target {
             update_mp_msg(
@@ -236,8 +448,9 @@ impl Broker<'_> {
                     self.project.args.vapoursynth_plugins,
                 );
                 match res {
-                    Ok(cq) => {
+                    Ok((cq, score)) => {
                         chunk.tq_cq = Some(cq);
+                        chunk.tq_score = Some(score);
                         break;
                     },
                     Err(e) => {
@@ -253,22 +466,19 @@ impl Broker<'_> {
                 }
             }
 
+            // Probes are encoded from every frame of the chunk, so they cannot
+            // stand in for a chunk that drops duplicate frames
             if chunk.target_quality.params_copied
                 && chunk.target_quality.probing_rate == 1
                 && self.project.args.ffmpeg_filter_args.is_empty()
                 && chunk.proxy.is_none()
+                && chunk.dropped_frames.is_empty()
                 && let Some(optimal_q) = chunk.tq_cq
             {
-                let extension = match self.project.args.encoder {
-                    crate::encoder::Encoder::x264 => "264",
-                    crate::encoder::Encoder::x265 => "hevc",
-                    _ => "ivf",
-                };
-                let probe_file =
-                    std::path::Path::new(&self.project.args.temp).join("split").join({
-                        let q_str = crate::encoder::format_q(optimal_q);
-                        format!("v_{:05}_{}.{}", chunk.index, q_str, extension)
-                    });
+                let extension = self.project.args.encoder.output_extension();
+                let probe_file = std::path::Path::new(&self.project.args.temp).join("split").join(
+                    probe_file_name(self.project.args.encoder, chunk.index, optimal_q),
+                );
 
                 if probe_file.exists() {
                     let encode_dir = std::path::Path::new(&self.project.args.temp).join("encode");
@@ -283,10 +493,16 @@ impl Broker<'_> {
                     get_done().done.insert(chunk.name(), DoneChunk {
                         frames:     chunk.frames(),
                         size_bytes: output_file.metadata()?.len(),
+                        psnr:       None,
+                        avg_qp:     None,
+                        tq_cq:      chunk.tq_cq,
+                        tq_score:   chunk.tq_score,
+                        peak_rss:   None,
+                        encode_fps: Some(chunk.frames() as f64 / st_time.elapsed().as_secs_f64()),
+                        retries:    Some(0),
                     });
 
-                    let mut progress_file = File::create(progress_file)?;
-                    progress_file.write_all(serde_json::to_string(get_done())?.as_bytes())?;
+                    write_atomic(&progress_file, serde_json::to_string(get_done())?)?;
 
                     update_progress_bar_estimates(
                         chunk.frame_rate,
@@ -307,6 +523,17 @@ impl Broker<'_> {
             );
         }
 
+        if let Some(live_params) = &self.live_params
+            && chunk.encoder == live_params.encoder
+        {
+            let (generation, params) = live_params.current();
+            merge_video_params(chunk.encoder, &mut chunk.video_params, params);
+            debug!(
+                "chunk {index:05} uses video params generation {generation}",
+                index = chunk.index
+            );
+        }
+
         // space padding at the beginning to align with "finished chunk"
         debug!(
             " started chunk {index:05}: {frames} frames",
@@ -314,55 +541,111 @@ impl Broker<'_> {
             frames = chunk.frames()
         );
 
+        // Source frames that failed to decode and are replaced according to
+        // `--decode-error` on subsequent attempts
+        let mut patched_frames = Vec::new();
         let passes = chunk.passes;
-        for current_pass in 1..=passes {
-            for r#try in 1..=self.project.args.max_tries {
-                let res = self.project.create_pipes(chunk, current_pass, worker_id, padding);
-                if let Err((e, frames)) = res {
-                    dec_bar(frames);
-
-                    // If user presses CTRL+C more than once, do not let the worker finish
-                    if terminations_requested.load(Ordering::SeqCst) > 1 {
-                        bail!(
-                            "Termination requested after Worker restart. Skipping chunk {}",
-                            chunk.index
-                        );
-                    }
-
-                    if r#try == self.project.args.max_tries {
-                        bail!(
-                            "[chunk {index}] encoder failed {tries} times, shutting down worker: \
-                             {e}",
-                            index = chunk.index,
-                            tries = self.project.args.max_tries
-                        );
-                    }
-                    // avoids double-print of the error message as both a WARN and ERROR,
-                    // since `Broker::encoding_loop` will print the error message as well
+        // The first pass stats were sliced from the first pass over the whole clip
+        let first_pass = if self.project.uses_whole_clip_first_pass(chunk) {
+            2
+        } else {
+            1
+        };
+        let mut stats = EncoderStats::default();
+        let mut retries = 0;
+        for current_pass in first_pass..=passes {
+            let mut r#try = 1;
+            loop {
+                let res = self.project.create_pipes(
+                    chunk,
+                    current_pass,
+                    worker_id,
+                    padding,
+                    &patched_frames,
+                );
+                let (e, frames) = match res {
+                    Ok(pass_stats) => {
+                        stats = pass_stats;
+                        break;
+                    },
+                    Err(err) => err,
+                };
+                dec_bar(frames);
+
+                // If user presses CTRL+C more than once, do not let the worker finish
+                if terminations_requested.load(Ordering::SeqCst) > 1 {
+                    bail!(
+                        "Termination requested after Worker restart. Skipping chunk {}",
+                        chunk.index
+                    );
+                }
+
+                // Patching a frame does not count as a try, since every corrupt frame
+                // in the chunk needs its own attempt to be found
+                if self.project.args.decode_error != DecodeErrorPolicy::Fail
+                    && let Some(frame) = parse_failed_frame(&e.to_string())
+                    && (chunk.start_frame..chunk.end_frame).contains(&frame)
+                    && !patched_frames.contains(&frame)
+                {
                     warn!(
-                        "Encoder failed (on chunk {index}):\n{e}",
-                        index = chunk.index
+                        "[chunk {index}] frame {frame} could not be decoded, replacing it \
+                         ({policy})",
+                        index = chunk.index,
+                        policy = self.project.args.decode_error
+                    );
+                    patched_frames.push(frame);
+                    continue;
+                }
+
+                if r#try == self.project.args.max_tries {
+                    bail!(
+                        "[chunk {index}] encoder failed {tries} times, shutting down worker: {e}",
+                        index = chunk.index,
+                        tries = self.project.args.max_tries
                     );
-                } else {
-                    break;
                 }
+                // avoids double-print of the error message as both a WARN and ERROR,
+                // since `Broker::encoding_loop` will print the error message as well
+                warn!(
+                    "Encoder failed (on chunk {index}):\n{e}",
+                    index = chunk.index
+                );
+                RETRIES.fetch_add(1, Ordering::SeqCst);
+                retries += 1;
+                r#try += 1;
             }
         }
 
+        // Dropped duplicate frames are never sent to the encoder
+        inc_bar((chunk.frames() - chunk.encoded_frames()) as u64);
+
         let enc_time = st_time.elapsed();
         let fps = chunk.frames() as f64 / enc_time.as_secs_f64();
 
+        // Check the first finished chunk before it is marked as done, so a resumed
+        // encode does not keep a chunk in the wrong format
+        if !self.output_verified.swap(true, Ordering::SeqCst) {
+            self.verify_output_format(chunk)?;
+        }
+
         let progress_file = Path::new(&self.project.args.temp).join("done.json");
         get_done().done.insert(chunk.name(), DoneChunk {
             frames:     chunk.frames(),
-            size_bytes: Path::new(&chunk.output())
+            size_bytes: chunk
+                .output()
                 .metadata()
                 .expect("Unable to get size of finished chunk")
                 .len(),
+            psnr:       stats.psnr,
+            avg_qp:     stats.avg_qp,
+            tq_cq:      chunk.tq_cq,
+            tq_score:   chunk.tq_score,
+            peak_rss:   take_chunk_peak_rss(&chunk.name()),
+            encode_fps: Some(fps),
+            retries:    Some(retries),
         });
 
-        let mut progress_file = File::create(progress_file)?;
-        progress_file.write_all(serde_json::to_string(get_done())?.as_bytes())?;
+        write_atomic(&progress_file, serde_json::to_string(get_done())?)?;
 
         update_progress_bar_estimates(
             chunk.frame_rate,
@@ -379,4 +662,79 @@ impl Broker<'_> {
 
         Ok(())
     }
+
+    /// Compares the bit depth, chroma subsampling and resolution of an encoded
+    /// chunk with the requested output pixel format and the resolution of the
+    /// input, so that an encoder silently producing a different format (e.g.
+    /// because of a missing parameter) is caught after the first chunk instead
+    /// of at the end of the encode.
+    fn verify_output_format(&self, chunk: &Chunk) -> anyhow::Result<()> {
+        let requested = self.project.args.output_pix_format;
+        let (format, resolution) = match get_video_format(&chunk.output()) {
+            Ok(format) => format,
+            Err(e) => {
+                warn!(
+                    "Unable to verify the output format of chunk {index}: {e}",
+                    index = chunk.index
+                );
+                return Ok(());
+            },
+        };
+
+        let bit_depth = format.get_format_bit_depth_usize();
+        if bit_depth != requested.bit_depth {
+            bail!(
+                "{encoder} produced {bit_depth}-bit output ({format}) for chunk {index}, but \
+                 {requested_depth}-bit output was requested with --pix-format {requested_format}. \
+                 Check that the video params set the output bit depth of the encoder.",
+                encoder = chunk.encoder,
+                format = format.to_pix_fmt_string(),
+                index = chunk.index,
+                requested_depth = requested.bit_depth,
+                requested_format = requested.format.to_pix_fmt_string(),
+            );
+        }
+
+        let subsampling = format.chroma_subsampling();
+        if subsampling != requested.format.chroma_subsampling() {
+            bail!(
+                "{encoder} produced {subsampling} output ({format}) for chunk {index}, but \
+                 {requested_subsampling} output was requested with --pix-format \
+                 {requested_format}. Check that the video params set the chroma subsampling of \
+                 the encoder.",
+                encoder = chunk.encoder,
+                format = format.to_pix_fmt_string(),
+                index = chunk.index,
+                requested_subsampling = requested.format.chroma_subsampling(),
+                requested_format = requested.format.to_pix_fmt_string(),
+            );
+        }
+
+        // The resolution is only known if no FFmpeg filters scale the frames
+        if let Some(expected) = self.project.chunk_resolution()?
+            && resolution != expected
+        {
+            bail!(
+                "{encoder} produced {width}x{height} output for chunk {index}, but the input is \
+                 {expected_width}x{expected_height}. Check that the video params do not resize \
+                 the frames.",
+                encoder = chunk.encoder,
+                width = resolution.0,
+                height = resolution.1,
+                index = chunk.index,
+                expected_width = expected.0,
+                expected_height = expected.1,
+            );
+        }
+
+        debug!(
+            "verified output format of chunk {index:05}: {bit_depth}-bit {subsampling} \
+             {width}x{height}",
+            index = chunk.index,
+            width = resolution.0,
+            height = resolution.1
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,95 @@
+use std::{
+    collections::HashSet,
+    io,
+    process::{Child, Command, Output, Stdio},
+    sync::Mutex,
+    thread,
+};
+
+use once_cell::sync::Lazy;
+use sysinfo::{Pid, ProcessesToUpdate, System};
+
+/// Child processes that are still running, such as encoders, ffmpeg, vspipe
+/// and mkvmerge
+static RUNNING: Lazy<Mutex<HashSet<u32>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Keeps a child process in the registry of running processes until dropped.
+/// A child whose guard is dropped while its thread is panicking is killed, as
+/// nothing is going to wait for it anymore.
+pub(crate) struct ChildGuard {
+    pid: u32,
+}
+
+impl ChildGuard {
+    pub(crate) fn new(child: &Child) -> Self {
+        let pid = child.id();
+        RUNNING.lock().expect("mutex should acquire lock").insert(pid);
+        Self {
+            pid,
+        }
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            kill(&[self.pid]);
+        }
+        // Poisoned while another thread panicked, but the set is still valid
+        let mut running = RUNNING.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        running.remove(&self.pid);
+    }
+}
+
+/// Runs `command` to completion and collects its output like
+/// [`Command::output`], while keeping it in the registry of running processes.
+/// Unlike [`Command::output`], stdin is inherited unless set.
+pub(crate) fn output(command: &mut Command) -> io::Result<Output> {
+    let child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let _guard = ChildGuard::new(&child);
+    child.wait_with_output()
+}
+
+/// Kills every child process that is still running. Used before exiting
+/// after a panic, which would otherwise leave them running without anything
+/// consuming their output.
+#[inline]
+pub fn kill_children() {
+    let running = RUNNING.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    kill(&running.iter().copied().collect::<Vec<_>>());
+}
+
+fn kill(pids: &[u32]) {
+    if pids.is_empty() {
+        return;
+    }
+    let pids = pids.iter().map(|&pid| Pid::from_u32(pid)).collect::<Vec<_>>();
+    let mut system = System::new();
+    system.refresh_processes(ProcessesToUpdate::Some(&pids), true);
+    for pid in &pids {
+        if let Some(process) = system.process(*pid) {
+            process.kill();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::{ChildGuard, RUNNING};
+
+    #[test]
+    #[cfg(unix)]
+    fn guard_registers_child_until_dropped() {
+        let mut child = Command::new("sleep").arg("1").spawn().expect("sleep should spawn");
+        let pid = child.id();
+        let guard = ChildGuard::new(&child);
+        assert!(RUNNING.lock().expect("mutex should acquire lock").contains(&pid));
+
+        child.kill().expect("sleep should be killed");
+        child.wait().expect("sleep should be waited for");
+        drop(guard);
+        assert!(!RUNNING.lock().expect("mutex should acquire lock").contains(&pid));
+    }
+}
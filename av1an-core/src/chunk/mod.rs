@@ -1,20 +1,39 @@
 #[cfg(test)]
 mod tests;
 
-use std::{ffi::OsString, path::Path};
+use std::{
+    ffi::OsString,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
 
-use av1_grain::{generate_photon_noise_params, write_grain_table, NoiseGenArgs};
+use arrayvec::ArrayVec;
+use av1_grain::{
+    generate_photon_noise_params,
+    write_grain_table,
+    NoiseGenArgs,
+    ScalingPoints,
+    NUM_UV_POINTS,
+};
 use serde::{Deserialize, Serialize};
 use tracing::debug;
 
 use crate::{
     encoder::Encoder,
+    ffmpeg::FFPixelFormat,
+    into_vec,
     settings::insert_noise_table_params,
+    util::remap_paths,
     ColorRange,
     Input,
     TargetQuality,
 };
 
+/// Index of the chunk that runs the first pass over the whole clip for
+/// `--whole-clip-first-pass`. No chunk of the queue uses it, so the files of
+/// that chunk never replace those of a queued chunk.
+pub const WHOLE_CLIP_INDEX: usize = usize::MAX;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Chunk {
     pub temp:                  String,
@@ -37,36 +56,148 @@ pub struct Chunk {
     /// Optional target quality CQ level
     #[serde(rename = "per_shot_target_quality_cq")]
     pub tq_cq:                 Option<f32>,
+    /// Score that target quality probed or predicted for `tq_cq`
+    #[serde(default)]
+    pub tq_score:              Option<f64>,
+    /// Frame ranges relative to `start_frame`, with an exclusive end, that are
+    /// dropped before encoding because they repeat the frame before them
+    #[serde(default)]
+    pub dropped_frames:        Vec<(usize, usize)>,
     pub ignore_frame_mismatch: bool,
 }
 
 impl Chunk {
-    /// Returns numeric name of chunk `00001`
+    /// Returns numeric name of chunk `00001`, or `whole_clip` for the chunk
+    /// that covers the whole clip
     pub fn name(&self) -> String {
-        format!("{:05}", self.index)
+        if self.index == WHOLE_CLIP_INDEX {
+            "whole_clip".to_owned()
+        } else {
+            format!("{:05}", self.index)
+        }
     }
 
-    pub fn output(&self) -> String {
+    /// Returns the index of the chunk as shown in messages, or `whole clip`
+    /// for the chunk that covers the whole clip
+    pub fn label(&self) -> String {
+        if self.index == WHOLE_CLIP_INDEX {
+            "whole clip".to_owned()
+        } else {
+            self.index.to_string()
+        }
+    }
+
+    pub fn output(&self) -> PathBuf {
         Path::new(&self.temp)
             .join("encode")
             .join(format!("{}.{}", self.name(), self.output_ext))
-            .to_string_lossy()
-            .to_string()
     }
 
     pub const fn frames(&self) -> usize {
         self.end_frame - self.start_frame
     }
 
+    /// Number of frames passed to the encoder
+    pub fn encoded_frames(&self) -> usize {
+        self.frames() - self.dropped_frames.iter().map(|(start, end)| end - start).sum::<usize>()
+    }
+
+    /// Drops the frames within the `duplicates` ranges of source frames, except
+    /// for the first frame of the chunk
+    pub(crate) fn drop_duplicate_frames(&mut self, duplicates: &[(usize, usize)]) {
+        self.dropped_frames = duplicates
+            .iter()
+            .filter_map(|&(start, end)| {
+                let start = start.max(self.start_frame + 1);
+                let end = end.min(self.end_frame);
+                (start < end).then(|| (start - self.start_frame, end - self.start_frame))
+            })
+            .collect();
+    }
+
+    /// FFmpeg arguments that drop the `dropped_frames` from the piped frames
+    pub(crate) fn drop_frames_args(&self) -> Vec<String> {
+        if self.dropped_frames.is_empty() {
+            return Vec::new();
+        }
+
+        let dropped = self
+            .dropped_frames
+            .iter()
+            .map(|(start, end)| format!(r"between(n\,{start}\,{last})", last = end - 1))
+            .collect::<Vec<_>>()
+            .join("+");
+        into_vec!["-vf", format!("select=not({dropped})"), "-fps_mode", "passthrough"]
+    }
+
+    /// Rewrites the paths of the chunk with `remaps`, so that a chunk queue
+    /// saved with a different temp folder or input location can be resumed
+    pub(crate) fn remap_paths(&mut self, remaps: &[(String, String)]) {
+        let remap_strings = |strings: &mut Vec<String>| {
+            for string in strings {
+                if let Some(remapped) = remap_paths(string, remaps) {
+                    *string = remapped;
+                }
+            }
+        };
+        let remap_args = |args: &mut Vec<OsString>| {
+            for arg in args {
+                if let Some(remapped) = arg.to_str().and_then(|arg| remap_paths(arg, remaps)) {
+                    *arg = remapped.into();
+                }
+            }
+        };
+
+        for temp in [&mut self.temp, &mut self.target_quality.temp] {
+            if let Some(remapped) = remap_paths(temp, remaps) {
+                *temp = remapped;
+            }
+        }
+        self.input.remap_paths(remaps);
+        if let Some(proxy) = &mut self.proxy {
+            proxy.remap_paths(remaps);
+        }
+        remap_args(&mut self.source_cmd);
+        if let Some(proxy_cmd) = &mut self.proxy_cmd {
+            remap_args(proxy_cmd);
+        }
+        remap_strings(&mut self.video_params);
+        if let Some(video_params) = &mut self.target_quality.video_params {
+            remap_strings(video_params);
+        }
+    }
+
+    /// Appends the geometry and bit depth arguments that the chunk's encoder
+    /// needs passed explicitly, unless they are already in the video params.
+    pub(crate) fn apply_geometry_args(
+        &mut self,
+        resolution: Option<(u32, u32)>,
+        format: FFPixelFormat,
+    ) {
+        let args = self.encoder.get_geometry_arguments(&self.video_params, resolution, format);
+        self.video_params.extend(args);
+    }
+
+    /// Generates the photon noise table of the chunk and passes it to the
+    /// encoder. With a `seed`, the grain of the chunk is seeded from it and
+    /// the chunk index, so that every chunk gets its own grain that is the
+    /// same in every run.
     pub(crate) fn apply_photon_noise_args(
         &mut self,
         photon_noise: Option<u8>,
-        chroma_noise: bool,
+        chroma_noise: ChromaNoise,
         color_range: Option<ColorRange>,
+        seed: Option<u16>,
     ) -> anyhow::Result<()> {
         if let Some(strength) = photon_noise {
             let iso_setting = u32::from(strength) * 100;
-            let grain_table = Path::new(&self.temp).join(format!("iso{iso_setting}-grain.tbl"));
+            let random_seed = seed.map(|seed| chunk_grain_seed(seed, self.index));
+            let grain_table = Path::new(&self.temp).join(grain_table_name(
+                iso_setting,
+                chroma_noise,
+                self.noise_size,
+                random_seed,
+            ));
             if !grain_table.exists() {
                 debug!("Generating grain table at ISO {iso_setting}");
                 let clip_info = self.input.clip_info()?;
@@ -79,15 +210,34 @@ impl Chunk {
                 }
                 let transfer_function =
                     clip_info.transfer_function_params_adjusted(&self.video_params);
-                let params = generate_photon_noise_params(0, u64::MAX, NoiseGenArgs {
+                let noise_args = |iso_setting| NoiseGenArgs {
                     iso_setting,
                     width,
                     height,
                     transfer_function,
-                    chroma_grain: chroma_noise,
+                    chroma_grain: chroma_noise == ChromaNoise::FromLuma,
                     full_range: matches!(color_range, Some(ColorRange::Full)),
-                    random_seed: None,
-                });
+                    random_seed,
+                };
+                let mut params = generate_photon_noise_params(0, u64::MAX, noise_args(iso_setting));
+                if let ChromaNoise::Strength {
+                    strength,
+                    cb,
+                    cr,
+                } = chroma_noise
+                {
+                    let chroma = generate_photon_noise_params(
+                        0,
+                        u64::MAX,
+                        noise_args(u32::from(strength) * 100),
+                    );
+                    params.scaling_points_cb = chroma_scaling_points(&chroma.scaling_points_y, cb);
+                    params.scaling_points_cr = chroma_scaling_points(&chroma.scaling_points_y, cr);
+                    // Neutral multipliers, so that the chroma grain is scaled by
+                    // the luma of the pixel like the luma grain
+                    (params.cb_mult, params.cb_luma_mult, params.cb_offset) = (128, 192, 256);
+                    (params.cr_mult, params.cr_luma_mult, params.cr_offset) = (128, 192, 256);
+                }
                 write_grain_table(&grain_table, &[params])?;
             }
 
@@ -97,3 +247,188 @@ impl Chunk {
         Ok(())
     }
 }
+
+/// Chroma grain of the photon noise tables
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ChromaNoise {
+    /// Grain only on the luma plane
+    #[default]
+    None,
+    /// Chroma grain scaled like the luma grain
+    FromLuma,
+    /// Chroma grain with its own strength, multiplied by the coefficients of
+    /// the Cb and Cr planes
+    Strength {
+        strength: u8,
+        cb:       f64,
+        cr:       f64,
+    },
+}
+
+/// File name of the grain table, which is shared by every chunk with the same
+/// settings
+fn grain_table_name(
+    iso_setting: u32,
+    chroma_noise: ChromaNoise,
+    noise_size: (Option<u32>, Option<u32>),
+    random_seed: Option<u16>,
+) -> String {
+    let mut name = format!("iso{iso_setting}");
+    match chroma_noise {
+        ChromaNoise::None => (),
+        ChromaNoise::FromLuma => name.push_str("-chroma"),
+        ChromaNoise::Strength {
+            strength,
+            cb,
+            cr,
+        } => {
+            write!(
+                name,
+                "-chroma{}-cb{cb:.2}-cr{cr:.2}",
+                u32::from(strength) * 100
+            )
+            .expect("write to string should work");
+        },
+    }
+    if let Some(width) = noise_size.0 {
+        write!(name, "-w{width}").expect("write to string should work");
+    }
+    if let Some(height) = noise_size.1 {
+        write!(name, "-h{height}").expect("write to string should work");
+    }
+    if let Some(random_seed) = random_seed {
+        write!(name, "-seed{random_seed}").expect("write to string should work");
+    }
+    name.push_str("-grain.tbl");
+    name
+}
+
+/// Picks chroma scaling points from the luma scaling points `luma`, which have
+/// more points than the chroma planes allow, with their strength multiplied by
+/// `coeff`. The first and last points are always kept.
+fn chroma_scaling_points(luma: &ScalingPoints, coeff: f64) -> ArrayVec<[u8; 2], NUM_UV_POINTS> {
+    if luma.len() < 2 {
+        return luma.iter().copied().collect();
+    }
+    let last = luma.len() - 1;
+    let picked = luma.len().min(NUM_UV_POINTS);
+    (0..picked)
+        .map(|idx| {
+            let [x, strength] = luma[(idx * last).div_ceil(picked - 1)];
+            [x, (f64::from(strength) * coeff).round().min(255.0) as u8]
+        })
+        .collect()
+}
+
+/// Derives the grain seed of the chunk with `index` from the seed of the run
+/// with SplitMix64, so that neighboring chunks get unrelated seeds
+pub(crate) fn chunk_grain_seed(seed: u16, index: usize) -> u16 {
+    let mut z = ((u64::from(seed) << 32) | index as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)) as u16
+}
+
+/// Linearly interpolates the photon noise strength at `seconds` between
+/// `anchors`, which must be sorted by time. Times before the first or after
+/// the last anchor use that anchor's strength. Returns `None` if there are no
+/// anchors or the resulting strength is 0.
+pub(crate) fn photon_noise_at(anchors: &[(f64, u8)], seconds: f64) -> Option<u8> {
+    let next = anchors.iter().position(|&(time, _)| time > seconds);
+    let strength = match next {
+        None => f64::from(anchors.last()?.1),
+        Some(0) => f64::from(anchors[0].1),
+        Some(idx) => {
+            let (start_time, start_strength) = anchors[idx - 1];
+            let (end_time, end_strength) = anchors[idx];
+            let progress = (seconds - start_time) / (end_time - start_time);
+            (f64::from(end_strength) - f64::from(start_strength))
+                .mul_add(progress, f64::from(start_strength))
+        },
+    };
+
+    let strength = strength.round() as u8;
+    (strength > 0).then_some(strength)
+}
+
+/// Averages per-chunk values weighted by the number of frames in each chunk.
+///
+/// Each value is given as `(frames, value)`. Returns `None` if there are no
+/// frames to average over.
+pub(crate) fn frame_weighted_mean(values: &[(usize, f64)]) -> Option<f64> {
+    let frames = values.iter().map(|&(frames, _)| frames).sum::<usize>();
+    if frames == 0 {
+        return None;
+    }
+    let total = values.iter().map(|&(frames, value)| frames as f64 * value).sum::<f64>();
+    Some(total / frames as f64)
+}
+
+/// Returns the timestamps of the frames of `chunks` that are not dropped, in
+/// the timestamp format v2 of mkvmerge, so that every kept frame lasts until
+/// the next one. Frames are timed by `source_timestamps` in seconds if given,
+/// or by `frame_rate` otherwise.
+pub(crate) fn kept_frame_timestamps(
+    chunks: &[Chunk],
+    frame_rate: f64,
+    source_timestamps: Option<&[f64]>,
+) -> String {
+    let mut chunks = chunks.iter().collect::<Vec<_>>();
+    chunks.sort_unstable_by_key(|chunk| chunk.index);
+
+    let mut timestamps = String::from("# timestamp format v2\n");
+    for chunk in chunks {
+        for frame in 0..chunk.frames() {
+            if chunk.dropped_frames.iter().any(|&(start, end)| (start..end).contains(&frame)) {
+                continue;
+            }
+            let source_frame = chunk.start_frame + frame;
+            let milliseconds = source_timestamps
+                .and_then(|timestamps| timestamps.get(source_frame))
+                .map_or(source_frame as f64 * 1000.0 / frame_rate, |seconds| {
+                    seconds * 1000.0
+                });
+            writeln!(timestamps, "{milliseconds:.6}").expect("writing to a String cannot fail");
+        }
+    }
+
+    timestamps
+}
+
+/// Returns the chunks whose bitrate is more than `threshold` times the median
+/// chunk bitrate, along with that ratio, sorted from the largest spike down.
+///
+/// Each chunk is given as `(name, frames, size_bytes)`. Since all chunks share
+/// the same frame rate, the size per frame is used as the bitrate.
+pub(crate) fn find_bitrate_spikes(
+    chunks: &[(String, usize, u64)],
+    threshold: f64,
+) -> Vec<(String, f64)> {
+    let mut bitrates = chunks
+        .iter()
+        .filter(|&&(_, frames, _)| frames > 0)
+        .map(|&(ref name, frames, size)| (name, size as f64 / frames as f64))
+        .collect::<Vec<_>>();
+    if bitrates.is_empty() {
+        return Vec::new();
+    }
+
+    let mut sorted = bitrates.iter().map(|&(_, bitrate)| bitrate).collect::<Vec<_>>();
+    sorted.sort_by(f64::total_cmp);
+    let mid = sorted.len() / 2;
+    let median = if sorted.len() % 2 == 0 {
+        f64::midpoint(sorted[mid - 1], sorted[mid])
+    } else {
+        sorted[mid]
+    };
+    if median <= 0.0 {
+        return Vec::new();
+    }
+
+    bitrates.sort_by(|a, b| b.1.total_cmp(&a.1));
+    bitrates
+        .into_iter()
+        .map(|(name, bitrate)| (name.clone(), bitrate / median))
+        .filter(|&(_, ratio)| ratio > threshold)
+        .collect()
+}
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{collections::HashSet, path::PathBuf};
 
 use super::*;
 use crate::{vapoursynth, ChunkMethod};
@@ -24,6 +24,8 @@ fn chunk_name_1() {
         frame_rate:            30.0,
         target_quality:        TargetQuality::default("none", Encoder::x264),
         tq_cq:                 None,
+        tq_score:              None,
+        dropped_frames:        Vec::new(),
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
@@ -53,6 +55,8 @@ fn chunk_name_10000() {
         frame_rate:            30.0,
         target_quality:        TargetQuality::default("none", Encoder::x264),
         tq_cq:                 None,
+        tq_score:              None,
+        dropped_frames:        Vec::new(),
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
@@ -60,6 +64,40 @@ fn chunk_name_10000() {
         ignore_frame_mismatch: false,
     };
     assert_eq!("10000", ch.name());
+    assert_eq!("10000", ch.label());
+}
+
+#[test]
+fn chunk_name_whole_clip() {
+    let ch = Chunk {
+        temp:                  "none".to_owned(),
+        index:                 WHOLE_CLIP_INDEX,
+        input:                 Input::Video {
+            path:         "test.mkv".into(),
+            temp:         "none".to_owned(),
+            chunk_method: ChunkMethod::LSMASH,
+            is_proxy:     false,
+            cache_mode:   vapoursynth::CacheSource::SOURCE,
+        },
+        proxy:                 None,
+        source_cmd:            vec!["".into()],
+        proxy_cmd:             None,
+        output_ext:            "ivf".to_owned(),
+        start_frame:           0,
+        end_frame:             5,
+        frame_rate:            30.0,
+        target_quality:        TargetQuality::default("none", Encoder::aom),
+        tq_cq:                 None,
+        tq_score:              None,
+        dropped_frames:        Vec::new(),
+        passes:                2,
+        video_params:          vec![],
+        encoder:               Encoder::aom,
+        noise_size:            (None, None),
+        ignore_frame_mismatch: false,
+    };
+    assert_eq!("whole_clip", ch.name());
+    assert_eq!("whole clip", ch.label());
 }
 
 #[test]
@@ -83,6 +121,8 @@ fn chunk_output() {
         frame_rate:            30.0,
         target_quality:        TargetQuality::default("d", Encoder::x264),
         tq_cq:                 None,
+        tq_score:              None,
+        dropped_frames:        Vec::new(),
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
@@ -90,10 +130,9 @@ fn chunk_output() {
         ignore_frame_mismatch: false,
     };
 
-    // Convert output path to PathBuf for comparison
     let expected_output: PathBuf = ["d", "encode", "00001.ivf"].iter().collect();
 
-    assert_eq!(expected_output.to_string_lossy(), ch.output());
+    assert_eq!(expected_output, ch.output());
 }
 
 #[test]
@@ -117,6 +156,8 @@ fn chunk_frames() {
         frame_rate:            30.0,
         target_quality:        TargetQuality::default("none", Encoder::x264),
         tq_cq:                 None,
+        tq_score:              None,
+        dropped_frames:        Vec::new(),
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
@@ -152,6 +193,8 @@ fn apply_photon_noise_args_with_noise() -> anyhow::Result<()> {
             Encoder::svt_av1,
         ),
         tq_cq:                 None,
+        tq_score:              None,
+        dropped_frames:        Vec::new(),
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::svt_av1,
@@ -159,7 +202,7 @@ fn apply_photon_noise_args_with_noise() -> anyhow::Result<()> {
         ignore_frame_mismatch: false,
     };
 
-    ch.apply_photon_noise_args(Some(8), true, None)?;
+    ch.apply_photon_noise_args(Some(8), ChromaNoise::FromLuma, None, None)?;
     assert!(ch.video_params.iter().any(|p| p.contains("fgs-table")));
     Ok(())
 }
@@ -190,6 +233,8 @@ fn apply_photon_noise_args_no_noise() -> anyhow::Result<()> {
             Encoder::svt_av1,
         ),
         tq_cq:                 None,
+        tq_score:              None,
+        dropped_frames:        Vec::new(),
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::svt_av1,
@@ -197,7 +242,7 @@ fn apply_photon_noise_args_no_noise() -> anyhow::Result<()> {
         ignore_frame_mismatch: false,
     };
 
-    ch.apply_photon_noise_args(None, false, None)?;
+    ch.apply_photon_noise_args(None, ChromaNoise::None, None, None)?;
     assert!(!ch.video_params.iter().any(|p| p.contains("fgs-table")));
     Ok(())
 }
@@ -228,6 +273,8 @@ fn apply_photon_noise_args_unsupported_encoder() -> anyhow::Result<()> {
             Encoder::x264,
         ),
         tq_cq:                 None,
+        tq_score:              None,
+        dropped_frames:        Vec::new(),
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
@@ -235,6 +282,195 @@ fn apply_photon_noise_args_unsupported_encoder() -> anyhow::Result<()> {
         ignore_frame_mismatch: false,
     };
 
-    assert!(ch.apply_photon_noise_args(Some(8), true, None).is_err());
+    assert!(ch.apply_photon_noise_args(Some(8), ChromaNoise::FromLuma, None, None).is_err());
     Ok(())
 }
+
+#[test]
+fn photon_noise_at_interpolates_between_anchors() {
+    let anchors = [(0.0, 4), (100.0, 16)];
+    assert_eq!(photon_noise_at(&anchors, 0.0), Some(4));
+    assert_eq!(photon_noise_at(&anchors, 50.0), Some(10));
+    assert_eq!(photon_noise_at(&anchors, 100.0), Some(16));
+}
+
+#[test]
+fn photon_noise_at_clamps_outside_anchors() {
+    let anchors = [(10.0, 8), (20.0, 2)];
+    assert_eq!(photon_noise_at(&anchors, 0.0), Some(8));
+    assert_eq!(photon_noise_at(&anchors, 15.0), Some(5));
+    assert_eq!(photon_noise_at(&anchors, 1000.0), Some(2));
+}
+
+#[test]
+fn photon_noise_at_disabled() {
+    assert_eq!(photon_noise_at(&[], 10.0), None);
+    assert_eq!(photon_noise_at(&[(0.0, 0), (10.0, 0)], 5.0), None);
+}
+
+#[test]
+fn find_bitrate_spikes_above_median() {
+    let chunks = [
+        ("00000".to_owned(), 100, 10_000),
+        ("00001".to_owned(), 100, 12_000),
+        ("00002".to_owned(), 50, 20_000),
+        ("00003".to_owned(), 100, 11_000),
+        ("00004".to_owned(), 10, 3_000),
+    ];
+    let spikes = find_bitrate_spikes(&chunks, 2.0);
+    let names = spikes.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["00002", "00004"]);
+    assert!((spikes[1].1 - 2.5).abs() < 1e-9);
+    assert_eq!(find_bitrate_spikes(&chunks, 3.0).len(), 1);
+    assert!(find_bitrate_spikes(&chunks, 4.0).is_empty());
+}
+
+#[test]
+fn find_bitrate_spikes_empty() {
+    assert!(find_bitrate_spikes(&[], 2.0).is_empty());
+    assert!(find_bitrate_spikes(&[("00000".to_owned(), 0, 100)], 2.0).is_empty());
+}
+
+#[test]
+fn chunk_grain_seeds_are_reproducible() {
+    assert_eq!(chunk_grain_seed(42, 7), chunk_grain_seed(42, 7));
+    let seeds = (0..100).map(|index| chunk_grain_seed(42, index)).collect::<HashSet<_>>();
+    assert!(seeds.len() > 95);
+    assert_ne!(chunk_grain_seed(42, 0), chunk_grain_seed(43, 0));
+}
+
+#[test]
+fn frame_weighted_mean_weights_by_frames() {
+    assert_eq!(frame_weighted_mean(&[(100, 40.0), (300, 36.0)]), Some(37.0));
+    assert_eq!(frame_weighted_mean(&[(0, 40.0)]), None);
+    assert_eq!(frame_weighted_mean(&[]), None);
+}
+
+#[test]
+fn drop_duplicate_frames_keeps_first_frame() {
+    let mut ch = Chunk {
+        temp:                  "none".to_owned(),
+        index:                 1,
+        input:                 Input::Video {
+            path:         "test.mkv".into(),
+            temp:         "none".to_owned(),
+            chunk_method: ChunkMethod::LSMASH,
+            is_proxy:     false,
+            cache_mode:   vapoursynth::CacheSource::SOURCE,
+        },
+        proxy:                 None,
+        source_cmd:            vec!["".into()],
+        proxy_cmd:             None,
+        output_ext:            "ivf".to_owned(),
+        start_frame:           10,
+        end_frame:             20,
+        frame_rate:            25.0,
+        target_quality:        TargetQuality::default("none", Encoder::x264),
+        tq_cq:                 None,
+        tq_score:              None,
+        dropped_frames:        Vec::new(),
+        passes:                1,
+        video_params:          vec![],
+        encoder:               Encoder::x264,
+        noise_size:            (None, None),
+        ignore_frame_mismatch: false,
+    };
+    ch.drop_duplicate_frames(&[(2, 4), (8, 13), (15, 17), (19, 30)]);
+
+    assert_eq!(ch.dropped_frames, [(1, 3), (5, 7), (9, 10)]);
+    assert_eq!(ch.encoded_frames(), 5);
+    assert_eq!(ch.drop_frames_args(), [
+        "-vf",
+        r"select=not(between(n\,1\,2)+between(n\,5\,6)+between(n\,9\,9))",
+        "-fps_mode",
+        "passthrough"
+    ]);
+    assert_eq!(
+        kept_frame_timestamps(std::slice::from_ref(&ch), 25.0, None),
+        "# timestamp format v2\n400.000000\n520.000000\n560.000000\n680.000000\n720.000000\n"
+    );
+    let source_timestamps = (0..20).map(|frame| f64::from(frame) * 0.05).collect::<Vec<_>>();
+    assert_eq!(
+        kept_frame_timestamps(&[ch], 25.0, Some(&source_timestamps)),
+        "# timestamp format v2\n500.000000\n650.000000\n700.000000\n850.000000\n900.000000\n"
+    );
+}
+
+#[test]
+fn remap_paths_moves_temp_and_input() {
+    let mut ch = Chunk {
+        temp:                  "/mnt/a/job".to_owned(),
+        index:                 1,
+        input:                 Input::Video {
+            path:         "/mnt/a/in.mkv".into(),
+            temp:         "/mnt/a/job".to_owned(),
+            chunk_method: ChunkMethod::Hybrid,
+            is_proxy:     false,
+            cache_mode:   vapoursynth::CacheSource::SOURCE,
+        },
+        proxy:                 None,
+        source_cmd:            into_vec!["ffmpeg", "-i", "/mnt/a/in.mkv", "-"],
+        proxy_cmd:             None,
+        output_ext:            "ivf".to_owned(),
+        start_frame:           0,
+        end_frame:             10,
+        frame_rate:            25.0,
+        target_quality:        TargetQuality::default("/mnt/a/job", Encoder::aom),
+        tq_cq:                 None,
+        tq_score:              None,
+        dropped_frames:        Vec::new(),
+        passes:                2,
+        video_params:          into_vec!["--film-grain-table=/mnt/a/job/grain/00001.tbl"],
+        encoder:               Encoder::aom,
+        noise_size:            (None, None),
+        ignore_frame_mismatch: false,
+    };
+    ch.remap_paths(&[
+        ("/mnt/a/job".to_owned(), "/data/job".to_owned()),
+        ("/mnt/a/in.mkv".to_owned(), "/data/in.mkv".to_owned()),
+    ]);
+
+    assert_eq!(ch.output(), PathBuf::from("/data/job/encode/00001.ivf"));
+    assert_eq!(ch.target_quality.temp, "/data/job");
+    assert_eq!(ch.input.as_path(), PathBuf::from("/data/in.mkv"));
+    assert_eq!(ch.source_cmd[2], "/data/in.mkv");
+    assert_eq!(ch.video_params, [
+        "--film-grain-table=/data/job/grain/00001.tbl"
+    ]);
+}
+
+#[test]
+fn grain_table_names_include_chroma_and_size() {
+    assert_eq!(
+        grain_table_name(800, ChromaNoise::None, (None, None), None),
+        "iso800-grain.tbl"
+    );
+    assert_eq!(
+        grain_table_name(800, ChromaNoise::FromLuma, (Some(1920), None), Some(7)),
+        "iso800-chroma-w1920-seed7-grain.tbl"
+    );
+    assert_eq!(
+        grain_table_name(
+            800,
+            ChromaNoise::Strength {
+                strength: 4,
+                cb:       1.0,
+                cr:       0.5,
+            },
+            (Some(1920), Some(1080)),
+            None
+        ),
+        "iso800-chroma400-cb1.00-cr0.50-w1920-h1080-grain.tbl"
+    );
+}
+
+#[test]
+fn chroma_scaling_points_keep_ends_and_scale_strength() {
+    let luma = (0..14u8).map(|idx| [idx * 18, idx * 10]).collect::<ScalingPoints>();
+    let points = chroma_scaling_points(&luma, 0.5);
+
+    assert_eq!(points.len(), NUM_UV_POINTS);
+    assert_eq!(points[0], [0, 0]);
+    assert_eq!(points[NUM_UV_POINTS - 1], [234, 65]);
+    assert!(points.windows(2).all(|pair| pair[0][0] < pair[1][0]));
+}
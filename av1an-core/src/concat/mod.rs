@@ -22,7 +22,12 @@ use path_abs::{PathAbs, PathInfo};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error, trace, warn};
 
-use crate::{encoder::Encoder, util::read_in_dir};
+use crate::{
+    children,
+    encoder::Encoder,
+    get_done,
+    util::{move_file, partial_path, read_in_dir},
+};
 
 #[derive(
     PartialEq,
@@ -163,37 +168,177 @@ fn read_encoded_chunks(encode_dir: &Path) -> anyhow::Result<Vec<DirEntry>> {
         .collect::<Result<Vec<_>, _>>()?)
 }
 
+#[cfg(windows)]
+const MAXIMUM_CHUNKS_PER_MERGE: usize = usize::MAX;
+#[cfg(not(windows))]
+const MAXIMUM_CHUNKS_PER_MERGE: usize = 960;
+
+// mkvmerge does not accept UNC paths on Windows
+#[cfg(windows)]
+fn fix_path<P: AsRef<Path>>(p: P) -> String {
+    const UNC_PREFIX: &str = r#"\\?\"#;
+
+    let p = p.as_ref().display().to_string();
+    p.strip_prefix(UNC_PREFIX).map_or_else(
+        || p.clone(),
+        |path| {
+            path.strip_prefix("UNC")
+                .map_or_else(|| path.to_string(), |p2| format!("\\{p2}"))
+        },
+    )
+}
+
+#[cfg(not(windows))]
+fn fix_path<P: AsRef<Path>>(p: P) -> String {
+    p.as_ref().display().to_string()
+}
+
+/// Splits the encoded chunk file names into the groups that are merged into
+/// intermediate files before the final merge
+fn mkvmerge_chunk_groups(encoder: Encoder, num_chunks: usize) -> Vec<Vec<String>> {
+    let num_chunk_groups = num_chunks.div_ceil(MAXIMUM_CHUNKS_PER_MERGE);
+    (0..num_chunk_groups)
+        .map(|group_index| {
+            let start = group_index * MAXIMUM_CHUNKS_PER_MERGE;
+            let end = (start + MAXIMUM_CHUNKS_PER_MERGE).min(num_chunks);
+            (start..end)
+                .map(|i| {
+                    format!(
+                        "{i:05}.{ext}",
+                        ext = match encoder {
+                            Encoder::x264 => "264",
+                            Encoder::x265 => "hevc",
+                            Encoder::vvenc => "266",
+                            _ => "ivf",
+                        }
+                    )
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Merges every group of chunks for which `is_finished` returns true for all of
+/// its chunks into `group_output_{index}.mkv`. A group whose merged file is
+/// newer than all of its chunks was merged by an earlier session and is kept.
+/// Groups are merged into a partial file that is only renamed once mkvmerge
+/// succeeds, so an interrupted merge is never reused.
+fn mkvmerge_groups(
+    temp_dir: &Path,
+    chunk_groups: &[Vec<String>],
+    is_finished: impl Fn(&str) -> bool,
+    output_fps: Option<Rational64>,
+) -> anyhow::Result<()> {
+    let encode_dir = PathBuf::from(temp_dir).join("encode");
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+
+    chunk_groups.iter().enumerate().try_for_each(|(group_index, chunk_group)| {
+        if !chunk_group.iter().all(|chunk| is_finished(chunk)) {
+            return Ok(());
+        }
+
+        let group_options_path =
+            PathBuf::from(&temp_dir).join(format!("group_options_{group_index:05}.json"));
+        let group_options_output_path = PathAbs::new(
+            PathBuf::from(&temp_dir).join(format!("group_output_{group_index:05}.mkv")),
+        )?;
+
+        if let Some(merged) = modified(group_options_output_path.as_path())
+            && chunk_group
+                .iter()
+                .all(|chunk| modified(&encode_dir.join(chunk)).is_some_and(|time| time <= merged))
+        {
+            debug!("reusing merged chunk group {group_index}");
+            return Ok(());
+        }
+
+        let partial_output_path = partial_path(group_options_output_path.as_path());
+        let group_options_json_contents = mkvmerge_options_json(
+            chunk_group,
+            &fix_path(&partial_output_path),
+            None,
+            output_fps,
+            None,
+            None,
+        );
+
+        let mut group_options_json = File::create(group_options_path)?;
+        group_options_json.write_all(group_options_json_contents?.as_bytes())?;
+
+        let mut group_cmd = Command::new("mkvmerge");
+        group_cmd.current_dir(&encode_dir);
+        group_cmd.arg(format!("@../group_options_{group_index:05}.json"));
+
+        let group_out = children::output(&mut group_cmd)
+            .with_context(|| "Failed to execute mkvmerge command for concatenation");
+        // A failed merge may leave a truncated file behind
+        if !group_out.as_ref().is_ok_and(|out| out.status.success()) {
+            let _ = fs::remove_file(&partial_output_path);
+        }
+        let group_out = group_out?;
+
+        if !group_out.status.success() {
+            return Err(anyhow::Error::msg(format!(
+                "Failed to execute mkvmerge command for concatenation: {}",
+                String::from_utf8_lossy(&group_out.stderr)
+            )));
+        }
+        fs::rename(&partial_output_path, group_options_output_path.as_path())?;
+
+        Ok(())
+    })
+}
+
+/// Merges the groups of chunks that have all finished encoding, so that a
+/// resumed encode only has to merge the remaining groups before appending
+/// all of them into the output. Does nothing if the chunks are merged in a
+/// single group.
 #[tracing::instrument(level = "debug")]
-pub fn mkvmerge(
+pub fn mkvmerge_finished_groups(
     temp_dir: &Path,
-    output: &Path,
     encoder: Encoder,
     num_chunks: usize,
     output_fps: Option<Rational64>,
 ) -> anyhow::Result<()> {
-    #[cfg(windows)]
-    const MAXIMUM_CHUNKS_PER_MERGE: usize = usize::MAX;
-    #[cfg(not(windows))]
-    const MAXIMUM_CHUNKS_PER_MERGE: usize = 960;
-
-    // mkvmerge does not accept UNC paths on Windows
-    #[cfg(windows)]
-    fn fix_path<P: AsRef<Path>>(p: P) -> String {
-        const UNC_PREFIX: &str = r#"\\?\"#;
-
-        let p = p.as_ref().display().to_string();
-        p.strip_prefix(UNC_PREFIX).map_or_else(
-            || p.clone(),
-            |path| {
-                path.strip_prefix("UNC")
-                    .map_or_else(|| path.to_string(), |p2| format!("\\{p2}"))
-            },
-        )
+    let chunk_groups = mkvmerge_chunk_groups(encoder, num_chunks);
+    if chunk_groups.len() <= 1 {
+        return Ok(());
     }
 
-    #[cfg(not(windows))]
-    fn fix_path<P: AsRef<Path>>(p: P) -> String {
-        p.as_ref().display().to_string()
+    mkvmerge_groups(
+        temp_dir,
+        &chunk_groups,
+        |chunk| {
+            let name = chunk.split_once('.').map_or(chunk, |(name, _)| name);
+            get_done().done.contains_key(name)
+        },
+        output_fps,
+    )
+}
+
+#[tracing::instrument(level = "debug")]
+pub fn mkvmerge(
+    temp_dir: &Path,
+    output: &Path,
+    encoder: Encoder,
+    num_chunks: usize,
+    output_fps: Option<Rational64>,
+    display_dimensions: Option<(u32, u32)>,
+) -> anyhow::Result<()> {
+    // The output path is passed to mkvmerge in a JSON options file, which cannot
+    // hold a path that is not valid UTF-8, so merge into the temporary folder and
+    // move the result into place instead
+    if output.to_str().is_none() {
+        let merged = temp_dir.join("output.mkv");
+        mkvmerge(
+            temp_dir,
+            &merged,
+            encoder,
+            num_chunks,
+            output_fps,
+            display_dimensions,
+        )?;
+        return Ok(move_file(&merged, output)?);
     }
 
     let audio_file = PathBuf::from(&temp_dir).join("audio.mkv");
@@ -202,29 +347,19 @@ pub fn mkvmerge(
 
     let encode_dir = PathBuf::from(temp_dir).join("encode");
 
+    // Written when duplicate frames were dropped, to restore their timing
+    let timestamps = PathBuf::from(temp_dir).join("timestamps.txt");
+    let timestamps = if timestamps.exists() {
+        Some(fix_path(PathAbs::new(&timestamps)?))
+    } else {
+        None
+    };
+
     let output = PathAbs::new(output)?;
 
     assert!(num_chunks != 0);
 
-    let num_chunk_groups = (num_chunks as f64 / MAXIMUM_CHUNKS_PER_MERGE as f64).ceil() as usize;
-    let chunk_groups: Vec<Vec<String>> = (0..num_chunk_groups)
-        .map(|group_index| {
-            let start = group_index * MAXIMUM_CHUNKS_PER_MERGE;
-            let end = (start + MAXIMUM_CHUNKS_PER_MERGE).min(num_chunks);
-            (start..end)
-                .map(|i| {
-                    format!(
-                        "{i:05}.{ext}",
-                        ext = match encoder {
-                            Encoder::x264 => "264",
-                            Encoder::x265 => "hevc",
-                            _ => "ivf",
-                        }
-                    )
-                })
-                .collect()
-        })
-        .collect();
+    let chunk_groups = mkvmerge_chunk_groups(encoder, num_chunks);
 
     // If there is only one chunk group, we can skip the intermediate merge/file
     // creation
@@ -235,6 +370,8 @@ pub fn mkvmerge(
             &fix_path(output.to_string_lossy().as_ref()),
             audio_file.as_deref(),
             output_fps,
+            timestamps.as_deref(),
+            display_dimensions,
         );
 
         let mut options_json = File::create(options_path)?;
@@ -244,8 +381,7 @@ pub fn mkvmerge(
         cmd.current_dir(&encode_dir);
         cmd.arg("@../options.json");
 
-        let out = cmd
-            .output()
+        let out = children::output(&mut cmd)
             .with_context(|| "Failed to execute mkvmerge command for concatenation")?;
 
         if !out.status.success() {
@@ -259,42 +395,9 @@ pub fn mkvmerge(
         return Ok(());
     }
 
-    chunk_groups.iter().enumerate().try_for_each(|(group_index, chunk_group)| {
-        let group_options_path =
-            PathBuf::from(&temp_dir).join(format!("group_options_{group_index:05}.json"));
-        let group_options_output_path = PathAbs::new(
-            PathBuf::from(&temp_dir).join(format!("group_output_{group_index:05}.mkv")),
-        )?;
-
-        let group_options_json_contents = mkvmerge_options_json(
-            chunk_group,
-            &fix_path(group_options_output_path.to_string_lossy().as_ref()),
-            None,
-            output_fps,
-        );
-
-        let mut group_options_json = File::create(group_options_path)?;
-        group_options_json.write_all(group_options_json_contents?.as_bytes())?;
-
-        let mut group_cmd = Command::new("mkvmerge");
-        group_cmd.current_dir(&encode_dir);
-        group_cmd.arg(format!("@../group_options_{group_index:05}.json"));
-
-        let group_out = group_cmd
-            .output()
-            .with_context(|| "Failed to execute mkvmerge command for concatenation")?;
+    mkvmerge_groups(temp_dir, &chunk_groups, |_| true, output_fps)?;
 
-        if !group_out.status.success() {
-            return Err(anyhow::Error::msg(format!(
-                "Failed to execute mkvmerge command for concatenation: {}",
-                String::from_utf8_lossy(&group_out.stderr)
-            )));
-        }
-
-        Ok(())
-    })?;
-
-    let chunk_group_options_names: Vec<String> = (0..num_chunk_groups)
+    let chunk_group_options_names: Vec<String> = (0..chunk_groups.len())
         .map(|group_index| format!("group_output_{group_index:05}.mkv"))
         .collect();
 
@@ -304,6 +407,8 @@ pub fn mkvmerge(
         &fix_path(output.to_string_lossy().as_ref()),
         audio_file.as_deref(),
         output_fps,
+        timestamps.as_deref(),
+        display_dimensions,
     );
 
     let mut options_json = File::create(options_path)?;
@@ -313,8 +418,7 @@ pub fn mkvmerge(
     cmd.current_dir(temp_dir);
     cmd.arg("@./options.json");
 
-    let out = cmd
-        .output()
+    let out = children::output(&mut cmd)
         .with_context(|| "Failed to execute mkvmerge command for concatenation")?;
 
     if !out.status.success() {
@@ -331,12 +435,18 @@ pub fn mkvmerge(
 }
 
 /// Create mkvmerge options.json
+///
+/// A `timestamps` file sets the timestamp of every video frame and replaces
+/// the default duration of `output_fps`. `display_dimensions` signal the
+/// aspect ratio of non-square samples.
 #[tracing::instrument(level = "debug")]
 pub fn mkvmerge_options_json(
     chunks: &[String],
     output: &str,
     audio: Option<&str>,
     output_fps: Option<Rational64>,
+    timestamps: Option<&str>,
+    display_dimensions: Option<(u32, u32)>,
 ) -> anyhow::Result<String> {
     let mut file_string = String::with_capacity(
         64 + output.len()
@@ -347,16 +457,27 @@ pub fn mkvmerge_options_json(
     if let Some(audio) = audio {
         write!(file_string, ", {audio:?}")?;
     }
-    if let Some(output_fps) = output_fps {
+    if let Some(timestamps) = timestamps {
         write!(
             file_string,
-            ", \"--default-duration\", \"0:{}/{}fps\", \"[\"",
+            ", \"--timestamps\", {timestamps:?}",
+            timestamps = format!("0:{timestamps}")
+        )?;
+    } else if let Some(output_fps) = output_fps {
+        write!(
+            file_string,
+            ", \"--default-duration\", \"0:{}/{}fps\"",
             output_fps.numer(),
             output_fps.denom()
         )?;
-    } else {
-        file_string.push_str(", \"[\"");
     }
+    if let Some((width, height)) = display_dimensions {
+        write!(
+            file_string,
+            ", \"--display-dimensions\", \"0:{width}x{height}\""
+        )?;
+    }
+    file_string.push_str(", \"[\"");
     for chunk in chunks {
         write!(file_string, ", \"{chunk}\"")?;
     }
@@ -399,8 +520,7 @@ pub fn ffmpeg(temp: &Path, output: &Path) -> anyhow::Result<()> {
     let temp = PathAbs::new(temp)?;
     let temp = temp.as_path();
 
-    let concat = temp.join("concat");
-    let concat_file = concat.to_string_lossy();
+    let concat_file = temp.join("concat");
 
     write_concat_file(temp)?;
 
@@ -412,47 +532,27 @@ pub fn ffmpeg(temp: &Path, output: &Path) -> anyhow::Result<()> {
 
     let mut cmd = Command::new("ffmpeg");
 
+    cmd.stdin(Stdio::null());
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
 
     if let Some(file) = audio_file {
-        cmd.args([
-            "-y",
-            "-hide_banner",
-            "-loglevel",
-            "error",
-            "-f",
-            "concat",
-            "-safe",
-            "0",
-            "-i",
-            &concat_file,
-            "-i",
-        ])
-        .arg(file)
-        .args(["-map", "0", "-map", "1", "-c", "copy"])
-        .arg(output);
+        cmd.args(["-y", "-hide_banner", "-loglevel", "error", "-f", "concat", "-safe", "0", "-i"])
+            .arg(&concat_file)
+            .arg("-i")
+            .arg(file)
+            .args(["-map", "0", "-map", "1", "-c", "copy"])
+            .arg(output);
     } else {
-        cmd.args([
-            "-y",
-            "-hide_banner",
-            "-loglevel",
-            "error",
-            "-f",
-            "concat",
-            "-safe",
-            "0",
-            "-i",
-            &concat_file,
-        ])
-        .args(["-map", "0", "-c", "copy"])
-        .arg(output);
+        cmd.args(["-y", "-hide_banner", "-loglevel", "error", "-f", "concat", "-safe", "0", "-i"])
+            .arg(&concat_file)
+            .args(["-map", "0", "-c", "copy"])
+            .arg(output);
     }
 
     debug!("FFmpeg concat command: {:?}", cmd);
 
-    let out = cmd
-        .output()
+    let out = children::output(&mut cmd)
         .with_context(|| "Failed to execute FFmpeg command for concatenation")?;
 
     if !out.status.success() {
@@ -9,6 +9,8 @@ fn mkvmerge_options_json_no_audio() {
         "output.mkv",
         None,
         Some(Rational64::new(30, 1)),
+        None,
+        None,
     )
     .expect("options call should succeed");
     assert_eq!(
@@ -24,6 +26,8 @@ fn mkvmerge_options_json_with_audio() {
         "output.mkv",
         Some("audio.mkv"),
         Some(Rational64::new(30, 1)),
+        None,
+        None,
     )
     .expect("options call should succeed");
     assert_eq!(
@@ -31,3 +35,52 @@ fn mkvmerge_options_json_with_audio() {
         r#"["-o", "output.mkv", "audio.mkv", "--default-duration", "0:30/1fps", "[", "00000.ivf", "00001.ivf","]"]"#
     );
 }
+
+#[test]
+fn mkvmerge_options_json_with_timestamps_and_display_dimensions() {
+    let result = mkvmerge_options_json(
+        &["00000.ivf".to_string()],
+        "output.mkv",
+        None,
+        Some(Rational64::new(30, 1)),
+        Some("/tmp/timestamps.txt"),
+        Some((853, 480)),
+    )
+    .expect("options call should succeed");
+    assert_eq!(
+        result,
+        r#"["-o", "output.mkv", "--timestamps", "0:/tmp/timestamps.txt", "--display-dimensions", "0:853x480", "[", "00000.ivf","]"]"#
+    );
+}
+
+#[test]
+fn mkvmerge_chunk_groups_split_at_maximum() {
+    let groups = mkvmerge_chunk_groups(Encoder::x265, 3);
+    assert_eq!(groups, [["00000.hevc", "00001.hevc", "00002.hevc"]]);
+
+    let groups = mkvmerge_chunk_groups(Encoder::aom, MAXIMUM_CHUNKS_PER_MERGE.min(2000) + 1);
+    if cfg!(windows) {
+        assert_eq!(groups.len(), 1);
+    } else {
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[1], [format!("{MAXIMUM_CHUNKS_PER_MERGE:05}.ivf")]);
+    }
+}
+
+#[test]
+fn failed_group_merge_leaves_no_group() {
+    let temp = tempfile::tempdir().expect("temp dir should be created");
+    let encode = temp.path().join("encode");
+    fs::create_dir_all(&encode).expect("encode dir should be created");
+    // Not a valid IVF file, so mkvmerge fails like an interrupted merge
+    fs::write(encode.join("00000.ivf"), b"not ivf").expect("chunk should be written");
+
+    let groups = [vec!["00000.ivf".to_string()]];
+    assert!(mkvmerge_groups(temp.path(), &groups, |_| true, None).is_err());
+    let mut files = fs::read_dir(temp.path())
+        .expect("temp dir should be readable")
+        .map(|entry| entry.expect("entry should be readable").file_name())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(files, ["encode", "group_options_00000.json"]);
+}
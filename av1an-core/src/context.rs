@@ -2,40 +2,60 @@ use std::{
     borrow::Cow,
     cmp::{self, Reverse},
     ffi::OsString,
-    fs::{self, File},
-    io::{BufRead, BufReader, Write},
+    fs,
+    io::{BufRead, BufReader},
     iter,
     path::{Path, PathBuf},
-    process::{exit, ChildStderr, Command, Stdio},
+    process::{exit, ChildStderr, ChildStdout, Command, Stdio},
     sync::{
-        atomic::{self, AtomicBool, AtomicUsize},
+        atomic::{self, AtomicBool, AtomicU8, AtomicUsize},
         mpsc,
         Arc,
         Mutex,
     },
     thread::{self, available_parallelism},
+    time::{Duration, UNIX_EPOCH},
 };
 
-use anyhow::Context;
+use anyhow::{anyhow, bail, Context};
 use av1_grain::TransferFunction;
 use av_decoders::VapoursynthDecoder;
 use colored::*;
+use indicatif::{HumanBytes, HumanDuration};
 use itertools::Itertools;
 use num_traits::cast::ToPrimitive;
 use rand::{prelude::SliceRandom, rng};
 use tracing::{debug, error, info, warn};
 
 use crate::{
-    broker::{Broker, EncoderCrash},
-    chunk::Chunk,
+    bitstream,
+    broker::{Broker, EncoderCrash, LiveParams},
+    chunk::{
+        find_bitrate_spikes,
+        frame_weighted_mean,
+        kept_frame_timestamps,
+        photon_noise_at,
+        Chunk,
+        WHOLE_CLIP_INDEX,
+    },
     concat::{self, ConcatMethod},
     create_dir,
     determine_workers,
-    ffmpeg::{compose_ffmpeg_pipe, get_num_frames},
+    device::DeviceTarget,
+    encoder::slice_aom_first_pass_stats,
+    ffmpeg::{
+        compose_ffmpeg_pipe,
+        detect_duplicate_frames,
+        frame_timestamps,
+        get_num_frames,
+        is_variable_frame_rate,
+    },
     get_done,
+    hdr10plus::Hdr10PlusMetadata,
+    heatmap::heatmap_svg,
     init_done,
     into_vec,
-    metrics::vmaf,
+    metrics::vmaf::{self, percentile_of_sorted, read_vmaf_file, VmafSummary},
     progress_bar::{
         finish_progress_bar,
         inc_bar,
@@ -48,23 +68,56 @@ use crate::{
         update_mp_chunk,
         update_mp_msg,
         update_progress_bar_estimates,
+        write_progress_snapshot,
+        ProgressCoalescer,
+        ProgressStage,
     },
     read_chunk_queue,
+    resources::{ChunkProcesses, MemoryGovernor, ResourceMonitor},
     save_chunk_queue,
-    scenes::{Scene, SceneFactory, ZoneOptions},
-    settings::{EncodeArgs, InputPixelFormat},
+    scenes::{sample_scenes, scene_sample_rate, Scene, SceneFactory, ZoneOptions},
+    settings::{insert_hdr10plus_params, merge_video_params, EncodeArgs, InputPixelFormat},
     split::segment,
+    stats::{export_stats, SceneStats, StatsFormat},
+    target_quality::{sampled_scene_scores, PredictionError},
+    temp_lock::TempLock,
+    util::{backup_path, dir_size, move_file, stable_hash, write_atomic},
     vapoursynth::{create_vs_file, LoadscriptArgs},
+    y4m::{self, HeaderOverrides},
     zones::{parse_zones, validate_zones},
     ChunkMethod,
     ChunkOrdering,
+    ClipInfo,
     DashMap,
     DoneJson,
+    Encoder,
+    EncoderStats,
     Input,
+    Instant,
+    KeepPolicy,
+    OverwritePolicy,
     PixelFormatConverter,
+    SavedRoots,
+    TargetMetric,
+    TargetQuality,
     Verbosity,
+    TIME_BUDGET_EXIT_CODE,
 };
 
+/// How often the groups of finished chunks are merged while encoding with
+/// mkvmerge concatenation
+const PRE_MERGE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Sets the flag when dropped, so that threads waiting for it stop even when
+/// an error or a panic leaves the scope early
+struct SetOnDrop(Arc<AtomicBool>);
+
+impl Drop for SetOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, atomic::Ordering::SeqCst);
+    }
+}
+
 #[derive(Debug)]
 pub struct Av1anContext {
     pub frames:               usize,
@@ -72,6 +125,9 @@ pub struct Av1anContext {
     pub vs_proxy_script:      Option<PathBuf>,
     pub args:                 EncodeArgs,
     pub(crate) scene_factory: SceneFactory,
+    pub(crate) hdr10plus:     Option<Hdr10PlusMetadata>,
+    /// Lock on the temporary folder, released before it is deleted
+    pub(crate) temp_lock:     Mutex<Option<TempLock>>,
 }
 
 impl Av1anContext {
@@ -86,6 +142,8 @@ impl Av1anContext {
             vs_proxy_script: None,
             args,
             scene_factory: SceneFactory::new(),
+            hdr10plus: None,
+            temp_lock: Mutex::new(None),
         };
         this.initialize()?;
         Ok(this)
@@ -95,6 +153,7 @@ impl Av1anContext {
     #[tracing::instrument(level = "debug")]
     fn initialize(&mut self) -> anyhow::Result<()> {
         if !self.args.resume && Path::new(&self.args.temp).is_dir() {
+            TempLock::check_unlocked(Path::new(&self.args.temp), self.args.force_lock)?;
             fs::remove_dir_all(&self.args.temp).with_context(|| {
                 format!(
                     "Failed to remove temporary directory {temp}",
@@ -106,6 +165,22 @@ impl Av1anContext {
         create_dir!(Path::new(&self.args.temp))?;
         create_dir!(Path::new(&self.args.temp).join("split"))?;
         create_dir!(Path::new(&self.args.temp).join("encode"))?;
+        *self.temp_lock.get_mut().expect("mutex should acquire lock") =
+            TempLock::acquire(Path::new(&self.args.temp), self.args.force_lock)?;
+
+        // Generated inputs such as test patterns keep their script in the temp
+        // folder, which may have just been removed
+        for input in std::iter::once(&self.args.input).chain(&self.args.proxy) {
+            if let Input::VapourSynth {
+                path,
+                script_text,
+                ..
+            } = input
+                && !path.exists()
+            {
+                fs::write(path, script_text)?;
+            }
+        }
 
         debug!("temporary directory: {temp}", temp = &self.args.temp);
 
@@ -162,10 +237,11 @@ impl Av1anContext {
                 frames:     AtomicUsize::new(0),
                 done:       DashMap::new(),
                 audio_done: AtomicBool::new(false),
+                resources:  Mutex::default(),
+                roots:      Mutex::new(Some(SavedRoots::new(&self.args)?)),
             });
 
-            let mut done_file = File::create(&done_path)?;
-            done_file.write_all(serde_json::to_string(get_done())?.as_bytes())?;
+            write_atomic(&done_path, serde_json::to_string(get_done())?)?;
         };
 
         Ok(())
@@ -174,84 +250,21 @@ impl Av1anContext {
     #[tracing::instrument(skip(self))]
     #[inline]
     pub fn encode_file(&mut self) -> anyhow::Result<()> {
+        let result = self.encode();
+        if result.is_err() && self.args.keep == KeepPolicy::Never {
+            self.remove_temp();
+        }
+        result
+    }
+
+    fn encode(&mut self) -> anyhow::Result<()> {
+        let deadline = self.args.time_budget.map(|budget| Instant::now() + budget);
+        let resource_monitor = ResourceMonitor::start();
         let initial_frames =
             get_done().done.iter().map(|ref_multi| ref_multi.frames).sum::<usize>();
 
-        // Create the VapourSynth script file and store the path to it and evaluate it
-        let cache_vs_input = |vs_input: &Input| {
-            let script_path = match vs_input {
-                Input::VapourSynth {
-                    path, ..
-                } => path.clone(),
-                Input::Video {
-                    path,
-                    is_proxy,
-                    ..
-                } => {
-                    let (script_path, _) = create_vs_file(&LoadscriptArgs {
-                        temp:         &self.args.temp,
-                        source:       path,
-                        chunk_method: self.args.chunk_method,
-                        is_proxy:     *is_proxy,
-                        cache_mode:   self.args.cache_mode,
-                    })?;
-                    script_path
-                },
-            };
-
-            let variables_map = vs_input.as_vspipe_args_hashmap()?;
-            let decoder = match vs_input {
-                Input::VapourSynth {
-                    path, ..
-                } => {
-                    let dec = VapoursynthDecoder::from_file(path, variables_map, None)?;
-                    av_scenechange::Decoder::from_decoder_impl(
-                        av_decoders::DecoderImpl::Vapoursynth(dec),
-                    )?
-                },
-                video_input => av_scenechange::Decoder::from_script(
-                    &video_input.as_script_text()?,
-                    variables_map,
-                )?,
-            };
-            // Getting the details will evaluate the script and produce the VapourSynth
-            // cache file
-            let _ = decoder.get_video_details();
-
-            Ok::<PathBuf, anyhow::Error>(script_path)
-        };
-
-        // Technically we should check if the vapoursynth cache file exists rather than
-        // !self.resume, but the code still works if we are resuming and the
-        // cache file doesn't exist (as it gets generated when vspipe is first
-        // called), so it's not worth adding all the extra complexity.
-        if (self.args.input.is_vapoursynth()
-            || (self.args.input.is_video()
-                && matches!(
-                    self.args.chunk_method,
-                    ChunkMethod::LSMASH
-                        | ChunkMethod::FFMS2
-                        | ChunkMethod::DGDECNV
-                        | ChunkMethod::BESTSOURCE
-                )))
-            && !self.args.resume
-        {
-            self.vs_script = Some(cache_vs_input(&self.args.input)?);
-        }
-        if let Some(proxy) = &self.args.proxy
-            && (proxy.is_vapoursynth()
-                || (proxy.is_video()
-                    && matches!(
-                        self.args.chunk_method,
-                        ChunkMethod::LSMASH
-                            | ChunkMethod::FFMS2
-                            | ChunkMethod::DGDECNV
-                            | ChunkMethod::BESTSOURCE
-                    )
-                    && !self.args.resume))
-        {
-            self.vs_proxy_script = Some(cache_vs_input(proxy)?);
-        }
+        self.cache_vs_inputs()?;
+        self.load_hdr10plus()?;
 
         let clip_info = self.args.input.clip_info()?;
         let res = clip_info.resolution;
@@ -278,11 +291,13 @@ impl Av1anContext {
             }
         );
 
+        self.write_progress_snapshot(ProgressStage::SceneDetection, 0);
         let splits = self.split_routine()?.to_vec();
 
         if self.args.sc_only {
             debug!("scene detection only");
 
+            self.temp_lock.lock().expect("mutex should acquire lock").take();
             if let Err(e) = fs::remove_dir_all(&self.args.temp) {
                 warn!("Failed to delete temp directory: {e}");
             }
@@ -290,8 +305,27 @@ impl Av1anContext {
             exit(0);
         }
 
+        let splits = if let Some(count) = self.args.sample {
+            let samples = sample_scenes(&splits, count);
+            info!(
+                "sample: encoding {} of {} scene(s)",
+                samples.len(),
+                splits.len()
+            );
+            samples
+        } else {
+            splits
+        };
+
         let (chunk_queue, total_chunks) = self.load_or_gen_chunk_queue(&splits)?;
 
+        // Chunks are still created from the full video, so the frame count only
+        // changes to the sampled frames after the queue exists
+        let full_frames = self.frames;
+        if self.args.sample.is_some() {
+            self.frames = splits.iter().map(|scene| scene.end_frame - scene.start_frame).sum();
+        }
+
         let mut chunks_done = 0;
         if self.args.resume {
             chunks_done = get_done().done.len();
@@ -303,21 +337,27 @@ impl Av1anContext {
             );
         }
 
+        if self.args.whole_clip_first_pass {
+            self.whole_clip_first_pass(&chunk_queue, full_frames)?;
+        }
+
         crossbeam_utils::thread::scope(|s| -> anyhow::Result<()> {
             // vapoursynth audio is currently unsupported
             let audio_thread = (self.args.input.is_video()
+                && self.args.sample.is_none()
                 && (!self.args.resume || !get_done().audio_done.load(atomic::Ordering::SeqCst)))
             .then(|| {
                 let input = self.args.input.as_video_path();
                 let temp = self.args.temp.as_str();
                 let audio_params = self.args.audio_params.as_slice();
+                let passthrough = &self.args.passthrough;
                 s.spawn(move |_| -> anyhow::Result<_> {
-                    let audio_output = crate::ffmpeg::encode_audio(input, temp, audio_params)?;
+                    let audio_output =
+                        crate::ffmpeg::encode_audio(input, temp, audio_params, passthrough)?;
                     get_done().audio_done.store(true, atomic::Ordering::SeqCst);
 
                     let progress_file = Path::new(temp).join("done.json");
-                    let mut progress_file = File::create(progress_file)?;
-                    progress_file.write_all(serde_json::to_string(get_done())?.as_bytes())?;
+                    write_atomic(&progress_file, serde_json::to_string(get_done())?)?;
 
                     if let Some(ref audio_output) = audio_output {
                         let audio_size = audio_output.metadata()?.len();
@@ -328,10 +368,28 @@ impl Av1anContext {
                 })
             });
 
+            let requested_workers = self.args.workers;
             if self.args.workers == 0 {
                 self.args.workers = determine_workers(&self.args)? as usize;
             }
-            self.args.workers = cmp::min(self.args.workers, chunk_queue.len());
+            if chunk_queue.len() < self.args.workers {
+                // Only worth a warning when the workers were chosen by the user for this
+                // encode, not when resuming with a few chunks left
+                if requested_workers > 0 && chunks_done == 0 {
+                    warn!(
+                        "only {chunks} chunk(s) to encode, using {chunks} of {requested_workers} \
+                         workers. Use --split-for-workers to split the longest scenes instead",
+                        chunks = chunk_queue.len()
+                    );
+                } else {
+                    info!(
+                        "using {chunks} of {workers} workers for {chunks} chunk(s)",
+                        chunks = chunk_queue.len(),
+                        workers = self.args.workers
+                    );
+                }
+                self.args.workers = chunk_queue.len();
+            }
 
             info!(
                 "\n{}{} {} {}{} {} {}{} {} {}{} {}\n{}: {}",
@@ -380,122 +438,1012 @@ impl Av1anContext {
             let broker = Broker {
                 chunk_queue,
                 project: self,
+                output_verified: AtomicBool::new(false),
+                deadline,
+                live_params: self
+                    .args
+                    .live_params
+                    .clone()
+                    .map(|path| LiveParams::new(path, self.args.encoder)),
+                pause_file: self.args.pause_file.clone(),
+                memory_governor: self.args.memory_ceiling.map(MemoryGovernor::new),
+            };
+
+            let (tx, rx) = mpsc::channel();
+            let handle = s.spawn(|_| -> anyhow::Result<()> {
+                broker.encoding_loop(tx, self.args.set_thread_affinity, total_chunks as u32)?;
+                Ok(())
+            });
+
+            let encoding_finished = Arc::new(AtomicBool::new(false));
+            // The scope waits for the threads below, which only stop once the flag is set
+            let _finished_guard = SetOnDrop(Arc::clone(&encoding_finished));
+            {
+                let encoding_finished = Arc::clone(&encoding_finished);
+                let context = &*self;
+                s.spawn(move |_| {
+                    while !encoding_finished.load(atomic::Ordering::SeqCst) {
+                        context.write_progress_snapshot(ProgressStage::Encoding, total_chunks);
+                        thread::sleep(Duration::from_secs(1));
+                    }
+                });
+            }
+
+            // Merge each group of chunks as soon as all of its chunks are encoded, so
+            // that mkvmerge does not have to read thousands of chunks after the last
+            // one finishes
+            let pre_merge = (self.args.concat == ConcatMethod::MKVMerge).then(|| {
+                let encoding_finished = Arc::clone(&encoding_finished);
+                let context = &*self;
+                s.spawn(move |_| {
+                    let mut last_merge = Instant::now();
+                    while !encoding_finished.load(atomic::Ordering::SeqCst) {
+                        thread::sleep(Duration::from_secs(1));
+                        if last_merge.elapsed() < PRE_MERGE_INTERVAL {
+                            continue;
+                        }
+
+                        if let Err(e) = concat::mkvmerge_finished_groups(
+                            context.args.temp.as_ref(),
+                            context.args.encoder,
+                            total_chunks,
+                            (!context.args.ignore_frame_mismatch).then_some(fps_ratio),
+                        ) {
+                            warn!("Failed to merge finished chunks while encoding: {e}");
+                            break;
+                        }
+                        last_merge = Instant::now();
+                    }
+                })
+            });
+
+            // Queue::encoding_loop only sends a message if there was an error (meaning a
+            // chunk crashed) more than MAX_TRIES. So, we have to explicitly
+            // exit the program if that happens.
+            if rx.recv().is_ok() {
+                exit(1);
+            }
+
+            let encoded = handle.join().expect("thread should join successfully");
+            encoding_finished.store(true, atomic::Ordering::SeqCst);
+            // A group merge that is still running must finish before the groups
+            // are merged again for the output
+            if let Some(pre_merge) = pre_merge {
+                pre_merge.join().expect("thread should join successfully");
+            }
+            encoded?;
+
+            finish_progress_bar();
+
+            let remaining = total_chunks.saturating_sub(get_done().done.len());
+            if remaining > 0 && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                warn!(
+                    "time budget exhausted with {remaining} of {total_chunks} chunks remaining, \
+                     run again with --resume to continue"
+                );
+                if self.args.concat == ConcatMethod::MKVMerge
+                    && let Err(e) = concat::mkvmerge_finished_groups(
+                        self.args.temp.as_ref(),
+                        self.args.encoder,
+                        total_chunks,
+                        (!self.args.ignore_frame_mismatch).then_some(fps_ratio),
+                    )
+                {
+                    warn!("Failed to merge finished chunks: {e}");
+                }
+                self.report_resource_usage(resource_monitor)?;
+                exit(TIME_BUDGET_EXIT_CODE);
+            }
+
+            if let Some(threshold) = self.args.bitrate_spike_threshold {
+                Self::report_bitrate_spikes(threshold, fps);
+            }
+            Self::report_encoder_stats();
+
+            // TODO add explicit parameter to concatenation functions to control whether
+            // audio is also muxed in
+            let _audio_output_exists = if let Some(audio_thread) = audio_thread {
+                audio_thread.join().expect("thread should join successfully")?
+            } else {
+                false
+            };
+
+            debug!(
+                "encoding finished, concatenating with {concat}",
+                concat = self.args.concat
+            );
+
+            self.guard_output()?;
+            self.concatenate(total_chunks, &clip_info)?;
+
+            if self.args.sample.is_some() {
+                Self::report_sample(self.frames, full_frames, fps);
+            }
+
+            if self.args.vmaf
+                && self.args.sample.is_none()
+                && let Some(sample_rate) = self.calculate_vmaf(&splits)?
+                && self.args.reencode_outliers
+            {
+                match self.reencode_outliers(&splits, sample_rate, total_chunks) {
+                    Ok(true) => {
+                        self.concatenate(total_chunks, &clip_info)?;
+                        self.calculate_vmaf(&splits)?;
+                    },
+                    Ok(false) => (),
+                    Err(e) => warn!("Failed to encode the outlier chunks again: {e}"),
+                }
+            }
+
+            if self.args.heatmap
+                && let Err(e) = self.write_heatmap(&splits, fps)
+            {
+                warn!("Failed to write the scene heatmap: {e}");
+            }
+            if let Some(format) = self.args.export_stats
+                && let Err(e) = self.write_stats(&splits, fps, format)
+            {
+                warn!("Failed to export the scene statistics: {e}");
+            }
+
+            self.report_resource_usage(resource_monitor)?;
+            self.write_progress_snapshot(ProgressStage::Done, total_chunks);
+
+            if !self.args.output_file.exists() {
+                warn!(
+                    "Concatenation failed for unknown reasons! Temp folder will not be deleted: \
+                     {temp}",
+                    temp = self.args.temp
+                );
+            } else if self.args.keep != KeepPolicy::Always {
+                self.remove_temp();
+            }
+
+            Ok(())
+        })
+        .expect("thread should spawn successfully")?;
+
+        Ok(())
+    }
+
+    /// Reads the HDR10+ metadata that is passed to the encoder for each chunk,
+    /// if requested
+    fn load_hdr10plus(&mut self) -> anyhow::Result<()> {
+        let Some(source) = &self.args.hdr10plus else {
+            return Ok(());
+        };
+        let metadata = Hdr10PlusMetadata::load(source, &self.args.input, &self.args.temp)?;
+        if metadata.frames() != self.frames {
+            warn!(
+                "HDR10+ metadata has {} frames but the input has {}, frames past the end of the \
+                 metadata are encoded without it",
+                metadata.frames(),
+                self.frames
+            );
+        }
+        self.hdr10plus = Some(metadata);
+        Ok(())
+    }
+
+    /// Creates the VapourSynth scripts of the input and proxy, if they are
+    /// needed by the chunk method, and evaluates them to create the source
+    /// plugin's cache file
+    fn cache_vs_inputs(&mut self) -> anyhow::Result<()> {
+        // Create the VapourSynth script file and store the path to it and evaluate it
+        let cache_vs_input = |vs_input: &Input| {
+            let script_path = match vs_input {
+                Input::VapourSynth {
+                    path, ..
+                } => path.clone(),
+                Input::Video {
+                    path,
+                    is_proxy,
+                    ..
+                } => {
+                    let (script_path, _) = create_vs_file(&LoadscriptArgs {
+                        temp:         &self.args.temp,
+                        source:       path,
+                        chunk_method: self.args.chunk_method,
+                        is_proxy:     *is_proxy,
+                        cache_mode:   self.args.cache_mode,
+                    })?;
+                    script_path
+                },
+            };
+
+            let variables_map = vs_input.as_vspipe_args_hashmap()?;
+            let decoder = match vs_input {
+                Input::VapourSynth {
+                    path, ..
+                } => {
+                    let dec = VapoursynthDecoder::from_file(path, variables_map, None)?;
+                    av_scenechange::Decoder::from_decoder_impl(
+                        av_decoders::DecoderImpl::Vapoursynth(dec),
+                    )?
+                },
+                video_input => av_scenechange::Decoder::from_script(
+                    &video_input.as_script_text()?,
+                    variables_map,
+                )?,
+            };
+            // Getting the details will evaluate the script and produce the VapourSynth
+            // cache file
+            let _ = decoder.get_video_details();
+
+            Ok::<PathBuf, anyhow::Error>(script_path)
+        };
+
+        // Technically we should check if the vapoursynth cache file exists rather than
+        // !self.resume, but the code still works if we are resuming and the
+        // cache file doesn't exist (as it gets generated when vspipe is first
+        // called), so it's not worth adding all the extra complexity.
+        if (self.args.input.is_vapoursynth()
+            || (self.args.input.is_video()
+                && matches!(
+                    self.args.chunk_method,
+                    ChunkMethod::LSMASH
+                        | ChunkMethod::FFMS2
+                        | ChunkMethod::DGDECNV
+                        | ChunkMethod::BESTSOURCE
+                )))
+            && !self.args.resume
+        {
+            self.vs_script = Some(cache_vs_input(&self.args.input)?);
+        }
+        if let Some(proxy) = &self.args.proxy
+            && (proxy.is_vapoursynth()
+                || (proxy.is_video()
+                    && matches!(
+                        self.args.chunk_method,
+                        ChunkMethod::LSMASH
+                            | ChunkMethod::FFMS2
+                            | ChunkMethod::DGDECNV
+                            | ChunkMethod::BESTSOURCE
+                    )
+                    && !self.args.resume))
+        {
+            self.vs_proxy_script = Some(cache_vs_input(proxy)?);
+        }
+
+        Ok(())
+    }
+
+    /// Detects the scenes of the input, or loads them from the scenes file,
+    /// without encoding. The scenes are written to the scenes file for later
+    /// encodes to reuse.
+    #[tracing::instrument(skip(self))]
+    #[inline]
+    pub fn detect_scenes(&mut self) -> anyhow::Result<()> {
+        self.cache_vs_inputs()?;
+        self.split_routine()?;
+        Ok(())
+    }
+
+    /// Encodes only the chunk with the given index, even if it is already
+    /// done, and returns the path of the encoded chunk. The scenes and chunks
+    /// of the temporary folder are reused when resuming.
+    #[tracing::instrument(skip(self))]
+    #[inline]
+    pub fn encode_scene(&mut self, index: usize) -> anyhow::Result<PathBuf> {
+        self.cache_vs_inputs()?;
+        self.load_hdr10plus()?;
+        let splits = self.split_routine()?.to_vec();
+
+        let chunks = if self.args.resume {
+            read_chunk_queue(self.args.temp.as_ref())?
+        } else {
+            let chunks = self.create_encoding_queue(&splits)?;
+            save_chunk_queue(&self.args.temp, &chunks)?;
+            chunks
+        };
+        let total_chunks = chunks.len();
+        let mut chunk = chunks.into_iter().find(|chunk| chunk.index == index).ok_or_else(|| {
+            anyhow!("Scene {index} does not exist, the input has {total_chunks} scenes")
+        })?;
+
+        get_done().done.remove(&chunk.name());
+        self.frames = chunk.frames();
+        info!(
+            "encoding scene {index}: frames {start}-{end}, encoder {encoder}",
+            start = chunk.start_frame,
+            end = chunk.end_frame,
+            encoder = chunk.encoder
+        );
+
+        match self.args.verbosity {
+            Verbosity::Normal => init_progress_bar(self.frames as u64, 0, None),
+            Verbosity::Verbose => {
+                init_multi_progress_bar(self.frames as u64, 1, 0, (0, total_chunks as u32));
+            },
+            Verbosity::Quiet => {},
+        }
+
+        let broker = Broker {
+            chunk_queue:     vec![chunk.clone()],
+            project:         self,
+            output_verified: AtomicBool::new(false),
+            deadline:        None,
+            live_params:     None,
+            pause_file:      None,
+            memory_governor: None,
+        };
+        broker.encode_chunk(
+            &mut chunk,
+            0,
+            &Arc::new(AtomicU8::new(0)),
+            total_chunks as u32,
+        )?;
+        finish_progress_bar();
+
+        Ok(chunk.output())
+    }
+
+    /// Logs the bitrate of a sample encode and extrapolates it to the size of
+    /// a full encode
+    fn report_sample(sample_frames: usize, full_frames: usize, fps: f64) {
+        let size_bytes = get_done().done.iter().map(|chunk| chunk.size_bytes).sum::<u64>();
+        let seconds = sample_frames as f64 / fps;
+        let kbps = size_bytes as f64 * 8. / 1000. / seconds;
+        let est_size = size_bytes as f64 * full_frames as f64 / sample_frames as f64;
+
+        info!(
+            "sample: {sample_frames} of {full_frames} frames ({percent:.1}%), {kbps:.1} Kbps, \
+             estimated full video size {est_size} (excluding audio)",
+            percent = sample_frames as f64 / full_frames as f64 * 100.,
+            est_size = HumanBytes(est_size as u64)
+        );
+    }
+
+    /// Logs how much space the temp folder takes up and deletes it
+    fn remove_temp(&self) {
+        let temp = Path::new(&self.args.temp);
+        if !temp.exists() {
+            return;
+        }
+        let mut sizes = fs::read_dir(temp)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| (entry.file_name(), dir_size(&entry.path())))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        sizes.sort_by_key(|&(_, size)| Reverse(size));
+
+        info!(
+            "Deleting temp directory {temp} ({size})",
+            temp = temp.display(),
+            size = HumanBytes(sizes.iter().map(|&(_, size)| size).sum())
+        );
+        for (name, size) in sizes {
+            debug!(
+                "{name}: {size}",
+                name = name.to_string_lossy(),
+                size = HumanBytes(size)
+            );
+        }
+
+        // Windows cannot delete the lock file while it is open
+        self.temp_lock.lock().expect("mutex should acquire lock").take();
+        if let Err(e) = fs::remove_dir_all(temp) {
+            warn!("Failed to delete temp directory: {e}");
+        }
+    }
+
+    /// Writes `progress.json` to the temp folder, logging instead of failing
+    /// the encode if it cannot be written
+    fn write_progress_snapshot(&self, stage: ProgressStage, total_chunks: usize) {
+        if let Err(e) = write_progress_snapshot(&self.args.temp, stage, self.frames, total_chunks) {
+            debug!("Failed to write progress snapshot: {e}");
+        }
+    }
+
+    /// Concatenates the encoded chunks and the audio into the output file
+    fn concatenate(&self, total_chunks: usize, clip_info: &ClipInfo) -> anyhow::Result<()> {
+        let fps_ratio = clip_info.frame_rate;
+        self.write_progress_snapshot(ProgressStage::Concatenating, total_chunks);
+        bitstream::verify_chunks(
+            &Path::new(&self.args.temp).join("encode"),
+            self.args.encoder,
+        )?;
+
+        match self.args.concat {
+            ConcatMethod::Ivf => {
+                concat::ivf(
+                    &Path::new(&self.args.temp).join("encode"),
+                    self.args.output_file.as_ref(),
+                )?;
+            },
+            ConcatMethod::MKVMerge => {
+                concat::mkvmerge(
+                    self.args.temp.as_ref(),
+                    self.args.output_file.as_ref(),
+                    self.args.encoder,
+                    total_chunks,
+                    if self.args.ignore_frame_mismatch {
+                        info!(
+                            "`--ignore-frame-mismatch` set. Don't force output FPS, as an FPS \
+                             changing filter might have been applied."
+                        );
+                        None
+                    } else {
+                        debug!(
+                            "`--ignore-frame-mismatch` not set. Forcing output FPS to {fps_ratio} \
+                             with mkvmerge."
+                        );
+                        Some(fps_ratio)
+                    },
+                    y4m::display_dimensions(
+                        clip_info.resolution,
+                        HeaderOverrides::new(&self.args, clip_info).sample_aspect_ratio,
+                    ),
+                )?;
+            },
+            ConcatMethod::FFmpeg => {
+                concat::ffmpeg(self.args.temp.as_ref(), self.args.output_file.as_ref())?;
+            },
+        }
+
+        if self.args.verify_bitstream {
+            bitstream::verify_output(self.args.output_file.as_ref(), self.args.encoder)?;
+        }
+
+        Ok(())
+    }
+
+    /// Calculates the VMAF of the output and compares it with the scores
+    /// target quality predicted. Returns the rate at which frames were
+    /// sampled, or `None` if the calculation failed.
+    fn calculate_vmaf(&self, splits: &[Scene]) -> anyhow::Result<Option<usize>> {
+        let vmaf_res = if self.args.target_quality.vmaf_res == "inputres" {
+            let inputres = self.args.input.clip_info()?.resolution;
+            format!("{width}x{height}", width = inputres.0, height = inputres.1)
+        } else {
+            self.args.target_quality.vmaf_res.clone()
+        };
+
+        let vmaf_model =
+            self.args.vmaf_path.as_deref().or(self.args.target_quality.model.as_deref());
+        let vmaf_scaler = "bicubic";
+        let vmaf_filter = self.args.vmaf_filter.as_deref().or(self
+            .args
+            .target_quality
+            .vmaf_filter
+            .as_deref());
+
+        let vmaf_threads = available_parallelism().map_or(1, std::num::NonZero::get);
+        let sample_rate = scene_sample_rate(
+            splits,
+            self.args.vmaf_sample_rate,
+            self.args.vmaf_min_scene_samples,
+        );
+
+        if let Err(e) = vmaf::plot(
+            self.args.output_file.as_ref(),
+            &self.args.input,
+            vmaf_model,
+            &vmaf_res,
+            vmaf_scaler,
+            sample_rate,
+            vmaf_filter,
+            vmaf_threads,
+            &self.args.target_quality.probing_vmaf_features,
+        ) {
+            error!("VMAF calculation failed with error: {e}");
+            return Ok(None);
+        }
+
+        if let Err(e) = self.report_vmaf_scores(sample_rate) {
+            warn!("Failed to write the VMAF report: {e}");
+        }
+
+        if splits.iter().any(|scene| {
+            let target_quality = self.scene_target_quality(scene);
+            target_quality.target.is_some() && target_quality.metric == TargetMetric::VMAF
+        }) && let Err(e) = self.report_prediction_errors(splits, sample_rate)
+        {
+            warn!("Failed to compare target quality predictions with VMAF: {e}");
+        }
+
+        Ok(Some(sample_rate))
+    }
+
+    /// Applies the overwrite policy again right before concatenation, in case
+    /// the output file was created while encoding
+    fn guard_output(&self) -> anyhow::Result<()> {
+        let output = self.args.output_file.as_path();
+        if !output.exists() {
+            return Ok(());
+        }
+
+        match self.args.overwrite_policy {
+            OverwritePolicy::Fail => bail!(
+                "Output file {} was created while encoding. Temp folder will not be deleted, move \
+                 the file and run again with --resume to concatenate: {}",
+                output.display(),
+                self.args.temp
+            ),
+            OverwritePolicy::Backup => {
+                let backup = backup_path(output);
+                info!(
+                    "backing up existing output file to {backup}",
+                    backup = backup.display()
+                );
+                fs::rename(output, backup)?;
+            },
+            OverwritePolicy::Overwrite => (),
+        }
+
+        Ok(())
+    }
+
+    fn report_bitrate_spikes(threshold: f64, fps: f64) {
+        let chunks = get_done()
+            .done
+            .iter()
+            .map(|chunk| (chunk.key().clone(), chunk.frames, chunk.size_bytes))
+            .collect::<Vec<_>>();
+        let spikes = find_bitrate_spikes(&chunks, threshold);
+        if spikes.is_empty() {
+            return;
+        }
+
+        warn!(
+            "{count} of {total} chunks have a bitrate more than {threshold}x the median",
+            count = spikes.len(),
+            total = chunks.len()
+        );
+        for (name, ratio) in spikes {
+            if let Some(chunk) = get_done().done.get(&name) {
+                let kbps = chunk.size_bytes as f64 * 8. / 1000. / (chunk.frames as f64 / fps);
+                warn!(
+                    "chunk {name}: {frames} frames, {kbps:.1} Kbps ({ratio:.2}x median)",
+                    frames = chunk.frames
+                );
+            }
+        }
+    }
+
+    /// Logs the PSNR and average QP reported by the encoder over the whole
+    /// encode, for the chunks where the encoder printed them
+    fn report_encoder_stats() {
+        let (psnr, avg_qp): (Vec<_>, Vec<_>) = get_done()
+            .done
+            .iter()
+            .map(|chunk| {
+                (
+                    chunk.psnr.map(|psnr| (chunk.frames, psnr)),
+                    chunk.avg_qp.map(|qp| (chunk.frames, qp)),
+                )
+            })
+            .unzip();
+        let psnr = psnr.into_iter().flatten().collect::<Vec<_>>();
+        let avg_qp = avg_qp.into_iter().flatten().collect::<Vec<_>>();
+
+        if let Some(mean) = frame_weighted_mean(&psnr) {
+            info!(
+                "encoder-reported PSNR: {mean:.3} ({count} chunks)",
+                count = psnr.len()
+            );
+        }
+        if let Some(mean) = frame_weighted_mean(&avg_qp) {
+            info!(
+                "encoder-reported average QP: {mean:.2} ({count} chunks)",
+                count = avg_qp.len()
+            );
+        }
+    }
+
+    /// Stops `monitor`, adds the resources used by this run to the ones of the
+    /// previous runs in `done.json` and logs the totals
+    fn report_resource_usage(&self, monitor: ResourceMonitor) -> anyhow::Result<()> {
+        let usage = {
+            let mut usage = get_done().resources.lock().expect("mutex should acquire lock");
+            usage.accumulate(monitor.finish());
+            *usage
+        };
+        write_atomic(
+            &Path::new(&self.args.temp).join("done.json"),
+            serde_json::to_string(get_done())?,
+        )?;
+
+        info!(
+            "resource usage: {cpu} of CPU time, {read} read and {written} written, peak memory \
+             {rss}, {count} child processes",
+            cpu = HumanDuration(Duration::from_millis(usage.cpu_time_ms)),
+            read = HumanBytes(usage.bytes_read),
+            written = HumanBytes(usage.bytes_written),
+            rss = HumanBytes(usage.peak_rss),
+            count = usage.child_processes
+        );
+        if let Some(chunk) = get_done()
+            .done
+            .iter()
+            .filter(|chunk| chunk.peak_rss.is_some())
+            .max_by_key(|chunk| chunk.peak_rss)
+        {
+            info!(
+                "largest chunk peak memory: {rss} (chunk {name})",
+                rss = HumanBytes(chunk.peak_rss.unwrap_or_default()),
+                name = chunk.key()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Logs the summary of the scores of the final VMAF run, and writes it and
+    /// the score of every frame next to the output
+    fn report_vmaf_scores(&self, sample_rate: usize) -> anyhow::Result<()> {
+        let scores = read_vmaf_file(self.args.output_file.with_extension("json"))?;
+        let Some(summary) = VmafSummary::new(&scores, sample_rate) else {
+            return Ok(());
+        };
+        info!(
+            "VMAF of {frames} frames: mean {mean:.3}, harmonic mean {harmonic_mean:.3}, 1% low \
+             {low_1:.3}, min {minimum:.3}",
+            frames = summary.frames,
+            mean = summary.mean,
+            harmonic_mean = summary.harmonic_mean,
+            low_1 = summary.low_1,
+            minimum = summary.minimum,
+        );
+
+        let summary_file = self.args.output_file.with_extension("summary.json");
+        fs::write(&summary_file, serde_json::to_string_pretty(&summary)?)?;
+        let scores_file = self.args.output_file.with_extension("csv");
+        fs::write(&scores_file, vmaf::scores_csv(&scores, sample_rate))?;
+        info!(
+            "VMAF summary written to {} and the score of each frame to {}",
+            summary_file.display(),
+            scores_file.display()
+        );
+
+        Ok(())
+    }
+
+    /// Compares the score target quality chose for each chunk with the score
+    /// of the chunk's frames in the final VMAF run
+    fn prediction_errors(
+        &self,
+        splits: &[Scene],
+        sample_rate: usize,
+    ) -> anyhow::Result<Vec<PredictionError>> {
+        let scores = read_vmaf_file(self.args.output_file.with_extension("json"))?;
+
+        let mut errors = Vec::new();
+        for chunk in get_done().done.iter() {
+            let (Some(quantizer), Some(predicted)) = (chunk.tq_cq, chunk.tq_score) else {
+                continue;
+            };
+            let Some(scene) = chunk.key().parse::<usize>().ok().and_then(|index| splits.get(index))
+            else {
+                continue;
             };
+            // Zones can give scenes their own target and metric
+            let target_quality = self.scene_target_quality(scene);
+            if target_quality.metric != TargetMetric::VMAF {
+                continue;
+            }
+            let scene_scores =
+                sampled_scene_scores(&scores, sample_rate, scene.start_frame, scene.end_frame);
+            if scene_scores.is_empty() {
+                continue;
+            }
 
-            let (tx, rx) = mpsc::channel();
-            let handle = s.spawn(|_| -> anyhow::Result<()> {
-                broker.encoding_loop(tx, self.args.set_thread_affinity, total_chunks as u32)?;
-                Ok(())
+            errors.push(PredictionError {
+                chunk: chunk.key().clone(),
+                quantizer,
+                predicted,
+                achieved: target_quality
+                    .aggregate_frame_scores(scene_scores.to_vec(), quantizer)?,
             });
+        }
+        errors.sort_unstable_by(|a, b| a.chunk.cmp(&b.chunk));
 
-            // Queue::encoding_loop only sends a message if there was an error (meaning a
-            // chunk crashed) more than MAX_TRIES. So, we have to explicitly
-            // exit the program if that happens.
-            if rx.recv().is_ok() {
-                exit(1);
-            }
+        Ok(errors)
+    }
 
-            handle.join().expect("thread should join successfully")?;
+    /// Logs the distribution of the differences between the scores target
+    /// quality chose and the scores in the final VMAF run, and writes them next
+    /// to the output
+    fn report_prediction_errors(&self, splits: &[Scene], sample_rate: usize) -> anyhow::Result<()> {
+        let errors = self.prediction_errors(splits, sample_rate)?;
+        if errors.is_empty() {
+            return Ok(());
+        }
 
-            finish_progress_bar();
+        let bias = errors.iter().map(PredictionError::error).sum::<f64>() / errors.len() as f64;
+        let mut absolute_errors = errors.iter().map(|e| e.error().abs()).collect::<Vec<_>>();
+        absolute_errors.sort_unstable_by(f64::total_cmp);
+        let mean_absolute = absolute_errors.iter().sum::<f64>() / absolute_errors.len() as f64;
+        info!(
+            "target quality prediction error over {count} chunks: bias {bias:+.3}, mean absolute \
+             {mean_absolute:.3}, median {median:.3}, 90th percentile {p90:.3}, max {max:.3}",
+            count = errors.len(),
+            median = percentile_of_sorted(&absolute_errors, 0.5),
+            p90 = percentile_of_sorted(&absolute_errors, 0.9),
+            max = percentile_of_sorted(&absolute_errors, 1.0),
+        );
 
-            // TODO add explicit parameter to concatenation functions to control whether
-            // audio is also muxed in
-            let _audio_output_exists = if let Some(audio_thread) = audio_thread {
-                audio_thread.join().expect("thread should join successfully")?
-            } else {
-                false
+        let report_file = self.args.output_file.with_extension("tq.json");
+        fs::write(&report_file, serde_json::to_string_pretty(&errors)?)?;
+        info!(
+            "predicted and achieved score of each chunk written to {}",
+            report_file.display()
+        );
+
+        Ok(())
+    }
+
+    /// Encodes the chunks whose score in the final VMAF run is outside of their
+    /// target quality range again, targeting a range shifted by how far off
+    /// the prediction of target quality was. Returns whether any chunk was
+    /// encoded again.
+    fn reencode_outliers(
+        &self,
+        splits: &[Scene],
+        sample_rate: usize,
+        total_chunks: usize,
+    ) -> anyhow::Result<bool> {
+        let errors = self.prediction_errors(splits, sample_rate)?;
+        let mut chunks = read_chunk_queue(self.args.temp.as_ref())?;
+        self.remap_saved_paths(&mut chunks)?;
+
+        let mut outliers = Vec::new();
+        for mut chunk in chunks {
+            let Some(error) = errors.iter().find(|error| error.chunk == chunk.name()) else {
+                continue;
+            };
+            let Some((min, max)) = chunk.target_quality.target else {
+                continue;
             };
+            let Some(target) = error.corrected_target((min, max)) else {
+                continue;
+            };
+            info!(
+                "chunk {name} scored {achieved:.3} instead of {min}-{max}, encoding it again \
+                 targeting {target_min:.3}-{target_max:.3}",
+                name = chunk.name(),
+                achieved = error.achieved,
+                target_min = target.0,
+                target_max = target.1,
+            );
+            chunk.target_quality.target = Some(target);
+            chunk.tq_cq = None;
+            chunk.tq_score = None;
+            outliers.push(chunk);
+        }
+        if outliers.is_empty() {
+            if !errors.is_empty() {
+                info!("all chunks scored within their target quality range");
+            }
+            return Ok(false);
+        }
 
-            debug!(
-                "encoding finished, concatenating with {concat}",
-                concat = self.args.concat
+        // The chunks count as not done until they are encoded again
+        let done = get_done();
+        for chunk in &outliers {
+            done.done.remove(&chunk.name());
+        }
+        let frames = outliers.iter().map(Chunk::frames).sum::<usize>();
+        let chunks_done = (total_chunks - outliers.len()) as u32;
+        if self.args.verbosity == Verbosity::Normal {
+            init_progress_bar(frames as u64, 0, Some((chunks_done, total_chunks as u32)));
+        } else if self.args.verbosity == Verbosity::Verbose {
+            init_multi_progress_bar(
+                frames as u64,
+                cmp::min(self.args.workers, outliers.len()),
+                0,
+                (chunks_done, total_chunks as u32),
             );
+        }
 
-            match self.args.concat {
-                ConcatMethod::Ivf => {
-                    concat::ivf(
-                        &Path::new(&self.args.temp).join("encode"),
-                        self.args.output_file.as_ref(),
-                    )?;
-                },
-                ConcatMethod::MKVMerge => {
-                    concat::mkvmerge(
-                        self.args.temp.as_ref(),
-                        self.args.output_file.as_ref(),
-                        self.args.encoder,
-                        total_chunks,
-                        if self.args.ignore_frame_mismatch {
-                            info!(
-                                "`--ignore-frame-mismatch` set. Don't force output FPS, as an FPS \
-                                 changing filter might have been applied."
-                            );
-                            None
-                        } else {
-                            debug!(
-                                "`--ignore-frame-mismatch` not set. Forcing output FPS to \
-                                 {fps_ratio} with mkvmerge."
-                            );
-                            Some(fps_ratio)
-                        },
-                    )?;
-                },
-                ConcatMethod::FFmpeg => {
-                    concat::ffmpeg(self.args.temp.as_ref(), self.args.output_file.as_ref())?;
+        let broker = Broker {
+            chunk_queue:     outliers,
+            project:         self,
+            output_verified: AtomicBool::new(true),
+            deadline:        None,
+            live_params:     None,
+            pause_file:      self.args.pause_file.clone(),
+            memory_governor: self.args.memory_ceiling.map(MemoryGovernor::new),
+        };
+        let (tx, rx) = mpsc::channel();
+        broker.encoding_loop(tx, self.args.set_thread_affinity, total_chunks as u32)?;
+        if rx.try_recv().is_ok() {
+            exit(1);
+        }
+
+        Ok(true)
+    }
+
+    /// Writes a timeline of the scenes colored by bitrate and quality next to
+    /// the output
+    fn write_heatmap(&self, splits: &[Scene], fps: f64) -> anyhow::Result<()> {
+        let (scenes, score_name) = self.scene_stats(splits, fps);
+        let heatmap_file = self.args.output_file.with_extension("heatmap.svg");
+        fs::write(&heatmap_file, heatmap_svg(&scenes, score_name))?;
+        info!("scene heatmap written to {}", heatmap_file.display());
+
+        Ok(())
+    }
+
+    /// Writes the results of every scene next to the output, for spreadsheets
+    /// and plotting
+    fn write_stats(&self, splits: &[Scene], fps: f64, format: StatsFormat) -> anyhow::Result<()> {
+        let (scenes, score_name) = self.scene_stats(splits, fps);
+        let stats_file = self.args.output_file.with_extension(format.extension());
+        fs::write(&stats_file, export_stats(&scenes, score_name, format))?;
+        info!("scene statistics written to {}", stats_file.display());
+
+        Ok(())
+    }
+
+    /// Results of the encoded scenes in order, and the name of their score.
+    /// The score is the VMAF of the output if it was calculated, or otherwise
+    /// the target quality score or the PSNR reported by the encoder. All
+    /// scenes are scored with the same metric, so scenes without it have no
+    /// score.
+    fn scene_stats(&self, splits: &[Scene], fps: f64) -> (Vec<SceneStats>, &'static str) {
+        let vmaf_scores = if self.args.vmaf && self.args.sample.is_none() {
+            read_vmaf_file(self.args.output_file.with_extension("json")).ok()
+        } else {
+            None
+        };
+        let sample_rate = scene_sample_rate(
+            splits,
+            self.args.vmaf_sample_rate,
+            self.args.vmaf_min_scene_samples,
+        );
+
+        let done = get_done();
+        let score_name = if vmaf_scores.is_some() {
+            "VMAF"
+        } else if done.done.iter().any(|chunk| chunk.tq_score.is_some()) {
+            "score"
+        } else {
+            "PSNR"
+        };
+        let mut scenes = Vec::new();
+        for chunk in done.done.iter() {
+            let Some((index, scene)) = chunk
+                .key()
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| splits.get(index).map(|scene| (index, scene)))
+            else {
+                continue;
+            };
+            let score = match &vmaf_scores {
+                Some(scores) => {
+                    let scene_scores = sampled_scene_scores(
+                        scores,
+                        sample_rate,
+                        scene.start_frame,
+                        scene.end_frame,
+                    );
+                    (!scene_scores.is_empty())
+                        .then(|| scene_scores.iter().sum::<f64>() / scene_scores.len() as f64)
                 },
-            }
+                None if score_name == "score" => chunk.tq_score,
+                None => chunk.psnr,
+            };
 
-            if self.args.vmaf {
-                let vmaf_res = if self.args.target_quality.vmaf_res == "inputres" {
-                    let inputres = self.args.input.clip_info()?.resolution;
-                    format!("{width}x{height}", width = inputres.0, height = inputres.1)
-                } else {
-                    self.args.target_quality.vmaf_res.clone()
-                };
+            scenes.push(SceneStats {
+                index,
+                start_frame: scene.start_frame,
+                frames: chunk.frames,
+                size_bytes: chunk.size_bytes,
+                kbps: chunk.size_bytes as f64 * 8.0 * fps / chunk.frames.max(1) as f64 / 1000.0,
+                quantizer: chunk.tq_cq.map(f64::from).or(chunk.avg_qp),
+                score,
+                encode_fps: chunk.encode_fps,
+                retries: chunk.retries,
+            });
+        }
+        scenes.sort_unstable_by_key(|scene| scene.start_frame);
 
-                let vmaf_model =
-                    self.args.vmaf_path.as_deref().or(self.args.target_quality.model.as_deref());
-                let vmaf_scaler = "bicubic";
-                let vmaf_filter = self.args.vmaf_filter.as_deref().or(self
-                    .args
-                    .target_quality
-                    .vmaf_filter
-                    .as_deref());
-
-                if self.args.vmaf {
-                    let vmaf_threads = available_parallelism().map_or(1, std::num::NonZero::get);
-
-                    if let Err(e) = vmaf::plot(
-                        self.args.output_file.as_ref(),
-                        &self.args.input,
-                        vmaf_model,
-                        &vmaf_res,
-                        vmaf_scaler,
-                        1,
-                        vmaf_filter,
-                        vmaf_threads,
-                        &self.args.target_quality.probing_vmaf_features,
-                    ) {
-                        error!("VMAF calculation failed with error: {e}");
-                    }
-                }
-            }
+        (scenes, score_name)
+    }
 
-            if !Path::new(&self.args.output_file).exists() {
-                warn!(
-                    "Concatenation failed for unknown reasons! Temp folder will not be deleted: \
-                     {temp}",
-                    temp = self.args.temp
-                );
-            } else if !self.args.keep
-                && let Err(e) = fs::remove_dir_all(&self.args.temp)
-            {
-                warn!("Failed to delete temp directory: {e}");
-            }
+    /// Target Quality settings of `scene`, which zones can override
+    fn scene_target_quality<'a>(&'a self, scene: &'a Scene) -> &'a TargetQuality {
+        scene
+            .zone_overrides
+            .as_ref()
+            .and_then(|ovr| ovr.target_quality.as_ref())
+            .unwrap_or(&self.args.target_quality)
+    }
 
-            Ok(())
-        })
-        .expect("thread should spawn successfully")?;
+    /// Whether the first pass of `chunk` is replaced by the stats of the first
+    /// pass over the whole clip
+    pub(crate) fn uses_whole_clip_first_pass(&self, chunk: &Chunk) -> bool {
+        self.args.whole_clip_first_pass && chunk.encoder == Encoder::aom && chunk.passes == 2
+    }
+
+    /// Runs the first pass over all `frames` frames of the clip, unless a
+    /// previous session already did, and writes the slice of its stats that
+    /// belongs to each chunk as the chunk's first pass stats
+    fn whole_clip_first_pass(&self, chunk_queue: &[Chunk], frames: usize) -> anyhow::Result<()> {
+        let chunks = chunk_queue
+            .iter()
+            .filter(|chunk| self.uses_whole_clip_first_pass(chunk))
+            .collect::<Vec<_>>();
+        if chunks.is_empty() {
+            return Ok(());
+        }
+
+        let split_dir = Path::new(&self.args.temp).join("split");
+        let stats_file = split_dir.join("whole_fpf.log");
+        if !stats_file.exists() {
+            info!("running the first pass over the whole clip");
+            let frame_rate = self
+                .args
+                .input
+                .clip_info()?
+                .frame_rate
+                .to_f64()
+                .expect("frame rate should not be NaN");
+            let whole_clip = Scene {
+                start_frame:    0,
+                end_frame:      frames,
+                zone_overrides: None,
+            };
+            let index = WHOLE_CLIP_INDEX;
+            let mut chunk = match &self.args.input {
+                Input::VapourSynth {
+                    path,
+                    vspipe_args,
+                    ..
+                } => self.create_vs_chunk(
+                    index,
+                    path,
+                    None,
+                    &vspipe_args.iter().map(String::as_str).collect::<Vec<_>>(),
+                    &whole_clip,
+                    frame_rate,
+                )?,
+                Input::Video {
+                    path,
+                    is_proxy,
+                    ..
+                } => {
+                    // The script is only created up front for new encodes, so a
+                    // resumed encode that has not finished this pass creates it here
+                    let vs_script = match &self.vs_script {
+                        Some(vs_script) => vs_script.clone(),
+                        None => {
+                            create_vs_file(&LoadscriptArgs {
+                                temp:         &self.args.temp,
+                                source:       path,
+                                chunk_method: self.args.chunk_method,
+                                is_proxy:     *is_proxy,
+                                cache_mode:   self.args.cache_mode,
+                            })?
+                            .0
+                        },
+                    };
+                    self.create_vs_chunk(index, &vs_script, None, &[], &whole_clip, frame_rate)?
+                },
+            };
+            chunk.passes = 2;
+
+            self.create_pipes(&chunk, 1, 0, 0, &[]).map_err(|(e, _)| e)?;
+            move_file(
+                &split_dir.join(format!("{name}_fpf.log", name = chunk.name())),
+                &stats_file,
+            )?;
+        }
+
+        let stats = fs::read(&stats_file)?;
+        for chunk in chunks {
+            let sliced =
+                slice_aom_first_pass_stats(&stats, frames, chunk.start_frame, chunk.end_frame)?;
+            fs::write(
+                split_dir.join(format!("{name}_fpf.log", name = chunk.name())),
+                sliced,
+            )?;
+        }
 
         Ok(())
     }
@@ -529,45 +1477,86 @@ impl Av1anContext {
         current_pass: u8,
         worker_id: usize,
         padding: usize,
-    ) -> Result<(), (anyhow::Error, u64)> {
-        update_mp_chunk(worker_id, chunk.index, padding);
+        patched_frames: &[usize],
+    ) -> Result<EncoderStats, (anyhow::Error, u64)> {
+        update_mp_chunk(worker_id, &chunk.label(), padding);
+
+        #[cfg(feature = "failure-injection")]
+        crate::failure_injection::before_encode(chunk).map_err(|e| (e, 0))?;
 
         let fpf_file = Path::new(&chunk.temp)
             .join("split")
             .join(format!("{name}_fpf", name = chunk.name()));
 
-        let video_params = chunk.video_params.clone();
+        let mut video_params = chunk.video_params.clone();
+        if let Some(per_shot_target_quality_cq) = chunk.tq_cq {
+            video_params = chunk.encoder.man_command(video_params, per_shot_target_quality_cq);
+            if let Some(bracket) = chunk.target_quality.q_bracket
+                && let Some(params) =
+                    chunk.encoder.q_bracket_params(per_shot_target_quality_cq, bracket)
+            {
+                merge_video_params(chunk.encoder, &mut video_params, params);
+            }
+        }
+        // The overrides are in the syntax of the encoder of the encode, which a zone
+        // may have replaced
+        if chunk.passes > 1 && chunk.encoder == self.args.encoder {
+            for (_, params) in
+                self.args.pass_params.iter().filter(|(pass, _)| *pass == current_pass)
+            {
+                merge_video_params(chunk.encoder, &mut video_params, params.clone());
+            }
+        }
+        if let Some(metadata) = &self.hdr10plus
+            && let Some(path) = metadata.write_chunk(chunk).map_err(|e| (e, 0))?
+        {
+            insert_hdr10plus_params(chunk.encoder, &mut video_params, &path).map_err(|e| (e, 0))?;
+        }
 
-        let mut enc_cmd = if chunk.passes == 1 {
-            chunk.encoder.compose_1_1_pass(video_params, chunk.output())
-        } else if current_pass == 1 {
+        let enc_cmd = if chunk.passes == 1 {
+            chunk.encoder.compose_1_1_pass(video_params, &chunk.output())
+        } else if chunk.passes == 3 {
             chunk
                 .encoder
-                .compose_1_2_pass(video_params, fpf_file.to_string_lossy().as_ref())
+                .compose_3_pass(video_params, &fpf_file, &chunk.output(), current_pass)
+        } else if current_pass == 1 {
+            chunk.encoder.compose_1_2_pass(video_params, &fpf_file)
         } else {
-            chunk.encoder.compose_2_2_pass(
-                video_params,
-                fpf_file.to_string_lossy().as_ref(),
-                chunk.output(),
-            )
+            chunk.encoder.compose_2_2_pass(video_params, &fpf_file, &chunk.output())
         };
 
-        if let Some(per_shot_target_quality_cq) = chunk.tq_cq {
-            enc_cmd = chunk.encoder.man_command(enc_cmd, per_shot_target_quality_cq);
-        }
+        let header_overrides = HeaderOverrides::new(
+            &self.args,
+            &self.args.input.clip_info().map_err(|e| (e, 0))?,
+        );
+
+        // Duplicate frames are dropped by the ffmpeg pipe
+        let filter_args = self
+            .args
+            .ffmpeg_filter_args
+            .iter()
+            .cloned()
+            .chain(chunk.drop_frames_args())
+            .collect::<Vec<_>>();
 
+        let chunk_processes = ChunkProcesses::new(chunk.name());
         let (source_pipe_stderr, ffmpeg_pipe_stderr, enc_output, enc_stderr, frame) =
             thread::scope(|scope| -> Result<_, (anyhow::Error, u64)> {
                 let mut use_vs_resize_converter = false;
                 let mut source_pipe = if let [source, args @ ..] = &*chunk.source_cmd {
                     let mut command = Command::new(source);
+                    self.args.priority.apply(&mut command);
 
                     for arg in chunk.input.as_vspipe_args_vec().map_err(|e| (e, 0))? {
                         command.args(["-a", &arg]);
                     }
 
                     command.args(args);
-                    if self.args.ffmpeg_filter_args.is_empty() {
+                    if !patched_frames.is_empty() {
+                        command.env("AV1AN_PATCH_FRAMES", patched_frames.iter().join(","));
+                        command.env("AV1AN_PATCH_MODE", self.args.decode_error.to_string());
+                    }
+                    if filter_args.is_empty() {
                         match &self.args.input_pix_format {
                             InputPixelFormat::FFmpeg {
                                 format,
@@ -618,44 +1607,49 @@ impl Av1anContext {
                 } else {
                     unreachable!()
                 };
+                chunk_processes.track(&source_pipe);
 
-                let source_pipe_stdout: Stdio =
-                    source_pipe.stdout.take().expect("source_pipe should have stdout").into();
+                let source_pipe_stdout =
+                    source_pipe.stdout.take().expect("source_pipe should have stdout");
                 let source_pipe_stderr =
                     source_pipe.stderr.take().expect("source_pipe should have stderr");
 
                 // converts the pixel format
-                let create_ffmpeg_pipe = |pipe_from: Stdio, source_pipe_stderr: ChildStderr| {
-                    let ffmpeg_pipe = compose_ffmpeg_pipe(
-                        self.args.ffmpeg_filter_args.as_slice(),
-                        self.args.output_pix_format.format,
-                    );
-
-                    let mut ffmpeg_pipe = if let [ffmpeg, args @ ..] = &*ffmpeg_pipe {
-                        Command::new(ffmpeg)
-                            .args(args)
-                            .stdin(pipe_from)
-                            .stdout(Stdio::piped())
-                            .stderr(Stdio::piped())
-                            .spawn()
-                            .map_err(|e| (e.into(), 0))?
-                    } else {
-                        unreachable!()
+                let create_ffmpeg_pipe =
+                    |pipe_from: ChildStdout, source_pipe_stderr: ChildStderr| {
+                        let ffmpeg_pipe = compose_ffmpeg_pipe(
+                            filter_args.as_slice(),
+                            self.args.output_pix_format.format,
+                        );
+
+                        let mut ffmpeg_pipe = if let [ffmpeg, args @ ..] = &*ffmpeg_pipe {
+                            self.args
+                                .priority
+                                .apply(&mut Command::new(ffmpeg))
+                                .args(args)
+                                .stdin(pipe_from)
+                                .stdout(Stdio::piped())
+                                .stderr(Stdio::piped())
+                                .spawn()
+                                .map_err(|e| (e.into(), 0))?
+                        } else {
+                            unreachable!()
+                        };
+                        chunk_processes.track(&ffmpeg_pipe);
+
+                        let ffmpeg_pipe_stdout =
+                            ffmpeg_pipe.stdout.take().expect("ffmpeg_pipe should have stdout");
+                        let ffmpeg_pipe_stderr =
+                            ffmpeg_pipe.stderr.take().expect("ffmpeg_pipe should have stderr");
+                        Ok((
+                            ffmpeg_pipe_stdout,
+                            source_pipe_stderr,
+                            Some(ffmpeg_pipe_stderr),
+                        ))
                     };
 
-                    let ffmpeg_pipe_stdout: Stdio =
-                        ffmpeg_pipe.stdout.take().expect("ffmpeg_pipe should have stdout").into();
-                    let ffmpeg_pipe_stderr =
-                        ffmpeg_pipe.stderr.take().expect("ffmpeg_pipe should have stderr");
-                    Ok((
-                        ffmpeg_pipe_stdout,
-                        source_pipe_stderr,
-                        Some(ffmpeg_pipe_stderr),
-                    ))
-                };
-
                 let (y4m_pipe, source_pipe_stderr, mut ffmpeg_pipe_stderr) =
-                    if self.args.ffmpeg_filter_args.is_empty() {
+                    if filter_args.is_empty() {
                         match &self.args.input_pix_format {
                             InputPixelFormat::FFmpeg {
                                 format,
@@ -714,10 +1708,20 @@ impl Av1anContext {
                     });
                 }
 
+                // The y4m header is only rewritten when it needs to change, since it
+                // takes another copy of every frame
+                let (enc_stdin, y4m_pipe) = if header_overrides.is_empty() {
+                    (Stdio::from(y4m_pipe), None)
+                } else {
+                    (Stdio::piped(), Some(y4m_pipe))
+                };
+
                 let mut enc_pipe = if let [encoder, args @ ..] = &*enc_cmd {
-                    Command::new(encoder)
+                    self.args
+                        .priority
+                        .apply(&mut Command::new(encoder))
                         .args(args)
-                        .stdin(y4m_pipe)
+                        .stdin(enc_stdin)
                         .stdout(Stdio::piped())
                         .stderr(Stdio::piped())
                         .spawn()
@@ -725,6 +1729,20 @@ impl Av1anContext {
                 } else {
                     unreachable!()
                 };
+                chunk_processes.track(&enc_pipe);
+
+                if let Some(y4m_pipe) = y4m_pipe {
+                    let enc_stdin = enc_pipe.stdin.take().expect("enc_pipe should have stdin");
+                    scope.spawn(move || {
+                        // Fails with a broken pipe when the encoder exits early, which is
+                        // reported by the encoder itself
+                        if let Err(e) =
+                            y4m::copy_with_header(y4m_pipe, enc_stdin, &header_overrides)
+                        {
+                            debug!("y4m pipe of chunk {} closed: {e}", chunk.label());
+                        }
+                    });
+                }
 
                 let mut frame = 0;
 
@@ -733,6 +1751,14 @@ impl Av1anContext {
 
                 let mut buf = Vec::with_capacity(128);
                 let mut enc_stderr = String::with_capacity(128);
+                let mut progress = ProgressCoalescer::new(self.args.progress_interval);
+                let report_progress = |frames: u64| {
+                    if self.args.verbosity == Verbosity::Normal {
+                        inc_bar(frames);
+                    } else if self.args.verbosity == Verbosity::Verbose {
+                        inc_mp_bar(frames);
+                    }
+                };
 
                 while let Ok(read) = reader.read_until(b'\r', &mut buf) {
                     if read == 0 {
@@ -752,10 +1778,8 @@ impl Av1anContext {
                             && let Some(new) = chunk.encoder.parse_encoded_frames(line)
                             && new > frame
                         {
-                            if self.args.verbosity == Verbosity::Normal {
-                                inc_bar(new - frame);
-                            } else if self.args.verbosity == Verbosity::Verbose {
-                                inc_mp_bar(new - frame);
+                            if let Some(frames) = progress.add(new - frame) {
+                                report_progress(frames);
                             }
                             frame = new;
                         }
@@ -763,6 +1787,9 @@ impl Av1anContext {
 
                     buf.clear();
                 }
+                if let Some(frames) = progress.flush() {
+                    report_progress(frames);
+                }
 
                 let enc_output = enc_pipe.wait_with_output().expect("enc_pipe should finish");
 
@@ -794,6 +1821,9 @@ impl Av1anContext {
         }
 
         if current_pass == chunk.passes {
+            #[cfg(feature = "failure-injection")]
+            crate::failure_injection::after_encode(chunk).map_err(|e| (e, frame))?;
+
             if !fs::exists(chunk.output()).map_err(|e| (anyhow::anyhow!("{e}"), frame))?
                 || fs::metadata(chunk.output()).map_err(|e| (anyhow::anyhow!("{e}"), frame))?.len()
                     == 0
@@ -802,28 +1832,28 @@ impl Av1anContext {
                     anyhow::anyhow!(
                         "ERROR: Output chunk file {} could not be created. Possible permissions \
                          or disk space issue?",
-                        chunk.output()
+                        chunk.output().display()
                     ),
                     frame,
                 ));
             }
 
-            let encoded_frames = get_num_frames(chunk.output().as_ref());
+            let encoded_frames = get_num_frames(&chunk.output());
 
             let err_str = match encoded_frames {
                 Ok(encoded_frames)
-                    if !chunk.ignore_frame_mismatch && encoded_frames != chunk.frames() =>
+                    if !chunk.ignore_frame_mismatch && encoded_frames != chunk.encoded_frames() =>
                 {
                     Some(format!(
                         "FRAME MISMATCH: chunk {index}: {encoded_frames}/{expected} \
                          (actual/expected frames)",
-                        index = chunk.index,
-                        expected = chunk.frames()
+                        index = chunk.label(),
+                        expected = chunk.encoded_frames()
                     ))
                 },
                 Err(error) => Some(format!(
                     "FAILED TO COUNT FRAMES: chunk {index}: {error}",
-                    index = chunk.index
+                    index = chunk.label()
                 )),
                 _ => None,
             };
@@ -841,8 +1871,86 @@ impl Av1anContext {
                     frame,
                 ));
             }
+
+            // Encoders that do not report their progress, such as vvenc, count the
+            // whole chunk once it is done
+            let unreported = (chunk.encoded_frames() as u64).saturating_sub(frame);
+            if unreported > 0 {
+                if self.args.verbosity == Verbosity::Normal {
+                    inc_bar(unreported);
+                } else if self.args.verbosity == Verbosity::Verbose {
+                    inc_mp_bar(unreported);
+                }
+            }
+
+            // Some encoders print their summary to stdout instead of stderr
+            let output = enc_stderr + &String::from_utf8_lossy(&enc_output.stdout);
+            return Ok(EncoderStats::parse(chunk.encoder, &output));
+        }
+
+        Ok(EncoderStats::default())
+    }
+
+    /// Drops the duplicate frames of every chunk
+    fn collapse_duplicate_frames(&self, chunks: &mut [Chunk]) -> anyhow::Result<()> {
+        info!("detecting duplicate frames");
+        let duplicates = detect_duplicate_frames(self.args.input.as_video_path())?;
+        for chunk in chunks.iter_mut() {
+            chunk.drop_duplicate_frames(&duplicates);
+        }
+
+        let dropped = chunks
+            .iter()
+            .map(|chunk| chunk.frames() - chunk.encoded_frames())
+            .sum::<usize>();
+        info!(
+            "collapsing {dropped} duplicate frame(s) in {} run(s)",
+            duplicates.len()
+        );
+
+        Ok(())
+    }
+
+    /// Writes the timestamps of the encoded frames for the final merge, timed
+    /// like the frames of the input with `--keep-vfr`, so that the dropped
+    /// frames and the variable frame rate of the input keep the output in sync
+    /// with the audio
+    fn write_timestamps(&self, chunks: &[Chunk]) -> anyhow::Result<()> {
+        let source_timestamps = if self.args.keep_vfr {
+            let timestamps = frame_timestamps(self.args.input.as_video_path())?;
+            if timestamps.len() != self.frames {
+                warn!(
+                    "the input has {} frame timestamps but {} frames, so its timing cannot be kept",
+                    timestamps.len(),
+                    self.frames
+                );
+                None
+            } else if is_variable_frame_rate(&timestamps) {
+                info!("keeping the variable frame rate of the input");
+                Some(timestamps)
+            } else {
+                info!("the input has a constant frame rate");
+                None
+            }
+        } else {
+            None
+        };
+        if source_timestamps.is_none() && !self.args.collapse_duplicates {
+            return Ok(());
         }
 
+        let frame_rate = self
+            .args
+            .input
+            .clip_info()?
+            .frame_rate
+            .to_f64()
+            .expect("frame rate should not be NaN");
+        write_atomic(
+            &Path::new(&self.args.temp).join("timestamps.txt"),
+            kept_frame_timestamps(chunks, frame_rate, source_timestamps.as_deref()),
+        )?;
+
         Ok(())
     }
 
@@ -876,6 +1984,16 @@ impl Av1anContext {
             )?,
         };
 
+        if let Some(target) = self.args.device_target {
+            self.apply_device_target(target, &mut chunks)?;
+        }
+        if self.args.collapse_duplicates {
+            self.collapse_duplicate_frames(&mut chunks)?;
+        }
+        if self.args.collapse_duplicates || self.args.keep_vfr {
+            self.write_timestamps(&chunks)?;
+        }
+
         match self.args.chunk_order {
             ChunkOrdering::LongestFirst => {
                 chunks.sort_unstable_by_key(|chunk| Reverse(chunk.frames()));
@@ -894,6 +2012,39 @@ impl Av1anContext {
         Ok(chunks)
     }
 
+    /// Checks that the output plays on the decoders of `target` and adds its
+    /// constraints to the parameters of every chunk
+    fn apply_device_target(
+        &self,
+        target: DeviceTarget,
+        chunks: &mut [Chunk],
+    ) -> anyhow::Result<()> {
+        let frame_rate = self.args.input.clip_info()?.frame_rate.to_f64().unwrap_or(0.0);
+        match self.chunk_resolution()? {
+            Some(resolution) => target.validate_output(resolution, frame_rate)?,
+            None => warn!(
+                "the resolution after the FFmpeg filters is unknown and is not checked against \
+                 --device-target {target}"
+            ),
+        }
+
+        for chunk in chunks {
+            target
+                .constrain_params(chunk.encoder, &mut chunk.video_params)
+                .with_context(|| {
+                    format!(
+                        "Chunk {index} (frames {start}-{end}) does not meet --device-target \
+                         {target}",
+                        index = chunk.label(),
+                        start = chunk.start_frame,
+                        end = chunk.end_frame
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
+
     // If we are not resuming, then do scene detection. Otherwise: get scenes from
     // scenes.json and return that.
     fn split_routine(&mut self) -> anyhow::Result<&[Scene]> {
@@ -903,16 +2054,140 @@ impl Av1anContext {
         );
         if scene_file.exists() && (self.args.scenes.is_some() || self.args.resume) {
             self.scene_factory = SceneFactory::from_scenes_file(&scene_file)?;
+            if self.args.scenes.is_some() {
+                self.scene_factory.validate(self.frames)?;
+            }
+            // The zones are stored with the scenes, so check them against the
+            // current arguments
+            validate_zones(&self.args, self.scene_factory.get_split_scenes()?)?;
+            if self.args.photon_noise_auto && self.scene_factory.noise_missing() {
+                self.scene_factory.measure_noise(&self.args)?;
+                // Saved with the scenes so that it is measured only once
+                self.scene_factory.write_scenes_to_file(&scene_file)?;
+            }
         } else {
             let zones = parse_zones(&self.args, self.frames)?;
             validate_zones(&self.args, &zones)?;
-            self.scene_factory.compute_scenes(&self.args, &zones)?;
+            let cached = match &self.args.sc_cache {
+                Some(dir) => Some(dir.join(format!("{key}.json", key = self.scene_cache_key()?))),
+                None => None,
+            };
+            let reused = cached.as_ref().filter(|cached| cached.exists()).and_then(|cached| {
+                SceneFactory::from_scenes_file(cached)
+                    .inspect(|_| info!("scenecut: reusing scenes from {}", cached.display()))
+                    .inspect_err(|e| warn!("ignoring the cached scenes: {e:#}"))
+                    .ok()
+            });
+            if let Some(factory) = reused {
+                self.scene_factory = factory;
+            } else {
+                self.scene_factory.compute_scenes(&self.args, &zones)?;
+                if let Some(cached) = &cached {
+                    self.scene_factory.write_scenes_to_file(cached)?;
+                }
+            }
             self.scene_factory.write_scenes_to_file(scene_file)?;
         }
+        if let Some(export) = &self.args.export_scenes {
+            let frame_rate = self.args.input.clip_info()?.frame_rate;
+            let fps = frame_rate.to_f64().filter(|fps| fps.is_finite() && *fps > 0.0).ok_or_else(
+                || anyhow!("Cannot export scenes of an input with frame rate {frame_rate}"),
+            )?;
+            self.scene_factory.export_scenes(export, self.args.scenes_format, fps)?;
+        }
         self.frames = self.scene_factory.get_frame_count();
         self.scene_factory.get_split_scenes()
     }
 
+    /// Name of the scenes of this input in the `--sc-cache` folder. The name
+    /// changes when the input file or any setting that affects the scenes
+    /// changes, including the zones, which are stored with the scenes.
+    fn scene_cache_key(&self) -> anyhow::Result<String> {
+        let args = &self.args;
+        let input = args.input.as_path();
+        let metadata = fs::metadata(input)
+            .with_context(|| format!("Failed to read metadata of {}", input.display()))?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut settings = serde_json::json!({
+            "input": dunce::canonicalize(input)?,
+            "size": metadata.len(),
+            "modified": modified.as_nanos().to_string(),
+            "split_method": args.split_method,
+            "sc_method": args.sc_method,
+            "sc_pix_format": args.sc_pix_format,
+            "sc_luma_only": args.sc_luma_only,
+            "sc_downscale_height": args.sc_downscale_height,
+            "sc_pq_mode": args.sc_pq_mode,
+            "sc_threshold": args.sc_threshold,
+            "scenes_format": args.scenes_format,
+            "extra_splits_len": args.extra_splits_len,
+            "min_scene_len": args.min_scene_len,
+            "force_keyframes": args.force_keyframes,
+            "sc_ignore_ranges": args.sc_ignore_ranges,
+            "sc_annotate": args.sc_annotate,
+            "sc_fades": args.sc_fades,
+            "sc_fade_zone": args.sc_fade_zone,
+            "photon_noise_auto": args.photon_noise_auto,
+            "vspipe_args": args.input.as_vspipe_args_vec()?,
+        });
+        if let Some(scenes) = &args.sc_import {
+            settings["sc_import"] = fs::read_to_string(scenes)?.into();
+        }
+        if args.split_for_workers {
+            settings["workers"] = args.workers.into();
+        }
+        if let Some(zones) = &args.zones {
+            settings["zones"] = fs::read_to_string(zones)?.into();
+            // The zones stored with the scenes inherit these unless they override them
+            settings["encoder"] = serde_json::json!({
+                "encoder": args.encoder,
+                "passes": args.passes,
+                "video_params": args.video_params,
+                "photon_noise": args.photon_noise,
+                "photon_noise_size": args.photon_noise_size,
+                "chroma_noise": args.chroma_noise,
+                "output_pix_format": args.output_pix_format.format,
+                "target_quality": args.target_quality,
+            });
+        }
+
+        Ok(format!(
+            "{:016x}",
+            stable_hash(settings.to_string().as_bytes())
+        ))
+    }
+
+    /// Resolution of the frames piped to the encoder, or `None` if FFmpeg
+    /// filters may change it
+    pub(crate) fn chunk_resolution(&self) -> anyhow::Result<Option<(u32, u32)>> {
+        if self.args.ffmpeg_filter_args.is_empty() {
+            Ok(Some(self.args.input.clip_info()?.resolution))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Photon noise strength for a chunk starting at `start_frame` of the
+    /// input. Falls back to interpolating `--photon-noise-anchors`, or to the
+    /// strength `--photon-noise-auto` picked for the scene, if neither the
+    /// zone nor `--photon-noise` set a strength.
+    fn chunk_photon_noise(
+        &self,
+        overrides: Option<&ZoneOptions>,
+        start_frame: usize,
+        frame_rate: f64,
+    ) -> Option<u8> {
+        overrides
+            .map_or(self.args.photon_noise, |ovr| ovr.photon_noise)
+            .or_else(|| {
+                photon_noise_at(
+                    &self.args.photon_noise_anchors,
+                    start_frame as f64 / frame_rate,
+                )
+            })
+            .or_else(|| self.scene_factory.auto_photon_noise(start_frame))
+    }
+
     fn create_select_chunk(
         &self,
         index: usize,
@@ -920,7 +2195,7 @@ impl Av1anContext {
         start_frame: usize,
         end_frame: usize,
         frame_rate: f64,
-        overrides: Option<ZoneOptions>,
+        overrides: Option<&ZoneOptions>,
     ) -> anyhow::Result<Chunk> {
         assert!(
             start_frame < end_frame,
@@ -975,34 +2250,40 @@ impl Av1anContext {
             start_frame,
             end_frame,
             frame_rate,
-            video_params: overrides.as_ref().map_or_else(
+            video_params: overrides.map_or_else(
                 || self.args.video_params.clone(),
                 |ovr| ovr.video_params.clone(),
             ),
-            passes: overrides.as_ref().map_or(self.args.passes, |ovr| ovr.passes),
-            encoder: overrides.as_ref().map_or(self.args.encoder, |ovr| ovr.encoder),
+            passes: overrides.map_or(self.args.passes, |ovr| ovr.passes),
+            encoder: overrides.map_or(self.args.encoder, |ovr| ovr.encoder),
             noise_size: self.args.photon_noise_size,
-            target_quality: overrides.as_ref().map_or_else(
+            target_quality: overrides.map_or_else(
                 || self.args.target_quality.clone(),
                 |ovr| {
                     ovr.target_quality.clone().unwrap_or_else(|| self.args.target_quality.clone())
                 },
             ),
             tq_cq: None,
+            tq_score: None,
+            dropped_frames: Vec::new(),
             ignore_frame_mismatch: self.args.ignore_frame_mismatch,
         };
+        chunk.apply_geometry_args(self.chunk_resolution()?, self.args.output_pix_format.format);
         let color_range = self.args.input.clip_info()?.color_range;
         chunk.apply_photon_noise_args(
-            overrides.map_or(self.args.photon_noise, |ovr| ovr.photon_noise),
-            self.args.chroma_noise,
+            self.chunk_photon_noise(overrides, start_frame, frame_rate),
+            self.args.chroma_noise(self.args.chroma_noise),
             color_range,
+            self.args.photon_noise_seed,
         )?;
         if chunk.target_quality.target.is_some() {
-            chunk.tq_cq = Some(chunk.target_quality.per_shot_target_quality(
+            let (cq, score) = chunk.target_quality.per_shot_target_quality(
                 &chunk,
                 None,
                 self.args.vapoursynth_plugins,
-            )?);
+            )?;
+            chunk.tq_cq = Some(cq);
+            chunk.tq_score = Some(score);
         }
         Ok(chunk)
     }
@@ -1102,19 +2383,22 @@ impl Av1anContext {
                 },
             ),
             tq_cq: None,
+            tq_score: None,
+            dropped_frames: Vec::new(),
             ignore_frame_mismatch: self.args.ignore_frame_mismatch,
         };
+        chunk.apply_geometry_args(self.chunk_resolution()?, self.args.output_pix_format.format);
         let color_range = self.args.input.clip_info()?.color_range;
         chunk.apply_photon_noise_args(
-            scene
-                .zone_overrides
-                .as_ref()
-                .map_or(self.args.photon_noise, |ovr| ovr.photon_noise),
-            scene
-                .zone_overrides
-                .as_ref()
-                .map_or(self.args.chroma_noise, |ovr| ovr.chroma_noise),
+            self.chunk_photon_noise(scene.zone_overrides.as_ref(), scene.start_frame, frame_rate),
+            self.args.chroma_noise(
+                scene
+                    .zone_overrides
+                    .as_ref()
+                    .map_or(self.args.chroma_noise, |ovr| ovr.chroma_noise),
+            ),
             color_range,
+            self.args.photon_noise_seed,
         )?;
         Ok(chunk)
     }
@@ -1171,7 +2455,7 @@ impl Av1anContext {
                     scene.start_frame,
                     scene.end_frame,
                     frame_rate,
-                    scene.zone_overrides.clone(),
+                    scene.zone_overrides.as_ref(),
                 )
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
@@ -1212,8 +2496,9 @@ impl Av1anContext {
                 self.create_chunk_from_segment(
                     index,
                     &file.as_path().to_string_lossy(),
+                    scenes[index].start_frame,
                     frame_rate,
-                    scenes[index].zone_overrides.clone(),
+                    scenes[index].zone_overrides.as_ref(),
                 )
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
@@ -1235,18 +2520,23 @@ impl Av1anContext {
 
         let to_split: Vec<usize> = keyframes
             .iter()
-            .filter(|kf| scenes.iter().any(|scene| scene.start_frame == **kf))
+            .filter(|kf| **kf > 0 && scenes.iter().any(|scene| scene.start_frame == **kf))
             .copied()
             .collect();
 
         debug!("Segmenting video");
-        segment(input, &self.args.temp, &to_split[1..])?;
+        segment(input, &self.args.temp, &to_split)?;
         debug!("Segment done");
 
         let source_path = Path::new(&self.args.temp).join("split");
         let queue_files = Self::read_queue_files(&source_path)?;
 
-        let kf_list = to_split.iter().copied().chain(iter::once(self.frames)).tuple_windows();
+        // The first segment always starts at frame 0, even if the scenes do not
+        // (e.g. with `--sample`)
+        let kf_list = iter::once(0)
+            .chain(to_split.iter().copied())
+            .chain(iter::once(self.frames))
+            .tuple_windows();
 
         let mut segments = Vec::with_capacity(scenes.len());
         for (file, (x, y)) in queue_files.iter().zip(kf_list) {
@@ -1269,7 +2559,7 @@ impl Av1anContext {
                     start,
                     end,
                     frame_rate,
-                    scene.zone_overrides.clone(),
+                    scene.zone_overrides.as_ref(),
                 )
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
@@ -1282,8 +2572,9 @@ impl Av1anContext {
         &self,
         index: usize,
         file: &str,
+        scene_start: usize,
         frame_rate: f64,
-        overrides: Option<ZoneOptions>,
+        overrides: Option<&ZoneOptions>,
     ) -> anyhow::Result<Chunk> {
         let ffmpeg_gen_cmd: Vec<OsString> = into_vec![
             "ffmpeg",
@@ -1329,36 +2620,95 @@ impl Av1anContext {
             start_frame: 0,
             end_frame: num_frames,
             frame_rate,
-            video_params: overrides.as_ref().map_or_else(
+            video_params: overrides.map_or_else(
                 || self.args.video_params.clone(),
                 |ovr| ovr.video_params.clone(),
             ),
-            passes: overrides.as_ref().map_or(self.args.passes, |ovr| ovr.passes),
-            encoder: overrides.as_ref().map_or(self.args.encoder, |ovr| ovr.encoder),
+            passes: overrides.map_or(self.args.passes, |ovr| ovr.passes),
+            encoder: overrides.map_or(self.args.encoder, |ovr| ovr.encoder),
             noise_size: self.args.photon_noise_size,
-            target_quality: overrides.as_ref().map_or_else(
+            target_quality: overrides.map_or_else(
                 || self.args.target_quality.clone(),
                 |ovr| {
                     ovr.target_quality.clone().unwrap_or_else(|| self.args.target_quality.clone())
                 },
             ),
             tq_cq: None,
+            tq_score: None,
+            dropped_frames: Vec::new(),
             ignore_frame_mismatch: self.args.ignore_frame_mismatch,
         };
+        chunk.apply_geometry_args(self.chunk_resolution()?, self.args.output_pix_format.format);
         let color_range = self.args.input.clip_info()?.color_range;
         chunk.apply_photon_noise_args(
-            overrides.map_or(self.args.photon_noise, |ovr| ovr.photon_noise),
-            self.args.chroma_noise,
+            self.chunk_photon_noise(overrides, scene_start, frame_rate),
+            self.args.chroma_noise(self.args.chroma_noise),
             color_range,
+            self.args.photon_noise_seed,
         )?;
         Ok(chunk)
     }
 
+    /// Rewrites the paths of a chunk queue that was saved with the temp folder
+    /// or inputs in other locations, e.g. on another machine, to their current
+    /// locations
+    fn remap_saved_paths(&self, chunks: &mut [Chunk]) -> anyhow::Result<()> {
+        let roots = SavedRoots::new(&self.args)?;
+        let mut saved_roots = get_done().roots.lock().expect("mutex should acquire lock");
+        // The temp folders of older versions do not record the locations
+        let Some(saved) = saved_roots.replace(roots.clone()) else {
+            return Ok(());
+        };
+        if saved == roots {
+            return Ok(());
+        }
+
+        let mut remaps: Vec<(String, String)> = Vec::new();
+        let mut add_remap = |from: &Path, to: &Path| {
+            if let (Some(from), Some(to)) = (from.to_str(), to.to_str())
+                && from != to
+                && !remaps.iter().any(|(remapped, _)| remapped == from)
+            {
+                remaps.push((from.to_string(), to.to_string()));
+            }
+        };
+        add_remap(&saved.temp, &roots.temp);
+        add_remap(&saved.input, &roots.input);
+        if let (Some(from), Some(to)) = (&saved.proxy, &roots.proxy) {
+            add_remap(from, to);
+        }
+        // The paths are saved as they were passed, which may be relative
+        if let Some(chunk) = chunks.first() {
+            add_remap(Path::new(&chunk.temp), Path::new(&self.args.temp));
+            add_remap(chunk.input.as_path(), self.args.input.as_path());
+            if let (Some(from), Some(to)) = (&chunk.proxy, &self.args.proxy) {
+                add_remap(from.as_path(), to.as_path());
+            }
+        }
+
+        for (from, to) in &remaps {
+            info!("resuming with the saved paths of {from} remapped to {to}");
+        }
+        for chunk in chunks.iter_mut() {
+            chunk.remap_paths(&remaps);
+        }
+        save_chunk_queue(&self.args.temp, chunks)?;
+
+        drop(saved_roots);
+        write_atomic(
+            &Path::new(&self.args.temp).join("done.json"),
+            serde_json::to_string(get_done())?,
+        )?;
+
+        Ok(())
+    }
+
     /// Returns unfinished chunks and number of total chunks
     fn load_or_gen_chunk_queue(&self, splits: &[Scene]) -> anyhow::Result<(Vec<Chunk>, usize)> {
         if self.args.resume {
             let mut chunks = read_chunk_queue(self.args.temp.as_ref())?;
             let num_chunks = chunks.len();
+            self.remap_saved_paths(&mut chunks)?;
 
             let done = get_done();
 
@@ -0,0 +1,233 @@
+use anyhow::{bail, ensure};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString, IntoStaticStr};
+
+use crate::{encoder::param_value, settings::merge_video_params, Encoder};
+
+/// Class of hardware decoders that the output has to play on
+#[derive(
+    PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug, Display, EnumString, IntoStaticStr,
+)]
+pub enum DeviceTarget {
+    /// Televisions and streaming devices with AV1 decoders from 2020 on, up
+    /// to 4K at 60 fps
+    #[strum(serialize = "tv-2020")]
+    Tv2020,
+    /// Phones and tablets, up to 1080p at 60 fps
+    #[strum(serialize = "mobile")]
+    Mobile,
+}
+
+/// Limits of the decoders of a [`DeviceTarget`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DeviceConstraints {
+    /// Highest AV1 level as major and minor version
+    level:                 (u8, u8),
+    /// Largest frame, in either orientation
+    max_size:              (u32, u32),
+    max_frame_rate:        f64,
+    /// Lowest `--fast-decode` of SVT-AV1
+    fast_decode:           u8,
+    /// Lowest number of tile columns as a log2, so that the decoder can work
+    /// on several tiles at once
+    min_tile_columns_log2: u8,
+}
+
+impl DeviceTarget {
+    const fn constraints(self) -> DeviceConstraints {
+        match self {
+            Self::Tv2020 => DeviceConstraints {
+                level:                 (5, 1),
+                max_size:              (3840, 2160),
+                max_frame_rate:        60.0,
+                fast_decode:           1,
+                min_tile_columns_log2: 1,
+            },
+            Self::Mobile => DeviceConstraints {
+                level:                 (4, 1),
+                max_size:              (1920, 1080),
+                max_frame_rate:        60.0,
+                fast_decode:           1,
+                min_tile_columns_log2: 0,
+            },
+        }
+    }
+
+    /// Checks that frames of `resolution` at `frame_rate` are within the limits
+    /// of the decoders
+    pub(crate) fn validate_output(
+        self,
+        (width, height): (u32, u32),
+        frame_rate: f64,
+    ) -> anyhow::Result<()> {
+        let constraints = self.constraints();
+        let (max_width, max_height) = constraints.max_size;
+        ensure!(
+            width.max(height) <= max_width && width.min(height) <= max_height,
+            "{width}x{height} is larger than the {max_width}x{max_height} supported by \
+             --device-target {self}"
+        );
+        ensure!(
+            frame_rate <= constraints.max_frame_rate + 0.01,
+            "{frame_rate:.3} fps is above the {max} fps supported by --device-target {self}",
+            max = constraints.max_frame_rate
+        );
+
+        Ok(())
+    }
+
+    /// Adds the level, fast decode and tile parameters of the target to
+    /// `video_params` unless they are already set, and fails if they are set
+    /// to values the decoders cannot handle
+    pub(crate) fn constrain_params(
+        self,
+        encoder: Encoder,
+        video_params: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        let constraints = self.constraints();
+        let (major, minor) = constraints.level;
+        let (level_key, level_value) = match encoder {
+            Encoder::svt_av1 => ("--level", format!("{major}.{minor}")),
+            Encoder::aom => (
+                "--target-seq-level-idx",
+                ((major - 2) * 4 + minor).to_string(),
+            ),
+            _ => bail!("--device-target is only supported by svt-av1 and aom"),
+        };
+
+        let mut missing = Vec::new();
+        match param_value(video_params, level_key).map(|value| (value, parse_level(encoder, value)))
+        {
+            Some((value, None)) => bail!("Invalid {level_key} {value}"),
+            Some((value, Some(Some(level)))) => ensure!(
+                level <= constraints.level,
+                "{level_key} {value} is above level {major}.{minor} of --device-target {self}"
+            ),
+            // Unset or chosen by the encoder
+            None | Some((_, Some(None))) => missing.push((level_key, level_value)),
+        }
+
+        if encoder == Encoder::svt_av1 {
+            check_minimum(
+                video_params,
+                "--fast-decode",
+                constraints.fast_decode,
+                self,
+                &mut missing,
+            )?;
+        }
+        if constraints.min_tile_columns_log2 > 0 {
+            check_minimum(
+                video_params,
+                "--tile-columns",
+                constraints.min_tile_columns_log2,
+                self,
+                &mut missing,
+            )?;
+        }
+
+        merge_video_params(
+            encoder,
+            video_params,
+            missing.into_iter().flat_map(|(key, value)| {
+                if encoder == Encoder::aom {
+                    vec![format!("{key}={value}")]
+                } else {
+                    vec![key.to_string(), value]
+                }
+            }),
+        );
+
+        Ok(())
+    }
+}
+
+/// Fails if `key` is set below `minimum`, or adds it to `missing` if it is not
+/// set
+fn check_minimum(
+    video_params: &[String],
+    key: &'static str,
+    minimum: u8,
+    target: DeviceTarget,
+    missing: &mut Vec<(&'static str, String)>,
+) -> anyhow::Result<()> {
+    match param_value(video_params, key) {
+        Some(value) => {
+            let parsed =
+                value.parse::<u8>().map_err(|_| anyhow::anyhow!("Invalid {key} {value}"))?;
+            ensure!(
+                parsed >= minimum,
+                "{key} {value} is below the {minimum} required by --device-target {target}"
+            );
+        },
+        None => missing.push((key, minimum.to_string())),
+    }
+
+    Ok(())
+}
+
+/// Parses a level given to `encoder`, which is `None` if the value is invalid
+/// and `Some(None)` if the encoder picks the level
+fn parse_level(encoder: Encoder, value: &str) -> Option<Option<(u8, u8)>> {
+    if encoder == Encoder::aom {
+        // Indices from 24 on, such as the default of 31, do not constrain the level
+        let index = value.parse::<u8>().ok()?;
+        return Some((index < 24).then(|| (2 + index / 4, index % 4)));
+    }
+
+    if value == "0" {
+        return Some(None);
+    }
+    let (major, minor) = value.split_once('.').unwrap_or((value, "0"));
+    Some(Some((major.parse().ok()?, minor.parse().ok()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeviceTarget;
+    use crate::Encoder;
+
+    fn params(params: &[&str]) -> Vec<String> {
+        params.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn constraints_are_added_to_params() {
+        let mut svt = params(&["--preset", "4", "--level", "0"]);
+        DeviceTarget::Tv2020
+            .constrain_params(Encoder::svt_av1, &mut svt)
+            .expect("params should be constrained");
+        assert_eq!(svt, [
+            "--preset",
+            "4",
+            "--level",
+            "5.1",
+            "--fast-decode",
+            "1",
+            "--tile-columns",
+            "1"
+        ]);
+
+        let mut aom = params(&["--cpu-used=4", "--target-seq-level-idx=8"]);
+        DeviceTarget::Mobile
+            .constrain_params(Encoder::aom, &mut aom)
+            .expect("params should be constrained");
+        assert_eq!(aom, ["--cpu-used=4", "--target-seq-level-idx=8"]);
+    }
+
+    #[test]
+    fn conflicting_params_are_rejected() {
+        let target = DeviceTarget::Mobile;
+        assert!(target
+            .constrain_params(Encoder::svt_av1, &mut params(&["--level", "5.0"]))
+            .is_err());
+        assert!(target
+            .constrain_params(Encoder::svt_av1, &mut params(&["--fast-decode", "0"]))
+            .is_err());
+        assert!(target.constrain_params(Encoder::x265, &mut Vec::new()).is_err());
+
+        assert!(target.validate_output((1080, 1920), 59.94).is_ok());
+        assert!(target.validate_output((2560, 1440), 30.0).is_err());
+        assert!(target.validate_output((1920, 1080), 120.0).is_err());
+    }
+}
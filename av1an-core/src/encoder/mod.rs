@@ -4,9 +4,10 @@ mod tests;
 use std::{
     borrow::Cow,
     cmp,
+    ffi::OsString,
     fmt::Display,
     iter::Iterator,
-    path::PathBuf,
+    path::Path,
     process::Command,
     sync::OnceLock,
 };
@@ -50,7 +51,7 @@ pub static USE_OLD_SVT_AV1: Lazy<bool> = Lazy::new(|| {
 });
 
 use crate::{
-    ffmpeg::{compose_ffmpeg_pipe, FFPixelFormat},
+    ffmpeg::{compose_ffmpeg_pipe, ffmpeg_has_encoder, FFPixelFormat},
     inplace_vec,
     into_array,
     into_vec,
@@ -59,6 +60,68 @@ use crate::{
 
 const NULL: &str = if cfg!(windows) { "nul" } else { "/dev/null" };
 
+/// Builds `{prefix}{fpf}{suffix}` as a single argument without converting the
+/// first pass file path to a `String`
+fn fpf_arg(prefix: &str, fpf: &Path, suffix: &str) -> OsString {
+    let mut arg = OsString::from(prefix);
+    arg.push(fpf);
+    arg.push(suffix);
+    arg
+}
+
+/// Slices aomenc first pass stats of `frames` frames down to the frames in
+/// `start_frame..end_frame`, followed by the totals of those frames.
+///
+/// The stats file holds one `FIRSTPASS_STATS` record per frame and a final
+/// record with the totals of all frames. Every field of a record is a double,
+/// which libaom accumulates field by field into the totals. Since the number of
+/// fields depends on the libaom version, the record size is derived from the
+/// size of the file and checked by summing the frame records, which has to
+/// reproduce the totals record of the file.
+pub(crate) fn slice_aom_first_pass_stats(
+    stats: &[u8],
+    frames: usize,
+    start_frame: usize,
+    end_frame: usize,
+) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(
+        start_frame < end_frame && end_frame <= frames,
+        "frames {start_frame}..{end_frame} are not within the {frames} frames of the first pass \
+         stats"
+    );
+    let record_size = stats.len() / (frames + 1);
+    anyhow::ensure!(
+        record_size > 0 && record_size.is_multiple_of(8) && stats.len().is_multiple_of(frames + 1),
+        "first pass stats of {len} bytes do not hold {frames} frames",
+        len = stats.len()
+    );
+
+    let (all_records, file_totals) = stats.split_at(frames * record_size);
+    anyhow::ensure!(
+        sum_first_pass_records(all_records, record_size) == file_totals,
+        "first pass stats of {len} bytes do not hold {frames} frames of doubles",
+        len = stats.len()
+    );
+
+    let records = all_records
+        .get(start_frame * record_size..end_frame * record_size)
+        .expect("range is within the frame records");
+    let mut sliced = records.to_vec();
+    sliced.extend(sum_first_pass_records(records, record_size));
+    Ok(sliced)
+}
+
+/// Sums first pass stats records field by field, returning the totals record
+fn sum_first_pass_records(records: &[u8], record_size: usize) -> Vec<u8> {
+    let mut totals = vec![0.0f64; record_size / 8];
+    for record in records.chunks_exact(record_size) {
+        for (total, field) in totals.iter_mut().zip(record.chunks_exact(8)) {
+            *total += f64::from_ne_bytes(field.try_into().expect("field is 8 bytes"));
+        }
+    }
+    totals.iter().flat_map(|total| total.to_ne_bytes()).collect()
+}
+
 // Encoder Maximum Speed Values
 const MAXIMUM_SPEED_AOM: u8 = 6;
 const MAXIMUM_SPEED_RAV1E: u8 = 10;
@@ -67,6 +130,9 @@ const MAXIMUM_SPEED_OLD_SVT_AV1: u8 = 8;
 const MAXIMUM_SPEED_SVT_AV1: u8 = 12;
 const MAXIMUM_SPEED_X264: &str = "medium";
 const MAXIMUM_SPEED_X265: &str = "fast";
+const MAXIMUM_SPEED_VVENC: &str = "faster";
+
+const HARDWARE_ENCODER_SESSIONS: u64 = 3;
 
 #[expect(non_camel_case_types)]
 #[derive(
@@ -88,6 +154,13 @@ pub enum Encoder {
     svt_av1,
     x264,
     x265,
+    vvenc,
+    /// AV1 with NVIDIA NVENC through FFmpeg
+    nvenc,
+    /// AV1 with Intel Quick Sync Video through FFmpeg
+    qsv,
+    /// AV1 with VA-API through FFmpeg
+    vaapi,
 }
 
 #[tracing::instrument(level = "debug")]
@@ -152,6 +225,18 @@ pub(crate) fn svt_av1_supports_quarter_steps(temp: &str) -> bool {
     })
 }
 
+/// Returns the value of the last `name` parameter in `params`, given either as
+/// `name=value` or as `name value`
+pub(crate) fn param_value<'a>(params: &'a [String], name: &str) -> Option<&'a str> {
+    params.iter().enumerate().rev().find_map(|(index, param)| {
+        if param == name {
+            params.get(index + 1).map(String::as_str)
+        } else {
+            param.strip_prefix(name).and_then(|rest| rest.strip_prefix('='))
+        }
+    })
+}
+
 pub(crate) fn format_q(q: f32) -> String {
     if q.fract().abs() < 1e-6 {
         format!("{:.0}", q)
@@ -160,6 +245,33 @@ pub(crate) fn format_q(q: f32) -> String {
     }
 }
 
+/// FFmpeg pipe that converts the source of a probe to `pix_fmt`, keeping
+/// every `probing_rate`-th frame
+fn probe_pipe(pix_fmt: FFPixelFormat, probing_rate: usize) -> Vec<String> {
+    let filters = if probing_rate > 1 {
+        vec![
+            "-vf".to_string(),
+            format!("select=not(mod(n\\,{probing_rate}))"),
+            "-vsync".to_string(),
+            "0".to_string(),
+        ]
+    } else {
+        Vec::new()
+    };
+
+    compose_ffmpeg_pipe(filters, pix_fmt)
+}
+
+/// Name of the file in the `split` folder that the target quality probe of
+/// chunk `index` at quantizer `q` is written to
+pub(crate) fn probe_file_name(encoder: Encoder, index: usize, q: f32) -> String {
+    format!(
+        "v_{index:05}_{q}.{extension}",
+        q = format_q(q),
+        extension = encoder.output_extension()
+    )
+}
+
 impl Display for Encoder {
     #[inline]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -170,7 +282,8 @@ impl Display for Encoder {
 impl Encoder {
     /// Composes 1st pass command for 1 pass encoding
     #[inline]
-    pub fn compose_1_1_pass(self, params: Vec<String>, output: String) -> Vec<String> {
+    pub fn compose_1_1_pass(self, params: Vec<String>, output: &Path) -> Vec<OsString> {
+        let params = params.into_iter().map(OsString::from);
         match self {
             Self::aom => chain!(into_array!["aomenc", "--passes=1"], params, into_array![
                 "-o", output, "-"
@@ -200,29 +313,53 @@ impl Encoder {
                 "--input", "-", "-o", output
             ])
             .collect(),
+            Self::vvenc => chain!(
+                into_array!["vvencapp", "--y4m", "-i", "-", "--verbosity", "2"],
+                params,
+                into_array!["-o", output]
+            )
+            .collect(),
+            Self::nvenc | Self::qsv | Self::vaapi => chain!(
+                into_array![
+                    "ffmpeg",
+                    "-y",
+                    "-hide_banner",
+                    "-loglevel",
+                    "error",
+                    "-stats",
+                    "-i",
+                    "-",
+                    "-c:v",
+                    self.hardware_codec().expect("hardware encoder should have a codec"),
+                ],
+                params,
+                into_array!["-f", "ivf", output]
+            )
+            .collect(),
         }
     }
 
     /// Composes 1st pass command for 2 pass encoding
     #[inline]
-    pub fn compose_1_2_pass(self, params: Vec<String>, fpf: &str) -> Vec<String> {
+    pub fn compose_1_2_pass(self, params: Vec<String>, fpf: &Path) -> Vec<OsString> {
+        let params = params.into_iter().map(OsString::from);
         match self {
             Self::aom => chain!(
                 into_array!["aomenc", "--passes=2", "--pass=1"],
                 params,
-                into_array![format!("--fpf={fpf}.log"), "-o", NULL, "-"],
+                into_array![fpf_arg("--fpf=", fpf, ".log"), "-o", NULL, "-"],
             )
             .collect(),
             Self::rav1e => chain!(
                 into_array!["rav1e", "-", "-y", "--quiet",],
                 params,
-                into_array!["--first-pass", format!("{fpf}.stat"), "--output", NULL]
+                into_array!["--first-pass", fpf_arg("", fpf, ".stat"), "--output", NULL]
             )
             .collect(),
             Self::vpx => chain!(
                 into_array!["vpxenc", "--passes=2", "--pass=1"],
                 params,
-                into_array![format!("--fpf={fpf}.log"), "-o", NULL, "-"],
+                into_array![fpf_arg("--fpf=", fpf, ".log"), "-o", NULL, "-"],
             )
             .collect(),
             Self::svt_av1 => chain!(
@@ -236,7 +373,7 @@ impl Encoder {
                     "2",
                 ],
                 params,
-                into_array!["--pass", "1", "--stats", format!("{fpf}.stat"), "-b", NULL,],
+                into_array!["--pass", "1", "--stats", fpf_arg("", fpf, ".stat"), "-b", NULL,],
             )
             .collect(),
             Self::x264 => chain!(
@@ -251,7 +388,7 @@ impl Encoder {
                     "y4m",
                 ],
                 params,
-                into_array!["--stats", format!("{fpf}.log"), "-", "-o", NULL]
+                into_array!["--stats", fpf_arg("", fpf, ".log"), "-", "-o", NULL]
             )
             .collect(),
             Self::x265 => chain!(
@@ -267,9 +404,9 @@ impl Encoder {
                 params,
                 into_array![
                     "--stats",
-                    format!("{fpf}.log"),
+                    fpf_arg("", fpf, ".log"),
                     "--analysis-reuse-file",
-                    format!("{fpf}_analysis.dat"),
+                    fpf_arg("", fpf, "_analysis.dat"),
                     "--input",
                     "-",
                     "-o",
@@ -277,29 +414,50 @@ impl Encoder {
                 ]
             )
             .collect(),
+            Self::vvenc => chain!(
+                into_array![
+                    "vvencapp",
+                    "--y4m",
+                    "-i",
+                    "-",
+                    "--verbosity",
+                    "2",
+                    "--passes",
+                    "2",
+                    "--pass",
+                    "1",
+                ],
+                params,
+                into_array!["--rcstatsfile", fpf_arg("", fpf, ".json"), "-o", NULL]
+            )
+            .collect(),
+            Self::nvenc | Self::qsv | Self::vaapi => {
+                unreachable!("hardware encoders only encode in one pass")
+            },
         }
     }
 
     /// Composes 2st pass command for 2 pass encoding
     #[inline]
-    pub fn compose_2_2_pass(self, params: Vec<String>, fpf: &str, output: String) -> Vec<String> {
+    pub fn compose_2_2_pass(self, params: Vec<String>, fpf: &Path, output: &Path) -> Vec<OsString> {
+        let params = params.into_iter().map(OsString::from);
         match self {
             Self::aom => chain!(
                 into_array!["aomenc", "--passes=2", "--pass=2"],
                 params,
-                into_array![format!("--fpf={fpf}.log"), "-o", output, "-"],
+                into_array![fpf_arg("--fpf=", fpf, ".log"), "-o", output, "-"],
             )
             .collect(),
             Self::rav1e => chain!(
                 into_array!["rav1e", "-", "-y", "--quiet",],
                 params,
-                into_array!["--second-pass", format!("{fpf}.stat"), "--output", output]
+                into_array!["--second-pass", fpf_arg("", fpf, ".stat"), "--output", output]
             )
             .collect(),
             Self::vpx => chain!(
                 into_array!["vpxenc", "--passes=2", "--pass=2"],
                 params,
-                into_array![format!("--fpf={fpf}.log"), "-o", output, "-"],
+                into_array![fpf_arg("--fpf=", fpf, ".log"), "-o", output, "-"],
             )
             .collect(),
             Self::svt_av1 => chain!(
@@ -313,7 +471,7 @@ impl Encoder {
                     "2",
                 ],
                 params,
-                into_array!["--pass", "2", "--stats", format!("{fpf}.stat"), "-b", output,],
+                into_array!["--pass", "2", "--stats", fpf_arg("", fpf, ".stat"), "-b", output,],
             )
             .collect(),
             Self::x264 => chain!(
@@ -328,7 +486,7 @@ impl Encoder {
                     "y4m",
                 ],
                 params,
-                into_array!["--stats", format!("{fpf}.log"), "-", "-o", output]
+                into_array!["--stats", fpf_arg("", fpf, ".log"), "-", "-o", output]
             )
             .collect(),
             Self::x265 => chain!(
@@ -344,9 +502,9 @@ impl Encoder {
                 params,
                 into_array![
                     "--stats",
-                    format!("{fpf}.log"),
+                    fpf_arg("", fpf, ".log"),
                     "--analysis-reuse-file",
-                    format!("{fpf}_analysis.dat"),
+                    fpf_arg("", fpf, "_analysis.dat"),
                     "--input",
                     "-",
                     "-o",
@@ -354,6 +512,68 @@ impl Encoder {
                 ]
             )
             .collect(),
+            Self::vvenc => chain!(
+                into_array![
+                    "vvencapp",
+                    "--y4m",
+                    "-i",
+                    "-",
+                    "--verbosity",
+                    "2",
+                    "--passes",
+                    "2",
+                    "--pass",
+                    "2",
+                ],
+                params,
+                into_array!["--rcstatsfile", fpf_arg("", fpf, ".json"), "-o", output]
+            )
+            .collect(),
+            Self::nvenc | Self::qsv | Self::vaapi => {
+                unreachable!("hardware encoders only encode in one pass")
+            },
+        }
+    }
+
+    /// Composes the command of pass `pass` for 3 pass encoding, which only
+    /// svt-av1 supports. The first two passes only write the stats to `fpf`.
+    #[inline]
+    pub fn compose_3_pass(
+        self,
+        params: Vec<String>,
+        fpf: &Path,
+        output: &Path,
+        pass: u8,
+    ) -> Vec<OsString> {
+        let params = params.into_iter().map(OsString::from);
+        let output = if pass == 3 {
+            output.as_os_str().to_owned()
+        } else {
+            NULL.into()
+        };
+        match self {
+            Self::svt_av1 => chain!(
+                into_array![
+                    "SvtAv1EncApp",
+                    "-i",
+                    "stdin",
+                    "--progress",
+                    "2",
+                    "--irefresh-type",
+                    "2",
+                ],
+                params,
+                into_array![
+                    "--pass",
+                    pass.to_string(),
+                    "--stats",
+                    fpf_arg("", fpf, ".stat"),
+                    "-b",
+                    output,
+                ],
+            )
+            .collect(),
+            _ => unreachable!("only svt-av1 encodes in three passes"),
         }
     }
 
@@ -361,10 +581,10 @@ impl Encoder {
     #[inline]
     pub fn get_default_arguments(self, (cols, rows): (u32, u32)) -> Vec<String> {
         match self {
-            // aomenc automatically infers the correct bit depth, and thus for aomenc, not
-            // specifying the bit depth is actually more accurate because if for example
-            // you specify `--pix-format yuv420p`, aomenc will encode 10-bit when that
-            // is not actually the desired pixel format.
+            // The bit depth is not part of the defaults for aomenc and vpxenc, as it is
+            // added to each chunk from the output pixel format instead (see
+            // `get_geometry_arguments`). Hardcoding e.g. `-b 10` here would encode 10-bit
+            // even when `--pix-format yuv420p` was requested.
             Encoder::aom => {
                 let defaults: Vec<String> = into_vec![
                     "--threads=8",
@@ -407,14 +627,9 @@ impl Encoder {
                     defaults
                 }
             },
-            // vpxenc does not infer the pixel format from the input, so `-b 10` is still required
-            // to work with the default pixel format (yuv420p10le).
             Encoder::vpx => {
                 let defaults = into_vec![
                     "--codec=vp9",
-                    "-b",
-                    "10",
-                    "--profile=2",
                     "--threads=4",
                     "--cpu-used=2",
                     "--end-usage=q",
@@ -481,7 +696,130 @@ impl Encoder {
                 "--scenecut",
                 "0",
             ],
+            Encoder::vvenc => into_vec!["--preset", "medium", "--qp", "32", "--threads", "4"],
+            Encoder::nvenc => {
+                into_vec!["-preset", "p5", "-tune", "hq", "-rc", "vbr", "-cq", "30", "-b:v", "0"]
+            },
+            Encoder::qsv => into_vec!["-preset", "medium", "-global_quality", "30"],
+            Encoder::vaapi => into_vec!["-rc_mode", "CQP", "-qp", "120"],
+        }
+    }
+
+    /// Returns the frame geometry and bit depth arguments for encoders that do
+    /// not reliably infer them from the y4m input. Arguments already present in
+    /// `params` are not returned, so user supplied values always take
+    /// precedence. `resolution` should be `None` if the geometry of the piped
+    /// frames is not known, e.g. because FFmpeg filters are applied.
+    #[inline]
+    pub fn get_geometry_arguments(
+        self,
+        params: &[String],
+        resolution: Option<(u32, u32)>,
+        format: FFPixelFormat,
+    ) -> Vec<String> {
+        if self == Self::vaapi {
+            return Self::vaapi_upload_arguments(params, format);
+        }
+        if !matches!(self, Self::aom | Self::vpx) {
+            return Vec::new();
+        }
+
+        // aomenc and vpxenc accept both `--key=value` and short `-k value` forms
+        let has_param = |keys: &[&str]| {
+            params
+                .iter()
+                .any(|param| keys.contains(&param.split('=').next().unwrap_or(param)))
+        };
+
+        let mut args = Vec::new();
+        if let Some((width, height)) = resolution {
+            if !has_param(&["--width", "-w"]) {
+                args.push(format!("--width={width}"));
+            }
+            if !has_param(&["--height", "-h"]) {
+                args.push(format!("--height={height}"));
+            }
+        }
+
+        if let Ok(bit_depth) = self.get_format_bit_depth(format) {
+            if !has_param(&["--bit-depth", "-b"]) {
+                args.push(format!("--bit-depth={bit_depth}"));
+            }
+            if !has_param(&["--input-bit-depth"]) {
+                args.push(format!("--input-bit-depth={bit_depth}"));
+            }
+            // vpxenc rejects high bit depth and non-4:2:0 input in the default profile 0
+            if self == Self::vpx && !has_param(&["--profile"]) {
+                let subsampled = matches!(
+                    format,
+                    FFPixelFormat::YUV420P
+                        | FFPixelFormat::YUVA420P
+                        | FFPixelFormat::YUV420P10LE
+                        | FFPixelFormat::YUV420P12LE
+                );
+                let profile = match (bit_depth, subsampled) {
+                    (8, true) => 0,
+                    (8, false) => 1,
+                    (_, true) => 2,
+                    (_, false) => 3,
+                };
+                args.push(format!("--profile={profile}"));
+            }
+        }
+
+        args
+    }
+
+    /// Returns the VA-API device and the filter uploading the frames to it in
+    /// the surface format of `format`, as VA-API encoders only take frames in
+    /// GPU memory. Arguments already present in `params` are not returned, so
+    /// the device can be picked with `-vaapi_device`.
+    fn vaapi_upload_arguments(params: &[String], format: FFPixelFormat) -> Vec<String> {
+        let mut args = Vec::new();
+        if !params.iter().any(|param| param == "-vaapi_device") {
+            args.extend(into_vec!["-vaapi_device", "/dev/dri/renderD128"]);
+        }
+        if !params.iter().any(|param| param == "-vf" || param == "-filter:v") {
+            let surface = if Self::vaapi.get_format_bit_depth(format).is_ok_and(|depth| depth > 8) {
+                "p010"
+            } else {
+                "nv12"
+            };
+            args.extend(into_vec!["-vf", format!("format={surface}|vaapi,hwupload")]);
         }
+        args
+    }
+
+    /// Returns the maximum keyframe interval set in `params`, or `None` if the
+    /// encoder does not place keyframes on its own
+    pub(crate) fn max_keyint(self, params: &[String]) -> Option<usize> {
+        match self {
+            Self::aom | Self::vpx => {
+                if params.iter().any(|param| param == "--disable-kf") {
+                    return None;
+                }
+                param_value(params, "--kf-max-dist")
+            },
+            Self::rav1e | Self::svt_av1 | Self::x264 | Self::x265 => {
+                param_value(params, "--keyint")
+            },
+            Self::vvenc => param_value(params, "--intraperiod"),
+            Self::nvenc | Self::qsv | Self::vaapi => param_value(params, "-g"),
+        }
+        // 0 or a negative interval disables keyframes, and x264 takes "infinite"
+        .and_then(|keyint| keyint.parse().ok())
+        .filter(|&keyint| keyint > 0)
+    }
+
+    /// Returns the minimum keyframe interval set in `params`
+    pub(crate) fn min_keyint(self, params: &[String]) -> Option<usize> {
+        match self {
+            Self::aom | Self::vpx => param_value(params, "--kf-min-dist"),
+            Self::rav1e | Self::x264 | Self::x265 => param_value(params, "--min-keyint"),
+            Self::svt_av1 | Self::vvenc | Self::nvenc | Self::qsv | Self::vaapi => None,
+        }
+        .and_then(|keyint| keyint.parse().ok())
+        .filter(|&keyint| keyint > 1)
     }
 
     /// Return number of default passes for encoder
@@ -493,6 +831,17 @@ impl Encoder {
         }
     }
 
+    /// Maximum number of passes supported by the encoder
+    #[inline]
+    #[must_use]
+    pub const fn max_passes(self) -> u8 {
+        match self {
+            Self::svt_av1 => 3,
+            Self::nvenc | Self::qsv | Self::vaapi => 1,
+            _ => 2,
+        }
+    }
+
     /// Default quantizer range target quality mode
     #[inline]
     pub const fn get_default_cq_range(self) -> (usize, usize) {
@@ -501,6 +850,10 @@ impl Encoder {
             Self::rav1e => (50, 140),
             Self::svt_av1 => (15, 50),
             Self::x264 | Self::x265 => (15, 35),
+            Self::vvenc => (20, 45),
+            Self::nvenc => (20, 50),
+            Self::qsv => (20, 40),
+            Self::vaapi => (80, 200),
         }
     }
 
@@ -508,9 +861,11 @@ impl Encoder {
     #[inline]
     pub fn get_cq_relative_percentage(self, quantizer: usize) -> f64 {
         let percentage = match self {
-            Self::aom | Self::vpx | Self::svt_av1 => quantizer as f64 / 64.0, // 0-63
-            Self::rav1e => quantizer as f64 / 256.0,                          // 0-255
-            Self::x264 | Self::x265 => quantizer as f64 / 52.0,               // 0-51
+            Self::aom | Self::vpx | Self::svt_av1 | Self::vvenc | Self::nvenc => {
+                quantizer as f64 / 64.0 // 0-63
+            },
+            Self::rav1e | Self::vaapi => quantizer as f64 / 256.0, // 0-255
+            Self::x264 | Self::x265 | Self::qsv => quantizer as f64 / 52.0, // 0-51
         };
 
         // Clamp to 0-1 in case quantizer is out of expected range
@@ -527,9 +882,17 @@ impl Encoder {
             Self::svt_av1 => ["SvtAv1EncApp", "--help"],
             Self::x264 => ["x264", "--fullhelp"],
             Self::x265 => ["x265", "--fullhelp"],
+            Self::vvenc => ["vvencapp", "--fullhelp"],
+            Self::nvenc | Self::qsv | Self::vaapi => ["ffmpeg", "-encoders"],
         }
     }
 
+    /// Runs the encoder's help command and returns its output
+    pub(crate) fn help_text(self) -> anyhow::Result<String> {
+        let [cmd, arg] = self.help_command();
+        Ok(String::from_utf8_lossy(&Command::new(cmd).arg(arg).output()?.stdout).to_string())
+    }
+
     /// Returns version text for encoder, or None if encoder is not available in
     /// PATH
     #[inline]
@@ -594,6 +957,16 @@ impl Encoder {
                         .to_string(),
                 )
             },
+            Self::vvenc => {
+                let result = Command::new("vvencapp").arg("--version").output().ok()?;
+                let stdout = String::from_utf8_lossy(&result.stdout);
+                let (_, version) = stdout.lines().find_map(|line| line.split_once("ver. "))?;
+                Some(version.split_whitespace().next()?.to_string())
+            },
+            Self::nvenc | Self::qsv | Self::vaapi => {
+                let codec = self.hardware_codec()?;
+                ffmpeg_has_encoder(codec).ok()?.then(|| format!("{codec} (FFmpeg)"))
+            },
         }
     }
 
@@ -607,6 +980,32 @@ impl Encoder {
             Self::svt_av1 => "SvtAv1EncApp",
             Self::x264 => "x264",
             Self::x265 => "x265",
+            Self::vvenc => "vvencapp",
+            Self::nvenc | Self::qsv | Self::vaapi => "ffmpeg",
+        }
+    }
+
+    /// Get the FFmpeg encoder of a hardware encoder, or `None` for encoders
+    /// with their own binary
+    #[inline]
+    pub const fn hardware_codec(self) -> Option<&'static str> {
+        match self {
+            Self::nvenc => Some("av1_nvenc"),
+            Self::qsv => Some("av1_qsv"),
+            Self::vaapi => Some("av1_vaapi"),
+            _ => None,
+        }
+    }
+
+    /// Number of encoding sessions a hardware encoder runs at once unless the
+    /// number of workers is set, as consumer GPUs limit the sessions and more
+    /// of them do not encode faster
+    #[inline]
+    pub const fn hardware_sessions(self) -> Option<u64> {
+        if self.hardware_codec().is_some() {
+            Some(HARDWARE_ENCODER_SESSIONS)
+        } else {
+            None
         }
     }
 
@@ -614,10 +1013,13 @@ impl Encoder {
     #[inline]
     pub const fn format(self) -> &'static str {
         match self {
-            Self::aom | Self::rav1e | Self::svt_av1 => "av1",
+            Self::aom | Self::rav1e | Self::svt_av1 | Self::nvenc | Self::qsv | Self::vaapi => {
+                "av1"
+            },
             Self::vpx => "vpx",
             Self::x264 => "h264",
             Self::x265 => "h265",
+            Self::vvenc => "h266",
         }
     }
 
@@ -625,9 +1027,16 @@ impl Encoder {
     #[inline]
     pub const fn output_extension(&self) -> &'static str {
         match &self {
-            Self::aom | Self::rav1e | Self::vpx | Self::svt_av1 => "ivf",
+            Self::aom
+            | Self::rav1e
+            | Self::vpx
+            | Self::svt_av1
+            | Self::nvenc
+            | Self::qsv
+            | Self::vaapi => "ivf",
             Self::x264 => "264",
             Self::x265 => "hevc",
+            Self::vvenc => "266",
         }
     }
 
@@ -639,13 +1048,28 @@ impl Encoder {
             Self::rav1e => |p| p == "--quantizer",
             Self::svt_av1 => |p| matches!(p, "--qp" | "-q" | "--crf"),
             Self::x264 | Self::x265 => |p| p == "--crf",
+            Self::vvenc => |p| matches!(p, "--qp" | "-q"),
+            Self::nvenc => |p| p == "-cq",
+            Self::qsv => |p| p == "-global_quality",
+            Self::vaapi => |p| p == "-qp",
+        }
+    }
+
+    /// Option that sets the quantizer of a hardware encoder
+    const fn hardware_q_option(self) -> &'static str {
+        match self {
+            Self::qsv => "-global_quality",
+            Self::vaapi => "-qp",
+            _ => "-cq",
         }
     }
 
     fn replace_q(self, index: usize, q: f32) -> (usize, String) {
         match self {
             Self::aom | Self::vpx => (index, format!("--cq-level={}", q.round() as usize)),
-            Self::rav1e => (index + 1, (q.round() as usize).to_string()),
+            Self::rav1e | Self::vvenc | Self::nvenc | Self::qsv | Self::vaapi => {
+                (index + 1, (q.round() as usize).to_string())
+            },
             Self::svt_av1 | Self::x265 | Self::x264 => {
                 let q_str = format_q(q);
                 (index + 1, q_str)
@@ -663,6 +1087,14 @@ impl Encoder {
                 output.push("--quantizer".into());
                 output.push((q.round() as usize).to_string());
             },
+            Self::vvenc => {
+                output.push("--qp".into());
+                output.push((q.round() as usize).to_string());
+            },
+            Self::nvenc | Self::qsv | Self::vaapi => {
+                output.push(self.hardware_q_option().into());
+                output.push((q.round() as usize).to_string());
+            },
             Self::svt_av1 | Self::x264 | Self::x265 => {
                 output.push("--crf".into());
                 let q_str = format_q(q);
@@ -672,6 +1104,30 @@ impl Encoder {
         output
     }
 
+    /// Returns the parameters that limit the quantizers used by the rate
+    /// control to within `bracket` of `q`, or `None` if the encoder does not
+    /// limit the quantizers of its quality mode
+    pub(crate) fn q_bracket_params(self, q: f32, bracket: u32) -> Option<Vec<String>> {
+        let q = q.round() as u32;
+        let max = match self {
+            Self::aom | Self::vpx => 63,
+            Self::rav1e => 255,
+            Self::x264 | Self::x265 => 51,
+            Self::svt_av1 | Self::vvenc | Self::nvenc | Self::qsv | Self::vaapi => return None,
+        };
+        let (low, high) = (q.saturating_sub(bracket), (q + bracket).min(max));
+
+        Some(match self {
+            Self::aom | Self::vpx => into_vec![format!("--min-q={low}"), format!("--max-q={high}")],
+            // The quantizer of rav1e is already its maximum
+            Self::rav1e => into_vec!["--min-quantizer", low.to_string()],
+            Self::x264 | Self::x265 => {
+                into_vec!["--qpmin", low.to_string(), "--qpmax", high.to_string()]
+            },
+            Self::svt_av1 | Self::vvenc | Self::nvenc | Self::qsv | Self::vaapi => unreachable!(),
+        })
+    }
+
     /// Returns changed q/crf in command line arguments
     #[inline]
     pub fn man_command(self, mut params: Vec<String>, q: f32) -> Vec<String> {
@@ -707,6 +1163,10 @@ impl Encoder {
             Self::rav1e => parse_rav1e_frames(line),
             Self::svt_av1 => parse_svt_av1_frames(line),
             Self::x264 | Self::x265 => parse_x26x_frames(line),
+            // vvencapp prints its progress to stdout, so the frames of a chunk
+            // are counted once it is finished
+            Self::vvenc => None,
+            Self::nvenc | Self::qsv | Self::vaapi => parse_ffmpeg_frames(line),
         }
     }
 
@@ -895,6 +1355,54 @@ impl Encoder {
                 "--input",
                 "-",
             ],
+            Self::vvenc => inplace_vec![
+                "vvencapp",
+                "--y4m",
+                "-i",
+                "-",
+                "--verbosity",
+                "2",
+                "--threads",
+                threads.to_string(),
+                "--preset",
+                MAXIMUM_SPEED_VVENC,
+                "--qp",
+                (q.round() as usize).to_string(),
+            ],
+            Self::nvenc | Self::qsv => inplace_vec![
+                "ffmpeg",
+                "-y",
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-i",
+                "-",
+                "-c:v",
+                self.hardware_codec().expect("hardware encoder should have a codec"),
+                "-preset",
+                if self == Self::nvenc {
+                    "p1"
+                } else {
+                    "veryfast"
+                },
+                self.hardware_q_option(),
+                (q.round() as usize).to_string(),
+            ],
+            Self::vaapi => inplace_vec![
+                "ffmpeg",
+                "-y",
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-i",
+                "-",
+                "-c:v",
+                "av1_vaapi",
+                "-rc_mode",
+                "CQP",
+                "-qp",
+                (q.round() as usize).to_string(),
+            ],
         }
     }
 
@@ -942,6 +1450,29 @@ impl Encoder {
                 "--input",
                 "-",
             ],
+            Self::vvenc => inplace_vec![
+                "vvencapp",
+                "--y4m",
+                "-i",
+                "-",
+                "--verbosity",
+                "2",
+                "--qp",
+                (q.round() as usize).to_string(),
+            ],
+            Self::nvenc | Self::qsv | Self::vaapi => inplace_vec![
+                "ffmpeg",
+                "-y",
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-i",
+                "-",
+                "-c:v",
+                self.hardware_codec().expect("hardware encoder should have a codec"),
+                self.hardware_q_option(),
+                (q.round() as usize).to_string(),
+            ],
         }
     }
 
@@ -960,42 +1491,21 @@ impl Encoder {
         }
     }
 
-    #[expect(clippy::too_many_arguments)]
     #[inline]
-    /// Constructs tuple of commands for target quality probing
+    /// Constructs tuple of commands for target quality probing, with the
+    /// encoder writing the probe to `output`
     pub fn probe_cmd(
         self,
-        temp: String,
-        chunk_index: usize,
+        output: &Path,
         q: f32,
         pix_fmt: FFPixelFormat,
         probing_rate: usize,
         vmaf_threads: usize,
         custom_video_params: Option<Vec<String>>,
     ) -> (Option<Vec<String>>, Vec<Cow<'static, str>>) {
-        let filters = if probing_rate > 1 {
-            vec![
-                "-vf".to_string(),
-                format!("select=not(mod(n\\,{probing_rate}))"),
-                "-vsync".to_string(),
-                "0".to_string(),
-            ]
-        } else {
-            Vec::new()
-        };
-
-        let pipe = Some(compose_ffmpeg_pipe(filters, pix_fmt));
-
-        let extension = match self {
-            Encoder::x264 => "264",
-            Encoder::x265 => "hevc",
-            _ => "ivf",
-        };
-        let q_str = format_q(q);
-        let probe_name = format!("v_{index:05}_{q_str}.{extension}", index = chunk_index);
+        let pipe = Some(probe_pipe(pix_fmt, probing_rate));
 
-        let probe = PathBuf::from(temp).join("split").join(&probe_name);
-        let probe_path = probe.to_string_lossy().to_string();
+        let probe_path = output.to_string_lossy().to_string();
 
         let params: Vec<Cow<str>> = custom_video_params.map_or_else(
             || self.construct_target_quality_command(vmaf_threads, q),
@@ -1003,6 +1513,13 @@ impl Encoder {
                 let quantizer_patterns =
                     ["--cq-level=", "--passes=", "--pass=", "--crf", "--quantizer"];
                 Self::remove_patterns(&mut video_params, &quantizer_patterns);
+                // The quantizer options of these encoders are prefixes of other
+                // options, such as `--qpa` of vvenc, so they have to match exactly
+                if matches!(self, Self::vvenc | Self::nvenc | Self::qsv | Self::vaapi)
+                    && let Some(index) = video_params.iter().position(|arg| self.q_match_fn()(arg))
+                {
+                    video_params.drain(index..(index + 2).min(video_params.len()));
+                }
 
                 let mut ps = self.construct_target_quality_command_probe_slow(q);
 
@@ -1020,12 +1537,61 @@ impl Encoder {
             Self::aom | Self::rav1e | Self::vpx | Self::x264 => {
                 chain!(params, into_array!["-o", probe_path, "-"]).collect()
             },
-            Self::x265 => chain!(params, into_array!["-o", probe_path]).collect(),
+            Self::x265 | Self::vvenc => chain!(params, into_array!["-o", probe_path]).collect(),
+            Self::nvenc | Self::qsv => {
+                chain!(params, into_array!["-f", "ivf", probe_path]).collect()
+            },
+            Self::vaapi => {
+                let existing = params.iter().map(ToString::to_string).collect::<Vec<_>>();
+                let upload = Self::vaapi_upload_arguments(&existing, pix_fmt);
+                chain!(params, upload.into_iter().map(Cow::Owned), into_array![
+                    "-f", "ivf", probe_path
+                ])
+                .collect()
+            },
         };
 
         (pipe, output)
     }
 
+    /// Returns the command that encodes a probe with NVENC (HEVC) at the
+    /// constant QP whose position in its range matches `q` of this encoder
+    pub(crate) fn nvenc_probe_cmd(
+        self,
+        output: &Path,
+        q: f32,
+        pix_fmt: FFPixelFormat,
+        probing_rate: usize,
+    ) -> (Option<Vec<String>>, Vec<Cow<'static, str>>) {
+        let qp = (self.get_cq_relative_percentage(q.round() as usize) * 52.0).round().min(51.0);
+
+        let output = into_vec![
+            "ffmpeg",
+            "-y",
+            "-hide_banner",
+            "-loglevel",
+            "error",
+            "-i",
+            "-",
+            "-c:v",
+            "hevc_nvenc",
+            "-preset",
+            "p1",
+            "-rc",
+            "constqp",
+            "-qp",
+            qp.to_string(),
+            "-f",
+            "matroska",
+            output.to_string_lossy().to_string(),
+        ]
+        .into_iter()
+        .map(Cow::Owned)
+        .collect();
+
+        (Some(probe_pipe(pix_fmt, probing_rate)), output)
+    }
+
     #[inline]
     pub fn get_format_bit_depth(
         self,
@@ -1040,7 +1606,7 @@ impl Encoder {
         }
       };
     }
-        impl_this_function!(x264, x265, vpx, aom, rav1e, svt_av1)
+        impl_this_function!(x264, x265, vpx, aom, rav1e, svt_av1, vvenc, nvenc, qsv, vaapi)
     }
 }
 
@@ -1107,3 +1673,27 @@ create_get_format_bit_depth_function!(
   10: [YUV420P10LE],
   12: []
 );
+create_get_format_bit_depth_function!(
+  nvenc,
+   8: [YUV420P],
+  10: [YUV420P10LE],
+  12: []
+);
+create_get_format_bit_depth_function!(
+  qsv,
+   8: [YUV420P],
+  10: [YUV420P10LE],
+  12: []
+);
+create_get_format_bit_depth_function!(
+  vaapi,
+   8: [YUV420P],
+  10: [YUV420P10LE],
+  12: []
+);
+create_get_format_bit_depth_function!(
+  vvenc,
+   8: [YUV420P, YUVJ420P, GRAY8],
+  10: [YUV420P10LE, GRAY10LE],
+  12: []
+);
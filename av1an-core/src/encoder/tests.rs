@@ -1,4 +1,7 @@
-use crate::encoder::parse_svt_av1_version;
+use crate::{
+    encoder::{parse_svt_av1_version, slice_aom_first_pass_stats, Encoder},
+    ffmpeg::FFPixelFormat,
+};
 
 #[test]
 fn svt_av1_parsing() {
@@ -17,3 +20,220 @@ fn svt_av1_parsing() {
         assert_eq!(parse_svt_av1_version(s.as_bytes()), ans);
     }
 }
+
+#[test]
+fn geometry_arguments_injected_for_vpx() {
+    let args = Encoder::vpx.get_geometry_arguments(
+        &["--codec=vp9".to_string()],
+        Some((1920, 1080)),
+        FFPixelFormat::YUV420P10LE,
+    );
+    assert_eq!(args, [
+        "--width=1920",
+        "--height=1080",
+        "--bit-depth=10",
+        "--input-bit-depth=10",
+        "--profile=2"
+    ]);
+
+    let args = Encoder::vpx.get_geometry_arguments(&[], None, FFPixelFormat::YUV444P);
+    assert_eq!(args, [
+        "--bit-depth=8",
+        "--input-bit-depth=8",
+        "--profile=1"
+    ]);
+}
+
+#[test]
+fn geometry_arguments_respect_user_params() {
+    let params = ["-w".to_string(), "1280".to_string(), "--bit-depth=8".to_string()];
+    let args =
+        Encoder::aom.get_geometry_arguments(&params, Some((1920, 1080)), FFPixelFormat::YUV420P);
+    assert_eq!(args, ["--height=1080", "--input-bit-depth=8"]);
+}
+
+#[test]
+fn geometry_arguments_only_for_aom_and_vpx() {
+    for encoder in [Encoder::rav1e, Encoder::svt_av1, Encoder::x264, Encoder::x265] {
+        assert!(encoder
+            .get_geometry_arguments(&[], Some((1920, 1080)), FFPixelFormat::YUV420P)
+            .is_empty());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn compose_keeps_non_utf8_paths() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path};
+
+    let output = Path::new(OsStr::from_bytes(b"encode/caf\xe9.ivf"));
+    let fpf = Path::new(OsStr::from_bytes(b"split/caf\xe9_fpf"));
+
+    let cmd = Encoder::aom.compose_1_1_pass(vec!["--cpu-used=6".to_string()], output);
+    assert_eq!(cmd[cmd.len() - 2], output.as_os_str());
+
+    let cmd = Encoder::aom.compose_2_2_pass(Vec::new(), fpf, output);
+    assert!(cmd.iter().any(|arg| arg.as_bytes() == b"--fpf=split/caf\xe9_fpf.log"));
+
+    let cmd = Encoder::x265.compose_1_2_pass(Vec::new(), fpf);
+    assert!(cmd.iter().any(|arg| arg.as_bytes() == b"split/caf\xe9_fpf_analysis.dat"));
+}
+
+#[test]
+fn svt_av1_three_pass_writes_output_in_last_pass() {
+    use std::path::Path;
+
+    let fpf = Path::new("split/00000_fpf");
+    let output = Path::new("encode/00000.ivf");
+    let args = |pass| {
+        Encoder::svt_av1
+            .compose_3_pass(
+                vec!["--preset".to_string(), "8".to_string()],
+                fpf,
+                output,
+                pass,
+            )
+            .into_iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+    };
+
+    for pass in 1..=3 {
+        let cmd = args(pass);
+        let tail = &cmd[cmd.len() - 6..];
+        assert_eq!(tail[..4], [
+            "--pass".to_string(),
+            pass.to_string(),
+            "--stats".to_string(),
+            "split/00000_fpf.stat".to_string()
+        ]);
+        assert_eq!(tail[5] == "encode/00000.ivf", pass == 3, "pass {pass}");
+    }
+    assert_eq!(Encoder::svt_av1.max_passes(), 3);
+    assert_eq!(Encoder::aom.max_passes(), 2);
+    assert_eq!(Encoder::nvenc.max_passes(), 1);
+}
+
+#[test]
+fn q_bracket_params_are_clamped() {
+    assert_eq!(
+        Encoder::aom.q_bracket_params(30.0, 4),
+        Some(vec!["--min-q=26".to_string(), "--max-q=34".to_string()])
+    );
+    assert_eq!(
+        Encoder::x264.q_bracket_params(49.6, 4),
+        Some(["--qpmin", "46", "--qpmax", "51"].map(ToString::to_string).to_vec())
+    );
+    assert_eq!(
+        Encoder::rav1e.q_bracket_params(2.0, 8),
+        Some(vec!["--min-quantizer".to_string(), "0".to_string()])
+    );
+    assert_eq!(Encoder::svt_av1.q_bracket_params(30.0, 4), None);
+}
+
+#[test]
+fn keyint_is_read_from_params() {
+    let params = |params: &[&str]| params.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+    let svt = params(&["--preset", "4", "--keyint", "0", "--keyint", "240"]);
+    assert_eq!(Encoder::svt_av1.max_keyint(&svt), Some(240));
+    assert_eq!(Encoder::svt_av1.min_keyint(&svt), None);
+
+    let aom = params(&["--kf-max-dist=300", "--kf-min-dist=12"]);
+    assert_eq!(Encoder::aom.max_keyint(&aom), Some(300));
+    assert_eq!(Encoder::aom.min_keyint(&aom), Some(12));
+    let aom = params(&["--disable-kf", "--kf-max-dist=9999"]);
+    assert_eq!(Encoder::aom.max_keyint(&aom), None);
+
+    let x264 = params(&["--keyint", "infinite", "--min-keyint", "24"]);
+    assert_eq!(Encoder::x264.max_keyint(&x264), None);
+    assert_eq!(Encoder::x264.min_keyint(&x264), Some(24));
+    assert_eq!(Encoder::x265.max_keyint(&params(&["--keyint", "-1"])), None);
+}
+
+#[test]
+fn vvenc_quantizer_is_replaced_exactly() {
+    let params = ["--preset", "medium", "--qpa", "1", "--qp", "32"].map(ToString::to_string);
+    assert_eq!(Encoder::vvenc.man_command(params.to_vec(), 27.4), [
+        "--preset", "medium", "--qpa", "1", "--qp", "27"
+    ]);
+    assert_eq!(
+        Encoder::vvenc.man_command(vec!["--preset".to_string(), "slow".to_string()], 30.0),
+        ["--preset", "slow", "--qp", "30"]
+    );
+
+    let (_, cmd) = Encoder::vvenc.probe_cmd(
+        std::path::Path::new("probe.266"),
+        24.0,
+        FFPixelFormat::YUV420P10LE,
+        1,
+        1,
+        Some(params.to_vec()),
+    );
+    let cmd = cmd.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+    assert_eq!(cmd.iter().filter(|&&arg| arg == "--qp").count(), 1);
+    assert!(cmd.windows(2).any(|pair| pair == ["--qp", "24"]));
+    assert!(cmd.windows(2).any(|pair| pair == ["--qpa", "1"]));
+}
+
+#[test]
+fn hardware_encoders_use_ffmpeg() {
+    let params = ["-preset", "p5", "-cq", "30"].map(ToString::to_string).to_vec();
+    let cmd = Encoder::nvenc.compose_1_1_pass(
+        Encoder::nvenc.man_command(params, 24.0),
+        std::path::Path::new("00001.ivf"),
+    );
+    assert_eq!(cmd, [
+        "ffmpeg",
+        "-y",
+        "-hide_banner",
+        "-loglevel",
+        "error",
+        "-stats",
+        "-i",
+        "-",
+        "-c:v",
+        "av1_nvenc",
+        "-preset",
+        "p5",
+        "-cq",
+        "24",
+        "-f",
+        "ivf",
+        "00001.ivf"
+    ]);
+
+    assert_eq!(
+        Encoder::vaapi.get_geometry_arguments(&[], None, FFPixelFormat::YUV420P10LE),
+        ["-vaapi_device", "/dev/dri/renderD128", "-vf", "format=p010|vaapi,hwupload"]
+    );
+    let params = ["-vaapi_device", "/dev/dri/renderD129"].map(ToString::to_string);
+    assert_eq!(
+        Encoder::vaapi.get_geometry_arguments(&params, None, FFPixelFormat::YUV420P),
+        ["-vf", "format=nv12|vaapi,hwupload"]
+    );
+    assert_eq!(Encoder::qsv.hardware_sessions(), Some(3));
+    assert_eq!(Encoder::svt_av1.hardware_sessions(), None);
+}
+
+#[test]
+fn slice_aom_first_pass_stats_sums_totals() {
+    // Three frames of two fields, then the totals record
+    let record = |value: f64, count: f64| [value.to_ne_bytes(), count.to_ne_bytes()].concat();
+    let stats = [record(1.5, 1.0), record(2.0, 1.0), record(4.25, 1.0), record(7.75, 3.0)].concat();
+
+    let sliced = slice_aom_first_pass_stats(&stats, 3, 1, 3).expect("stats should slice");
+    assert_eq!(
+        sliced,
+        [record(2.0, 1.0), record(4.25, 1.0), record(6.25, 2.0)].concat()
+    );
+
+    // A totals record that is not the sum of the frames means the layout is not
+    // understood
+    let mismatched =
+        [record(1.5, 1.0), record(2.0, 1.0), record(4.25, 1.0), record(7.75, 2.0)].concat();
+    assert!(slice_aom_first_pass_stats(&mismatched, 3, 1, 3).is_err());
+
+    assert!(slice_aom_first_pass_stats(&stats, 4, 0, 2).is_err());
+    assert!(slice_aom_first_pass_stats(&stats, 3, 2, 4).is_err());
+}
@@ -0,0 +1,202 @@
+//! Failure injection for testing how the pipeline recovers from failed
+//! encodes, slow chunks and broken chunks. Only built with the
+//! `failure-injection` feature.
+//!
+//! Failures are read from the `AV1AN_INJECT_FAILURES` environment variable as
+//! a comma separated list of:
+//!
+//! - `encoder-exit:<chunk>[:<attempts>]` - fail the encoder of the chunk on its
+//!   first `attempts` attempts (1 by default)
+//! - `corrupt-output:<chunk>[:<attempts>]` - truncate the output of the chunk
+//!   after its first `attempts` encodes (1 by default)
+//! - `start-delay:<chunk>:<milliseconds>` - delay the start of every encode of
+//!   the chunk
+
+use std::{
+    env,
+    fs::File,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context};
+use once_cell::sync::Lazy;
+use tracing::warn;
+
+use crate::chunk::Chunk;
+
+const FAILURE_INJECTION_ENV: &str = "AV1AN_INJECT_FAILURES";
+
+static INJECTIONS: Lazy<Vec<Injection>> = Lazy::new(|| {
+    env::var(FAILURE_INJECTION_ENV).map_or_else(
+        |_| Vec::new(),
+        |spec| {
+            parse_injections(&spec)
+                .with_context(|| format!("Invalid {FAILURE_INJECTION_ENV}"))
+                .expect("failure injections should parse")
+        },
+    )
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    EncoderExit,
+    CorruptOutput,
+    StartDelay(Duration),
+}
+
+#[derive(Debug)]
+struct Injection {
+    chunk:     usize,
+    failure:   Failure,
+    /// Number of attempts that still fail
+    remaining: AtomicUsize,
+}
+
+fn parse_injections(spec: &str) -> anyhow::Result<Vec<Injection>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|injection| !injection.is_empty())
+        .map(|injection| {
+            let mut parts = injection.split(':');
+            let kind = parts.next().unwrap_or_default();
+            let chunk = parts
+                .next()
+                .ok_or_else(|| anyhow!("{injection}: missing chunk index"))?
+                .parse()
+                .with_context(|| format!("{injection}: invalid chunk index"))?;
+            let value = parts
+                .next()
+                .map(str::parse::<usize>)
+                .transpose()
+                .with_context(|| format!("{injection}: invalid value"))?;
+            if parts.next().is_some() {
+                bail!("{injection}: too many values");
+            }
+
+            let (failure, attempts) = match kind {
+                "encoder-exit" => (Failure::EncoderExit, value.unwrap_or(1)),
+                "corrupt-output" => (Failure::CorruptOutput, value.unwrap_or(1)),
+                "start-delay" => (
+                    Failure::StartDelay(Duration::from_millis(
+                        value.ok_or_else(|| anyhow!("{injection}: missing delay"))? as u64,
+                    )),
+                    usize::MAX,
+                ),
+                _ => bail!("{injection}: unknown failure {kind:?}"),
+            };
+
+            Ok(Injection {
+                chunk,
+                failure,
+                remaining: AtomicUsize::new(attempts),
+            })
+        })
+        .collect()
+}
+
+/// Returns the failure of the given kind injected for `chunk`, if it has
+/// attempts left, and counts the attempt
+fn take(
+    injections: &[Injection],
+    chunk: usize,
+    matches: impl Fn(Failure) -> bool,
+) -> Option<Failure> {
+    injections
+        .iter()
+        .filter(|injection| injection.chunk == chunk && matches(injection.failure))
+        .find(|injection| {
+            injection
+                .remaining
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| {
+                    remaining.checked_sub(1)
+                })
+                .is_ok()
+        })
+        .map(|injection| injection.failure)
+}
+
+/// Delays the start of the encode of `chunk` and fails its encoder, if
+/// injected
+pub(crate) fn before_encode(chunk: &Chunk) -> anyhow::Result<()> {
+    if let Some(Failure::StartDelay(delay)) = take(&INJECTIONS, chunk.index, |failure| {
+        matches!(failure, Failure::StartDelay(_))
+    }) {
+        warn!("injected start delay of {delay:?} on chunk {}", chunk.index);
+        thread::sleep(delay);
+    }
+
+    if take(&INJECTIONS, chunk.index, |failure| {
+        failure == Failure::EncoderExit
+    })
+    .is_some()
+    {
+        bail!("injected encoder failure on chunk {}", chunk.index);
+    }
+
+    Ok(())
+}
+
+/// Truncates the output of `chunk` to half of its size, if injected
+pub(crate) fn after_encode(chunk: &Chunk) -> anyhow::Result<()> {
+    if take(&INJECTIONS, chunk.index, |failure| {
+        failure == Failure::CorruptOutput
+    })
+    .is_some()
+    {
+        warn!("injected output corruption on chunk {}", chunk.index);
+        let output = File::options().write(true).open(chunk.output())?;
+        output.set_len(output.metadata()?.len() / 2)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_injections, take, Failure};
+
+    #[test]
+    fn injected_failures_run_out() {
+        let injections = parse_injections("encoder-exit:2:2, corrupt-output:3, start-delay:2:50")
+            .expect("injections should parse");
+
+        let exit = |chunk| {
+            take(&injections, chunk, |failure| {
+                failure == Failure::EncoderExit
+            })
+        };
+        assert_eq!(exit(1), None);
+        assert_eq!(exit(2), Some(Failure::EncoderExit));
+        assert_eq!(exit(2), Some(Failure::EncoderExit));
+        assert_eq!(exit(2), None);
+
+        let corrupt = |chunk| {
+            take(&injections, chunk, |failure| {
+                failure == Failure::CorruptOutput
+            })
+        };
+        assert_eq!(corrupt(3), Some(Failure::CorruptOutput));
+        assert_eq!(corrupt(3), None);
+
+        let delay = |chunk| {
+            take(&injections, chunk, |failure| {
+                matches!(failure, Failure::StartDelay(_))
+            })
+        };
+        for _ in 0..3 {
+            assert_eq!(
+                delay(2),
+                Some(Failure::StartDelay(Duration::from_millis(50)))
+            );
+        }
+
+        assert!(parse_injections("encoder-exit").is_err());
+        assert!(parse_injections("start-delay:1").is_err());
+        assert!(parse_injections("segfault:1").is_err());
+        assert!(parse_injections("encoder-exit:1:2:3").is_err());
+    }
+}
@@ -5,14 +5,22 @@ use std::{
     str::FromStr,
 };
 
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use av_format::rational::Rational64;
 use path_abs::{PathAbs, PathInfo};
 use serde::{Deserialize, Serialize};
 use tracing::warn;
 use vapoursynth::format::PresetFormat;
 
-use crate::{into_array, into_vec, ClipInfo, ColorRange, InputPixelFormat};
+use crate::{
+    children,
+    into_array,
+    into_vec,
+    scenes::{AnnotationKind, SceneAnnotation},
+    ClipInfo,
+    ColorRange,
+    InputPixelFormat,
+};
 
 #[inline]
 pub fn compose_ffmpeg_pipe<S: Into<String>>(
@@ -37,6 +45,27 @@ pub fn compose_ffmpeg_pipe<S: Into<String>>(
     p
 }
 
+/// Returns whether FFmpeg is compiled with the encoder `codec`, such as
+/// `hevc_nvenc`
+pub(crate) fn ffmpeg_has_encoder(codec: &str) -> anyhow::Result<bool> {
+    let out = Command::new("ffmpeg")
+        .args(["-hide_banner", "-encoders"])
+        .stderr(Stdio::null())
+        .output()?;
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .any(|line| line.split_ascii_whitespace().nth(1) == Some(codec)))
+}
+
+/// Ensures that FFmpeg can encode with NVENC, which is used for the coarse
+/// Target Quality probes of `--nvenc-probes`
+pub(crate) fn validate_hevc_nvenc() -> anyhow::Result<()> {
+    if !ffmpeg_has_encoder("hevc_nvenc")? {
+        bail!("FFmpeg is not compiled with NVENC (hevc_nvenc), but --nvenc-probes was enabled");
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct FfProbeInfo {
     pub streams: Vec<FfProbeStreamInfo>,
@@ -44,13 +73,14 @@ struct FfProbeInfo {
 
 #[derive(Debug, Clone, Deserialize)]
 struct FfProbeStreamInfo {
-    pub width:          u32,
-    pub height:         u32,
-    pub pix_fmt:        String,
-    pub color_range:    Option<String>,
-    pub color_transfer: Option<String>,
-    pub avg_frame_rate: String,
-    pub nb_frames:      Option<String>,
+    pub width:               u32,
+    pub height:              u32,
+    pub pix_fmt:             String,
+    pub color_range:         Option<String>,
+    pub color_transfer:      Option<String>,
+    pub sample_aspect_ratio: Option<String>,
+    pub avg_frame_rate:      String,
+    pub nb_frames:           Option<String>,
 }
 
 #[inline]
@@ -63,7 +93,10 @@ pub fn get_clip_info(source: &Path) -> anyhow::Result<ClipInfo> {
         .arg("-print_format")
         .arg("json")
         .arg("-show_entries")
-        .arg("stream=width,height,pix_fmt,avg_frame_rate,nb_frames,color_range,color_transfer")
+        .arg(
+            "stream=width,height,pix_fmt,avg_frame_rate,nb_frames,color_range,color_transfer,\
+             sample_aspect_ratio",
+        )
         .arg(source)
         .output()?
         .stdout;
@@ -85,6 +118,10 @@ pub fn get_clip_info(source: &Path) -> anyhow::Result<ClipInfo> {
         frame_rate: parse_frame_rate(&stream_info.avg_frame_rate)?,
         resolution: (stream_info.width, stream_info.height),
         color_range,
+        sample_aspect_ratio: stream_info
+            .sample_aspect_ratio
+            .as_deref()
+            .and_then(parse_sample_aspect_ratio),
         transfer_characteristics: match stream_info.color_transfer.as_deref() {
             Some("smpte2084") => av1_grain::TransferFunction::SMPTE2084,
             _ => av1_grain::TransferFunction::BT1886,
@@ -115,6 +152,15 @@ fn parse_ffprobe_color_range(color_range: &str) -> Option<ColorRange> {
     }
 }
 
+/// Parses a `num:den` sample aspect ratio, which ffprobe reports as `0:1` or
+/// `N/A` when it is unknown
+#[inline]
+fn parse_sample_aspect_ratio(sar: &str) -> Option<(u32, u32)> {
+    let (num, den) = sar.split_once(':')?;
+    let (num, den) = (num.parse().ok()?, den.parse().ok()?);
+    (num != 0 && den != 0).then_some((num, den))
+}
+
 /// Get frame count using FFmpeg
 #[inline]
 pub fn get_num_frames(source: &Path) -> anyhow::Result<usize> {
@@ -141,6 +187,40 @@ pub fn get_num_frames(source: &Path) -> anyhow::Result<usize> {
     }
 }
 
+/// Get the pixel format and resolution of the first video stream using FFmpeg
+#[inline]
+pub fn get_video_format(source: &Path) -> anyhow::Result<(FFPixelFormat, (u32, u32))> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=pix_fmt,width,height")
+        .arg("-print_format")
+        .arg("default=noprint_wrappers=1")
+        .arg(source)
+        .output()?
+        .stdout;
+    parse_video_format(&String::from_utf8_lossy(&output))
+}
+
+/// Parses the `key=value` lines of the pixel format, width and height printed
+/// by ffprobe
+fn parse_video_format(output: &str) -> anyhow::Result<(FFPixelFormat, (u32, u32))> {
+    let field = |key: &str| {
+        output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(key)?.strip_prefix('='))
+            .ok_or_else(|| anyhow!("ffprobe did not report the {key} of the video stream"))
+    };
+    let format = FFPixelFormat::from_str(field("pix_fmt")?)?;
+    let width = field("width")?.parse()?;
+    let height = field("height")?.parse()?;
+
+    Ok((format, (width, height)))
+}
+
 /// Slower but more reliable frame count method
 fn get_num_frames_slow(source: &Path) -> anyhow::Result<usize> {
     let output = Command::new("ffprobe")
@@ -208,14 +288,413 @@ pub fn get_keyframes(source: &Path) -> anyhow::Result<Vec<usize>> {
         .collect())
 }
 
-/// Returns true if input file have audio in it
+/// Finds black and frozen sections of `source` with FFmpeg's `blackdetect`
+/// and `freezedetect` filters, decoding the whole video.
+#[tracing::instrument(level = "debug")]
 #[inline]
-pub fn has_audio(file: &Path) -> anyhow::Result<bool> {
+pub fn detect_black_and_frozen(
+    source: &Path,
+    frame_rate: f64,
+    frames: usize,
+) -> anyhow::Result<Vec<SceneAnnotation>> {
+    let output = children::output(
+        Command::new("ffmpeg")
+            .args(["-hide_banner", "-nostats", "-i"])
+            .arg(source)
+            .args([
+                "-map",
+                "0:V:0",
+                "-vf",
+                "blackdetect=d=0.5:pix_th=0.10,freezedetect=n=-60dB:d=2",
+                "-f",
+                "null",
+                "-",
+            ])
+            .stdin(Stdio::null()),
+    )?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        bail!("FFmpeg failed to detect black and frozen frames: {stderr}");
+    }
+
+    Ok(parse_black_and_frozen(&stderr, frame_rate, frames))
+}
+
+/// Parses the sections logged by the `blackdetect` and `freezedetect`
+/// filters. A freeze that lasts until the end of the video has no end time
+/// and ends at `frames`.
+fn parse_black_and_frozen(stderr: &str, frame_rate: f64, frames: usize) -> Vec<SceneAnnotation> {
+    let to_frame = |seconds: &str| {
+        seconds
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .map(|seconds| ((seconds * frame_rate).round() as usize).min(frames))
+    };
+
+    let mut annotations = Vec::new();
+    let mut freeze_start = None;
+    for line in stderr.lines() {
+        if let Some((_, rest)) = line.split_once("black_start:") {
+            let start = rest.split_whitespace().next().and_then(to_frame);
+            let end = rest
+                .split_once("black_end:")
+                .and_then(|(_, end)| end.split_whitespace().next())
+                .and_then(to_frame);
+            if let (Some(start_frame), Some(end_frame)) = (start, end) {
+                annotations.push(SceneAnnotation {
+                    kind: AnnotationKind::Black,
+                    start_frame,
+                    end_frame,
+                });
+            }
+        } else if let Some((_, start)) = line.split_once("lavfi.freezedetect.freeze_start:") {
+            freeze_start = to_frame(start);
+        } else if let Some((_, end)) = line.split_once("lavfi.freezedetect.freeze_end:")
+            && let (Some(start_frame), Some(end_frame)) = (freeze_start.take(), to_frame(end))
+        {
+            annotations.push(SceneAnnotation {
+                kind: AnnotationKind::Frozen,
+                start_frame,
+                end_frame,
+            });
+        }
+    }
+    if let Some(start_frame) = freeze_start {
+        annotations.push(SceneAnnotation {
+            kind: AnnotationKind::Frozen,
+            start_frame,
+            end_frame: frames,
+        });
+    }
+
+    annotations.retain(|annotation| annotation.end_frame > annotation.start_frame);
+    annotations.sort_by_key(|annotation| annotation.start_frame);
+    annotations
+}
+
+/// Measures the average luma of every frame of `source` with FFmpeg's
+/// `signalstats` filter, on a scale from 0 to 255, decoding the whole video.
+#[tracing::instrument(level = "debug")]
+#[inline]
+pub fn luma_averages(source: &Path) -> anyhow::Result<Vec<f64>> {
+    let output = children::output(
+        Command::new("ffmpeg")
+            .args(["-hide_banner", "-nostats", "-i"])
+            .arg(source)
+            .args([
+                "-map",
+                "0:V:0",
+                "-vf",
+                "format=gray,signalstats,metadata=print:key=lavfi.signalstats.YAVG",
+                "-f",
+                "null",
+                "-",
+            ])
+            .stdin(Stdio::null()),
+    )?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        bail!("FFmpeg failed to measure the luma of the frames: {stderr}");
+    }
+
+    Ok(parse_luma_averages(&stderr))
+}
+
+/// Measures the scene change score of every frame of `source` with FFmpeg's
+/// `select` filter, from 0 for a frame identical to the previous one to 1 for
+/// an entirely different frame, decoding the whole video.
+#[tracing::instrument(level = "debug")]
+#[inline]
+pub fn scene_scores(source: &Path) -> anyhow::Result<Vec<f64>> {
+    let output = children::output(
+        Command::new("ffmpeg")
+            .args(["-hide_banner", "-nostats", "-i"])
+            .arg(source)
+            .args([
+                "-map",
+                "0:V:0",
+                "-vf",
+                "select='gte(scene,0)',metadata=print:key=lavfi.scene_score",
+                "-f",
+                "null",
+                "-",
+            ])
+            .stdin(Stdio::null()),
+    )?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        bail!("FFmpeg failed to measure the scene scores of the frames: {stderr}");
+    }
+
+    Ok(parse_scene_scores(&stderr))
+}
+
+/// Measures the noise of every frame of `source` as the mean squared error
+/// between its luma and the luma denoised by FFmpeg's `hqdn3d` filter, on a
+/// scale from 0 to 255, decoding the whole video.
+#[tracing::instrument(level = "debug")]
+#[inline]
+pub fn noise_levels(source: &Path) -> anyhow::Result<Vec<f64>> {
+    let output = children::output(
+        Command::new("ffmpeg")
+            .args(["-hide_banner", "-loglevel", "error", "-i"])
+            .arg(source)
+            .args([
+                "-filter_complex",
+                "[0:V:0]format=gray,split[source][reference];[reference]hqdn3d=4:0:6:0[denoised];\
+                 [source][denoised]psnr=stats_file=-",
+                "-f",
+                "null",
+                "-",
+            ])
+            .stdin(Stdio::null()),
+    )?;
+    if !output.status.success() {
+        bail!(
+            "FFmpeg failed to measure the noise of the frames: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(parse_noise_levels(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses the mean squared error of each frame from the statistics written by
+/// the `psnr` filter
+fn parse_noise_levels(stats: &str) -> Vec<f64> {
+    stats
+        .lines()
+        .filter_map(|line| line.split_once("mse_avg:"))
+        .filter_map(|(_, rest)| rest.split_whitespace().next()?.parse().ok())
+        .collect()
+}
+
+/// Parses the scene scores logged for each frame by the `metadata` filter
+fn parse_scene_scores(stderr: &str) -> Vec<f64> {
+    stderr
+        .lines()
+        .filter_map(|line| line.split_once("lavfi.scene_score="))
+        .filter_map(|(_, value)| value.trim().parse().ok())
+        .collect()
+}
+
+/// Parses the `YAVG` values logged for each frame by the `metadata` filter
+fn parse_luma_averages(stderr: &str) -> Vec<f64> {
+    stderr
+        .lines()
+        .filter_map(|line| line.split_once("lavfi.signalstats.YAVG="))
+        .filter_map(|(_, value)| value.trim().parse().ok())
+        .collect()
+}
+
+/// Finds the runs of frames of `source` that are identical to the frame before
+/// them by hashing every decoded frame with FFmpeg's `framemd5` muxer.
+/// Returns the runs as `(start, end)` frame ranges with an exclusive end.
+#[tracing::instrument(level = "debug")]
+#[inline]
+pub fn detect_duplicate_frames(source: &Path) -> anyhow::Result<Vec<(usize, usize)>> {
+    let output = children::output(
+        Command::new("ffmpeg")
+            .args(["-hide_banner", "-loglevel", "error", "-i"])
+            .arg(source)
+            .args(["-map", "0:V:0", "-f", "framemd5", "-"])
+            .stdin(Stdio::null()),
+    )?;
+    if !output.status.success() {
+        bail!(
+            "FFmpeg failed to hash frames: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(parse_duplicate_frames(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Parses the output of the `framemd5` muxer, whose lines are the stream
+/// index, timestamps, size and hash of each frame in decoding order
+fn parse_duplicate_frames(framemd5: &str) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    let mut previous_hash = None;
+    let hashes = framemd5
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.rsplit(',').next().map(str::trim))
+        .filter(|hash| !hash.is_empty());
+    for (frame, hash) in hashes.enumerate() {
+        if previous_hash == Some(hash) {
+            match runs.last_mut() {
+                Some((_, end)) if *end == frame => *end += 1,
+                _ => runs.push((frame, frame + 1)),
+            }
+        }
+        previous_hash = Some(hash);
+    }
+
+    runs
+}
+
+/// Returns the presentation time in seconds of every frame of `source`,
+/// relative to the first frame, by reading the packets of the video stream
+/// without decoding them
+#[tracing::instrument(level = "debug")]
+#[inline]
+pub fn frame_timestamps(source: &Path) -> anyhow::Result<Vec<f64>> {
+    let output = children::output(
+        Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", "V:0"])
+            .args(["-show_entries", "packet=pts_time", "-of", "csv=p=0"])
+            .arg(source)
+            .stdin(Stdio::null()),
+    )?;
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed to read frame timestamps: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    parse_frame_timestamps(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses the packet timestamps printed by ffprobe, which are in decoding
+/// order, into presentation order starting at 0. Packets without a timestamp
+/// are skipped, which leaves fewer timestamps than frames.
+fn parse_frame_timestamps(csv: &str) -> anyhow::Result<Vec<f64>> {
+    let mut timestamps = csv
+        .lines()
+        .map(|line| line.trim().trim_end_matches(','))
+        .filter(|line| !line.is_empty() && *line != "N/A")
+        .map(|line| line.parse::<f64>().map_err(|_| anyhow!("frame without a timestamp: {line:?}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    timestamps.sort_unstable_by(f64::total_cmp);
+    if let Some(&first) = timestamps.first() {
+        for timestamp in &mut timestamps {
+            *timestamp -= first;
+        }
+    }
+
+    Ok(timestamps)
+}
+
+/// Returns the frame numbers of the keyframes of `source`, read from the
+/// packets of the video stream without decoding them
+#[tracing::instrument(level = "debug")]
+#[inline]
+pub fn keyframes(source: &Path) -> anyhow::Result<Vec<usize>> {
+    let output = children::output(
+        Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", "V:0"])
+            .args(["-show_entries", "packet=pts_time,flags", "-of", "csv=p=0"])
+            .arg(source)
+            .stdin(Stdio::null()),
+    )?;
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed to read keyframes: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    parse_keyframes(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses the packet timestamps and flags printed by ffprobe, which are in
+/// decoding order, into the presentation order frame numbers of the packets
+/// flagged as keyframes. Packets without a timestamp cannot be ordered and are
+/// skipped.
+fn parse_keyframes(csv: &str) -> anyhow::Result<Vec<usize>> {
+    let mut packets = csv
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("N/A"))
+        .map(|line| {
+            let (timestamp, flags) = line.split_once(',').unwrap_or((line, ""));
+            let timestamp = timestamp
+                .parse::<f64>()
+                .map_err(|_| anyhow!("frame without a timestamp: {line:?}"))?;
+            Ok((timestamp, flags.starts_with('K')))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    packets.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    Ok(packets
+        .iter()
+        .enumerate()
+        .filter_map(|(frame, &(_, keyframe))| keyframe.then_some(frame))
+        .collect())
+}
+
+/// Whether the durations of the frames at `timestamps` differ by more than
+/// the rounding of container timestamps to milliseconds
+#[inline]
+pub fn is_variable_frame_rate(timestamps: &[f64]) -> bool {
+    let (shortest, longest) = timestamps.windows(2).map(|pair| pair[1] - pair[0]).fold(
+        (f64::INFINITY, f64::NEG_INFINITY),
+        |(shortest, longest), duration| (shortest.min(duration), longest.max(duration)),
+    );
+    longest - shortest > 0.0015
+}
+
+/// Tracks of the input besides video and audio that are copied to the output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Passthrough {
+    /// Indices of the subtitle tracks to copy, counted among the subtitle
+    /// tracks only, or `None` to copy all of them
+    pub subtitle_tracks: Option<Vec<usize>>,
+    pub chapters:        bool,
+    /// Attachments such as the fonts used by subtitles
+    pub attachments:     bool,
+}
+
+impl Default for Passthrough {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            subtitle_tracks: None,
+            chapters:        true,
+            attachments:     true,
+        }
+    }
+}
+
+impl Passthrough {
+    /// FFmpeg options that copy the selected tracks of an input with
+    /// `subtitle_count` subtitle tracks, after all tracks were mapped
+    fn map_args(&self, subtitle_count: usize) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(tracks) = &self.subtitle_tracks {
+            args.extend(into_vec!["-map", "-0:s"]);
+            for &track in tracks {
+                if track < subtitle_count {
+                    args.extend(into_vec!["-map", format!("0:s:{track}")]);
+                } else {
+                    warn!(
+                        "Input only has {subtitle_count} subtitle track(s), skipping subtitle \
+                         track {track}"
+                    );
+                }
+            }
+        }
+        if !self.attachments {
+            args.extend(into_vec!["-map", "-0:t"]);
+        }
+        if !self.chapters {
+            args.extend(into_vec!["-map_chapters", "-1"]);
+        }
+        args
+    }
+}
+
+/// Number of streams of `file` that match the ffprobe stream specifier
+fn count_streams(file: &Path, specifier: &str) -> anyhow::Result<usize> {
     let output = Command::new("ffprobe")
         .arg("-v")
         .arg("error")
         .arg("-select_streams")
-        .arg("a")
+        .arg(specifier)
         .arg("-show_entries")
         .arg("stream=index")
         .arg("-of")
@@ -224,38 +703,69 @@ pub fn has_audio(file: &Path) -> anyhow::Result<bool> {
         .output()?
         .stdout;
     let output = String::from_utf8_lossy(&output);
-    Ok(!output.trim().is_empty())
+    Ok(output.lines().filter(|line| !line.trim().is_empty()).count())
+}
+
+/// Returns true if input file have audio in it
+#[inline]
+pub fn has_audio(file: &Path) -> anyhow::Result<bool> {
+    Ok(count_streams(file, "a")? > 0)
 }
 
-/// Encodes the audio using FFmpeg, blocking the current thread.
+/// Returns true if the input file has chapters
+fn has_chapters(file: &Path) -> anyhow::Result<bool> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-show_chapters", "-of", "csv=p=0"])
+        .arg(file)
+        .output()?
+        .stdout;
+    Ok(!String::from_utf8_lossy(&output).trim().is_empty())
+}
+
+/// Encodes the audio using FFmpeg, blocking the current thread. The subtitles,
+/// attachments and chapters selected by `passthrough` are copied along with
+/// the audio.
 ///
-/// This function returns `Some(output)` if the audio exists and the audio
-/// successfully encoded, or `None` otherwise.
+/// This function returns `Some(output)` if the input has any of these tracks
+/// and they were successfully encoded, or `None` otherwise.
 #[inline]
 pub fn encode_audio<S: AsRef<OsStr>>(
     input: impl AsRef<Path> + std::fmt::Debug,
     temp: impl AsRef<Path> + std::fmt::Debug,
     audio_params: &[S],
+    passthrough: &Passthrough,
 ) -> anyhow::Result<Option<PathBuf>> {
     let input = input.as_ref();
     let temp = temp.as_ref();
 
-    if has_audio(input)? {
+    let subtitle_count = count_streams(input, "s")?;
+    let copies_subtitles =
+        passthrough.subtitle_tracks.as_ref().map_or(subtitle_count > 0, |tracks| {
+            tracks.iter().any(|&track| track < subtitle_count)
+        });
+    let has_tracks = has_audio(input)?
+        || copies_subtitles
+        || (passthrough.attachments && count_streams(input, "t")? > 0)
+        || (passthrough.chapters && has_chapters(input)?);
+
+    if has_tracks {
         let audio_file = Path::new(temp).join("audio.mkv");
         let mut encode_audio = Command::new("ffmpeg");
 
+        encode_audio.stdin(Stdio::null());
         encode_audio.stdout(Stdio::piped());
         encode_audio.stderr(Stdio::piped());
 
         encode_audio.args(["-y", "-hide_banner", "-loglevel", "error"]);
-        encode_audio.args(["-i", &input.to_string_lossy()]);
+        encode_audio.arg("-i").arg(input);
         encode_audio.args(["-map_metadata", "0"]);
         encode_audio.args(["-map", "0", "-c", "copy", "-vn", "-dn"]);
+        encode_audio.args(passthrough.map_args(subtitle_count));
 
         encode_audio.args(audio_params);
         encode_audio.arg(&audio_file);
 
-        let output = encode_audio.output()?;
+        let output = children::output(&mut encode_audio)?;
 
         if !output.status.success() {
             warn!("FFmpeg failed to encode audio!\n{output:#?}\nParams: {encode_audio:?}");
@@ -418,6 +928,42 @@ impl FFPixelFormat {
         }
     }
 
+    /// The chroma subsampling of the format in J:a:b notation, with `4:0:0`
+    /// for grayscale formats
+    #[inline]
+    pub fn chroma_subsampling(&self) -> &'static str {
+        match self {
+            FFPixelFormat::GRAY10LE
+            | FFPixelFormat::GRAY12L
+            | FFPixelFormat::GRAY12LE
+            | FFPixelFormat::GRAY8 => "4:0:0",
+            FFPixelFormat::NV12
+            | FFPixelFormat::NV21
+            | FFPixelFormat::YUV420P
+            | FFPixelFormat::YUV420P10LE
+            | FFPixelFormat::YUV420P12LE
+            | FFPixelFormat::YUVA420P
+            | FFPixelFormat::YUVJ420P => "4:2:0",
+            FFPixelFormat::NV16
+            | FFPixelFormat::NV20LE
+            | FFPixelFormat::YUV422P
+            | FFPixelFormat::YUV422P10LE
+            | FFPixelFormat::YUV422P12LE
+            | FFPixelFormat::YUVJ422P => "4:2:2",
+            FFPixelFormat::YUV440P | FFPixelFormat::YUV440P10LE | FFPixelFormat::YUV440P12LE => {
+                "4:4:0"
+            },
+            FFPixelFormat::GBRP
+            | FFPixelFormat::GBRP10LE
+            | FFPixelFormat::GBRP12L
+            | FFPixelFormat::GBRP12LE
+            | FFPixelFormat::YUV444P
+            | FFPixelFormat::YUV444P10LE
+            | FFPixelFormat::YUV444P12LE
+            | FFPixelFormat::YUVJ444P => "4:4:4",
+        }
+    }
+
     // use to convert ffmpeg pixel format to vapoursynth format for use in python
     // script.
     #[inline]
@@ -492,6 +1038,13 @@ impl FromStr for FFPixelFormat {
 mod tests {
     use super::*;
 
+    #[test]
+    fn parse_ffprobe_sample_aspect_ratio() {
+        assert_eq!(parse_sample_aspect_ratio("32:27"), Some((32, 27)));
+        assert_eq!(parse_sample_aspect_ratio("0:1"), None);
+        assert_eq!(parse_sample_aspect_ratio("N/A"), None);
+    }
+
     #[test]
     fn parse_ffprobe_color_range_aliases() {
         assert_eq!(parse_ffprobe_color_range("pc"), Some(ColorRange::Full));
@@ -522,4 +1075,149 @@ mod tests {
         );
         assert_eq!(infer_color_range_from_pix_fmt(FFPixelFormat::YUV420P), None);
     }
+
+    #[test]
+    fn parse_blackdetect_and_freezedetect_output() {
+        let stderr = "\
+[blackdetect @ 0x5600] black_start:0 black_end:1 black_duration:1
+[freezedetect @ 0x5601] lavfi.freezedetect.freeze_start: 4.004
+[freezedetect @ 0x5601] lavfi.freezedetect.freeze_duration: 2.002
+[freezedetect @ 0x5601] lavfi.freezedetect.freeze_end: 6.006
+[blackdetect @ 0x5600] black_start:8.5 black_end:9 black_duration:0.5
+[freezedetect @ 0x5601] lavfi.freezedetect.freeze_start: 9.5
+";
+        let annotation = |kind, start_frame, end_frame| SceneAnnotation {
+            kind,
+            start_frame,
+            end_frame,
+        };
+
+        assert_eq!(parse_black_and_frozen(stderr, 24000.0 / 1001.0, 240), [
+            annotation(AnnotationKind::Black, 0, 24),
+            annotation(AnnotationKind::Frozen, 96, 144),
+            annotation(AnnotationKind::Black, 204, 216),
+            annotation(AnnotationKind::Frozen, 228, 240),
+        ]);
+    }
+
+    #[test]
+    fn parse_signalstats_luma_averages() {
+        let stderr = "\
+[Parsed_metadata_2 @ 0x5600] frame:0    pts:0       pts_time:0
+[Parsed_metadata_2 @ 0x5600] lavfi.signalstats.YAVG=16.25
+[Parsed_metadata_2 @ 0x5600] frame:1    pts:1001    pts_time:0.0417083
+[Parsed_metadata_2 @ 0x5600] lavfi.signalstats.YAVG=48
+";
+
+        assert_eq!(parse_luma_averages(stderr), [16.25, 48.0]);
+    }
+
+    #[test]
+    fn parse_select_scene_scores() {
+        let stderr = "\
+[Parsed_metadata_1 @ 0x5600] frame:0    pts:0       pts_time:0
+[Parsed_metadata_1 @ 0x5600] lavfi.scene_score=0.000000
+[Parsed_metadata_1 @ 0x5600] frame:1    pts:1001    pts_time:0.0417083
+[Parsed_metadata_1 @ 0x5600] lavfi.scene_score=0.731250
+";
+
+        assert_eq!(parse_scene_scores(stderr), [0.0, 0.73125]);
+    }
+
+    #[test]
+    fn parse_psnr_noise_levels() {
+        let stats = "\
+n:1 mse_avg:0.52 mse_y:0.52 psnr_avg:50.97 psnr_y:50.97
+n:2 mse_avg:4.00 mse_y:4.00 psnr_avg:42.11 psnr_y:42.11
+";
+
+        assert_eq!(parse_noise_levels(stats), [0.52, 4.0]);
+    }
+
+    #[test]
+    fn parse_framemd5_duplicate_runs() {
+        let framemd5 = "\
+#format: frame checksums
+#version: 2
+#hash: MD5
+#tb 0: 1001/24000
+#stream#, dts,        pts, duration,     size, hash
+0,          0,          0,        1,   152064, 6f6b3e9c4a0ed0b1a45f1cd5b3a3a3a1
+0,          1,          1,        1,   152064, 6f6b3e9c4a0ed0b1a45f1cd5b3a3a3a1
+0,          2,          2,        1,   152064, 6f6b3e9c4a0ed0b1a45f1cd5b3a3a3a1
+0,          3,          3,        1,   152064, 0c2cf04f0a5c1f2f4b4d4b0d77e8f9a2
+0,          4,          4,        1,   152064, 6f6b3e9c4a0ed0b1a45f1cd5b3a3a3a1
+0,          5,          5,        1,   152064, 6f6b3e9c4a0ed0b1a45f1cd5b3a3a3a1
+";
+
+        assert_eq!(parse_duplicate_frames(framemd5), [(1, 3), (5, 6)]);
+    }
+
+    #[test]
+    fn parse_packet_timestamps_in_presentation_order() {
+        let timestamps = parse_frame_timestamps("1.000000\n1.083000,\n1.042000\n1.150000\n")
+            .expect("timestamps should parse");
+
+        assert_eq!(timestamps.len(), 4);
+        assert!((timestamps[1] - 0.042).abs() < 1e-9);
+        assert!((timestamps[3] - 0.15).abs() < 1e-9);
+        assert!(is_variable_frame_rate(&timestamps));
+        assert!(!is_variable_frame_rate(&[0.0, 0.042, 0.083, 0.125, 0.167]));
+        assert_eq!(
+            parse_frame_timestamps("0.000000\nN/A\n0.040000\n").expect("timestamps should parse"),
+            [0.0, 0.04]
+        );
+        assert!(parse_frame_timestamps("0.000000\nframe\n").is_err());
+    }
+
+    #[test]
+    fn parse_video_format_fields() {
+        assert_eq!(
+            parse_video_format("width=1920\nheight=1080\npix_fmt=yuv420p10le\n")
+                .expect("format should parse"),
+            (FFPixelFormat::YUV420P10LE, (1920, 1080))
+        );
+        assert!(parse_video_format("width=1920\npix_fmt=yuv420p\n").is_err());
+    }
+
+    #[test]
+    fn parse_keyframes_in_presentation_order() {
+        let csv = "\
+0.000000,K__
+0.125000,___
+0.041667,___
+0.083333,___
+0.166667,K__
+0.250000,___
+0.208333,___
+";
+        assert_eq!(parse_keyframes(csv).expect("packets should parse"), [0, 4]);
+        assert_eq!(
+            parse_keyframes("0.000000,K__\nN/A,___\n0.041667,K__\n").expect("packets should parse"),
+            [0, 1]
+        );
+        assert!(parse_keyframes("frame,K__\n").is_err());
+    }
+
+    #[test]
+    fn passthrough_map_args() {
+        assert!(Passthrough::default().map_args(2).is_empty());
+        assert_eq!(
+            Passthrough {
+                subtitle_tracks: Some(vec![1, 3]),
+                chapters:        false,
+                attachments:     false,
+            }
+            .map_args(2),
+            ["-map", "-0:s", "-map", "0:s:1", "-map", "-0:t", "-map_chapters", "-1"]
+        );
+        assert_eq!(
+            Passthrough {
+                subtitle_tracks: Some(Vec::new()),
+                ..Passthrough::default()
+            }
+            .map_args(2),
+            ["-map", "-0:s"]
+        );
+    }
 }
@@ -0,0 +1,350 @@
+//! Frame by frame decoding of an [`Input`] for tools built on Av1an's input
+//! handling, such as thumbnailers or custom analyzers. VapourSynth scripts,
+//! including the scripts generated for video inputs and proxies, are decoded
+//! with VapourSynth, and other video inputs through FFmpeg.
+
+use std::{
+    io::Read,
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::bail;
+use av_decoders::{
+    v_frame::{frame::Frame, pixel::Pixel, plane::Plane},
+    Decoder,
+    DecoderError,
+    DecoderImpl,
+    VapoursynthDecoder,
+    Y4mDecoder,
+};
+
+use crate::{children::ChildGuard, Input};
+
+/// Plane of a [`FrameRef`], stored row by row without padding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaneRef<'a> {
+    pub width:   usize,
+    pub height:  usize,
+    /// `width * height` samples, widened to 16 bits for every bit depth
+    pub samples: &'a [u16],
+}
+
+/// Decoded frame of an [`Input`], borrowed from [`InputFrames`] until the
+/// next frame is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRef<'a> {
+    /// Index of the frame in the input, starting at 0
+    pub index:     usize,
+    pub width:     usize,
+    pub height:    usize,
+    /// Bits used by each sample
+    pub bit_depth: usize,
+    pub luma:      PlaneRef<'a>,
+    /// U and V planes, or `None` for grayscale inputs
+    pub chroma:    Option<[PlaneRef<'a>; 2]>,
+}
+
+/// Decoder reading the frames of an [`Input`] in order, created with
+/// [`Input::frames`]
+pub struct InputFrames {
+    decoder:    Decoder,
+    bit_depth:  usize,
+    index:      usize,
+    /// Only every `step`-th frame is handed out
+    step:       usize,
+    /// Samples and dimensions of the Y, U and V planes of the last frame
+    planes:     [(Vec<u16>, usize, usize); 3],
+    has_chroma: bool,
+    /// FFmpeg process decoding a video input
+    ffmpeg:     Option<FfmpegProcess>,
+}
+
+/// FFmpeg process writing the y4m stream, killed and reaped when dropped
+struct FfmpegProcess {
+    child:  Child,
+    _guard: ChildGuard,
+    /// Collects the stderr of FFmpeg, so that it can be reported on failure
+    stderr: Option<JoinHandle<String>>,
+}
+
+impl FfmpegProcess {
+    /// Stops FFmpeg if it is still running and returns its stderr
+    fn stop(&mut self) -> String {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        self.stderr.take().and_then(|stderr| stderr.join().ok()).unwrap_or_default()
+    }
+
+    /// Waits for FFmpeg to exit after it wrote the whole stream, failing if
+    /// it could not decode the input
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let status = self.child.wait()?;
+        let stderr = self.stop();
+        if !status.success() {
+            bail!("FFmpeg failed to decode the input ({status}):\n{stderr}");
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FfmpegProcess {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl InputFrames {
+    pub(crate) fn new(input: &Input) -> anyhow::Result<Self> {
+        if !(input.is_vapoursynth() || input.is_vapoursynth_script()) {
+            return Self::from_video(input.as_video_path());
+        }
+
+        // from_file sets the working directory to the directory of the script,
+        // which user-provided scripts may rely on
+        let decoder = VapoursynthDecoder::from_file(
+            input.as_script_path(),
+            input.as_vspipe_args_hashmap()?,
+            None,
+        )?;
+        Ok(Self::from_decoder(
+            Decoder::from_decoder_impl(DecoderImpl::Vapoursynth(decoder))?,
+            1,
+        ))
+    }
+
+    /// Decodes the video file at `path` through FFmpeg
+    pub(crate) fn from_video(path: &Path) -> anyhow::Result<Self> {
+        let mut child = Command::new("ffmpeg")
+            .args(["-hide_banner", "-loglevel", "error", "-i"])
+            .arg(path)
+            .args(["-f", "yuv4mpegpipe", "-strict", "-1", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().expect("ffmpeg should have stdout");
+        let mut stderr = child.stderr.take().expect("ffmpeg should have stderr");
+        let mut ffmpeg = FfmpegProcess {
+            _guard: ChildGuard::new(&child),
+            child,
+            stderr: Some(thread::spawn(move || {
+                let mut buf = String::new();
+                let _ = stderr.read_to_string(&mut buf);
+                buf
+            })),
+        };
+
+        match Self::from_y4m(stdout, 1) {
+            Ok(mut frames) => {
+                frames.ffmpeg = Some(ffmpeg);
+                Ok(frames)
+            },
+            Err(e) => Err(e.context(format!(
+                "FFmpeg failed to decode {path}:\n{stderr}",
+                path = path.display(),
+                stderr = ffmpeg.stop()
+            ))),
+        }
+    }
+
+    /// Decodes the y4m stream read from `reader`, handing out only every
+    /// `step`-th frame, starting with the first
+    pub(crate) fn from_y4m(reader: impl Read + 'static, step: usize) -> anyhow::Result<Self> {
+        let decoder = Y4mDecoder::new(Box::new(reader) as Box<dyn Read>)?;
+        Ok(Self::from_decoder(
+            Decoder::from_decoder_impl(DecoderImpl::Y4m(decoder))?,
+            step,
+        ))
+    }
+
+    fn from_decoder(decoder: Decoder, step: usize) -> Self {
+        let bit_depth = decoder.get_video_details().bit_depth;
+        Self {
+            decoder,
+            bit_depth,
+            index: 0,
+            step: step.max(1),
+            planes: Default::default(),
+            has_chroma: false,
+            ffmpeg: None,
+        }
+    }
+
+    /// Width, height and bit depth of the frames
+    #[inline]
+    #[must_use]
+    pub fn geometry(&self) -> (usize, usize, usize) {
+        let details = self.decoder.get_video_details();
+        (details.width, details.height, details.bit_depth)
+    }
+
+    /// Number of frames of the input, if the decoder knows it in advance
+    #[inline]
+    #[must_use]
+    pub fn total_frames(&self) -> Option<usize> {
+        self.decoder.get_video_details().total_frames
+    }
+
+    /// Decodes the next frame, or returns `None` at the end of the input
+    #[inline]
+    pub fn next_frame(&mut self) -> anyhow::Result<Option<FrameRef<'_>>> {
+        let read = if self.bit_depth > 8 {
+            self.decoder.read_video_frame::<u16>().map(|frame| self.store(&frame))
+        } else {
+            self.decoder.read_video_frame::<u8>().map(|frame| self.store(&frame))
+        };
+        match read {
+            Ok(()) => {},
+            Err(DecoderError::EndOfFile) => {
+                if let Some(mut ffmpeg) = self.ffmpeg.take() {
+                    ffmpeg.finish()?;
+                }
+                return Ok(None);
+            },
+            Err(e) => return Err(self.decode_error(e.into())),
+        }
+
+        let index = self.index;
+        self.index += self.step;
+        for _ in 1..self.step {
+            let skipped = if self.bit_depth > 8 {
+                self.decoder.read_video_frame::<u16>().map(drop)
+            } else {
+                self.decoder.read_video_frame::<u8>().map(drop)
+            };
+            match skipped {
+                Ok(()) => {},
+                Err(DecoderError::EndOfFile) => break,
+                Err(e) => return Err(self.decode_error(e.into())),
+            }
+        }
+        let [y, u, v] = &self.planes;
+        Ok(Some(FrameRef {
+            index,
+            width: y.1,
+            height: y.2,
+            bit_depth: self.bit_depth,
+            luma: plane(y),
+            chroma: self.has_chroma.then(|| [plane(u), plane(v)]),
+        }))
+    }
+
+    /// Adds the stderr of FFmpeg to an error of the decoder, stopping FFmpeg
+    fn decode_error(&mut self, e: anyhow::Error) -> anyhow::Error {
+        match self.ffmpeg.take() {
+            Some(mut ffmpeg) => e.context(format!("FFmpeg failed:\n{}", ffmpeg.stop())),
+            None => e,
+        }
+    }
+
+    /// Copies the planes of `frame` into the buffers of the frame handed out
+    fn store<T: Pixel>(&mut self, frame: &Frame<T>) {
+        self.has_chroma = frame.u_plane.is_some() && frame.v_plane.is_some();
+        let planes = [Some(&frame.y_plane), frame.u_plane.as_ref(), frame.v_plane.as_ref()];
+        for (buffer, plane) in self.planes.iter_mut().zip(planes) {
+            match plane {
+                Some(plane) => copy_plane(plane, buffer),
+                None => *buffer = (Vec::new(), 0, 0),
+            }
+        }
+    }
+}
+
+fn plane((samples, width, height): &(Vec<u16>, usize, usize)) -> PlaneRef<'_> {
+    PlaneRef {
+        width:   *width,
+        height:  *height,
+        samples: samples.as_slice(),
+    }
+}
+
+/// Copies the visible samples of `plane` into `buffer`, widened to 16 bits
+fn copy_plane<T: Pixel>(plane: &Plane<T>, buffer: &mut (Vec<u16>, usize, usize)) {
+    let (samples, width, height) = buffer;
+    samples.clear();
+    samples.reserve(plane.width() * plane.height());
+    for row in plane.rows() {
+        samples.extend(row.iter().map(|&sample| sample.into()));
+    }
+    *width = plane.width();
+    *height = plane.height();
+}
+
+#[cfg(test)]
+mod tests {
+    use av_decoders::v_frame::{chroma::ChromaSubsampling, frame::FrameBuilder};
+
+    use super::{copy_plane, FfmpegProcess, InputFrames};
+
+    #[test]
+    fn planes_are_copied_without_padding() {
+        let mut frame = FrameBuilder::new(4, 2, ChromaSubsampling::Yuv420, 8)
+            .luma_padding_left(8)
+            .luma_padding_right(8)
+            .build::<u8>()
+            .expect("frame should build");
+        for (y, row) in frame.y_plane.rows_mut().enumerate() {
+            for (x, sample) in row.iter_mut().enumerate() {
+                *sample = (y * 10 + x) as u8 + 200;
+            }
+        }
+
+        let mut buffer = (vec![7; 3], 0, 0);
+        copy_plane(&frame.y_plane, &mut buffer);
+        assert_eq!(buffer, (vec![200, 201, 202, 203, 210, 211, 212, 213], 4, 2));
+
+        let u_plane = frame.u_plane.as_ref().expect("frame should have chroma");
+        copy_plane(u_plane, &mut buffer);
+        assert_eq!((buffer.0.len(), buffer.1, buffer.2), (2, 2, 1));
+    }
+
+    #[test]
+    fn y4m_frames_are_stepped() {
+        let mut y4m = b"YUV4MPEG2 W2 H2 F25:1 Ip A1:1 C420jpeg\n".to_vec();
+        for luma in 0..5 {
+            y4m.extend_from_slice(b"FRAME\n");
+            y4m.extend_from_slice(&[luma; 4]);
+            y4m.extend_from_slice(&[128, 128]);
+        }
+
+        let mut frames =
+            InputFrames::from_y4m(std::io::Cursor::new(y4m), 2).expect("y4m header should parse");
+        let mut read = Vec::new();
+        while let Some(frame) = frames.next_frame().expect("frame should decode") {
+            read.push((frame.index, frame.luma.samples[0]));
+        }
+        assert_eq!(read, [(0, 0), (2, 2), (4, 4)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_decoder_reports_stderr() {
+        use std::{
+            io::Read,
+            process::{Command, Stdio},
+            thread,
+        };
+
+        use crate::children::ChildGuard;
+
+        let mut child = Command::new("sh")
+            .args(["-c", "echo broken input >&2; exit 3"])
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("sh should run");
+        let mut stderr = child.stderr.take().expect("sh should have stderr");
+        let mut process = FfmpegProcess {
+            _guard: ChildGuard::new(&child),
+            child,
+            stderr: Some(thread::spawn(move || {
+                let mut buf = String::new();
+                let _ = stderr.read_to_string(&mut buf);
+                buf
+            })),
+        };
+        let e = process.finish().expect_err("exit status 3 should fail");
+        assert!(e.to_string().contains("broken input"));
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
+};
+
+use anyhow::{bail, ensure, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::info;
+
+use crate::{
+    children::{self, ChildGuard},
+    chunk::Chunk,
+    Input,
+};
+
+/// Where the HDR10+ dynamic metadata of the input comes from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Hdr10PlusSource {
+    /// Extracted from the HEVC video stream of the input with hdr10plus_tool
+    Extract,
+    /// JSON file in the format written by hdr10plus_tool
+    Json(PathBuf),
+}
+
+impl FromStr for Hdr10PlusSource {
+    type Err = std::convert::Infallible;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(if s == "extract" {
+            Self::Extract
+        } else {
+            Self::Json(PathBuf::from(s))
+        })
+    }
+}
+
+/// HDR10+ metadata of every frame of the input
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Hdr10PlusMetadata {
+    /// Top level fields other than the frames and their summary, such as
+    /// `JSONInfo`
+    info:   Map<String, Value>,
+    /// `SceneInfo` entry of each frame
+    frames: Vec<Map<String, Value>>,
+}
+
+impl Hdr10PlusMetadata {
+    /// Reads the metadata from `source`, extracting it into the temp folder
+    /// unless it was already extracted by a previous run
+    pub(crate) fn load(
+        source: &Hdr10PlusSource,
+        input: &Input,
+        temp: &str,
+    ) -> anyhow::Result<Self> {
+        let path = match source {
+            Hdr10PlusSource::Json(path) => path.clone(),
+            Hdr10PlusSource::Extract => {
+                let path = Path::new(temp).join("hdr10plus.json");
+                if !path.exists() {
+                    info!("Extracting HDR10+ metadata");
+                    extract(input.as_video_path(), &path)?;
+                }
+                path
+            },
+        };
+
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read HDR10+ metadata {}", path.display()))?;
+        Self::parse(&text)
+            .with_context(|| format!("Failed to parse HDR10+ metadata {}", path.display()))
+    }
+
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        let Value::Object(mut info) = serde_json::from_str(text)? else {
+            bail!("expected a JSON object");
+        };
+        let Some(Value::Array(frames)) = info.remove("SceneInfo") else {
+            bail!("missing the SceneInfo array");
+        };
+        info.remove("SceneInfoSummary");
+        let frames = frames
+            .into_iter()
+            .map(|frame| match frame {
+                Value::Object(frame) => Ok(frame),
+                _ => bail!("expected the SceneInfo entries to be objects"),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        ensure!(!frames.is_empty(), "the SceneInfo array is empty");
+
+        Ok(Self {
+            info,
+            frames,
+        })
+    }
+
+    pub(crate) fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Metadata of the source frames from `start_frame` to `end_frame` that are
+    /// passed to the encoder, with the frame and scene indices renumbered from
+    /// `start_frame`. `dropped_frames` are relative to `start_frame`, as in a
+    /// [`Chunk`]. Returns `None` if the metadata ends before `start_frame`.
+    fn chunk_metadata(
+        &self,
+        start_frame: usize,
+        end_frame: usize,
+        dropped_frames: &[(usize, usize)],
+    ) -> Option<Value> {
+        let mut frames = Vec::new();
+        let mut first_frames = Vec::new();
+        let mut frame_counts = Vec::new();
+        let mut previous_scene = None;
+        for frame in 0..end_frame - start_frame {
+            if dropped_frames.iter().any(|&(start, end)| (start..end).contains(&frame)) {
+                continue;
+            }
+            let Some(metadata) = self.frames.get(start_frame + frame) else {
+                break;
+            };
+
+            let scene = metadata.get("SceneId").cloned();
+            if previous_scene.is_none() || previous_scene != Some(scene.clone()) {
+                first_frames.push(frames.len());
+                frame_counts.push(0);
+                previous_scene = Some(scene);
+            }
+            let scene_frame = frame_counts.last_mut().expect("a scene was started");
+
+            let mut metadata = metadata.clone();
+            metadata.insert("SequenceFrameIndex".to_string(), json!(frames.len()));
+            metadata.insert("SceneId".to_string(), json!(first_frames.len() - 1));
+            metadata.insert("SceneFrameIndex".to_string(), json!(*scene_frame));
+            *scene_frame += 1;
+            frames.push(Value::Object(metadata));
+        }
+        if frames.is_empty() {
+            return None;
+        }
+
+        let mut metadata = self.info.clone();
+        metadata.insert("SceneInfo".to_string(), Value::Array(frames));
+        metadata.insert(
+            "SceneInfoSummary".to_string(),
+            json!({
+                "SceneFirstFrameIndex": first_frames,
+                "SceneFrameNumbers": frame_counts,
+            }),
+        );
+        Some(Value::Object(metadata))
+    }
+
+    /// Writes the metadata of `chunk` to the temp folder, returning its path
+    /// or `None` if the metadata ends before the chunk
+    pub(crate) fn write_chunk(&self, chunk: &Chunk) -> anyhow::Result<Option<PathBuf>> {
+        let Some(metadata) =
+            self.chunk_metadata(chunk.start_frame, chunk.end_frame, &chunk.dropped_frames)
+        else {
+            return Ok(None);
+        };
+        let path = Path::new(&chunk.temp)
+            .join("split")
+            .join(format!("{}_hdr10plus.json", chunk.name()));
+        fs::write(&path, serde_json::to_string(&metadata)?)?;
+        Ok(Some(path))
+    }
+}
+
+/// Extracts the HDR10+ metadata of the HEVC video stream of `input` to `output`
+fn extract(input: &Path, output: &Path) -> anyhow::Result<()> {
+    let mut ffmpeg = Command::new("ffmpeg")
+        .args(["-hide_banner", "-loglevel", "error", "-i"])
+        .arg(input)
+        .args(["-map", "0:v:0", "-c:v", "copy", "-bsf:v", "hevc_mp4toannexb", "-f", "hevc", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to spawn ffmpeg")?;
+    let _ffmpeg_guard = ChildGuard::new(&ffmpeg);
+    let stream = ffmpeg.stdout.take().expect("ffmpeg stdout should be piped");
+    let out = children::output(
+        Command::new("hdr10plus_tool")
+            .arg("extract")
+            .arg("-o")
+            .arg(output)
+            .arg("-")
+            .stdin(stream),
+    )
+    .context("Failed to run hdr10plus_tool, is it installed?")?;
+    let _ = ffmpeg.wait();
+
+    ensure!(
+        out.status.success() && output.exists(),
+        "hdr10plus_tool could not extract HDR10+ metadata from {}: {}",
+        input.display(),
+        String::from_utf8_lossy(&out.stderr).trim()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Hdr10PlusMetadata;
+
+    #[test]
+    fn chunk_metadata_is_renumbered() {
+        // Two scenes of 3 and 5 frames
+        let frames = (0..8)
+            .map(|frame| {
+                let scene = usize::from(frame >= 3);
+                json!({
+                    "SceneId": scene,
+                    "SceneFrameIndex": if scene == 0 { frame } else { frame - 3 },
+                    "SequenceFrameIndex": frame,
+                    "LuminanceParameters": { "AverageRGB": frame * 10 },
+                })
+            })
+            .collect::<Vec<_>>();
+        let metadata = Hdr10PlusMetadata::parse(
+            &json!({
+                "JSONInfo": { "HDR10plusProfile": "B", "Version": "1.0" },
+                "SceneInfo": frames,
+                "SceneInfoSummary": {
+                    "SceneFirstFrameIndex": [0, 3],
+                    "SceneFrameNumbers": [3, 5],
+                },
+            })
+            .to_string(),
+        )
+        .expect("metadata should parse");
+        assert_eq!(metadata.frames(), 8);
+
+        let chunk_metadata = metadata.chunk_metadata(2, 7, &[(2, 3)]).expect("chunk has metadata");
+        assert_eq!(chunk_metadata["JSONInfo"]["HDR10plusProfile"], "B");
+        assert_eq!(
+            chunk_metadata["SceneInfoSummary"],
+            json!({ "SceneFirstFrameIndex": [0, 1], "SceneFrameNumbers": [1, 3] })
+        );
+        let scene_info = chunk_metadata["SceneInfo"].as_array().expect("SceneInfo is an array");
+        // Source frames 2, 3, 5 and 6, as frame 4 is dropped
+        let summary = scene_info
+            .iter()
+            .map(|frame| {
+                (
+                    frame["LuminanceParameters"]["AverageRGB"].as_u64().unwrap_or_default(),
+                    frame["SequenceFrameIndex"].as_u64().unwrap_or_default(),
+                    frame["SceneId"].as_u64().unwrap_or_default(),
+                    frame["SceneFrameIndex"].as_u64().unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [
+            (20, 0, 0, 0),
+            (30, 1, 1, 0),
+            (50, 2, 1, 1),
+            (60, 3, 1, 2)
+        ]);
+
+        assert_eq!(metadata.chunk_metadata(8, 10, &[]), None);
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt::Write;
+
+use crate::stats::SceneStats;
+
+/// Width of the timeline in the SVG, not counting the labels
+const TIMELINE_WIDTH: f64 = 1600.0;
+const LABEL_WIDTH: f64 = 80.0;
+const ROW_HEIGHT: f64 = 60.0;
+
+/// Draws a timeline of `scenes` as an SVG with a row colored by bitrate and a
+/// row colored by quality score, where the highest bitrates and the lowest
+/// scores are red. Every scene shows its details in a tooltip.
+pub(crate) fn heatmap_svg(scenes: &[SceneStats], score_name: &str) -> String {
+    let total_frames =
+        scenes.iter().map(|scene| scene.start_frame + scene.frames).max().unwrap_or(1);
+    let kbps_range = range(scenes.iter().map(|scene| Some(scene.kbps)));
+    let score_range = range(scenes.iter().map(|scene| scene.score));
+
+    let width = LABEL_WIDTH + TIMELINE_WIDTH;
+    let height = ROW_HEIGHT.mul_add(2.0, 30.0);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         font-family=\"sans-serif\" font-size=\"14\">\n<rect width=\"100%\" height=\"100%\" \
+         fill=\"white\"/>\n<text x=\"4\" y=\"{kbps_y}\">kbps</text>\n<text x=\"4\" \
+         y=\"{score_y}\">{score_name}</text>\n",
+        kbps_y = ROW_HEIGHT / 2.0,
+        score_y = ROW_HEIGHT * 1.5,
+    );
+
+    for scene in scenes {
+        let x =
+            (scene.start_frame as f64 / total_frames as f64).mul_add(TIMELINE_WIDTH, LABEL_WIDTH);
+        let scene_width = (scene.frames as f64 / total_frames as f64 * TIMELINE_WIDTH).max(1.0);
+        let title = format!(
+            "Scene {index}: frames {start}-{end} ({frames} frames)\nSize: {size:.1} KiB, \
+             {kbps:.0} kbps\nQuantizer: {quantizer}\n{score_name}: {score}",
+            index = scene.index,
+            start = scene.start_frame,
+            end = scene.start_frame + scene.frames,
+            frames = scene.frames,
+            size = scene.size_bytes as f64 / 1024.0,
+            kbps = scene.kbps,
+            quantizer = scene.quantizer.map_or_else(|| "-".to_string(), |q| format!("{q:.2}")),
+            score = scene.score.map_or_else(|| "-".to_string(), |score| format!("{score:.3}")),
+        );
+
+        let kbps_color = heat_color(normalize(scene.kbps, kbps_range));
+        // Low scores are the problem spots, so they are drawn hot
+        let score_color = scene.score.map_or_else(
+            || "#cccccc".to_string(),
+            |score| heat_color(1.0 - normalize(score, score_range)),
+        );
+        for (row, color) in [(0.0, kbps_color), (ROW_HEIGHT, score_color)] {
+            writeln!(
+                svg,
+                "<rect x=\"{x:.2}\" y=\"{row}\" width=\"{scene_width:.2}\" \
+                 height=\"{ROW_HEIGHT}\" fill=\"{color}\"><title>{title}</title></rect>"
+            )
+            .expect("write to string should work");
+        }
+    }
+
+    writeln!(
+        svg,
+        "<text x=\"{LABEL_WIDTH}\" y=\"{y}\">0</text>\n<text x=\"{width}\" y=\"{y}\" \
+         text-anchor=\"end\">{total_frames}</text>\n</svg>",
+        y = ROW_HEIGHT.mul_add(2.0, 20.0),
+    )
+    .expect("write to string should work");
+
+    svg
+}
+
+/// Smallest and largest of the values, if there are any
+fn range(values: impl Iterator<Item = Option<f64>>) -> Option<(f64, f64)> {
+    values.flatten().fold(None, |range, value| match range {
+        None => Some((value, value)),
+        Some((min, max)) => Some((min.min(value), max.max(value))),
+    })
+}
+
+/// Position of `value` in `range` from 0 to 1, or 0.5 if all values are the
+/// same
+fn normalize(value: f64, range: Option<(f64, f64)>) -> f64 {
+    match range {
+        Some((min, max)) if max > min => ((value - min) / (max - min)).clamp(0.0, 1.0),
+        _ => 0.5,
+    }
+}
+
+/// Color from green at 0 over yellow to red at 1
+fn heat_color(heat: f64) -> String {
+    let red = (heat * 2.0).min(1.0) * 255.0;
+    let green = ((1.0 - heat) * 2.0).min(1.0) * 200.0;
+    format!("#{:02x}{:02x}40", red.round() as u8, green.round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{heat_color, heatmap_svg};
+    use crate::stats::SceneStats;
+
+    #[test]
+    fn heatmap_colors_scenes_by_bitrate_and_score() {
+        let scenes = [
+            SceneStats {
+                index:       0,
+                start_frame: 0,
+                frames:      100,
+                size_bytes:  10240,
+                kbps:        1000.0,
+                quantizer:   Some(30.0),
+                score:       Some(95.0),
+                encode_fps:  None,
+                retries:     None,
+            },
+            SceneStats {
+                index:       1,
+                start_frame: 100,
+                frames:      300,
+                size_bytes:  81920,
+                kbps:        3000.0,
+                quantizer:   None,
+                score:       Some(85.0),
+                encode_fps:  None,
+                retries:     None,
+            },
+        ];
+        let svg = heatmap_svg(&scenes, "VMAF");
+
+        assert_eq!(svg.matches("<rect x=").count(), 4);
+        assert!(svg.contains("Scene 1: frames 100-400 (300 frames)\nSize: 80.0 KiB, 3000 kbps"));
+        assert!(svg.contains("Quantizer: -\nVMAF: 85.000"));
+        // The first scene has the lowest bitrate and the highest score
+        let green = heat_color(0.0);
+        let red = heat_color(1.0);
+        assert_eq!(green, "#00c840");
+        assert_eq!(red, "#ff0040");
+        assert_eq!(svg.matches(&format!("fill=\"{green}\"")).count(), 2);
+        assert_eq!(svg.matches(&format!("fill=\"{red}\"")).count(), 2);
+        // The second scene starts a quarter into the timeline
+        assert!(svg.contains("<rect x=\"480.00\" y=\"0\" width=\"1200.00\""));
+    }
+}
@@ -4,7 +4,7 @@ use std::{
     fs::{self, read_to_string, File},
     hash::{Hash, Hasher},
     io::Write,
-    path::{Path, PathBuf},
+    path::{absolute, Path, PathBuf},
     string::ToString,
     sync::{
         atomic::{AtomicBool, AtomicUsize},
@@ -22,44 +22,88 @@ use dashmap::DashMap;
 use once_cell::sync::{Lazy, OnceCell};
 use serde::{Deserialize, Serialize};
 use strum::{Display, EnumString, IntoStaticStr};
-use tracing::info;
+use tracing::{info, warn};
 
 pub use crate::{
+    broker::{encode_state, set_encode_state, EncodeState},
+    children::kill_children,
     concat::ConcatMethod,
     context::Av1anContext,
+    device::DeviceTarget,
     encoder::Encoder,
-    settings::{EncodeArgs, InputPixelFormat, PixelFormat, PixelFormatConverter},
+    frames::{FrameRef, InputFrames, PlaneRef},
+    hdr10plus::Hdr10PlusSource,
+    metrics::{
+        plugin::{register_scorer, QualityScorer},
+        vmaf::read_vmaf_file,
+    },
+    parse::EncoderStats,
+    priority::ProcessPriority,
+    progress_bar::{ProgressStage, PROGRESS_SCHEMA, PROGRESS_SCHEMA_VERSION},
+    scenes::{AnnotationKind, IgnoreRange, SceneAnnotation, ScenesFormat},
+    settings::{
+        merge_video_params,
+        EncodeArgs,
+        InputPixelFormat,
+        PixelFormat,
+        PixelFormatConverter,
+    },
+    stats::StatsFormat,
     target_quality::{InterpolationMethod, TargetQuality},
-    util::read_in_dir,
+    test_pattern::{PatternKind, TestPattern},
+    util::{executable_search_path, read_in_dir},
 };
 use crate::{
     ffmpeg::FFPixelFormat,
     progress_bar::finish_progress_bar,
-    vapoursynth::{create_vs_file, generate_loadscript_text, CacheSource, LoadscriptArgs},
+    resources::ResourceUsage,
+    util::{remap_path_buf, remap_paths},
+    vapoursynth::{
+        create_vs_file,
+        generate_loadscript_text,
+        parse_vspipe_arg,
+        CacheSource,
+        LoadscriptArgs,
+    },
 };
 
+mod bitstream;
 mod broker;
+mod children;
 mod chunk;
 mod concat;
 mod context;
+mod device;
 mod encoder;
+#[cfg(feature = "failure-injection")]
+mod failure_injection;
 pub mod ffmpeg;
+mod frames;
+mod hdr10plus;
+mod heatmap;
 mod metrics {
     pub mod butteraugli;
+    pub mod plugin;
     pub mod statistics;
     pub mod vmaf;
     pub mod xpsnr;
 }
 mod interpol;
 mod parse;
+mod priority;
 mod progress_bar;
+mod resources;
 mod scene_detect;
 mod scenes;
 mod settings;
 mod split;
+mod stats;
 mod target_quality;
+mod temp_lock;
+mod test_pattern;
 mod util;
 pub mod vapoursynth;
+mod y4m;
 mod zones;
 
 static CLIP_INFO_CACHE: Lazy<Mutex<HashMap<CacheKey, ClipInfo>>> =
@@ -109,6 +153,15 @@ impl Input {
             if ext == "py" || ext == "vpy" {
                 let input_path = path.into();
                 let script_text = read_to_string(input_path.clone())?;
+                for arg in &vspipe_args {
+                    let (key, _) = parse_vspipe_arg(arg)?;
+                    if !script_text.contains(key) {
+                        warn!(
+                            "vspipe argument {key} is not used by {script}",
+                            script = input_path.display()
+                        );
+                    }
+                }
                 Ok::<Self, anyhow::Error>(Self::VapourSynth {
                     path: input_path,
                     vspipe_args,
@@ -167,6 +220,26 @@ impl Input {
         Ok(input)
     }
 
+    /// Creates an input that generates `pattern` with VapourSynth. The script
+    /// is written to the temp folder and is recreated there if the folder is
+    /// cleared when the encode starts.
+    #[inline]
+    pub fn test_pattern(pattern: TestPattern, temporary_directory: &str) -> anyhow::Result<Self> {
+        let split_folder = Path::new(temporary_directory).join("split");
+        fs::create_dir_all(&split_folder)?;
+        let path = split_folder.join(pattern.file_name());
+        let script_text = pattern.script_text();
+        fs::write(&path, &script_text)
+            .with_context(|| format!("Failed to write test pattern script {}", path.display()))?;
+
+        Ok(Self::VapourSynth {
+            path,
+            vspipe_args: Vec::new(),
+            script_text,
+            is_proxy: false,
+        })
+    }
+
     /// Returns a reference to the inner path, panicking if the input is not an
     /// `Input::Video`.
     #[inline]
@@ -278,6 +351,33 @@ impl Input {
         }
     }
 
+    /// Rewrites the paths of the input and its temp folder with `remaps`, see
+    /// [`remap_paths`]
+    pub(crate) fn remap_paths(&mut self, remaps: &[(String, String)]) {
+        match self {
+            Input::VapourSynth {
+                path,
+                script_text,
+                ..
+            } => {
+                remap_path_buf(path, remaps);
+                if let Some(remapped) = remap_paths(script_text, remaps) {
+                    *script_text = remapped;
+                }
+            },
+            Input::Video {
+                path,
+                temp,
+                ..
+            } => {
+                remap_path_buf(path, remaps);
+                if let Some(remapped) = remap_paths(temp, remaps) {
+                    *temp = remapped;
+                }
+            },
+        }
+    }
+
     #[inline]
     pub const fn is_video(&self) -> bool {
         matches!(&self, Input::Video { .. })
@@ -389,9 +489,9 @@ impl Input {
         );
 
         for arg in self.as_vspipe_args_vec()? {
-            let split: Vec<&str> = arg.split_terminator('=').collect();
-            if args_map.set_data(split[0], split[1].as_bytes()).is_err() {
-                bail!("Failed to split vspipe arguments");
+            let (key, value) = parse_vspipe_arg(&arg)?;
+            if args_map.set_data(key, value.as_bytes()).is_err() {
+                bail!("Failed to set vspipe argument {key}");
             };
         }
 
@@ -402,17 +502,65 @@ impl Input {
     pub fn as_vspipe_args_hashmap(&self) -> anyhow::Result<HashMap<String, String>> {
         let mut args_map = HashMap::new();
         for arg in self.as_vspipe_args_vec()? {
-            let split: Vec<&str> = arg.split_terminator('=').collect();
-            args_map.insert(split[0].to_string(), split[1].to_string());
+            let (key, value) = parse_vspipe_arg(&arg)?;
+            args_map.insert(key.to_string(), value.to_string());
         }
         Ok(args_map)
     }
+
+    /// Opens a decoder returning the frames of the input one at a time, for
+    /// tools that need the decoded video instead of an encode
+    #[inline]
+    pub fn frames(&self) -> anyhow::Result<InputFrames> {
+        InputFrames::new(self)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy)]
 struct DoneChunk {
     frames:     usize,
     size_bytes: u64,
+    /// PSNR reported by the encoder, if its PSNR output is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    psnr:       Option<f64>,
+    /// Average QP reported by the encoder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    avg_qp:     Option<f64>,
+    /// Quantizer chosen by target quality
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tq_cq:      Option<f32>,
+    /// Score that target quality probed or predicted for `tq_cq`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tq_score:   Option<f64>,
+    /// Largest combined resident set size of the chunk's processes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    peak_rss:   Option<u64>,
+    /// Frames per second at which the chunk was encoded, including target
+    /// quality probing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encode_fps: Option<f64>,
+    /// Times the encoder failed on the chunk before it succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retries:    Option<u32>,
+}
+
+/// Absolute locations of the temp folder and inputs of an encode, which the
+/// paths saved in `chunks.json` are remapped from when they change
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct SavedRoots {
+    temp:  PathBuf,
+    input: PathBuf,
+    proxy: Option<PathBuf>,
+}
+
+impl SavedRoots {
+    fn new(args: &EncodeArgs) -> std::io::Result<Self> {
+        Ok(Self {
+            temp:  absolute(&args.temp)?,
+            input: absolute(args.input.as_path())?,
+            proxy: args.proxy.as_ref().map(|proxy| absolute(proxy.as_path())).transpose()?,
+        })
+    }
 }
 
 /// Concurrent data structure for keeping track of the finished chunks in an
@@ -422,8 +570,17 @@ struct DoneJson {
     frames:     AtomicUsize,
     done:       DashMap<String, DoneChunk>,
     audio_done: AtomicBool,
+    /// Resources used by all runs of the encode so far
+    #[serde(default)]
+    resources:  Mutex<ResourceUsage>,
+    /// Locations of the temp folder and inputs when the chunks were saved
+    #[serde(default)]
+    roots:      Mutex<Option<SavedRoots>>,
 }
 
+/// Exit status used when `--time-budget` runs out before all chunks are encoded
+pub const TIME_BUDGET_EXIT_CODE: i32 = 3;
+
 static DONE_JSON: OnceCell<DoneJson> = OnceCell::new();
 
 // once_cell::sync::Lazy cannot be used here due to Lazy<T> not implementing
@@ -451,6 +608,20 @@ pub fn list_index(params: &[impl AsRef<str>], is_match: fn(&str) -> bool) -> Opt
 pub enum SplitMethod {
     #[strum(serialize = "av-scenechange")]
     AvScenechange,
+    /// Cut where the scene score of FFmpeg's `select` filter exceeds
+    /// `--sc-threshold`
+    #[strum(serialize = "ffmpeg")]
+    FFmpeg,
+    /// Import the scenes detected by PySceneDetect from `--sc-import`
+    #[strum(serialize = "pyscenedetect")]
+    PySceneDetect,
+    /// Import the scenes of `--sc-import` in `--scenes-format`
+    #[strum(serialize = "import")]
+    Import,
+    /// Cut at the keyframes of the input, so that the scenes can be trimmed
+    /// from the input without reencoding
+    #[strum(serialize = "keyframes")]
+    Keyframes,
     #[strum(serialize = "none")]
     None,
 }
@@ -463,6 +634,19 @@ pub enum ScenecutMethod {
     Standard,
 }
 
+/// How scene detection handles inputs with the PQ transfer function
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, EnumString, IntoStaticStr, Display,
+)]
+pub enum ScenecutPqMode {
+    /// Tone map the luma plane to SDR before detection
+    #[strum(serialize = "tonemap")]
+    Tonemap,
+    /// Detect on the PQ signal as it is
+    #[strum(serialize = "none")]
+    None,
+}
+
 #[derive(
     PartialEq,
     Eq,
@@ -493,6 +677,23 @@ pub enum ChunkMethod {
     BESTSOURCE,
 }
 
+/// What to do if the output file already exists when it is about to be
+/// written
+#[derive(
+    PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug, Display, EnumString, IntoStaticStr,
+)]
+pub enum OverwritePolicy {
+    /// Stop without touching the existing file
+    #[strum(serialize = "fail")]
+    Fail,
+    /// Rename the existing file to a backup
+    #[strum(serialize = "backup")]
+    Backup,
+    /// Replace the existing file
+    #[strum(serialize = "overwrite")]
+    Overwrite,
+}
+
 #[derive(
     PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug, Display, EnumString, IntoStaticStr,
 )]
@@ -507,6 +708,40 @@ pub enum ChunkOrdering {
     Random,
 }
 
+/// When to keep the temporary folder, with the split scenes, probes and
+/// encoded chunks, after an encode
+#[derive(
+    PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug, Display, EnumString, IntoStaticStr,
+)]
+pub enum KeepPolicy {
+    /// Always keep the temporary folder
+    #[strum(serialize = "always")]
+    Always,
+    /// Keep the temporary folder only if the encode fails, so it can be
+    /// resumed
+    #[strum(serialize = "on-failure")]
+    OnFailure,
+    /// Delete the temporary folder even if the encode fails
+    #[strum(serialize = "never")]
+    Never,
+}
+
+/// How to handle source frames that fail to decode
+#[derive(
+    PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug, Display, EnumString, IntoStaticStr,
+)]
+pub enum DecodeErrorPolicy {
+    /// Fail the chunk, like any other error in the source pipe
+    #[strum(serialize = "fail")]
+    Fail,
+    /// Replace the frame with the closest previous frame that decodes
+    #[strum(serialize = "duplicate")]
+    Duplicate,
+    /// Replace the frame with a black frame
+    #[strum(serialize = "black")]
+    Black,
+}
+
 #[derive(
     PartialEq,
     Eq,
@@ -549,6 +784,9 @@ pub enum TargetMetric {
     XPSNR,
     #[strum(serialize = "xpsnr-weighted")]
     XPSNRWeighted,
+    /// [`QualityScorer`] registered as [`TargetQuality::metric_plugin`]
+    #[strum(serialize = "plugin")]
+    Plugin,
 }
 
 /// Determine the optimal number of workers for an encoder
@@ -571,6 +809,9 @@ pub fn determine_workers(args: &EncodeArgs) -> anyhow::Result<u64> {
         Encoder::vpx => 0.3,
         Encoder::x264 => 0.7,
         Encoder::x265 => 0.6,
+        Encoder::vvenc => 1.5,
+        // Only decoding and converting the frames, the encoder runs on the GPU
+        Encoder::nvenc | Encoder::qsv | Encoder::vaapi => 0.2,
     };
     // This is a rough estimate of how many cpu cores will be fully loaded by an
     // encoder worker. With rav1e, CPU usage scales with tiles, but not 1:1.
@@ -582,7 +823,9 @@ pub fn determine_workers(args: &EncodeArgs) -> anyhow::Result<u64> {
         Encoder::rav1e => ((tiles.0 * tiles.1) as f32 * 0.7).ceil() as u64,
         Encoder::svt_av1 => 6,
         Encoder::vpx => 3,
+        Encoder::vvenc => 4,
         Encoder::x264 | Encoder::x265 => 8,
+        Encoder::nvenc | Encoder::qsv | Encoder::vaapi => 1,
     };
     // memory usage scales with pixel format, expressed as a multiplier of memory
     // usage. Roughly the same behavior was observed accross all encoders.
@@ -601,13 +844,17 @@ pub fn determine_workers(args: &EncodeArgs) -> anyhow::Result<u64> {
     // use total instead of available, because av1an does not resize worker pool
     let ram_gb = system.total_memory() as f64 / 1e9;
 
-    Ok(std::cmp::max(
+    let workers = std::cmp::max(
         std::cmp::min(
             cpu / cpu_threads,
             (ram_gb / (megapixels * (enc_ram + cm_ram) * pix_mult)).round() as u64,
         ),
         1,
-    ))
+    );
+    Ok(args
+        .encoder
+        .hardware_sessions()
+        .map_or(workers, |sessions| workers.min(sessions)))
 }
 
 #[inline]
@@ -676,12 +923,28 @@ pub struct ProbingStatistic {
     pub value: Option<f64>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString, IntoStaticStr)]
 pub enum ColorRange {
+    #[strum(serialize = "full")]
     Full,
+    #[strum(serialize = "limited")]
     Limited,
 }
 
+/// Field order signaled to the encoder in the y4m header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString, IntoStaticStr)]
+pub enum Interlacing {
+    #[strum(serialize = "progressive")]
+    Progressive,
+    #[strum(serialize = "tff")]
+    TopFieldFirst,
+    #[strum(serialize = "bff")]
+    BottomFieldFirst,
+    /// Progressive and interlaced frames are mixed
+    #[strum(serialize = "mixed")]
+    Mixed,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct ClipInfo {
     pub num_frames:               usize,
@@ -689,6 +952,8 @@ pub struct ClipInfo {
     pub frame_rate:               Rational64,
     pub resolution:               (u32, u32), // (width, height), consider using type aliases
     pub color_range:              Option<ColorRange>,
+    /// (numerator, denominator) of the sample aspect ratio, if known
+    pub sample_aspect_ratio:      Option<(u32, u32)>,
     /// This is overly simplified because we currently only use it for photon
     /// noise gen, which only supports two transfer functions
     pub transfer_characteristics: TransferFunction,
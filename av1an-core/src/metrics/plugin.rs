@@ -0,0 +1,125 @@
+//! Quality metrics provided by programs using Av1an as a library, such as
+//! neural metrics, which target quality uses by name with
+//! [`TargetMetric::Plugin`](crate::TargetMetric::Plugin).
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    path::Path,
+    process::{Command, Stdio},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{bail, ensure, Context};
+use once_cell::sync::Lazy;
+
+use crate::{children::ChildGuard, InputFrames};
+
+/// Metric scoring distorted frames against their reference frames
+pub trait QualityScorer: Send + Sync {
+    /// Scores each frame of `distorted` against the frame of `reference` at the
+    /// same position. Higher scores must mean higher quality. The frames keep
+    /// their decoded resolution, so the distorted frames are smaller than the
+    /// reference frames when probes are encoded at a lower resolution.
+    fn score(
+        &self,
+        reference: &mut InputFrames,
+        distorted: &mut InputFrames,
+    ) -> anyhow::Result<Vec<f64>>;
+}
+
+static SCORERS: Lazy<RwLock<HashMap<String, Arc<dyn QualityScorer>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Makes `scorer` available to target quality as `name`, replacing and
+/// returning the scorer previously registered with that name
+#[inline]
+pub fn register_scorer(
+    name: impl Into<String>,
+    scorer: impl QualityScorer + 'static,
+) -> Option<Arc<dyn QualityScorer>> {
+    SCORERS
+        .write()
+        .expect("lock should not be poisoned")
+        .insert(name.into(), Arc::new(scorer))
+}
+
+/// Scorer registered as `name`
+pub(crate) fn scorer(name: &str) -> Option<Arc<dyn QualityScorer>> {
+    SCORERS.read().expect("lock should not be poisoned").get(name).cloned()
+}
+
+/// Checks that a scorer is registered as `name`
+pub(crate) fn validate_scorer(name: Option<&str>) -> anyhow::Result<()> {
+    let Some(name) = name else {
+        bail!("The plugin target metric requires the name of a registered quality scorer");
+    };
+    ensure!(
+        scorer(name).is_some(),
+        "No quality scorer is registered as {name:?}"
+    );
+    Ok(())
+}
+
+/// Scores the probe at `encoded` with `scorer` against the frames of
+/// `reference_pipe_cmd`, of which only every `probing_rate`-th frame was
+/// encoded
+pub(crate) fn measure_plugin(
+    scorer: &dyn QualityScorer,
+    encoded: &Path,
+    reference_pipe_cmd: &[impl AsRef<OsStr>],
+    vspipe_args: Vec<String>,
+    probing_rate: usize,
+) -> anyhow::Result<Vec<f64>> {
+    let [cmd, args @ ..] = reference_pipe_cmd else {
+        unreachable!()
+    };
+    let mut source_pipe = Command::new(cmd);
+    // Append vspipe python arguments to the environment if there are any
+    for arg in vspipe_args {
+        source_pipe.args(["-a", &arg]);
+    }
+    let mut source_pipe = source_pipe
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let _source_guard = ChildGuard::new(&source_pipe);
+
+    let stdout = source_pipe.stdout.take().expect("source pipe should have stdout");
+    let mut reference = InputFrames::from_y4m(stdout, probing_rate)?;
+    let mut distorted = InputFrames::from_video(encoded)?;
+    let scores = scorer.score(&mut reference, &mut distorted).context("Quality scorer failed")?;
+    // The scorer may stop reading before the end of the reference
+    let _ = source_pipe.kill();
+    let _ = source_pipe.wait();
+
+    Ok(scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{register_scorer, scorer, QualityScorer};
+    use crate::InputFrames;
+
+    struct Constant(f64);
+
+    impl QualityScorer for Constant {
+        fn score(
+            &self,
+            _reference: &mut InputFrames,
+            _distorted: &mut InputFrames,
+        ) -> anyhow::Result<Vec<f64>> {
+            Ok(vec![self.0])
+        }
+    }
+
+    #[test]
+    fn scorers_are_found_by_name() {
+        assert!(register_scorer("test-constant", Constant(1.0)).is_none());
+        assert!(register_scorer("test-constant", Constant(2.0)).is_some());
+        assert!(scorer("test-constant").is_some());
+        assert!(scorer("test-missing").is_none());
+    }
+}
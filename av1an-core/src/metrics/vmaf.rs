@@ -1,6 +1,7 @@
 use std::{
     cmp::Ordering,
     ffi::OsStr,
+    fmt::Write,
     path::Path,
     process::{Command, Stdio},
 };
@@ -12,7 +13,9 @@ use smallvec::SmallVec;
 
 use crate::{
     broker::EncoderCrash,
+    children::{self, ChildGuard},
     ffmpeg,
+    metrics::statistics::MetricStatistics,
     ref_smallvec,
     util::printable_base10_digits,
     Input,
@@ -34,8 +37,23 @@ struct VmafResult {
     frames: Vec<Metrics>,
 }
 
-pub fn plot_vmaf_score_file(scores_file: &Path, plot_path: &Path) -> anyhow::Result<()> {
+/// Plots the per-frame scores in `scores_file` as an SVG.
+///
+/// `sample_rate` is the frame interval the scores were computed at. Sampled
+/// scores are plotted at their source frame numbers and the percentiles are
+/// labeled as sampled.
+pub fn plot_vmaf_score_file(
+    scores_file: &Path,
+    plot_path: &Path,
+    sample_rate: usize,
+) -> anyhow::Result<()> {
     let scores = read_vmaf_file(scores_file).with_context(|| "Failed to parse VMAF file")?;
+    let sample_rate = sample_rate.max(1) as u32;
+    let sampled = if sample_rate > 1 {
+        format!(" (sampled, every {sample_rate} frames)")
+    } else {
+        String::new()
+    };
 
     let mut sorted_scores = scores.clone();
     sorted_scores.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Less));
@@ -43,7 +61,7 @@ pub fn plot_vmaf_score_file(scores_file: &Path, plot_path: &Path) -> anyhow::Res
     let plot_width = 1600 + (printable_base10_digits(scores.len()) * 200);
     let plot_heigth = 600;
 
-    let length = scores.len() as u32;
+    let length = scores.len() as u32 * sample_rate;
 
     let root =
         SVGBackend::new(plot_path.as_os_str(), (plot_width, plot_heigth)).into_drawing_area();
@@ -68,30 +86,30 @@ pub fn plot_vmaf_score_file(scores_file: &Path, plot_path: &Path) -> anyhow::Res
     // 1%
     chart
         .draw_series(LineSeries::new((0..=length).map(|x| (x, perc_1)), RED))?
-        .label(format!("1%: {perc_1}"))
+        .label(format!("1%: {perc_1}{sampled}"))
         .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
 
     // 25%
     chart
         .draw_series(LineSeries::new((0..=length).map(|x| (x, perc_25)), YELLOW))?
-        .label(format!("25%: {perc_25}"))
+        .label(format!("25%: {perc_25}{sampled}"))
         .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], YELLOW));
 
     // 50% (median, except not averaged in the case of an even number of elements)
     chart
         .draw_series(LineSeries::new((0..=length).map(|x| (x, perc_50)), BLACK))?
-        .label(format!("50%: {perc_50}"))
+        .label(format!("50%: {perc_50}{sampled}"))
         .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLACK));
 
     // 75%
     chart
         .draw_series(LineSeries::new((0..=length).map(|x| (x, perc_75)), GREEN))?
-        .label(format!("75%: {perc_75}"))
+        .label(format!("75%: {perc_75}{sampled}"))
         .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], GREEN));
 
     // Data
     chart.draw_series(LineSeries::new(
-        (0..).zip(scores.iter()).map(|(x, y)| (x, *y)),
+        (0..).step_by(sample_rate as usize).zip(scores.iter()).map(|(x, y)| (x, *y)),
         BLUE,
     ))?;
 
@@ -141,7 +159,11 @@ pub fn plot(
     let plot_file = encoded.with_extension("svg");
     let vspipe_args;
 
-    println!(":: VMAF Run");
+    if sample_rate > 1 {
+        println!(":: VMAF Run (sampled, every {sample_rate} frames)");
+    } else {
+        println!(":: VMAF Run");
+    }
 
     let pipe_cmd: SmallVec<[&OsStr; 8]> = match reference {
         Input::Video {
@@ -177,6 +199,7 @@ pub fn plot(
         model,
         res,
         scaler,
+        1,
         sample_rate,
         filter,
         threads,
@@ -185,7 +208,7 @@ pub fn plot(
         probing_vmaf_features,
     )?;
 
-    plot_vmaf_score_file(&json_file, &plot_file)?;
+    plot_vmaf_score_file(&json_file, &plot_file, sample_rate)?;
     Ok(())
 }
 
@@ -211,6 +234,7 @@ pub fn run_vmaf(
     res: &str,
     scaler: &str,
     sample_rate: usize,
+    subsample: usize,
     vmaf_filter: Option<&str>,
     threads: usize,
     framerate: f64,
@@ -233,6 +257,15 @@ pub fn run_vmaf(
         filter.push(',');
     }
 
+    // Unlike `sample_rate`, which only selects from the reference because the
+    // encoded probe already contains just the sampled frames, `subsample` has
+    // libvmaf score every n-th frame of two full-length clips.
+    let subsample = if subsample > 1 {
+        format!(":n_subsample={subsample}")
+    } else {
+        String::new()
+    };
+
     let vmaf = if let Some(model) = model {
         let model_path = if model.as_ref().as_os_str().to_string_lossy().ends_with(".json") {
             format!(
@@ -249,14 +282,15 @@ pub fn run_vmaf(
         };
         format!(
             "[distorted][ref]libvmaf=log_fmt='json':eof_action=endall:log_path={}:model='{}':\
-             n_threads={}",
+             n_threads={}{}",
             ffmpeg::escape_path_in_filter(stat_file)?,
             model_path,
-            threads
+            threads,
+            subsample
         )
     } else {
         format!(
-            "[distorted][ref]libvmaf=log_fmt='json':eof_action=endall:log_path={}{}:n_threads={}",
+            "[distorted][ref]libvmaf=log_fmt='json':eof_action=endall:log_path={}{}:n_threads={}{}",
             ffmpeg::escape_path_in_filter(stat_file)?,
             if disable_motion {
                 format!(
@@ -266,7 +300,8 @@ pub fn run_vmaf(
             } else {
                 String::new()
             },
-            threads
+            threads,
+            subsample
         )
     };
 
@@ -283,6 +318,7 @@ pub fn run_vmaf(
     } else {
         unreachable!()
     };
+    let _source_guard = ChildGuard::new(&source_pipe);
 
     let mut cmd = Command::new("ffmpeg");
     cmd.args([
@@ -317,7 +353,7 @@ pub fn run_vmaf(
     cmd.stderr(Stdio::piped());
     cmd.stdout(Stdio::null());
 
-    let output = cmd.output()?;
+    let output = children::output(&mut cmd)?;
 
     if !output.status.success() {
         return Err(EncoderCrash {
@@ -399,6 +435,7 @@ pub fn run_vmaf_weighted(
     } else {
         unreachable!()
     };
+    let _source_guard = ChildGuard::new(&source_pipe);
 
     let mut cmd = Command::new("ffmpeg");
     cmd.args([
@@ -442,7 +479,7 @@ pub fn run_vmaf_weighted(
     cmd.stderr(Stdio::piped());
     cmd.stdout(Stdio::null());
 
-    let output = cmd.output()?;
+    let output = children::output(&mut cmd)?;
 
     let _ = source_pipe.wait();
 
@@ -471,6 +508,7 @@ pub fn run_vmaf_weighted(
     Ok(weighted_scores)
 }
 
+#[inline]
 pub fn read_vmaf_file(file: impl AsRef<Path>) -> anyhow::Result<Vec<f64>> {
     let json_str = std::fs::read_to_string(file)?;
     let vmaf_results = serde_json::from_str::<VmafResult>(&json_str)?;
@@ -479,6 +517,62 @@ pub fn read_vmaf_file(file: impl AsRef<Path>) -> anyhow::Result<Vec<f64>> {
     Ok(v)
 }
 
+/// Summary of the per-frame scores of a VMAF run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VmafSummary {
+    /// Number of scored frames
+    pub frames:        usize,
+    /// Frame interval the scores were computed at
+    pub sample_rate:   usize,
+    pub mean:          f64,
+    pub harmonic_mean: f64,
+    pub median:        f64,
+    pub percentile_5:  f64,
+    pub percentile_1:  f64,
+    /// Mean of the lowest 1% of the scores
+    pub low_1:         f64,
+    pub minimum:       f64,
+}
+
+impl VmafSummary {
+    /// Summarizes `scores`, which were measured on every `sample_rate`th frame,
+    /// or returns `None` if there are no scores
+    #[inline]
+    pub fn new(scores: &[f64], sample_rate: usize) -> Option<Self> {
+        if scores.is_empty() {
+            return None;
+        }
+
+        let mut sorted_scores = scores.to_vec();
+        sorted_scores.sort_unstable_by(f64::total_cmp);
+        let lowest = &sorted_scores[..scores.len().div_ceil(100)];
+        let mut statistics = MetricStatistics::new(scores.to_vec());
+
+        Some(Self {
+            frames:        scores.len(),
+            sample_rate:   sample_rate.max(1),
+            mean:          statistics.mean(),
+            harmonic_mean: statistics.harmonic_mean(),
+            median:        statistics.median(),
+            percentile_5:  percentile_of_sorted(&sorted_scores, 0.05),
+            percentile_1:  percentile_of_sorted(&sorted_scores, 0.01),
+            low_1:         lowest.iter().sum::<f64>() / lowest.len() as f64,
+            minimum:       sorted_scores[0],
+        })
+    }
+}
+
+/// Formats `scores`, which were measured on every `sample_rate`th frame, as
+/// CSV with the source frame number of each score
+#[inline]
+pub fn scores_csv(scores: &[f64], sample_rate: usize) -> String {
+    let mut csv = String::from("frame,vmaf\n");
+    for (idx, score) in scores.iter().enumerate() {
+        writeln!(csv, "{},{score}", idx * sample_rate.max(1)).expect("write to string should work");
+    }
+    csv
+}
+
 pub fn percentile_of_sorted(scores: &[f64], percentile: f64) -> f64 {
     assert!(!scores.is_empty());
 
@@ -486,3 +580,27 @@ pub fn percentile_of_sorted(scores: &[f64], percentile: f64) -> f64 {
 
     scores[k]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{scores_csv, VmafSummary};
+
+    #[test]
+    fn summary_reports_mean_and_lows() {
+        let scores = (0..200).map(f64::from).collect::<Vec<_>>();
+        let summary = VmafSummary::new(&scores, 2).expect("scores are not empty");
+
+        assert_eq!(summary.frames, 200);
+        assert_eq!(summary.sample_rate, 2);
+        assert!((summary.mean - 99.5).abs() < 1e-9);
+        assert!((summary.low_1 - 0.5).abs() < 1e-9);
+        assert_eq!(summary.percentile_1, 1.0);
+        assert_eq!(summary.minimum, 0.0);
+        assert_eq!(VmafSummary::new(&[], 1), None);
+    }
+
+    #[test]
+    fn scores_csv_uses_source_frame_numbers() {
+        assert_eq!(scores_csv(&[95.5, 90.0], 4), "frame,vmaf\n0,95.5\n4,90\n");
+    }
+}
@@ -9,7 +9,11 @@ use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 use strum::{Display, EnumString, IntoStaticStr};
 
-use crate::{broker::EncoderCrash, ffmpeg};
+use crate::{
+    broker::EncoderCrash,
+    children::{self, ChildGuard},
+    ffmpeg,
+};
 
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, EnumString, IntoStaticStr,
@@ -79,6 +83,7 @@ pub fn run_xpsnr(
     } else {
         unreachable!()
     };
+    let _source_guard = ChildGuard::new(&source_pipe);
 
     let mut cmd = Command::new("ffmpeg");
     cmd.args([
@@ -111,7 +116,7 @@ pub fn run_xpsnr(
     cmd.stderr(Stdio::piped());
     cmd.stdout(Stdio::null());
 
-    let output = cmd.output()?;
+    let output = children::output(&mut cmd)?;
 
     if !output.status.success() {
         println!(
@@ -9,6 +9,8 @@ mod tests;
 
 use std::{borrow::Cow, collections::HashSet};
 
+use serde::{Deserialize, Serialize};
+
 use crate::encoder::Encoder;
 
 // We can safely always ignore this prefix, as the second number will
@@ -253,6 +255,66 @@ pub fn parse_x26x_frames(s: &str) -> Option<u64> {
         .and_then(|s| s.parse().ok())
 }
 
+/// Parses the frame count of the statistics FFmpeg prints with `-stats`, e.g.
+/// `frame=  120 fps= 45 q=30.0 size=     512KiB time=00:00:05.00`
+pub fn parse_ffmpeg_frames(s: &str) -> Option<u64> {
+    s.trim_start()
+        .strip_prefix("frame=")?
+        .split_ascii_whitespace()
+        .next()
+        .and_then(|s| s.parse().ok())
+}
+
+/// Statistics reported by the encoder itself at the end of an encode
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct EncoderStats {
+    pub psnr:   Option<f64>,
+    pub avg_qp: Option<f64>,
+}
+
+impl EncoderStats {
+    #[inline]
+    #[must_use]
+    pub fn parse(encoder: Encoder, output: &str) -> Self {
+        Self {
+            psnr:   parse_encoder_psnr(encoder, output),
+            avg_qp: parse_encoder_avg_qp(encoder, output),
+        }
+    }
+}
+
+/// Parses the number that follows the last occurrence of `marker` in `output`
+fn parse_number_after(output: &str, marker: &str) -> Option<f64> {
+    let (_, rest) = output.rsplit_once(marker)?;
+    rest.split_ascii_whitespace().next()?.trim_end_matches(',').parse().ok()
+}
+
+/// Parses the overall PSNR that the encoder reports at the end of an encode
+/// when its PSNR output is enabled, e.g. with `--psnr`
+#[must_use]
+pub fn parse_encoder_psnr(encoder: Encoder, output: &str) -> Option<f64> {
+    match encoder {
+        Encoder::aom | Encoder::vpx => parse_number_after(output, "PSNR (Overall/Avg/Y/U/V)"),
+        Encoder::x264 => parse_number_after(output, "Global:"),
+        Encoder::x265 => parse_number_after(output, "Global PSNR:"),
+        Encoder::rav1e
+        | Encoder::svt_av1
+        | Encoder::vvenc
+        | Encoder::nvenc
+        | Encoder::qsv
+        | Encoder::vaapi => None,
+    }
+}
+
+/// Parses the average QP that the encoder reports at the end of an encode
+#[must_use]
+pub fn parse_encoder_avg_qp(encoder: Encoder, output: &str) -> Option<f64> {
+    match encoder {
+        Encoder::x265 => parse_number_after(output, "Avg QP:"),
+        _ => None,
+    }
+}
+
 fn strip_ansi_escape_sequences(input: &str) -> Cow<'_, str> {
     const ESC: char = '\x1b';
 
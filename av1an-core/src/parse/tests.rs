@@ -87,6 +87,28 @@ fn x26x_parsing() {
     }
 }
 
+#[test]
+fn ffmpeg_parsing() {
+    let test_cases = [
+        (
+            "frame=  120 fps= 45 q=30.0 size=     512KiB time=00:00:05.00 bitrate= 838.9kbits/s \
+             speed=1.88x",
+            Some(120),
+        ),
+        (
+            "\nframe=12345 fps=300 q=-0.0 Lsize=N/A time=00:08:34.37",
+            Some(12345),
+        ),
+        ("frame=N/A fps=0.0", None),
+        ("[av1_nvenc @ 0x55d0] InitializeEncoder failed", None),
+        ("", None),
+    ];
+
+    for (s, ans) in test_cases {
+        assert_eq!(parse_ffmpeg_frames(s), ans);
+    }
+}
+
 #[test]
 fn svt_av1_parsing() {
     let test_cases = [
@@ -189,3 +211,34 @@ fn aom_vpx_parsing() {
         assert_eq!(parse_aom_vpx_frames(s), ans);
     }
 }
+
+#[test]
+fn encoder_reported_stats() {
+    assert_eq!(
+        parse_encoder_psnr(
+            Encoder::aom,
+            "Pass 1/1 frame  240/240  1234B\nStream 0 PSNR (Overall/Avg/Y/U/V)   38.870  39.121  \
+             37.982  43.876  44.563"
+        ),
+        Some(38.870)
+    );
+    assert_eq!(
+        parse_encoder_psnr(
+            Encoder::x264,
+            "x264 [info]: PSNR Mean Y:41.573 U:45.418 V:46.624 Avg:42.625 Global:42.329 \
+             kb/s:1009.48"
+        ),
+        Some(42.329)
+    );
+
+    let x265 =
+        "encoded 240 frames in 5.65s (42.45 fps), 1234.56 kb/s, Avg QP:32.55, Global PSNR: 39.713";
+    assert_eq!(parse_encoder_psnr(Encoder::x265, x265), Some(39.713));
+    assert_eq!(parse_encoder_avg_qp(Encoder::x265, x265), Some(32.55));
+
+    assert_eq!(
+        parse_encoder_psnr(Encoder::aom, "Pass 1/1 frame  240/240"),
+        None
+    );
+    assert_eq!(parse_encoder_avg_qp(Encoder::aom, "Avg QP:32.55"), None);
+}
@@ -0,0 +1,101 @@
+use std::process::Command;
+
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString, IntoStaticStr};
+
+/// Scheduling priority of the source pipes and encoders of chunks and probes
+#[derive(
+    PartialEq,
+    Eq,
+    Copy,
+    Clone,
+    Default,
+    Serialize,
+    Deserialize,
+    Debug,
+    Display,
+    EnumString,
+    IntoStaticStr,
+)]
+pub enum ProcessPriority {
+    /// Same priority as Av1an
+    #[default]
+    #[strum(serialize = "normal")]
+    Normal,
+    /// Nice 10 on Unix, below normal priority class on Windows
+    #[strum(serialize = "low")]
+    Low,
+    /// Nice 19 on Unix, idle priority class on Windows
+    #[strum(serialize = "idle")]
+    Idle,
+}
+
+impl ProcessPriority {
+    /// Makes the process started by `command` run with this priority. The
+    /// priority is only ever lowered, so a process started from an Av1an that
+    /// already has a lower priority keeps that priority.
+    pub(crate) fn apply(self, command: &mut Command) -> &mut Command {
+        if self == Self::Normal {
+            return command;
+        }
+
+        cfg_if! {
+            if #[cfg(unix)] {
+                use std::{io, os::unix::process::CommandExt};
+
+                let nice = if self == Self::Low { 10 } else { 19 };
+                // SAFETY: getpriority and setpriority are async-signal-safe and only
+                // change the forked process
+                unsafe {
+                    command.pre_exec(move || {
+                        // Returns -1 both on errors and for a nice value of -1, either of
+                        // which is raised to `nice`
+                        let current = libc::getpriority(libc::PRIO_PROCESS, 0);
+                        if libc::setpriority(libc::PRIO_PROCESS, 0, current.max(nice)) == -1 {
+                            return Err(io::Error::last_os_error());
+                        }
+                        Ok(())
+                    })
+                }
+            } else if #[cfg(windows)] {
+                use std::os::windows::process::CommandExt;
+
+                const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+                const IDLE_PRIORITY_CLASS: u32 = 0x0000_0040;
+
+                command.creation_flags(if self == Self::Low {
+                    BELOW_NORMAL_PRIORITY_CLASS
+                } else {
+                    IDLE_PRIORITY_CLASS
+                })
+            } else {
+                command
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use std::process::Command;
+
+    use super::ProcessPriority;
+
+    fn nice_of(priority: ProcessPriority) -> String {
+        let output = priority
+            .apply(Command::new("sh").args(["-c", "nice"]))
+            .output()
+            .expect("sh should run");
+        String::from_utf8_lossy(&output.stdout).trim().to_owned()
+    }
+
+    #[test]
+    fn priority_is_lowered() {
+        let normal = nice_of(ProcessPriority::Normal);
+        let idle = nice_of(ProcessPriority::Idle);
+        assert_eq!(idle, "19");
+        assert!(normal.parse::<i32>().expect("nice should print a number") <= 19);
+    }
+}
@@ -1,4 +1,9 @@
-use std::{fmt::Write, time::Duration};
+use std::{
+    fmt::Write,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
 
 use indicatif::{
     HumanBytes,
@@ -10,8 +15,13 @@ use indicatif::{
     ProgressStyle,
 };
 use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
 
-use crate::{get_done, util::printable_base10_digits, Verbosity};
+use crate::{
+    get_done,
+    util::{printable_base10_digits, write_atomic},
+    Verbosity,
+};
 
 const PROGRESS_CHARS: &str = if cfg!(windows) {
     "█▓▒░  "
@@ -29,6 +39,11 @@ const INDICATIF_SC_SPINNER_TEMPLATE: &str =
 static PROGRESS_BAR: OnceCell<ProgressBar> = OnceCell::new();
 static AUDIO_BYTES: OnceCell<u64> = OnceCell::new();
 
+/// Number of workers currently encoding a chunk
+pub(crate) static ACTIVE_WORKERS: AtomicUsize = AtomicUsize::new(0);
+/// Number of failed chunk attempts that were retried
+pub(crate) static RETRIES: AtomicUsize = AtomicUsize::new(0);
+
 pub fn set_audio_size(val: u64) {
     AUDIO_BYTES.get_or_init(|| val);
 }
@@ -146,6 +161,42 @@ pub fn convert_to_progress(resume_frames: u64) {
     }
 }
 
+/// Coalesces the encoded frame updates of a single worker, so encoders that
+/// report progress on every frame do not flood the progress bars
+#[derive(Debug)]
+pub(crate) struct ProgressCoalescer {
+    interval:  Duration,
+    last_sent: Instant,
+    pending:   u64,
+}
+
+impl ProgressCoalescer {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_sent: Instant::now(),
+            pending: 0,
+        }
+    }
+
+    /// Adds newly encoded frames, returning the frames to report once at
+    /// least `interval` has passed since the last report
+    pub(crate) fn add(&mut self, frames: u64) -> Option<u64> {
+        self.pending += frames;
+        if self.last_sent.elapsed() < self.interval {
+            return None;
+        }
+        self.flush()
+    }
+
+    /// Returns all frames that have not been reported yet, e.g. at the end of
+    /// a pass
+    pub(crate) fn flush(&mut self) -> Option<u64> {
+        self.last_sent = Instant::now();
+        (self.pending > 0).then(|| std::mem::take(&mut self.pending))
+    }
+}
+
 pub fn inc_bar(inc: u64) {
     if let Some(pb) = PROGRESS_BAR.get() {
         pb.inc(inc);
@@ -260,7 +311,7 @@ pub fn init_multi_progress_bar(len: u64, workers: usize, resume_frames: u64, chu
     });
 }
 
-pub fn update_mp_chunk(worker_idx: usize, chunk: usize, padding: usize) {
+pub fn update_mp_chunk(worker_idx: usize, chunk: &str, padding: usize) {
     if let Some((_, pbs)) = MULTI_PROGRESS_BAR.get() {
         pbs[worker_idx].set_prefix(format!("[Chunk {chunk:>padding$}]"));
     }
@@ -325,3 +376,143 @@ pub fn update_progress_bar_estimates(
         update_mp_bar_info(kbps, HumanBytes(est_size as u64), chunks);
     }
 }
+
+/// Version of the `progress.json` format. Increase it whenever a field is
+/// renamed or removed or its meaning changes, and update
+/// [`PROGRESS_SCHEMA`] to match.
+pub const PROGRESS_SCHEMA_VERSION: u32 = 1;
+
+/// JSON schema of `progress.json`
+pub const PROGRESS_SCHEMA: &str = include_str!("progress.schema.json");
+
+/// Stage of the encode reported in `progress.json`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProgressStage {
+    SceneDetection,
+    Encoding,
+    Concatenating,
+    Done,
+}
+
+/// Progress of the encode written to `progress.json` in the temp folder, so
+/// scripts and dashboards can poll a single file.
+///
+/// The serialized field names are part of [`PROGRESS_SCHEMA`], so do not
+/// rename them without increasing [`PROGRESS_SCHEMA_VERSION`].
+#[derive(Serialize, Debug)]
+struct ProgressSnapshot {
+    schema_version: u32,
+    stage:          ProgressStage,
+    percent:        f64,
+    frames_done:    u64,
+    frames_total:   u64,
+    fps:            f64,
+    eta_seconds:    Option<u64>,
+    chunks_done:    usize,
+    chunks_total:   usize,
+    active_workers: usize,
+    retries:        usize,
+}
+
+/// Writes the current progress to `progress.json` in `temp`. The file is
+/// replaced atomically, so readers never see a partially written snapshot.
+pub(crate) fn write_progress_snapshot(
+    temp: &str,
+    stage: ProgressStage,
+    total_frames: usize,
+    total_chunks: usize,
+) -> anyhow::Result<()> {
+    let bar = PROGRESS_BAR
+        .get()
+        .or_else(|| MULTI_PROGRESS_BAR.get().and_then(|(_, pbs)| pbs.last()))
+        .filter(|pb| pb.length().is_some_and(|len| len > 0));
+    let frames_done = bar.map_or_else(
+        || get_done().done.iter().map(|ref_multi| ref_multi.value().frames as u64).sum(),
+        ProgressBar::position,
+    );
+    let frames_total = total_frames as u64;
+    let (fps, eta) = bar.map_or((0.0, None), |pb| (pb.per_sec(), Some(pb.eta())));
+
+    let snapshot = ProgressSnapshot {
+        schema_version: PROGRESS_SCHEMA_VERSION,
+        stage,
+        percent: if frames_total == 0 {
+            0.0
+        } else {
+            frames_done as f64 * 100.0 / frames_total as f64
+        },
+        frames_done,
+        frames_total,
+        fps,
+        eta_seconds: eta.filter(|_| stage == ProgressStage::Encoding).map(|eta| eta.as_secs()),
+        chunks_done: get_done().done.len(),
+        chunks_total: total_chunks,
+        active_workers: ACTIVE_WORKERS.load(Ordering::SeqCst),
+        retries: RETRIES.load(Ordering::SeqCst),
+    };
+
+    write_atomic(
+        &Path::new(temp).join("progress.json"),
+        serde_json::to_string_pretty(&snapshot)?,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_snapshot_matches_schema() {
+        let schema: serde_json::Value =
+            serde_json::from_str(PROGRESS_SCHEMA).expect("schema should be valid JSON");
+        assert_eq!(
+            schema["properties"]["schema_version"]["const"],
+            PROGRESS_SCHEMA_VERSION
+        );
+
+        let snapshot = serde_json::to_value(ProgressSnapshot {
+            schema_version: PROGRESS_SCHEMA_VERSION,
+            stage:          ProgressStage::SceneDetection,
+            percent:        0.0,
+            frames_done:    0,
+            frames_total:   0,
+            fps:            0.0,
+            eta_seconds:    None,
+            chunks_done:    0,
+            chunks_total:   0,
+            active_workers: 0,
+            retries:        0,
+        })
+        .expect("snapshot should serialize");
+        let mut fields = snapshot
+            .as_object()
+            .expect("snapshot should be an object")
+            .keys()
+            .collect::<Vec<_>>();
+        let mut properties = schema["properties"]
+            .as_object()
+            .expect("schema should have properties")
+            .keys()
+            .collect::<Vec<_>>();
+        fields.sort();
+        properties.sort();
+        assert_eq!(fields, properties);
+        assert_eq!(snapshot["stage"], "scene-detection");
+    }
+
+    #[test]
+    fn progress_coalescer_holds_frames_until_interval() {
+        let mut progress = ProgressCoalescer::new(Duration::from_secs(3600));
+        assert_eq!(progress.add(1), None);
+        assert_eq!(progress.add(2), None);
+        assert_eq!(progress.flush(), Some(3));
+        assert_eq!(progress.flush(), None);
+
+        let mut progress = ProgressCoalescer::new(Duration::ZERO);
+        assert_eq!(progress.add(4), Some(4));
+        assert_eq!(progress.flush(), None);
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    process::Child,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+        Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+
+use crate::children::ChildGuard;
+
+/// How often the processes are sampled. Processes that start and exit
+/// between two samples are not counted.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Processes of the chunk that is currently encoding them
+static CHUNK_PIDS: Lazy<DashMap<Pid, String>> = Lazy::new(DashMap::new);
+/// Largest combined resident set size seen for the processes of each chunk
+static CHUNK_PEAK_RSS: Lazy<DashMap<String, u64>> = Lazy::new(DashMap::new);
+/// Largest combined resident set size seen for the processes of any chunk
+static LARGEST_CHUNK_RSS: AtomicU64 = AtomicU64::new(0);
+
+/// How long a started chunk reserves memory for itself, as its processes
+/// take a while to load the source and reach their full memory use
+const CHUNK_STARTUP: Duration = Duration::from_secs(10);
+
+/// Resources used by av1an and all of its child processes, sampled over the
+/// run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct ResourceUsage {
+    /// CPU time in milliseconds
+    pub cpu_time_ms:     u64,
+    /// Largest combined resident set size in bytes
+    pub peak_rss:        u64,
+    /// Bytes read from disk, mostly the chunks and probes in the temp folder
+    pub bytes_read:      u64,
+    /// Bytes written to disk, mostly the chunks and probes in the temp folder
+    pub bytes_written:   u64,
+    pub child_processes: u64,
+}
+
+impl ResourceUsage {
+    /// Adds the usage of a resumed run to the usage of the previous runs
+    pub(crate) fn accumulate(&mut self, run: Self) {
+        self.cpu_time_ms += run.cpu_time_ms;
+        self.peak_rss = self.peak_rss.max(run.peak_rss);
+        self.bytes_read += run.bytes_read;
+        self.bytes_written += run.bytes_written;
+        self.child_processes += run.child_processes;
+    }
+}
+
+/// Last sampled totals of a single process
+#[derive(Debug, Clone, Copy)]
+struct ProcessTotals {
+    cpu_time_ms:   u64,
+    bytes_read:    u64,
+    bytes_written: u64,
+}
+
+/// Samples the processes of av1an on a background thread until finished
+pub(crate) struct ResourceMonitor {
+    stop:   Arc<AtomicBool>,
+    handle: JoinHandle<ResourceUsage>,
+}
+
+impl ResourceMonitor {
+    pub(crate) fn start() -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let own_pid = Pid::from_u32(std::process::id());
+                let mut system = System::new();
+                let mut totals = HashMap::new();
+                let mut peak_rss = 0;
+                while !stop.load(Ordering::SeqCst) {
+                    peak_rss = peak_rss.max(sample(&mut system, own_pid, &mut totals));
+                    thread::sleep(SAMPLE_INTERVAL);
+                }
+                peak_rss = peak_rss.max(sample(&mut system, own_pid, &mut totals));
+
+                ResourceUsage {
+                    cpu_time_ms: totals.values().map(|p| p.cpu_time_ms).sum(),
+                    peak_rss,
+                    bytes_read: totals.values().map(|p| p.bytes_read).sum(),
+                    bytes_written: totals.values().map(|p| p.bytes_written).sum(),
+                    // The totals include av1an itself
+                    child_processes: totals.len().saturating_sub(1) as u64,
+                }
+            })
+        };
+
+        Self {
+            stop,
+            handle,
+        }
+    }
+
+    /// Stops sampling and returns the resources used since the monitor was
+    /// started
+    pub(crate) fn finish(self) -> ResourceUsage {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.join().expect("thread should join successfully")
+    }
+}
+
+/// Refreshes the totals of av1an and its descendants and attributes the
+/// memory of the tracked chunk processes, returning the combined resident set
+/// size of all of them
+fn sample(
+    system: &mut System,
+    own_pid: Pid,
+    totals: &mut HashMap<(Pid, u64), ProcessTotals>,
+) -> u64 {
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::All,
+        true,
+        // Threads are listed as processes of their own with tasks, which would
+        // count their CPU time twice
+        ProcessRefreshKind::nothing()
+            .without_tasks()
+            .with_cpu()
+            .with_memory()
+            .with_disk_usage(),
+    );
+    let processes = system.processes();
+    let is_own = |mut pid: Pid| {
+        // Bounded in case the parents of exited processes form a cycle
+        for _ in 0..64 {
+            if pid == own_pid {
+                return true;
+            }
+            match processes.get(&pid).and_then(sysinfo::Process::parent) {
+                Some(parent) => pid = parent,
+                None => return false,
+            }
+        }
+        false
+    };
+
+    let mut rss = 0;
+    for (&pid, process) in processes {
+        if !is_own(pid) {
+            continue;
+        }
+        let disk_usage = process.disk_usage();
+        // The start time keeps a reused PID from replacing an exited process
+        totals.insert((pid, process.start_time()), ProcessTotals {
+            cpu_time_ms:   process.accumulated_cpu_time(),
+            bytes_read:    disk_usage.total_read_bytes,
+            bytes_written: disk_usage.total_written_bytes,
+        });
+        rss += process.memory();
+    }
+
+    let mut chunk_rss = HashMap::<String, u64>::new();
+    for entry in CHUNK_PIDS.iter() {
+        if let Some(process) = processes.get(entry.key()) {
+            *chunk_rss.entry(entry.value().clone()).or_default() += process.memory();
+        }
+    }
+    for (chunk, rss) in chunk_rss {
+        LARGEST_CHUNK_RSS.fetch_max(rss, Ordering::SeqCst);
+        let mut peak = CHUNK_PEAK_RSS.entry(chunk).or_default();
+        *peak = (*peak).max(rss);
+    }
+
+    rss
+}
+
+/// Attributes the memory of the tracked processes to a chunk until dropped,
+/// and keeps them in the registry of running child processes
+pub(crate) struct ChunkProcesses {
+    chunk:  String,
+    guards: Mutex<Vec<ChildGuard>>,
+}
+
+impl ChunkProcesses {
+    pub(crate) fn new(chunk: String) -> Self {
+        Self {
+            chunk,
+            guards: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn track(&self, child: &Child) {
+        CHUNK_PIDS.insert(Pid::from_u32(child.id()), self.chunk.clone());
+        self.guards
+            .lock()
+            .expect("mutex should acquire lock")
+            .push(ChildGuard::new(child));
+    }
+}
+
+impl Drop for ChunkProcesses {
+    fn drop(&mut self) {
+        CHUNK_PIDS.retain(|_, chunk| *chunk != self.chunk);
+    }
+}
+
+/// Returns the largest combined resident set size sampled for the processes
+/// of `chunk` over all of its passes, if any were sampled
+pub(crate) fn take_chunk_peak_rss(chunk: &str) -> Option<u64> {
+    CHUNK_PEAK_RSS.remove(chunk).map(|(_, rss)| rss)
+}
+
+/// Holds back new chunks while the memory in use by the system is close to
+/// `--memory-ceiling`
+#[derive(Debug)]
+pub(crate) struct MemoryGovernor {
+    /// Percentage of the total memory
+    ceiling: u8,
+    state:   Mutex<GovernorState>,
+}
+
+#[derive(Debug)]
+struct GovernorState {
+    system:        System,
+    /// Chunks started within the last `CHUNK_STARTUP`
+    recent_starts: VecDeque<Instant>,
+}
+
+impl MemoryGovernor {
+    pub(crate) fn new(ceiling: u8) -> Self {
+        Self {
+            ceiling,
+            state: Mutex::new(GovernorState {
+                system:        System::new(),
+                recent_starts: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Whether a new chunk may start, in which case memory is reserved for
+    /// it. A chunk is expected to use as much memory as the largest chunk
+    /// seen so far, and the memory of recently started chunks is reserved
+    /// until they have loaded. A chunk always starts when no other chunk is
+    /// `running` or starting, so the encode cannot stall.
+    pub(crate) fn try_start(&self, running: usize) -> bool {
+        let mut state = self.state.lock().expect("mutex should acquire lock");
+        let now = Instant::now();
+        while state.recent_starts.front().is_some_and(|start| now - *start > CHUNK_STARTUP) {
+            state.recent_starts.pop_front();
+        }
+
+        state.system.refresh_memory();
+        let total = state.system.total_memory();
+        let used = total.saturating_sub(state.system.available_memory());
+        let starting = state.recent_starts.len() as u64 + 1;
+        if (running > 0 || starting > 1)
+            && exceeds_ceiling(
+                used,
+                LARGEST_CHUNK_RSS.load(Ordering::SeqCst).saturating_mul(starting),
+                total,
+                self.ceiling,
+            )
+        {
+            return false;
+        }
+
+        state.recent_starts.push_back(now);
+        true
+    }
+}
+
+/// Whether `used` and `reserved` bytes of `total` go past `ceiling` percent
+fn exceeds_ceiling(used: u64, reserved: u64, total: u64, ceiling: u8) -> bool {
+    u128::from(used.saturating_add(reserved)) * 100 > u128::from(total) * u128::from(ceiling)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exceeds_ceiling, ResourceUsage};
+
+    #[test]
+    fn ceiling_counts_reserved_memory() {
+        const GB: u64 = 1 << 30;
+        assert!(!exceeds_ceiling(8 * GB, 4 * GB, 16 * GB, 80));
+        assert!(exceeds_ceiling(8 * GB, 6 * GB, 16 * GB, 80));
+        assert!(exceeds_ceiling(15 * GB, 0, 16 * GB, 90));
+        assert!(!exceeds_ceiling(u64::MAX, 0, u64::MAX, 100));
+    }
+
+    #[test]
+    fn resumed_usage_is_accumulated() {
+        let mut usage = ResourceUsage {
+            cpu_time_ms:     1000,
+            peak_rss:        4096,
+            bytes_read:      10,
+            bytes_written:   20,
+            child_processes: 3,
+        };
+        usage.accumulate(ResourceUsage {
+            cpu_time_ms:     500,
+            peak_rss:        2048,
+            bytes_read:      5,
+            bytes_written:   5,
+            child_processes: 2,
+        });
+        assert_eq!(usage, ResourceUsage {
+            cpu_time_ms:     1500,
+            peak_rss:        4096,
+            bytes_read:      15,
+            bytes_written:   25,
+            child_processes: 5,
+        });
+    }
+}
@@ -1,11 +1,14 @@
 use std::{
     collections::BTreeMap,
+    fs,
     io::{IsTerminal, Read},
+    path::Path,
     process::{Command, Stdio},
     thread,
 };
 
-use anyhow::bail;
+use anyhow::{bail, ensure, Context};
+use av1_grain::TransferFunction;
 use av_decoders::{DecoderError, DecoderImpl, VapoursynthDecoder, Y4mDecoder};
 use av_scenechange::{
     detect_scene_changes,
@@ -17,16 +20,20 @@ use av_scenechange::{
 use colored::*;
 use itertools::Itertools;
 use smallvec::{smallvec, SmallVec};
+use tracing::{info, warn};
+use vapoursynth::format::SampleType;
 
 use crate::{
     ffmpeg::FFPixelFormat,
     into_smallvec,
     progress_bar,
     scenes::Scene,
-    vapoursynth::resize_node,
+    vapoursynth::{luma_lut_node, luma_node, resize_node},
+    ColorRange,
     Encoder,
     Input,
     ScenecutMethod,
+    ScenecutPqMode,
     Verbosity,
 };
 
@@ -40,8 +47,10 @@ pub fn av_scenechange_detect(
     verbosity: Verbosity,
     sc_scaler: &str,
     sc_pix_format: Option<FFPixelFormat>,
+    sc_luma_only: bool,
     sc_method: ScenecutMethod,
     sc_downscale_height: Option<usize>,
+    sc_pq_mode: ScenecutPqMode,
     zones: &[Scene],
 ) -> anyhow::Result<(Vec<Scene>, usize, BTreeMap<usize, ScenecutResult>)> {
     if verbosity != Verbosity::Quiet {
@@ -77,8 +86,10 @@ pub fn av_scenechange_detect(
         min_scene_len,
         sc_scaler,
         sc_pix_format,
+        sc_luma_only,
         sc_method,
         sc_downscale_height,
+        sc_pq_mode,
         zones,
     )?;
     let frames = frame_thread.join().expect("should join frame_thread successfully")?;
@@ -98,8 +109,10 @@ pub fn scene_detect(
     min_scene_len: usize,
     sc_scaler: &str,
     sc_pix_format: Option<FFPixelFormat>,
+    sc_luma_only: bool,
     sc_method: ScenecutMethod,
     sc_downscale_height: Option<usize>,
+    sc_pq_mode: ScenecutPqMode,
     zones: &[Scene],
 ) -> anyhow::Result<(Vec<Scene>, BTreeMap<usize, ScenecutResult>)> {
     let (mut decoder, bit_depth) = build_decoder(
@@ -107,7 +120,9 @@ pub fn scene_detect(
         encoder,
         sc_scaler,
         sc_pix_format,
+        sc_luma_only,
         sc_downscale_height,
+        sc_pq_mode,
     )?;
 
     let mut scenes = Vec::new();
@@ -225,11 +240,20 @@ fn build_decoder(
     encoder: Encoder,
     sc_scaler: &str,
     sc_pix_format: Option<FFPixelFormat>,
+    sc_luma_only: bool,
     sc_downscale_height: Option<usize>,
+    sc_pq_mode: ScenecutPqMode,
 ) -> anyhow::Result<(Decoder, usize)> {
     let clip_info = input.clip_info()?;
     let (input_width, input_height) = clip_info.resolution;
 
+    // The scene detection thresholds are tuned for SDR, and the PQ curve
+    // compresses the differences between dark frames and exaggerates them
+    // between bright frames
+    let tonemap_pq = sc_pq_mode == ScenecutPqMode::Tonemap
+        && clip_info.transfer_characteristics == TransferFunction::SMPTE2084;
+    let full_range = clip_info.color_range == Some(ColorRange::Full);
+
     // Only downscale if needed
     let sc_downscale_height =
         sc_downscale_height.filter(|&downscale_height| downscale_height < input_height as usize);
@@ -251,7 +275,8 @@ fn build_decoder(
         args_map.insert("AV1AN_PERFORM_SCENE_DETECTION".into(), "1".into());
         let mut vs_decoder = VapoursynthDecoder::from_file(input.as_script_path(), args_map, None)?;
 
-        if sc_downscale_height.is_some() || sc_pix_format.is_some() {
+        let resize = sc_downscale_height.is_some() || sc_pix_format.is_some();
+        if resize || sc_luma_only || tonemap_pq {
             let downscale_height = sc_downscale_height.map(|dh| dh as u32);
             let downscale_width = downscale_height
                 .map(|dh| (input_width as f64 * (dh as f64 / input_height as f64)).round() as u32);
@@ -267,20 +292,45 @@ fn build_decoder(
                     cause: "No output node".to_string(),
                 })?;
 
-                let resized_node = resize_node(
-                    core,
-                    &node,
-                    // Ensure width is divisible by 2
-                    downscale_width.map(|dw| (dw / 2) * 2),
-                    downscale_height,
-                    pix_format,
-                    None,
-                )
-                .map_err(|e| DecoderError::VapoursynthInternalError {
+                let to_decoder_error = |e: anyhow::Error| DecoderError::VapoursynthInternalError {
                     cause: e.to_string(),
-                })?;
+                };
+                // The lookup table only works with integer formats
+                let format = node.info().format;
+                let node = if tonemap_pq && format.sample_type() == SampleType::Integer {
+                    info!("tone mapping PQ input to SDR for scene detection");
+                    let lut = pq_to_sdr_luma_lut(format.bits_per_sample() as usize, full_range);
+                    luma_lut_node(core, &node, &lut).map_err(to_decoder_error)?
+                } else {
+                    if tonemap_pq {
+                        warn!(
+                            "PQ input with a float format is not tone mapped for scene detection"
+                        );
+                    }
+                    node
+                };
+                let node = if resize {
+                    resize_node(
+                        core,
+                        &node,
+                        // Ensure width is divisible by 2
+                        downscale_width.map(|dw| (dw / 2) * 2),
+                        downscale_height,
+                        pix_format,
+                        None,
+                    )
+                    .map_err(to_decoder_error)?
+                } else {
+                    node
+                };
 
-                Ok(resized_node)
+                // Scene detection only looks at the luma plane, so there is no need to
+                // decode and copy the chroma planes
+                if sc_luma_only {
+                    luma_node(core, &node).map_err(to_decoder_error)
+                } else {
+                    Ok(node)
+                }
             }))?;
         }
 
@@ -289,21 +339,35 @@ fn build_decoder(
         // FFmpeg is faster if the user provides video input
         let path = input.as_path();
 
-        let filters: SmallVec<[String; 4]> = match (sc_downscale_height, sc_pix_format) {
-            (Some(sdh), Some(spf)) => into_smallvec![
-                "-vf",
-                format!(
-                    "format={},scale=-2:'min({},ih)':flags={}",
-                    spf.to_pix_fmt_string(),
-                    sdh,
-                    sc_scaler
-                )
-            ],
+        // The y4m decoder only supports 8 and 12 bit grayscale, so high bit depth
+        // inputs are converted to 12 bit for luma-only scene detection
+        let pix_fmt = if sc_luma_only {
+            Some(if bit_depth > 8 { "gray12le" } else { "gray" })
+        } else {
+            sc_pix_format.map(|spf| spf.to_pix_fmt_string())
+        };
+        let mut video_filters: SmallVec<[String; 2]> = smallvec![];
+        if tonemap_pq {
+            info!("tone mapping PQ input to SDR for scene detection");
+            // The filter runs on the frames as they are decoded, before any
+            // conversion to the scene detection pixel format
+            let input_bit_depth = clip_info.format_info.as_bit_depth()?;
+            video_filters.push(pq_to_sdr_lutyuv(input_bit_depth, full_range));
+        }
+        match (sc_downscale_height, pix_fmt) {
+            (Some(sdh), Some(spf)) => video_filters.push(format!(
+                "format={spf},scale=-2:'min({sdh},ih)':flags={sc_scaler}"
+            )),
             (Some(sdh), None) => {
-                into_smallvec!["-vf", format!("scale=-2:'min({sdh},ih)':flags={sc_scaler}")]
+                video_filters.push(format!("scale=-2:'min({sdh},ih)':flags={sc_scaler}"));
             },
-            (None, Some(spf)) => into_smallvec!["-pix_fmt", spf.to_pix_fmt_string()],
-            (None, None) => smallvec![],
+            (None, Some(spf)) if tonemap_pq => video_filters.push(format!("format={spf}")),
+            _ => (),
+        }
+        let filters: SmallVec<[String; 4]> = match (video_filters.is_empty(), pix_fmt) {
+            (false, _) => into_smallvec!["-vf", video_filters.join(",")],
+            (true, Some(spf)) => into_smallvec!["-pix_fmt", spf],
+            (true, None) => smallvec![],
         };
 
         let stdout = Command::new("ffmpeg")
@@ -324,3 +388,323 @@ fn build_decoder(
 
     Ok((decoder, bit_depth))
 }
+
+/// Frames whose FFmpeg scene score exceeds `threshold`, leaving at least
+/// `min_scene_len` frames between cuts
+pub(crate) fn ffmpeg_scene_cuts(
+    scores: &[f64],
+    threshold: f64,
+    min_scene_len: usize,
+) -> Vec<usize> {
+    // The first frame has no previous frame to be compared with
+    let candidates = scores
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter_map(|(frame, &score)| (score > threshold).then_some(frame));
+    spaced_cuts(candidates, min_scene_len)
+}
+
+/// Keyframes of the input, which become the scene cuts of the keyframes split
+/// method, leaving at least `min_scene_len` frames between cuts
+pub(crate) fn keyframe_cuts(keyframes: &[usize], min_scene_len: usize) -> Vec<usize> {
+    spaced_cuts(
+        keyframes.iter().copied().filter(|&frame| frame > 0),
+        min_scene_len,
+    )
+}
+
+/// Keeps the `candidates`, in increasing order, that are at least
+/// `min_scene_len` frames after the previous cut
+fn spaced_cuts(candidates: impl Iterator<Item = usize>, min_scene_len: usize) -> Vec<usize> {
+    let mut cuts = Vec::new();
+    let mut last_cut = 0;
+    for frame in candidates {
+        if frame - last_cut >= min_scene_len {
+            cuts.push(frame);
+            last_cut = frame;
+        }
+    }
+    cuts
+}
+
+/// Reads the first frame of every scene from a scene list CSV ("list-scenes")
+/// or EDL ("save-edl") written by PySceneDetect. EDL timecodes are converted
+/// to frames with `fps`.
+pub(crate) fn import_pyscenedetect(path: &Path, fps: f64) -> anyhow::Result<Vec<usize>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read scene list {}", path.display()))?;
+    let is_edl = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("edl"));
+    let starts = if is_edl {
+        parse_edl_starts(&text, fps)
+    } else {
+        parse_scene_list_starts(&text)
+    };
+    starts.with_context(|| format!("Failed to parse PySceneDetect scenes {}", path.display()))
+}
+
+/// Parses the "Start Frame" column of a PySceneDetect scene list CSV
+fn parse_scene_list_starts(text: &str) -> anyhow::Result<Vec<usize>> {
+    // The header follows an optional "Timecode List:" line
+    let mut lines = text.lines().skip_while(|line| !line.contains("Start Frame"));
+    let Some(header) = lines.next() else {
+        bail!("no \"Start Frame\" column");
+    };
+    let field = |value: &str| value.trim().trim_matches('"').trim().to_owned();
+    let Some(column) = header.split(',').position(|name| field(name) == "Start Frame") else {
+        bail!("no \"Start Frame\" column in the header {header:?}");
+    };
+
+    let starts = lines
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let value = field(line.split(',').nth(column).unwrap_or_default());
+            value.parse::<usize>().with_context(|| format!("invalid start frame {value:?}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // Since PySceneDetect 0.6, frame numbers start at 1
+    if starts.first() == Some(&1) {
+        return starts
+            .into_iter()
+            .map(|start| {
+                start
+                    .checked_sub(1)
+                    .context("start frame 0 in a scene list whose frames start at 1")
+            })
+            .collect();
+    }
+    Ok(starts)
+}
+
+/// Parses the source in timecodes of the events of a CMX 3600 EDL
+fn parse_edl_starts(text: &str, fps: f64) -> anyhow::Result<Vec<usize>> {
+    // Timecodes count frames at the nominal frame rate, e.g. 30 for 29.97
+    let nominal_fps = fps.round() as usize;
+    let drop_frame_edl = text.lines().any(|line| line.trim() == "FCM: DROP FRAME");
+    text.lines()
+        .filter(|line| {
+            line.split_whitespace().next().is_some_and(|event| event.parse::<u32>().is_ok())
+        })
+        .map(|line| {
+            let timecode = line.split_whitespace().nth(4).unwrap_or_default();
+            let fields = timecode
+                .split([':', ';'])
+                .map(str::parse::<usize>)
+                .collect::<Result<Vec<_>, _>>()
+                .ok()
+                .filter(|fields| fields.len() == 4)
+                .with_context(|| format!("invalid timecode {timecode:?}"))?;
+            let [h, m, s, f] = fields[..] else {
+                unreachable!()
+            };
+            let frame = (h * 3600 + m * 60 + s) * nominal_fps + f;
+            if !(drop_frame_edl || timecode.contains(';')) {
+                return Ok(frame);
+            }
+
+            // Drop-frame timecodes skip the first frame numbers of every minute
+            // except every tenth, 2 at 29.97 fps and 4 at 59.94 fps
+            ensure!(
+                matches!(nominal_fps, 30 | 60),
+                "drop-frame timecode {timecode:?} at {fps:.3} fps, drop-frame timecodes are only \
+                 defined for 29.97 and 59.94 fps"
+            );
+            let dropped = nominal_fps / 15;
+            let minutes = h * 60 + m;
+            Ok(frame - dropped * (minutes - minutes / 10))
+        })
+        .collect()
+}
+
+/// SDR reference white in nits, which PQ is tone mapped relative to
+const PQ_REFERENCE_WHITE: f64 = 203.;
+/// Brightness in nits that is tone mapped to SDR peak white
+const PQ_TONEMAP_PEAK: f64 = 1000.;
+
+// Constants of the PQ (SMPTE ST 2084) transfer function
+const PQ_M1: f64 = 2610. / 16384.;
+const PQ_M2: f64 = 2523. / 4096. * 128.;
+const PQ_C1: f64 = 3424. / 4096.;
+const PQ_C2: f64 = 2413. / 4096. * 32.;
+const PQ_C3: f64 = 2392. / 4096. * 32.;
+
+/// FFmpeg filter that applies [`pq_to_sdr`] to the luma plane of an input of
+/// `bit_depth` bits. Limited range inputs use `minval` and `maxval`, the
+/// limited range black and white of the bit depth, and full range inputs use
+/// the whole range of values, like [`pq_to_sdr_luma_lut`].
+fn pq_to_sdr_lutyuv(bit_depth: usize, full_range: bool) -> String {
+    let peak = PQ_TONEMAP_PEAK / PQ_REFERENCE_WHITE;
+    let (black, white) = if full_range {
+        ("0".to_string(), ((1_i64 << bit_depth) - 1).to_string())
+    } else {
+        ("minval".to_string(), "maxval".to_string())
+    };
+    format!(
+        "lutyuv=y='st(0,pow(clip((val-{black})/({white}-{black}),0,1),{inv_m2}));st(1,10000*\
+         pow(max(ld(0)-{PQ_C1},0)/({PQ_C2}-{PQ_C3}*ld(0)),{inv_m1})/{PQ_REFERENCE_WHITE});\
+         {black}+pow(min(ld(1)*(1+ld(1)/{peak_squared})/(1+ld(1)),1),{inv_gamma})*\
+         ({white}-{black})'",
+        inv_m1 = 1. / PQ_M1,
+        inv_m2 = 1. / PQ_M2,
+        peak_squared = peak * peak,
+        inv_gamma = 1. / 2.4,
+    )
+}
+
+/// Maps a normalized PQ signal value to the BT.1886 signal value of the same
+/// brightness relative to reference white, compressing the highlights above
+/// reference white with an extended Reinhard curve
+fn pq_to_sdr(signal: f64) -> f64 {
+    let p = signal.clamp(0., 1.).powf(1. / PQ_M2);
+    let nits = 10000. * ((p - PQ_C1).max(0.) / PQ_C3.mul_add(-p, PQ_C2)).powf(1. / PQ_M1);
+
+    let relative = nits / PQ_REFERENCE_WHITE;
+    let peak = PQ_TONEMAP_PEAK / PQ_REFERENCE_WHITE;
+    let mapped = (relative * (1. + relative / (peak * peak)) / (1. + relative)).min(1.);
+    mapped.powf(1. / 2.4)
+}
+
+/// Lookup table of [`pq_to_sdr`] for the luma values of `bit_depth`
+fn pq_to_sdr_luma_lut(bit_depth: usize, full_range: bool) -> Vec<i64> {
+    let max = (1_i64 << bit_depth) - 1;
+    let (black, white) = if full_range {
+        (0., max as f64)
+    } else {
+        (
+            (16_i64 << (bit_depth - 8)) as f64,
+            (235_i64 << (bit_depth - 8)) as f64,
+        )
+    };
+
+    (0..=max)
+        .map(|value| {
+            let signal = (value as f64 - black) / (white - black);
+            pq_to_sdr(signal).mul_add(white - black, black).round() as i64
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ffmpeg_scene_cuts,
+        keyframe_cuts,
+        parse_edl_starts,
+        parse_scene_list_starts,
+        pq_to_sdr,
+        pq_to_sdr_luma_lut,
+        pq_to_sdr_lutyuv,
+    };
+
+    #[test]
+    fn ffmpeg_cuts_respect_min_scene_len() {
+        let scores = [1.0, 0.1, 0.5, 0.9, 0.2, 0.6, 0.3, 0.41];
+        assert_eq!(ffmpeg_scene_cuts(&scores, 0.4, 1), [2, 3, 5, 7]);
+        assert_eq!(ffmpeg_scene_cuts(&scores, 0.4, 3), [3, 7]);
+    }
+
+    #[test]
+    fn keyframe_cuts_respect_min_scene_len() {
+        let keyframes = [0, 48, 60, 120, 130, 250];
+        assert_eq!(keyframe_cuts(&keyframes, 24), [48, 120, 250]);
+        assert_eq!(keyframe_cuts(&keyframes, 1), [48, 60, 120, 130, 250]);
+    }
+
+    #[test]
+    fn parse_pyscenedetect_scene_lists() {
+        let current = "\
+Timecode List:,00:00:05.005,00:00:09.801
+Scene Number,Start Frame,Start Timecode,Start Time (seconds),End Frame,End Timecode
+1,1,00:00:00.000,0.000,120,00:00:05.005
+2,121,00:00:05.005,5.005,235,00:00:09.801
+3,236,00:00:09.801,9.801,300,00:00:12.513
+";
+        assert_eq!(
+            parse_scene_list_starts(current).expect("scene list should parse"),
+            [0, 120, 235]
+        );
+
+        let legacy = "\
+Scene Number,Start Frame,Start Timecode,Start Time (seconds),End Frame
+1,0,00:00:00.000,0.000,120
+2,120,00:00:05.005,5.005,235
+";
+        assert_eq!(
+            parse_scene_list_starts(legacy).expect("scene list should parse"),
+            [0, 120]
+        );
+        assert!(parse_scene_list_starts("1,0,00:00:00.000\n").is_err());
+
+        let quoted = "\"Scene Number\",\"Start Frame\"\n\"1\",\"1\"\n\"2\",\"49\"\n";
+        assert_eq!(
+            parse_scene_list_starts(quoted).expect("quoted scene list should parse"),
+            [0, 48]
+        );
+        assert!(parse_scene_list_starts("Start Frames\n1\n").is_err());
+        assert!(parse_scene_list_starts("Start Frame\n1\n0\n").is_err());
+    }
+
+    #[test]
+    fn parse_pyscenedetect_edl() {
+        let edl = "\
+TITLE: video
+FCM: NON-DROP FRAME
+
+001  AX V     C        00:00:00:00 00:00:05:00 00:00:00:00 00:00:05:00
+002  AX V     C        00:00:05:00 00:01:00:12 00:00:05:00 00:01:00:12
+";
+        assert_eq!(
+            parse_edl_starts(edl, 24000. / 1001.).expect("EDL should parse"),
+            [0, 120]
+        );
+        assert_eq!(
+            parse_edl_starts(edl, 30000. / 1001.).expect("EDL should parse"),
+            [0, 150]
+        );
+        assert!(parse_edl_starts("001  AX V     C        00:00:05\n", 24.).is_err());
+
+        let drop_frame = "\
+001  AX V     C        00:00:00;00 00:01:00;02 00:00:00;00 00:01:00;02
+002  AX V     C        00:01:00;02 00:10:00;00 00:01:00;02 00:10:00;00
+003  AX V     C        00:10:00;00 00:10:05;00 00:10:00;00 00:10:05;00
+";
+        assert_eq!(
+            parse_edl_starts(drop_frame, 30000. / 1001.).expect("EDL should parse"),
+            [0, 1800, 17982]
+        );
+        assert!(parse_edl_starts(drop_frame, 24000. / 1001.).is_err());
+    }
+
+    #[test]
+    fn pq_to_sdr_keeps_black_and_order() {
+        assert!(pq_to_sdr(0.).abs() < 1e-9);
+        // 10000 nits is brighter than the tone mapping peak
+        assert!((pq_to_sdr(1.) - 1.).abs() < 1e-9);
+        // PQ reference white of 203 nits is at about 58% of the signal
+        let reference_white = pq_to_sdr(0.58);
+        assert!(reference_white > 0.7 && reference_white < 0.8);
+
+        let lut = pq_to_sdr_luma_lut(10, false);
+        assert_eq!(lut.len(), 1024);
+        assert_eq!(lut[64], 64);
+        assert_eq!(lut[940], 940);
+        assert_eq!(lut[1023], 940);
+        assert!(lut.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let lut = pq_to_sdr_luma_lut(10, true);
+        assert_eq!(lut[0], 0);
+        assert_eq!(lut[1023], 1023);
+        assert!(lut.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn pq_to_sdr_lutyuv_uses_the_range_of_the_input() {
+        let limited = pq_to_sdr_lutyuv(10, false);
+        assert!(limited.contains("(val-minval)/(maxval-minval)"));
+
+        let full = pq_to_sdr_lutyuv(10, true);
+        assert!(full.contains("(val-0)/(1023-0)"));
+        assert!(full.contains("*(1023-0)'"));
+        assert!(!full.contains("minval") && !full.contains("maxval"));
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt::Write;
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString, IntoStaticStr};
+
+use super::ScenesData;
+
+/// Format of the scene lists exchanged with other tools through
+/// `--export-scenes` and `--sc-import`
+#[derive(
+    PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug, Display, EnumString, IntoStaticStr,
+)]
+pub enum ScenesFormat {
+    /// Av1an's scenes file, as written to `--scenes`
+    #[strum(serialize = "json")]
+    Json,
+    /// x264 and x265 `--qpfile`, with a keyframe line for each scene
+    #[strum(serialize = "qpfile")]
+    Qpfile,
+    /// Comma-separated first frames of the scenes, as taken by
+    /// `--force-keyframes`
+    #[strum(serialize = "keyframes")]
+    Keyframes,
+    /// Matroska XML chapters with a chapter for each scene
+    #[strum(serialize = "chapters")]
+    Chapters,
+}
+
+impl ScenesFormat {
+    /// Writes the scenes of `data` in this format, which is the whole scenes
+    /// file for JSON and the first frames of the split scenes otherwise.
+    /// Chapter times are computed with `fps`.
+    pub(crate) fn write(self, data: &ScenesData, fps: f64) -> String {
+        let scenes = data.split_scenes.as_deref().unwrap_or_default();
+        let mut out = String::new();
+        match self {
+            Self::Json => {
+                out = serde_json::to_string_pretty(data).expect("serialize should not fail");
+            },
+            Self::Qpfile => {
+                for scene in scenes {
+                    writeln!(out, "{} K", scene.start_frame).expect("write to string should work");
+                }
+            },
+            Self::Keyframes => {
+                out = scenes
+                    .iter()
+                    .map(|scene| scene.start_frame.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                out.push('\n');
+            },
+            Self::Chapters => {
+                out.push_str(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Chapters>\n  <EditionEntry>\n",
+                );
+                for (index, scene) in scenes.iter().enumerate() {
+                    writeln!(
+                        out,
+                        "    <ChapterAtom>\n      <ChapterTimeStart>{start}</ChapterTimeStart>\n      \
+                         <ChapterDisplay>\n        <ChapterString>Scene {index}</ChapterString>\n      \
+                         </ChapterDisplay>\n    </ChapterAtom>",
+                        start = chapter_time(scene.start_frame, fps),
+                    )
+                    .expect("write to string should work");
+                }
+                out.push_str("  </EditionEntry>\n</Chapters>\n");
+            },
+        }
+        out
+    }
+
+    /// Reads the first frames of the scenes from `text` in this format.
+    /// Chapter times are converted to frames with `fps`.
+    pub(crate) fn read_cuts(self, text: &str, fps: f64) -> anyhow::Result<Vec<usize>> {
+        match self {
+            Self::Json => {
+                let data: ScenesData = serde_json::from_str(text)?;
+                let scenes = data.split_scenes.or(data.scenes).unwrap_or_default();
+                Ok(scenes.iter().map(|scene| scene.start_frame).collect())
+            },
+            // Only keyframes start scenes, so P and B frame lines are skipped
+            Self::Qpfile => text
+                .lines()
+                .filter_map(|line| {
+                    let mut fields = line.split_whitespace();
+                    let frame = fields.next()?;
+                    matches!(fields.next()?, "I" | "i" | "K").then_some(frame)
+                })
+                .map(|frame| frame.parse().with_context(|| format!("invalid frame {frame:?}")))
+                .collect(),
+            Self::Keyframes => text
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|frame| !frame.is_empty())
+                .map(|frame| frame.parse().with_context(|| format!("invalid frame {frame:?}")))
+                .collect(),
+            Self::Chapters => text
+                .split("<ChapterTimeStart>")
+                .skip(1)
+                .map(|rest| {
+                    let time = rest.split('<').next().unwrap_or_default().trim();
+                    let seconds = parse_chapter_time(time)
+                        .with_context(|| format!("invalid chapter time {time:?}"))?;
+                    Ok((seconds * fps).round() as usize)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// `HH:MM:SS.nnnnnnnnn` time of `frame`
+fn chapter_time(frame: usize, fps: f64) -> String {
+    let nanos = (frame as f64 * 1e9 / fps).round() as u64;
+    let seconds = nanos / 1_000_000_000;
+    format!(
+        "{:02}:{:02}:{:02}.{:09}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        nanos % 1_000_000_000
+    )
+}
+
+/// Seconds of a `HH:MM:SS.nnnnnnnnn` chapter time
+fn parse_chapter_time(time: &str) -> anyhow::Result<f64> {
+    let [hours, minutes, seconds] = time.split(':').collect::<Vec<_>>()[..] else {
+        bail!("expected HH:MM:SS.nnnnnnnnn");
+    };
+    Ok(
+        hours.parse::<f64>()?.mul_add(3600.0, minutes.parse::<f64>()? * 60.0)
+            + seconds.parse::<f64>()?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScenesFormat;
+    use crate::scenes::{Scene, ScenesData};
+
+    fn scenes() -> ScenesData {
+        let scenes: Vec<_> = [(0, 120), (120, 250), (250, 300)]
+            .into_iter()
+            .map(|(start_frame, end_frame)| Scene {
+                start_frame,
+                end_frame,
+                zone_overrides: None,
+            })
+            .collect();
+        ScenesData {
+            frames:       300,
+            scenes:       Some(scenes.clone()),
+            split_scenes: Some(scenes),
+            annotations:  Vec::new(),
+            noise:        Vec::new(),
+        }
+    }
+
+    #[test]
+    fn scene_lists_round_trip() {
+        let fps = 24000.0 / 1001.0;
+        for format in [
+            ScenesFormat::Json,
+            ScenesFormat::Qpfile,
+            ScenesFormat::Keyframes,
+            ScenesFormat::Chapters,
+        ] {
+            let text = format.write(&scenes(), fps);
+            assert_eq!(
+                format.read_cuts(&text, fps).expect("written list should parse"),
+                [0, 120, 250],
+                "{format}"
+            );
+        }
+    }
+
+    #[test]
+    fn scene_lists_are_written_in_the_formats_of_other_tools() {
+        let fps = 24000.0 / 1001.0;
+        assert_eq!(
+            ScenesFormat::Qpfile.write(&scenes(), fps),
+            "0 K\n120 K\n250 K\n"
+        );
+        assert_eq!(ScenesFormat::Keyframes.write(&scenes(), fps), "0,120,250\n");
+        assert!(ScenesFormat::Chapters
+            .write(&scenes(), fps)
+            .contains("<ChapterTimeStart>00:00:05.005000000</ChapterTimeStart>"));
+    }
+
+    #[test]
+    fn qpfile_skips_non_keyframes() {
+        let qpfile = "0 I\n24 P 30\n48 i\n96 K -1\n";
+        assert_eq!(
+            ScenesFormat::Qpfile.read_cuts(qpfile, 24.0).expect("qpfile should parse"),
+            [0, 48, 96]
+        );
+        assert!(ScenesFormat::Keyframes.read_cuts("0,a", 24.0).is_err());
+    }
+}
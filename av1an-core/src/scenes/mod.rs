@@ -1,10 +1,11 @@
+mod format;
 #[cfg(test)]
 mod tests;
 
 use std::{
+    cmp,
     collections::{BTreeMap, HashMap},
-    fs::File,
-    io::Write,
+    fs::{self, File},
     path::Path,
     process::{exit, Command},
     str::FromStr,
@@ -12,6 +13,7 @@ use std::{
 };
 
 use anyhow::{anyhow, bail, Context, Result};
+pub use format::ScenesFormat;
 use itertools::Itertools;
 use nom::{
     branch::alt,
@@ -22,16 +24,21 @@ use nom::{
     sequence::preceded,
     Parser,
 };
+use num_traits::cast::ToPrimitive;
 use serde::{Deserialize, Serialize};
 use tracing::{error, info, warn};
 
 use crate::{
     create_dir,
+    determine_workers,
+    ffmpeg::{detect_black_and_frozen, keyframes, luma_averages, noise_levels, scene_scores},
     get_done,
     parse::valid_params,
-    scene_detect::av_scenechange_detect,
-    settings::{invalid_params, suggest_fix},
-    split::extra_splits,
+    scene_detect::{av_scenechange_detect, ffmpeg_scene_cuts, import_pyscenedetect, keyframe_cuts},
+    settings::{invalid_params, merge_video_params, suggest_fix},
+    split::{extra_splits, split_for_workers},
+    util::write_atomic,
+    zones::validate_zones,
     EncodeArgs,
     Encoder,
     SplitMethod,
@@ -83,6 +90,10 @@ impl Scene {
                     tag("rav1e"),
                     tag("x264"),
                     tag("x265"),
+                    tag("vvenc"),
+                    tag("nvenc"),
+                    tag("qsv"),
+                    tag("vaapi"),
                     tag("vpx"),
                     tag("svt-av1"),
                 )),
@@ -178,6 +189,15 @@ impl Scene {
         {
             passes = 1;
         }
+        if encoder.hardware_codec().is_some() && passes != 1 {
+            bail!("{encoder} only encodes in one pass, but the zone uses {passes} passes");
+        }
+        if passes > encoder.max_passes() {
+            bail!(
+                "{encoder} encodes in at most {max} passes, but the zone uses {passes} passes",
+                max = encoder.max_passes()
+            );
+        }
         if let Some(Some(zone_photon_noise)) = zone_args.remove("--photon-noise") {
             photon_noise = Some(zone_photon_noise.parse()?);
         }
@@ -250,6 +270,12 @@ impl Scene {
                 .map_err(|e| anyhow!("Invalid --interp-method: {}", e))?;
             target_quality.interp_method = Some((method4, method5));
         }
+        if let Some(Some(zone_q_bracket)) = zone_args.remove("--q-bracket") {
+            let parsed = zone_q_bracket
+                .parse()
+                .map_err(|_| anyhow!("Invalid --q-bracket: {}", zone_q_bracket))?;
+            target_quality.q_bracket = Some(parsed);
+        }
 
         let raw_zone_args = if [Encoder::aom, Encoder::vpx].contains(&encoder) {
             zone_args
@@ -302,28 +328,7 @@ impl Scene {
             }
         }
 
-        for arg in raw_zone_args {
-            if arg.starts_with("--")
-                || (arg.starts_with('-') && arg.chars().nth(1).is_some_and(char::is_alphabetic))
-            {
-                let key = arg.split_once('=').map_or(arg.as_str(), |split| split.0);
-                if let Some(pos) = video_params
-                    .iter()
-                    .position(|param| param == key || param.starts_with(&format!("{key}=")))
-                {
-                    video_params.remove(pos);
-                    if let Some(next) = video_params.get(pos)
-                        && !([Encoder::aom, Encoder::vpx].contains(&encoder)
-                            || next.starts_with("--")
-                            || (next.starts_with('-')
-                                && next.chars().nth(1).is_some_and(char::is_alphabetic)))
-                    {
-                        video_params.remove(pos);
-                    }
-                }
-            }
-            video_params.push(arg);
-        }
+        merge_video_params(encoder, &mut video_params, raw_zone_args);
 
         Ok(Self {
             start_frame:    start,
@@ -344,6 +349,289 @@ impl Scene {
     }
 }
 
+/// Picks `count` scenes spread evenly across `scenes`, taking the middle scene
+/// of each of `count` equally sized groups. Returns all scenes if there are
+/// not more than `count`.
+pub(crate) fn sample_scenes(scenes: &[Scene], count: usize) -> Vec<Scene> {
+    if count >= scenes.len() {
+        return scenes.to_vec();
+    }
+
+    (0..count)
+        .map(|i| scenes[(2 * i + 1) * scenes.len() / (2 * count)].clone())
+        .collect()
+}
+
+/// Lowers `sample_rate` until scoring every `sample_rate`-th frame yields at
+/// least `min_samples` frames from each of `scenes`, while always scoring at
+/// least every frame.
+pub(crate) fn scene_sample_rate(scenes: &[Scene], sample_rate: usize, min_samples: usize) -> usize {
+    if min_samples == 0 {
+        return sample_rate.max(1);
+    }
+
+    scenes
+        .iter()
+        .map(|scene| (scene.end_frame - scene.start_frame) / min_samples)
+        .fold(sample_rate, cmp::min)
+        .max(1)
+}
+
+/// What was detected in a section of the input by `--sc-annotate` or
+/// `--sc-fades`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnnotationKind {
+    Black,
+    Frozen,
+    Fade,
+}
+
+/// A section of the input that is black, frozen or fading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SceneAnnotation {
+    pub kind:        AnnotationKind,
+    pub start_frame: usize,
+    /// Exclusive, like [`Scene::end_frame`]
+    pub end_frame:   usize,
+}
+
+/// A scene that `--sc-annotate` found to be entirely black or frozen, as
+/// written to the annotations report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+struct AnnotatedScene {
+    scene:       usize,
+    start_frame: usize,
+    /// Exclusive, like [`Scene::end_frame`]
+    end_frame:   usize,
+    kind:        AnnotationKind,
+}
+
+/// Returns the kind of the annotation that covers all frames of `scene`, if
+/// any
+pub(crate) fn scene_annotation(
+    annotations: &[SceneAnnotation],
+    scene: &Scene,
+) -> Option<AnnotationKind> {
+    annotations
+        .iter()
+        .find(|a| a.start_frame <= scene.start_frame && a.end_frame >= scene.end_frame)
+        .map(|a| a.kind)
+}
+
+/// A range of frames in which scene detection must not place any cuts, e.g.
+/// an opening with flashing that would otherwise produce hundreds of scenes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct IgnoreRange {
+    pub start_frame:  usize,
+    /// Exclusive, like [`Scene::end_frame`]
+    pub end_frame:    usize,
+    /// Also cut at the start and end of the range, so that it is encoded as a
+    /// single scene
+    pub single_scene: bool,
+}
+
+/// Scenes for each of `zones` and for the frames between them, before any
+/// scene detection
+fn zone_scenes(zones: &[Scene], frames: usize) -> Vec<Scene> {
+    let mut scenes = Vec::with_capacity(2 * zones.len() + 1);
+    let mut frames_processed = 0;
+    // Add scenes for each zone and the scenes between zones
+    for zone in zones {
+        // Frames between the previous zone and this zone
+        if zone.start_frame > frames_processed {
+            // No overrides for unspecified frames between zones
+            scenes.push(Scene {
+                start_frame:    frames_processed,
+                end_frame:      zone.start_frame,
+                zone_overrides: None,
+            });
+        }
+
+        // Add the zone with its overrides
+        scenes.push(zone.clone());
+        // Update the frames processed
+        frames_processed = zone.end_frame;
+    }
+    if frames > frames_processed {
+        scenes.push(Scene {
+            start_frame:    frames_processed,
+            end_frame:      frames,
+            zone_overrides: None,
+        });
+    }
+    scenes
+}
+
+/// Splits the scene containing `frame` into two scenes at `frame`. Returns
+/// false if `frame` is outside of `scenes`.
+fn insert_cut(scenes: &mut Vec<Scene>, frame: usize) -> bool {
+    let Some((scene_pos, s)) = scenes
+        .iter_mut()
+        .find_position(|s| (s.start_frame..s.end_frame).contains(&frame))
+    else {
+        return false;
+    };
+    if frame != s.start_frame {
+        let mut new = s.clone();
+        s.end_frame = frame;
+        new.start_frame = frame;
+        scenes.insert(scene_pos + 1, new);
+    }
+    true
+}
+
+/// Removes the detected cuts inside each of `ranges`. Cuts next to a zone are
+/// kept, so zones keep their own scenes.
+pub(crate) fn apply_ignore_ranges(scenes: &mut Vec<Scene>, ranges: &[IgnoreRange]) {
+    for range in ranges {
+        let mut i = 1;
+        while i < scenes.len() {
+            let cut = scenes[i].start_frame;
+            if cut > range.start_frame
+                && cut < range.end_frame
+                && scenes[i - 1].zone_overrides.is_none()
+                && scenes[i].zone_overrides.is_none()
+            {
+                let removed = scenes.remove(i);
+                scenes[i - 1].end_frame = removed.end_frame;
+            } else {
+                i += 1;
+            }
+        }
+
+        if range.single_scene {
+            insert_cut(scenes, range.start_frame);
+            insert_cut(scenes, range.end_frame);
+        }
+    }
+}
+
+/// Smallest change of the average luma between two frames of a fade, on a
+/// scale from 0 to 255
+const FADE_MIN_STEP: f64 = 0.75;
+/// Smallest change of the average luma over a whole fade
+const FADE_MIN_CHANGE: f64 = 40.0;
+/// Smallest number of frames of a fade, so that hard cuts are not taken for
+/// fades
+const FADE_MIN_FRAMES: usize = 6;
+
+/// Finds the fades in `luma`, the average luma of each frame, as runs of
+/// frames in which the luma keeps rising or falling
+pub(crate) fn find_fades(luma: &[f64]) -> Vec<SceneAnnotation> {
+    let mut fades = Vec::new();
+    let mut start = 0;
+    let mut direction = 0;
+    for frame in 1..=luma.len() {
+        let step = luma.get(frame).map_or(0.0, |luma_avg| luma_avg - luma[frame - 1]);
+        let step_direction = if step >= FADE_MIN_STEP {
+            1
+        } else if step <= -FADE_MIN_STEP {
+            -1
+        } else {
+            0
+        };
+        if step_direction != 0 && step_direction == direction {
+            continue;
+        }
+
+        // The run that started at `start` ended with the previous frame
+        if direction != 0
+            && frame - start >= FADE_MIN_FRAMES
+            && (luma[frame - 1] - luma[start]).abs() >= FADE_MIN_CHANGE
+        {
+            fades.push(SceneAnnotation {
+                kind:        AnnotationKind::Fade,
+                start_frame: start,
+                end_frame:   frame,
+            });
+        }
+        start = frame - 1;
+        direction = step_direction;
+    }
+    fades
+}
+
+/// Moves the cuts placed inside each of `fades` to its end, and also cuts at
+/// its start, so that the fade becomes a scene of its own. Cuts that would
+/// leave a scene shorter than `min_scene_len` are not placed, and fades that
+/// overlap a zone are left alone.
+pub(crate) fn apply_fades(
+    scenes: &mut Vec<Scene>,
+    fades: &[SceneAnnotation],
+    min_scene_len: usize,
+) {
+    let fits = |scenes: &[Scene], frame: usize| {
+        scenes
+            .iter()
+            .find(|s| (s.start_frame..s.end_frame).contains(&frame))
+            .is_some_and(|s| {
+                frame - s.start_frame >= min_scene_len && s.end_frame - frame >= min_scene_len
+            })
+    };
+
+    for fade in fades {
+        if scenes.iter().any(|s| {
+            s.zone_overrides.is_some()
+                && s.start_frame < fade.end_frame
+                && s.end_frame > fade.start_frame
+        }) {
+            continue;
+        }
+
+        apply_ignore_ranges(scenes, &[IgnoreRange {
+            start_frame:  fade.start_frame,
+            end_frame:    fade.end_frame,
+            single_scene: false,
+        }]);
+        if fits(scenes, fade.end_frame) {
+            insert_cut(scenes, fade.end_frame);
+        }
+        if fits(scenes, fade.start_frame) {
+            insert_cut(scenes, fade.start_frame);
+        }
+    }
+}
+
+/// Photon noise strength chosen by `--photon-noise-auto` for a scene
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SceneNoise {
+    pub start_frame: usize,
+    /// Exclusive, like [`Scene::end_frame`]
+    pub end_frame:   usize,
+    /// 0 if the scene is too clean for added grain
+    pub strength:    u8,
+}
+
+/// Noise that the denoiser removes from clean sources as well, by smoothing
+/// fine detail
+const NOISE_FLOOR: f64 = 0.5;
+/// Photon noise strength per unit of standard deviation of the measured noise
+const STRENGTH_PER_NOISE: f64 = 8.0;
+
+/// Picks a photon noise strength for each of `scenes` from `noise`, the mean
+/// squared error between each frame and its denoised version. Scenes without
+/// any measured frames are skipped.
+pub(crate) fn scene_noise(scenes: &[Scene], noise: &[f64]) -> Vec<SceneNoise> {
+    scenes
+        .iter()
+        .filter_map(|scene| {
+            let frames = noise.get(scene.start_frame..scene.end_frame.min(noise.len()))?;
+            if frames.is_empty() {
+                return None;
+            }
+            let deviation = (frames.iter().sum::<f64>() / frames.len() as f64).sqrt();
+            let strength =
+                ((deviation - NOISE_FLOOR) * STRENGTH_PER_NOISE).round().clamp(0.0, 64.0);
+            Some(SceneNoise {
+                start_frame: scene.start_frame,
+                end_frame:   scene.end_frame,
+                strength:    strength as u8,
+            })
+        })
+        .collect()
+}
+
 /// This struct is responsible for choosing and building a list of video chunks.
 /// It is responsible for managing both scene detection and extra splits.
 #[derive(Debug)]
@@ -357,6 +645,13 @@ pub struct ScenesData {
     frames:       usize,
     scenes:       Option<Vec<Scene>>,
     split_scenes: Option<Vec<Scene>>,
+    /// Black and frozen sections found by `--sc-annotate` and fades found by
+    /// `--sc-fades`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    annotations:  Vec<SceneAnnotation>,
+    /// Photon noise strengths picked by `--photon-noise-auto`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    noise:        Vec<SceneNoise>,
 }
 
 impl SceneFactory {
@@ -367,6 +662,8 @@ impl SceneFactory {
                 frames:       0,
                 scenes:       None,
                 split_scenes: None,
+                annotations:  Vec::new(),
+                noise:        Vec::new(),
             },
         }
     }
@@ -397,6 +694,180 @@ impl SceneFactory {
         })
     }
 
+    /// Checks that the scenes loaded from a user-provided scenes file are
+    /// contiguous and cover exactly the `clip_frames` frames of the clip
+    pub fn validate(&self, clip_frames: usize) -> anyhow::Result<()> {
+        let scenes = self.get_split_scenes()?;
+        if scenes.is_empty() {
+            bail!("Scenes file contains no scenes");
+        }
+
+        let mut expected_start = 0;
+        for scene in scenes {
+            if scene.start_frame != expected_start {
+                bail!(
+                    "Scene {start}-{end} in scenes file should start at frame {expected_start}",
+                    start = scene.start_frame,
+                    end = scene.end_frame
+                );
+            }
+            if scene.end_frame <= scene.start_frame {
+                bail!(
+                    "Scene {start}-{end} in scenes file is empty",
+                    start = scene.start_frame,
+                    end = scene.end_frame
+                );
+            }
+            expected_start = scene.end_frame;
+        }
+
+        if expected_start != clip_frames {
+            bail!(
+                "Scenes file covers {expected_start} frames, but the input has {clip_frames} \
+                 frames"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Detects black and frozen sections of the input and reports the scenes
+    /// that consist only of them, which could be encoded with cheaper settings
+    /// or trimmed, in a file next to the output
+    fn annotate(&mut self, args: &EncodeArgs, frames: usize) -> anyhow::Result<()> {
+        if args.input.is_vapoursynth() {
+            warn!("--sc-annotate is not supported with VapourSynth script inputs, skipping");
+            return Ok(());
+        }
+
+        info!("detecting black and frozen frames");
+        let frame_rate = args.input.clip_info()?.frame_rate;
+        let frame_rate =
+            frame_rate.to_f64().filter(|fps| fps.is_finite() && *fps > 0.0).ok_or_else(|| {
+                anyhow!("Cannot annotate scenes of an input with frame rate {frame_rate}")
+            })?;
+        let annotations = detect_black_and_frozen(args.input.as_video_path(), frame_rate, frames)?;
+
+        let scenes = self.data.scenes.as_deref().unwrap_or_default();
+        let mut annotated = Vec::new();
+        for (index, scene) in scenes.iter().enumerate() {
+            if let Some(kind) = scene_annotation(&annotations, scene) {
+                annotated.push(AnnotatedScene {
+                    scene: index,
+                    start_frame: scene.start_frame,
+                    end_frame: scene.end_frame,
+                    kind,
+                });
+                info!(
+                    "scene {index} (frames {start}-{end}) is {kind}",
+                    start = scene.start_frame,
+                    end = scene.end_frame,
+                    kind = match kind {
+                        AnnotationKind::Black => "black",
+                        AnnotationKind::Frozen => "frozen",
+                        AnnotationKind::Fade => "a fade",
+                    }
+                );
+            }
+        }
+
+        let report_file = args.output_file.with_extension("annotations.json");
+        fs::write(&report_file, serde_json::to_string_pretty(&annotated)?)?;
+        info!(
+            "{count} black or frozen scene(s) written to {path}",
+            count = annotated.len(),
+            path = report_file.display()
+        );
+
+        self.data.annotations.extend(annotations);
+        self.data.annotations.sort_by_key(|annotation| annotation.start_frame);
+        Ok(())
+    }
+
+    /// Detects the fades of the input, moves the cuts inside them to their
+    /// ends and applies `--sc-fade-zone` to the scenes containing them
+    fn place_fades(
+        &mut self,
+        args: &EncodeArgs,
+        scenes: &mut Vec<Scene>,
+        frames: usize,
+    ) -> anyhow::Result<()> {
+        if args.input.is_vapoursynth() {
+            warn!("--sc-fades is not supported with VapourSynth script inputs, skipping");
+            return Ok(());
+        }
+
+        info!("detecting fades");
+        let mut fades = find_fades(&luma_averages(args.input.as_video_path())?);
+        // No cuts are placed in the ignore ranges, not even for fades
+        fades.retain(|fade| {
+            !args.sc_ignore_ranges.iter().any(|range| {
+                range.start_frame < fade.end_frame && range.end_frame > fade.start_frame
+            })
+        });
+        apply_fades(scenes, &fades, args.min_scene_len);
+
+        let mut fade_zones = Vec::new();
+        for fade in &fades {
+            let Some((index, scene)) = scenes.iter_mut().find_position(|s| {
+                s.start_frame <= fade.start_frame && s.end_frame >= fade.end_frame
+            }) else {
+                continue;
+            };
+            info!(
+                "scene {index} (frames {start}-{end}) contains a fade at frames \
+                 {fade_start}-{fade_end}",
+                start = scene.start_frame,
+                end = scene.end_frame,
+                fade_start = fade.start_frame,
+                fade_end = fade.end_frame
+            );
+
+            if let Some(zone) = &args.sc_fade_zone
+                && scene.zone_overrides.is_none()
+            {
+                let fade_zone = Scene::parse_from_zone(
+                    &format!("{} {} {zone}", scene.start_frame, scene.end_frame),
+                    args,
+                    frames,
+                )?;
+                scene.zone_overrides.clone_from(&fade_zone.zone_overrides);
+                fade_zones.push(fade_zone);
+            }
+        }
+        validate_zones(args, &fade_zones)?;
+
+        self.data.annotations.extend(fades);
+        Ok(())
+    }
+
+    /// Measures the noise of the input and picks a photon noise strength for
+    /// each scene
+    pub fn measure_noise(&mut self, args: &EncodeArgs) -> anyhow::Result<()> {
+        info!("measuring noise levels");
+        let noise = noise_levels(args.input.as_video_path())?;
+        let scenes = self.data.scenes.as_deref().or(self.data.split_scenes.as_deref());
+        self.data.noise = scene_noise(scenes.unwrap_or_default(), &noise);
+        Ok(())
+    }
+
+    /// Whether `--photon-noise-auto` still has to measure the noise of the
+    /// scenes, e.g. for a scenes file written without it
+    pub fn noise_missing(&self) -> bool {
+        self.data.noise.is_empty()
+    }
+
+    /// Photon noise strength picked by `--photon-noise-auto` for the scene
+    /// containing `frame`, or `None` if it should not get grain
+    pub fn auto_photon_noise(&self, frame: usize) -> Option<u8> {
+        self.data
+            .noise
+            .iter()
+            .find(|noise| (noise.start_frame..noise.end_frame).contains(&frame))
+            .map(|noise| noise.strength)
+            .filter(|&strength| strength > 0)
+    }
+
     /// Retrieve the pre-extra-split scenes data
     #[expect(dead_code)]
     pub fn get_scenecuts(&self) -> anyhow::Result<&[Scene]> {
@@ -431,9 +902,30 @@ impl SceneFactory {
         }
 
         let json = serde_json::to_string_pretty(&self.data).expect("serialize should not fail");
+        write_atomic(scene_path.as_ref(), json)?;
 
-        let mut file = File::create(scene_path)?;
-        file.write_all(json.as_bytes())?;
+        Ok(())
+    }
+
+    /// Writes the scenes to `scene_path` in `format`, for other tools. `fps` is
+    /// the frame rate of the input.
+    pub fn export_scenes<P: AsRef<Path>>(
+        &self,
+        scene_path: P,
+        format: ScenesFormat,
+        fps: f64,
+    ) -> anyhow::Result<()> {
+        if self.data.scenes.is_none() {
+            bail!("compute_scenes must be called first");
+        }
+
+        fs::write(&scene_path, format.write(&self.data, fps)).with_context(|| {
+            format!(
+                "Failed to write scenes to {}",
+                scene_path.as_ref().display()
+            )
+        })?;
+        info!("scenes written to {}", scene_path.as_ref().display());
 
         Ok(())
     }
@@ -456,59 +948,72 @@ impl SceneFactory {
                 args.verbosity,
                 args.scaler.as_str(),
                 args.sc_pix_format,
+                args.sc_luma_only,
                 args.sc_method,
                 args.sc_downscale_height,
+                args.sc_pq_mode,
                 zones,
             )?,
-            SplitMethod::None => {
-                let mut scenes = Vec::with_capacity(2 * zones.len() + 1);
-                let mut frames_processed = 0;
-                // Add scenes for each zone and the scenes between zones
-                for zone in zones {
-                    // Frames between the previous zone and this zone
-                    if zone.start_frame > frames_processed {
-                        // No overrides for unspecified frames between zones
-                        scenes.push(Scene {
-                            start_frame:    frames_processed,
-                            end_frame:      zone.start_frame,
-                            zone_overrides: None,
-                        });
+            SplitMethod::FFmpeg => {
+                info!("measuring scene scores with FFmpeg");
+                let scores = scene_scores(args.input.as_video_path())?;
+                let cuts = ffmpeg_scene_cuts(&scores, args.sc_threshold, args.min_scene_len);
+                let mut scenes = zone_scenes(zones, frames);
+                for cut in cuts {
+                    insert_cut(&mut scenes, cut);
+                }
+                (scenes, frames, BTreeMap::new())
+            },
+            SplitMethod::PySceneDetect | SplitMethod::Import => {
+                let path = args.sc_import.as_ref().expect("validated to be set");
+                let frame_rate = args.input.clip_info()?.frame_rate.to_f64().unwrap_or(0.0);
+                let starts = if matches!(args.split_method, SplitMethod::PySceneDetect) {
+                    import_pyscenedetect(path, frame_rate)?
+                } else {
+                    let text = fs::read_to_string(path)
+                        .with_context(|| format!("Failed to read scene list {}", path.display()))?;
+                    args.scenes_format.read_cuts(&text, frame_rate).with_context(|| {
+                        format!(
+                            "Failed to parse {format} scenes {path}",
+                            format = args.scenes_format,
+                            path = path.display()
+                        )
+                    })?
+                };
+                let mut scenes = zone_scenes(zones, frames);
+                for start in starts {
+                    if !insert_cut(&mut scenes, start) {
+                        warn!(
+                            "imported scene starts at frame {start} but video has {frames} \
+                             frames, ignoring"
+                        );
                     }
-
-                    // Add the zone with its overrides
-                    scenes.push(zone.clone());
-                    // Update the frames processed
-                    frames_processed = zone.end_frame;
                 }
-                if frames > frames_processed {
-                    scenes.push(Scene {
-                        start_frame:    frames_processed,
-                        end_frame:      frames,
-                        zone_overrides: None,
-                    });
+                (scenes, frames, BTreeMap::new())
+            },
+            SplitMethod::Keyframes => {
+                let keyframes = keyframes(args.input.as_video_path())?;
+                let mut scenes = zone_scenes(zones, frames);
+                for cut in keyframe_cuts(&keyframes, args.min_scene_len) {
+                    insert_cut(&mut scenes, cut);
                 }
                 (scenes, frames, BTreeMap::new())
             },
+            SplitMethod::None => (zone_scenes(zones, frames), frames, BTreeMap::new()),
         };
 
         self.data.frames = frames;
         get_done().frames.store(frames, atomic::Ordering::SeqCst);
 
+        apply_ignore_ranges(&mut scenes, &args.sc_ignore_ranges);
+
+        if args.sc_fades {
+            self.place_fades(args, &mut scenes, frames)?;
+        }
+
         // Add forced keyframes
         for kf in &args.force_keyframes {
-            if let Some((scene_pos, s)) =
-                scenes.iter_mut().find_position(|s| (s.start_frame..s.end_frame).contains(kf))
-            {
-                if *kf == s.start_frame {
-                    // Already a keyframe
-                    continue;
-                }
-                // Split this scene into two scenes at the requested keyframe
-                let mut new = s.clone();
-                s.end_frame = *kf;
-                new.start_frame = *kf;
-                scenes.insert(scene_pos + 1, new);
-            } else {
+            if !insert_cut(&mut scenes, *kf) {
                 warn!(
                     "scene {kf} was requested as a forced keyframe but video has {frames} frames, \
                      ignoring"
@@ -528,6 +1033,14 @@ impl SceneFactory {
         let scenes_before = scenes.len();
         self.data.scenes = Some(scenes);
 
+        if args.sc_annotate {
+            self.annotate(args, frames)?;
+        }
+
+        if args.photon_noise_auto {
+            self.measure_noise(args)?;
+        }
+
         if let Some(split_len @ 1..) = args.extra_splits_len {
             self.data.split_scenes = Some(extra_splits(
                 self.data.scenes.as_deref().expect("scenes is set"),
@@ -544,6 +1057,23 @@ impl SceneFactory {
             info!("scenecut: found {scenes_before} scene(s)");
         }
 
+        if args.split_for_workers {
+            let workers = match args.workers {
+                0 => determine_workers(args)? as usize,
+                workers => workers,
+            };
+            let split_scenes = self.data.split_scenes.as_mut().expect("split_scenes is set");
+            if split_scenes.len() < workers {
+                let scenes_before = split_scenes.len();
+                *split_scenes = split_for_workers(split_scenes, workers, args.min_scene_len);
+                info!(
+                    "scenecut: split {scenes_before} scene(s) into {scenes_after} for {workers} \
+                     worker(s)",
+                    scenes_after = split_scenes.len()
+                );
+            }
+        }
+
         Ok(())
     }
 }
@@ -1,11 +1,30 @@
-use std::str::FromStr;
+use std::{collections::BTreeMap, str::FromStr, time::Duration};
+
+use itertools::Itertools;
 
 use crate::{
     context::Av1anContext,
     encoder::Encoder,
-    scenes::{Scene, SceneFactory},
+    scenes::{
+        apply_fades,
+        apply_ignore_ranges,
+        find_fades,
+        sample_scenes,
+        scene_noise,
+        scene_sample_rate,
+        AnnotationKind,
+        IgnoreRange,
+        Scene,
+        SceneAnnotation,
+        SceneFactory,
+        SceneNoise,
+        ScenesData,
+    },
+    split::extra_splits,
     InterpolationMethod,
+    KeepPolicy,
     ProbingStatistic,
+    ProcessPriority,
     TargetMetric,
     TargetQuality,
 };
@@ -15,7 +34,7 @@ fn get_test_args() -> Av1anContext {
 
     use crate::{
         concat::ConcatMethod,
-        ffmpeg::FFPixelFormat,
+        ffmpeg::{FFPixelFormat, Passthrough},
         into_vec,
         settings::{EncodeArgs, InputPixelFormat, PixelFormat},
         vapoursynth::CacheSource,
@@ -23,71 +42,118 @@ fn get_test_args() -> Av1anContext {
         ChunkOrdering,
         Input,
         ScenecutMethod,
+        ScenecutPqMode,
+        ScenesFormat,
         SplitMethod,
         Verbosity,
     };
 
     let args = EncodeArgs {
-        ffmpeg_filter_args:    Vec::new(),
-        temp:                  String::new(),
-        force:                 false,
-        no_defaults:           false,
-        passes:                2,
-        video_params:          into_vec!["--cq-level=40", "--cpu-used=0", "--aq-mode=1"],
-        output_file:           String::new(),
-        audio_params:          Vec::new(),
-        chunk_method:          ChunkMethod::LSMASH,
-        chunk_order:           ChunkOrdering::Random,
-        concat:                ConcatMethod::FFmpeg,
-        encoder:               Encoder::aom,
-        extra_splits_len:      Some(100),
-        photon_noise:          Some(10),
-        photon_noise_size:     (None, None),
-        chroma_noise:          false,
-        sc_pix_format:         None,
-        keep:                  false,
-        max_tries:             3,
-        min_scene_len:         10,
-        input_pix_format:      InputPixelFormat::FFmpeg {
+        ffmpeg_filter_args:      Vec::new(),
+        temp:                    String::new(),
+        force:                   false,
+        no_defaults:             false,
+        passes:                  2,
+        pass_params:             Vec::new(),
+        whole_clip_first_pass:   false,
+        collapse_duplicates:     false,
+        keep_vfr:                false,
+        verify_bitstream:        false,
+        video_params:            into_vec!["--cq-level=40", "--cpu-used=0", "--aq-mode=1"],
+        live_params:             None,
+        pause_file:              None,
+        memory_ceiling:          None,
+        force_lock:              false,
+        priority:                ProcessPriority::Normal,
+        output_file:             PathBuf::new(),
+        overwrite_policy:        crate::OverwritePolicy::Fail,
+        audio_params:            Vec::new(),
+        passthrough:             Passthrough::default(),
+        chunk_method:            ChunkMethod::LSMASH,
+        chunk_order:             ChunkOrdering::Random,
+        sample:                  None,
+        bitrate_spike_threshold: None,
+        heatmap:                 false,
+        export_stats:            None,
+        decode_error:            crate::DecodeErrorPolicy::Fail,
+        concat:                  ConcatMethod::FFmpeg,
+        encoder:                 Encoder::aom,
+        extra_splits_len:        Some(100),
+        default_extra_splits:    false,
+        photon_noise:            Some(10),
+        photon_noise_anchors:    Vec::new(),
+        photon_noise_auto:       false,
+        photon_noise_size:       (None, None),
+        chroma_noise:            false,
+        chroma_noise_strength:   None,
+        chroma_noise_coeffs:     (1.0, 1.0),
+        photon_noise_seed:       None,
+        hdr10plus:               None,
+        device_target:           None,
+        sc_pix_format:           None,
+        sc_luma_only:            false,
+        keep:                    KeepPolicy::OnFailure,
+        max_tries:               3,
+        time_budget:             None,
+        split_for_workers:       false,
+        sc_cache:                None,
+        min_scene_len:           10,
+        input_pix_format:        InputPixelFormat::FFmpeg {
             format: FFPixelFormat::YUV420P10LE,
         },
-        input:                 Input::Video {
+        input:                   Input::Video {
             path:         PathBuf::new(),
             temp:         String::new(),
             chunk_method: ChunkMethod::LSMASH,
             is_proxy:     false,
             cache_mode:   CacheSource::SOURCE,
         },
-        proxy:                 None,
-        output_pix_format:     PixelFormat {
+        proxy:                   None,
+        output_pix_format:       PixelFormat {
             format:    FFPixelFormat::YUV420P10LE,
             bit_depth: 10,
         },
-        resume:                false,
-        scenes:                None,
-        split_method:          SplitMethod::AvScenechange,
-        sc_method:             ScenecutMethod::Standard,
-        sc_only:               false,
-        sc_downscale_height:   None,
-        force_keyframes:       Vec::new(),
-        target_quality:        TargetQuality::default("", Encoder::aom),
-        vmaf:                  false,
-        verbosity:             Verbosity::Normal,
-        workers:               1,
-        tiles:                 (1, 1),
-        tile_auto:             false,
-        set_thread_affinity:   None,
-        zones:                 None,
-        scaler:                String::new(),
-        ignore_frame_mismatch: false,
-        vmaf_path:             None,
-        vmaf_res:              "1920x1080".to_string(),
-        vmaf_threads:          None,
-        vmaf_filter:           None,
-        probe_res:             None,
-        vapoursynth_plugins:   None,
-        cache_mode:            CacheSource::SOURCE,
-        pix_format_converter:  crate::PixelFormatConverter::FFMPEG,
+        sar:                     None,
+        interlacing:             None,
+        color_range:             None,
+        resume:                  false,
+        scenes:                  None,
+        split_method:            SplitMethod::AvScenechange,
+        sc_method:               ScenecutMethod::Standard,
+        sc_pq_mode:              ScenecutPqMode::Tonemap,
+        sc_threshold:            0.4,
+        sc_import:               None,
+        export_scenes:           None,
+        scenes_format:           ScenesFormat::Json,
+        sc_only:                 false,
+        sc_downscale_height:     None,
+        force_keyframes:         Vec::new(),
+        sc_ignore_ranges:        Vec::new(),
+        sc_annotate:             false,
+        sc_fades:                false,
+        sc_fade_zone:            None,
+        target_quality:          TargetQuality::default("", Encoder::aom),
+        vmaf:                    false,
+        verbosity:               Verbosity::Normal,
+        progress_interval:       Duration::from_millis(100),
+        workers:                 1,
+        tiles:                   (1, 1),
+        tile_auto:               false,
+        set_thread_affinity:     None,
+        zones:                   None,
+        scaler:                  String::new(),
+        ignore_frame_mismatch:   false,
+        vmaf_path:               None,
+        vmaf_res:                "1920x1080".to_string(),
+        vmaf_threads:            None,
+        vmaf_filter:             None,
+        vmaf_sample_rate:        1,
+        vmaf_min_scene_samples:  0,
+        reencode_outliers:       false,
+        probe_res:               None,
+        vapoursynth_plugins:     None,
+        cache_mode:              CacheSource::SOURCE,
+        pix_format_converter:    crate::PixelFormatConverter::FFMPEG,
     };
     Av1anContext {
         vs_script: None,
@@ -95,6 +161,8 @@ fn get_test_args() -> Av1anContext {
         frames: 6900,
         args,
         scene_factory: SceneFactory::new(),
+        hdr10plus: None,
+        temp_lock: std::sync::Mutex::new(None),
     }
 }
 
@@ -305,3 +373,221 @@ fn validate_zones_target_quality() {
         ))
     );
 }
+
+#[test]
+fn validate_zones_target_quality_overrides_global_target() {
+    let mut args = get_test_args();
+    // Skips checking the zone parameters against the encoder help
+    args.args.force = true;
+    args.args.target_quality.target = Some((95.5, 96.5));
+
+    let credits = Scene::parse_from_zone(
+        "1000 -1 aom --target-quality 84.5-85.5 --q-bracket 4",
+        &args.args,
+        args.frames,
+    )
+    .expect("should parse zone successfully");
+    let zone_overrides = credits.zone_overrides.expect("should have zone overrides");
+    let target_quality = zone_overrides.target_quality.expect("should have target quality");
+    assert_eq!(target_quality.target, Some((84.5, 85.5)));
+    assert_eq!(target_quality.q_bracket, Some(4));
+    assert!(!zone_overrides.video_params.iter().any(|param| param.contains("q-bracket")));
+    assert_eq!(args.args.target_quality.target, Some((95.5, 96.5)));
+    assert_eq!(args.args.target_quality.q_bracket, None);
+}
+
+#[test]
+fn sample_scenes_evenly_spaced() {
+    let scenes = (0..10)
+        .map(|i| Scene {
+            start_frame:    i * 10,
+            end_frame:      (i + 1) * 10,
+            zone_overrides: None,
+        })
+        .collect::<Vec<_>>();
+
+    let starts = |samples: Vec<Scene>| samples.iter().map(|s| s.start_frame).collect::<Vec<_>>();
+    assert_eq!(starts(sample_scenes(&scenes, 1)), [50]);
+    assert_eq!(starts(sample_scenes(&scenes, 3)), [10, 50, 80]);
+    assert_eq!(
+        starts(sample_scenes(&scenes, 10)),
+        (0..10).map(|i| i * 10).collect::<Vec<_>>()
+    );
+    assert_eq!(sample_scenes(&scenes, 20).len(), 10);
+}
+
+#[test]
+fn scene_sample_rate_keeps_minimum_samples() {
+    let scenes = [(0, 240), (240, 300), (300, 1000)]
+        .into_iter()
+        .map(|(start_frame, end_frame)| Scene {
+            start_frame,
+            end_frame,
+            zone_overrides: None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(scene_sample_rate(&scenes, 24, 0), 24);
+    assert_eq!(scene_sample_rate(&scenes, 24, 2), 24);
+    assert_eq!(scene_sample_rate(&scenes, 24, 10), 6);
+    assert_eq!(scene_sample_rate(&scenes, 24, 100), 1);
+}
+
+#[test]
+fn validate_scenes_file() {
+    let factory = |ranges: &[(usize, usize)]| SceneFactory {
+        data: ScenesData {
+            frames:       ranges.last().map_or(0, |&(_, end)| end),
+            scenes:       None,
+            annotations:  Vec::new(),
+            noise:        Vec::new(),
+            split_scenes: Some(
+                ranges
+                    .iter()
+                    .map(|&(start_frame, end_frame)| Scene {
+                        start_frame,
+                        end_frame,
+                        zone_overrides: None,
+                    })
+                    .collect(),
+            ),
+        },
+    };
+
+    assert!(factory(&[(0, 50), (50, 100)]).validate(100).is_ok());
+    assert!(factory(&[(0, 50), (50, 100)]).validate(120).is_err());
+    assert!(factory(&[(0, 50), (50, 100)]).validate(80).is_err());
+    assert!(factory(&[(0, 50), (60, 100)]).validate(100).is_err());
+    assert!(factory(&[(10, 50), (50, 100)]).validate(100).is_err());
+    assert!(factory(&[(0, 50), (50, 50)]).validate(100).is_err());
+    assert!(factory(&[]).validate(100).is_err());
+}
+
+#[test]
+fn ignore_ranges_remove_cuts() {
+    let scenes_from = |cuts: &[usize]| {
+        cuts.iter()
+            .tuple_windows()
+            .map(|(&start_frame, &end_frame)| Scene {
+                start_frame,
+                end_frame,
+                zone_overrides: None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let cuts_of = |scenes: &[Scene]| {
+        scenes
+            .iter()
+            .map(|s| s.start_frame)
+            .chain(scenes.last().map(|s| s.end_frame))
+            .collect_vec()
+    };
+
+    let mut scenes = scenes_from(&[0, 10, 20, 25, 30, 40, 50]);
+    apply_ignore_ranges(&mut scenes, &[IgnoreRange {
+        start_frame:  15,
+        end_frame:    35,
+        single_scene: false,
+    }]);
+    assert_eq!(cuts_of(&scenes), [0, 10, 40, 50]);
+
+    let mut scenes = scenes_from(&[0, 10, 20, 25, 30, 40, 50]);
+    apply_ignore_ranges(&mut scenes, &[IgnoreRange {
+        start_frame:  15,
+        end_frame:    35,
+        single_scene: true,
+    }]);
+    assert_eq!(cuts_of(&scenes), [0, 10, 15, 35, 40, 50]);
+
+    // The merged scenes are split again by the extra splits that follow
+    let mut scenes = scenes_from(&[0, 10, 20, 25, 30, 40, 50]);
+    apply_ignore_ranges(&mut scenes, &[IgnoreRange {
+        start_frame:  0,
+        end_frame:    50,
+        single_scene: false,
+    }]);
+    assert_eq!(cuts_of(&scenes), [0, 50]);
+    let split = extra_splits(&scenes, 12, &BTreeMap::new());
+    assert!(split.iter().all(|s| s.end_frame - s.start_frame <= 12));
+    assert_eq!(cuts_of(&split).last(), Some(&50));
+}
+
+#[test]
+fn fades_found_from_gradual_luma_change() {
+    // A fade out to black, a hard cut and a fade in that is too short
+    let mut luma = vec![120.0; 10];
+    luma.extend((1..=12).map(|i| f64::from(i).mul_add(-10.0, 120.0)));
+    luma.extend([0.0; 5]);
+    luma.extend([100.0; 10]);
+    luma.extend([0.0, 30.0, 60.0, 90.0]);
+
+    assert_eq!(find_fades(&luma), [SceneAnnotation {
+        kind:        AnnotationKind::Fade,
+        start_frame: 9,
+        end_frame:   22,
+    }]);
+}
+
+#[test]
+fn fades_move_cuts_to_their_end() {
+    let scenes_from = |cuts: &[usize]| {
+        cuts.iter()
+            .tuple_windows()
+            .map(|(&start_frame, &end_frame)| Scene {
+                start_frame,
+                end_frame,
+                zone_overrides: None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let cuts_of = |scenes: &[Scene]| {
+        scenes
+            .iter()
+            .map(|s| s.start_frame)
+            .chain(scenes.last().map(|s| s.end_frame))
+            .collect_vec()
+    };
+    let fade = |start_frame, end_frame| SceneAnnotation {
+        kind: AnnotationKind::Fade,
+        start_frame,
+        end_frame,
+    };
+
+    let mut scenes = scenes_from(&[0, 50, 70, 150]);
+    apply_fades(&mut scenes, &[fade(60, 80)], 10);
+    assert_eq!(cuts_of(&scenes), [0, 50, 60, 80, 150]);
+
+    // Neither cut would leave a long enough scene
+    let mut scenes = scenes_from(&[0, 50, 70, 150]);
+    apply_fades(&mut scenes, &[fade(55, 145)], 10);
+    assert_eq!(cuts_of(&scenes), [0, 50, 150]);
+
+    // The fade itself is too short to be a scene of its own
+    let mut scenes = scenes_from(&[0, 50, 70, 150]);
+    apply_fades(&mut scenes, &[fade(64, 72)], 10);
+    assert_eq!(cuts_of(&scenes), [0, 50, 72, 150]);
+}
+
+#[test]
+fn noise_picks_strength_per_scene() {
+    let scenes = [(0, 4), (4, 8), (8, 12)].map(|(start_frame, end_frame)| Scene {
+        start_frame,
+        end_frame,
+        zone_overrides: None,
+    });
+    // A clean scene, a grainy scene and a scene past the measured frames
+    let noise = [0.1, 0.2, 0.1, 0.2, 9.0, 9.0, 9.0, 9.0];
+
+    assert_eq!(scene_noise(&scenes, &noise), [
+        SceneNoise {
+            start_frame: 0,
+            end_frame:   4,
+            strength:    0,
+        },
+        SceneNoise {
+            start_frame: 4,
+            end_frame:   8,
+            strength:    20,
+        },
+    ]);
+}
@@ -4,28 +4,42 @@ use std::{
     collections::HashSet,
     fmt::Display,
     path::{absolute, Path, PathBuf},
-    process::{exit, Command},
+    process::exit,
+    time::Duration,
 };
 
 use anyhow::{bail, ensure};
 use itertools::{chain, Itertools};
 use serde::{Deserialize, Serialize};
 use strum::{EnumString, IntoStaticStr};
-use tracing::warn;
+use tracing::{debug, info, warn};
 
 use crate::{
+    chunk::ChromaNoise,
     concat::ConcatMethod,
+    device::DeviceTarget,
     encoder::Encoder,
-    ffmpeg::FFPixelFormat,
-    metrics::{vmaf::validate_libvmaf, xpsnr::validate_libxpsnr},
+    ffmpeg::{ffmpeg_has_encoder, validate_hevc_nvenc, FFPixelFormat, Passthrough},
+    hdr10plus::Hdr10PlusSource,
+    metrics::{plugin::validate_scorer, vmaf::validate_libvmaf, xpsnr::validate_libxpsnr},
     parse::valid_params,
+    priority::ProcessPriority,
+    scenes::IgnoreRange,
     target_quality::TargetQuality,
     vapoursynth::{CacheSource, VSZipVersion, VapoursynthPlugins},
     ChunkMethod,
     ChunkOrdering,
+    ColorRange,
+    DecodeErrorPolicy,
     Input,
+    Interlacing,
+    KeepPolicy,
+    OverwritePolicy,
     ScenecutMethod,
+    ScenecutPqMode,
+    ScenesFormat,
     SplitMethod,
+    StatsFormat,
     TargetMetric,
     Verbosity,
 };
@@ -84,66 +98,161 @@ impl InputPixelFormat {
 }
 
 #[expect(clippy::struct_excessive_bools)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EncodeArgs {
-    pub input:       Input,
-    pub proxy:       Option<Input>,
-    pub temp:        String,
-    pub output_file: String,
-
-    pub chunk_method:          ChunkMethod,
-    pub chunk_order:           ChunkOrdering,
-    pub scaler:                String,
-    pub scenes:                Option<PathBuf>,
-    pub split_method:          SplitMethod,
-    pub sc_pix_format:         Option<FFPixelFormat>,
-    pub sc_method:             ScenecutMethod,
-    pub sc_only:               bool,
-    pub sc_downscale_height:   Option<usize>,
-    pub extra_splits_len:      Option<usize>,
-    pub min_scene_len:         usize,
-    pub force_keyframes:       Vec<usize>,
-    pub ignore_frame_mismatch: bool,
-
-    pub max_tries: usize,
-
-    pub passes:               u8,
-    pub video_params:         Vec<String>,
-    pub tiles:                (u32, u32), /* tile (cols, rows) count; log2 will be
-                                           * applied
-                                           * later
-                                           * for specific encoders */
-    pub encoder:              Encoder,
-    pub workers:              usize,
-    pub set_thread_affinity:  Option<usize>,
-    pub photon_noise:         Option<u8>,
-    pub photon_noise_size:    (Option<u32>, Option<u32>), // Width and Height
-    pub chroma_noise:         bool,
-    pub zones:                Option<PathBuf>,
-    pub cache_mode:           CacheSource,
-    pub pix_format_converter: PixelFormatConverter,
+    pub input:            Input,
+    pub proxy:            Option<Input>,
+    pub temp:             String,
+    pub output_file:      PathBuf,
+    /// What to do if the output file already exists
+    pub overwrite_policy: OverwritePolicy,
+
+    pub chunk_method:            ChunkMethod,
+    pub chunk_order:             ChunkOrdering,
+    /// Number of evenly spaced scenes to encode instead of the whole video
+    pub sample:                  Option<usize>,
+    /// Warn about chunks whose bitrate exceeds the median by this factor
+    pub bitrate_spike_threshold: Option<f64>,
+    /// Write a timeline of the scenes colored by bitrate and quality
+    pub heatmap:                 bool,
+    /// Write the results of every scene in this format
+    pub export_stats:            Option<StatsFormat>,
+    pub decode_error:            DecodeErrorPolicy,
+    pub scaler:                  String,
+    pub scenes:                  Option<PathBuf>,
+    pub split_method:            SplitMethod,
+    pub sc_pix_format:           Option<FFPixelFormat>,
+    /// Only decode the luma plane for scene detection
+    pub sc_luma_only:            bool,
+    pub sc_method:               ScenecutMethod,
+    pub sc_only:                 bool,
+    pub sc_downscale_height:     Option<usize>,
+    pub sc_pq_mode:              ScenecutPqMode,
+    /// Scene score above which the ffmpeg split method cuts
+    pub sc_threshold:            f64,
+    /// Scene list read by the pyscenedetect and import split methods
+    pub sc_import:               Option<PathBuf>,
+    /// Write the scenes to this file after scene detection
+    pub export_scenes:           Option<PathBuf>,
+    /// Format of `export_scenes` and of `sc_import` with the import split
+    /// method
+    pub scenes_format:           ScenesFormat,
+    pub extra_splits_len:        Option<usize>,
+    /// The maximum scene length is the default one, which is lowered to the
+    /// keyframe interval of the encoder
+    pub default_extra_splits:    bool,
+    pub min_scene_len:           usize,
+    /// Folder in which detected scenes are kept for later encodes of the same
+    /// input with the same settings
+    pub sc_cache:                Option<PathBuf>,
+    /// Split the longest scenes until every worker has one to encode
+    pub split_for_workers:       bool,
+    pub force_keyframes:         Vec<usize>,
+    /// Frame ranges in which scene detection does not place cuts
+    pub sc_ignore_ranges:        Vec<IgnoreRange>,
+    /// Detect black and frozen sections and annotate the affected scenes
+    pub sc_annotate:             bool,
+    /// Detect fades, move the cuts inside them to their ends and annotate them
+    pub sc_fades:                bool,
+    /// Zone applied to the scenes containing a fade, in the zones file syntax
+    /// without the frame range
+    pub sc_fade_zone:            Option<String>,
+    pub ignore_frame_mismatch:   bool,
+
+    pub max_tries:   usize,
+    /// Stop starting new chunks after this much time has passed
+    pub time_budget: Option<Duration>,
+
+    pub passes:                u8,
+    /// Encoder parameters merged into the video parameters of a single pass
+    /// of multi-pass encoding
+    pub pass_params:           Vec<(u8, Vec<String>)>,
+    /// Run the first pass over the whole clip and slice its stats per chunk
+    pub whole_clip_first_pass: bool,
+    /// Encode a single frame for every run of identical source frames
+    pub collapse_duplicates:   bool,
+    /// Time the output frames like the frames of a variable frame rate input
+    pub keep_vfr:              bool,
+    pub video_params:          Vec<String>,
+    /// File whose encoder parameters are reloaded while encoding
+    pub live_params:           Option<PathBuf>,
+    /// File whose existence pauses the encode: no new chunks are started
+    /// while it exists
+    pub pause_file:            Option<PathBuf>,
+    /// Percentage of the system memory in use above which no new chunks are
+    /// started
+    pub memory_ceiling:        Option<u8>,
+    /// Use the temporary folder even if another encode holds its lock
+    pub force_lock:            bool,
+    pub priority:              ProcessPriority,
+    pub tiles:                 (u32, u32), /* tile (cols, rows) count; log2 will be
+                                            * applied
+                                            * later
+                                            * for specific encoders */
+    pub encoder:               Encoder,
+    pub workers:               usize,
+    pub set_thread_affinity:   Option<usize>,
+    pub photon_noise:          Option<u8>,
+    /// (seconds, strength) pairs sorted by time
+    pub photon_noise_anchors:  Vec<(f64, u8)>,
+    /// Pick the photon noise strength of each scene from its measured noise
+    pub photon_noise_auto:     bool,
+    pub photon_noise_size:     (Option<u32>, Option<u32>), // Width and Height
+    pub chroma_noise:          bool,
+    /// Photon noise strength of the chroma planes, instead of chroma grain
+    /// scaled like the luma grain
+    pub chroma_noise_strength: Option<u8>,
+    /// Multipliers of the chroma grain strength of the Cb and Cr planes
+    pub chroma_noise_coeffs:   (f64, f64),
+    /// Seed of the photon noise of the run, from which each chunk's seed is
+    /// derived
+    pub photon_noise_seed:     Option<u16>,
+    /// HDR10+ dynamic metadata passed to the encoder of each chunk
+    pub hdr10plus:             Option<Hdr10PlusSource>,
+    /// Class of hardware decoders whose constraints are applied to every
+    /// chunk
+    pub device_target:         Option<DeviceTarget>,
+    pub zones:                 Option<PathBuf>,
+    pub cache_mode:            CacheSource,
+    pub pix_format_converter:  PixelFormatConverter,
 
     // FFmpeg params
     pub ffmpeg_filter_args: Vec<String>,
     pub audio_params:       Vec<String>,
+    /// Subtitles, attachments and chapters copied along with the audio
+    pub passthrough:        Passthrough,
     pub input_pix_format:   InputPixelFormat,
     pub output_pix_format:  PixelFormat,
-
-    pub verbosity:   Verbosity,
-    pub resume:      bool,
-    pub keep:        bool,
-    pub force:       bool,
-    pub no_defaults: bool,
-    pub tile_auto:   bool,
-
-    pub concat:         ConcatMethod,
-    pub target_quality: TargetQuality,
-    pub vmaf:           bool,
-    pub vmaf_path:      Option<PathBuf>,
-    pub vmaf_res:       String,
-    pub probe_res:      Option<String>,
-    pub vmaf_threads:   Option<usize>,
-    pub vmaf_filter:    Option<String>,
+    /// Overrides of the y4m header sent to the encoder
+    pub sar:                Option<(u32, u32)>,
+    pub interlacing:        Option<Interlacing>,
+    pub color_range:        Option<ColorRange>,
+
+    pub verbosity:         Verbosity,
+    /// Minimum time between progress updates sent by each worker
+    pub progress_interval: Duration,
+    pub resume:            bool,
+    pub keep:              KeepPolicy,
+    pub force:             bool,
+    pub no_defaults:       bool,
+    pub tile_auto:         bool,
+
+    pub concat:           ConcatMethod,
+    /// Check that the stream headers of the output match throughout
+    pub verify_bitstream: bool,
+    pub target_quality:   TargetQuality,
+    pub vmaf:             bool,
+    pub vmaf_path:        Option<PathBuf>,
+    pub vmaf_res:         String,
+    pub probe_res:        Option<String>,
+    pub vmaf_threads:     Option<usize>,
+    pub vmaf_filter:      Option<String>,
+
+    pub vmaf_sample_rate:       usize,
+    pub vmaf_min_scene_samples: usize,
+    /// Encode the chunks that missed their target quality range in the final
+    /// VMAF run again
+    pub reencode_outliers:      bool,
 
     pub vapoursynth_plugins: Option<VapoursynthPlugins>,
 }
@@ -154,7 +263,13 @@ impl EncodeArgs {
         if self.concat == ConcatMethod::Ivf
             && !matches!(
                 self.encoder,
-                Encoder::rav1e | Encoder::aom | Encoder::svt_av1 | Encoder::vpx
+                Encoder::rav1e
+                    | Encoder::aom
+                    | Encoder::svt_av1
+                    | Encoder::vpx
+                    | Encoder::nvenc
+                    | Encoder::qsv
+                    | Encoder::vaapi
             )
         {
             bail!(".ivf only supports VP8, VP9, and AV1");
@@ -203,6 +318,9 @@ impl EncodeArgs {
                 TargetMetric::Butteraugli3 => self.validate_butteraugli_3()?,
                 TargetMetric::XPSNR | TargetMetric::XPSNRWeighted => self
                     .validate_xpsnr(self.target_quality.metric, self.target_quality.probing_rate)?,
+                TargetMetric::Plugin => {
+                    validate_scorer(self.target_quality.metric_plugin.as_deref())?;
+                },
             }
         }
 
@@ -225,12 +343,15 @@ impl EncodeArgs {
             }
         }
 
-        if self.encoder == Encoder::x265 && self.concat != ConcatMethod::MKVMerge {
+        if matches!(self.encoder, Encoder::x265 | Encoder::vvenc)
+            && self.concat != ConcatMethod::MKVMerge
+        {
             bail!(
-                "mkvmerge is required for concatenating x265, as x265 outputs raw HEVC bitstream \
-                 files without the timestamps correctly set, which FFmpeg cannot concatenate \
-                 properly into a mkv file. Specify mkvmerge as the concatenation method by \
-                 setting `--concat mkvmerge`."
+                "mkvmerge is required for concatenating {}, as it outputs raw HEVC or VVC \
+                 bitstream files without the timestamps correctly set, which FFmpeg cannot \
+                 concatenate properly into a mkv file. Specify mkvmerge as the concatenation \
+                 method by setting `--concat mkvmerge`.",
+                <&'static str>::from(self.encoder)
             );
         }
 
@@ -267,6 +388,73 @@ impl EncodeArgs {
                 "BestSource is not installed, but it was specified as the chunk method"
             );
         }
+        if self.sample.is_some() {
+            ensure!(
+                self.chunk_method != ChunkMethod::Segment,
+                "--sample is not supported with the segment chunk method"
+            );
+            if self.vmaf {
+                warn!("VMAF is not calculated for sample encodes");
+            }
+        }
+        if self.decode_error != DecodeErrorPolicy::Fail {
+            ensure!(
+                self.input.is_video()
+                    && matches!(
+                        self.chunk_method,
+                        ChunkMethod::LSMASH
+                            | ChunkMethod::FFMS2
+                            | ChunkMethod::DGDECNV
+                            | ChunkMethod::BESTSOURCE
+                    ),
+                "--decode-error {policy} requires a video input and a VapourSynth chunk method",
+                policy = self.decode_error
+            );
+        }
+        ensure!(
+            self.overwrite_policy != OverwritePolicy::Fail || !self.output_file.exists(),
+            "Output file {} already exists",
+            self.output_file.display()
+        );
+        if self.live_params.is_some() {
+            ensure!(
+                self.target_quality.target.is_none(),
+                "--live-params is not supported with --target-quality"
+            );
+        }
+        if let Some(threshold) = self.bitrate_spike_threshold {
+            ensure!(
+                threshold > 1.0,
+                "--bitrate-spike-threshold must be greater than 1, got {threshold}"
+            );
+        }
+        match self.split_method {
+            SplitMethod::FFmpeg | SplitMethod::Keyframes => ensure!(
+                self.input.is_video(),
+                "The {} split method requires a video input",
+                self.split_method
+            ),
+            SplitMethod::PySceneDetect | SplitMethod::Import => ensure!(
+                self.sc_import.is_some(),
+                "The {} split method requires a scene list with --sc-import",
+                self.split_method
+            ),
+            SplitMethod::AvScenechange | SplitMethod::None => {},
+        }
+        if self.sc_import.is_some() {
+            ensure!(
+                matches!(
+                    self.split_method,
+                    SplitMethod::PySceneDetect | SplitMethod::Import
+                ),
+                "--sc-import requires --split-method pyscenedetect or import"
+            );
+        }
+        ensure!(
+            (0.0..=1.0).contains(&self.sc_threshold),
+            "--sc-threshold must be between 0 and 1, got {}",
+            self.sc_threshold
+        );
         if self.chunk_method == ChunkMethod::Select {
             warn!("It is not recommended to use the \"select\" chunk method, as it is very slow");
         }
@@ -287,11 +475,36 @@ impl EncodeArgs {
         }
 
         let encoder_bin = self.encoder.bin();
-        if which::which(encoder_bin).is_err() {
-            bail!(
-                "Encoder {} not found. Is it installed in the system path?",
+        match which::which(encoder_bin) {
+            Ok(path) => debug!("using {encoder_bin} at {path}", path = path.display()),
+            Err(_) => bail!(
+                "Encoder {} not found. Is it installed in the system path or a directory given \
+                 with --bin-path?",
                 encoder_bin
+            ),
+        }
+
+        if let Some(codec) = self.encoder.hardware_codec() {
+            ensure!(
+                ffmpeg_has_encoder(codec)?,
+                "FFmpeg is not compiled with {codec}, which the {encoder} encoder uses",
+                encoder = self.encoder
             );
+            ensure!(
+                self.passes == 1,
+                "{encoder} only encodes in one pass, set --passes 1",
+                encoder = self.encoder
+            );
+            if let Some(sessions) = self.encoder.hardware_sessions()
+                && self.workers as u64 > sessions
+            {
+                warn!(
+                    "{workers} workers run more than {sessions} {encoder} sessions at once, which \
+                     many GPUs do not allow",
+                    workers = self.workers,
+                    encoder = self.encoder
+                );
+            }
         }
 
         if self.tile_auto {
@@ -328,7 +541,14 @@ impl EncodeArgs {
             }
         }
 
-        if let Some(strength) = self.photon_noise {
+        self.check_scene_lengths();
+
+        if let Some(strength) = self
+            .photon_noise
+            .into_iter()
+            .chain(self.photon_noise_anchors.iter().map(|&(_, strength)| strength))
+            .max()
+        {
             if strength > 64 {
                 bail!("Valid strength values for photon noise are 0-64");
             }
@@ -337,6 +557,28 @@ impl EncodeArgs {
             }
         }
 
+        if let Some(strength) = self.chroma_noise_strength
+            && strength > 64
+        {
+            bail!("Valid strength values for chroma noise are 0-64");
+        }
+        let (cb, cr) = self.chroma_noise_coeffs;
+        if !(0.0..=4.0).contains(&cb) || !(0.0..=4.0).contains(&cr) {
+            bail!("Valid chroma noise coefficients are 0.0-4.0");
+        }
+        if self.photon_noise_size.0 == Some(0) || self.photon_noise_size.1 == Some(0) {
+            bail!("The photon noise table size must not be 0");
+        }
+
+        if self.photon_noise_auto {
+            if ![Encoder::aom, Encoder::rav1e, Encoder::svt_av1].contains(&self.encoder) {
+                bail!("Photon noise synth is only supported with aomenc, rav1e, and svt-av1");
+            }
+            if self.input.is_vapoursynth() {
+                bail!("--photon-noise-auto is not supported with VapourSynth script inputs");
+            }
+        }
+
         if self.encoder == Encoder::aom
             && self.concat != ConcatMethod::MKVMerge
             && self.video_params.iter().any(|param| param == "--enable-keyframe-filtering=2")
@@ -355,6 +597,113 @@ impl EncodeArgs {
             self.passes = 1;
         }
 
+        ensure!(
+            self.passes <= self.encoder.max_passes(),
+            "{encoder} encodes in at most {max} passes",
+            encoder = self.encoder,
+            max = self.encoder.max_passes()
+        );
+        for (pass, _) in &self.pass_params {
+            ensure!(
+                self.passes > 1 && (1..=self.passes).contains(pass),
+                "--pass-params sets the parameters of pass {pass}, but the encode has {passes} \
+                 pass(es)",
+                passes = self.passes
+            );
+        }
+
+        if self.whole_clip_first_pass {
+            ensure!(
+                self.encoder == Encoder::aom && self.passes == 2,
+                "--whole-clip-first-pass requires two-pass encoding with aom"
+            );
+            ensure!(
+                !self.input.is_video()
+                    || matches!(
+                        self.chunk_method,
+                        ChunkMethod::LSMASH
+                            | ChunkMethod::FFMS2
+                            | ChunkMethod::DGDECNV
+                            | ChunkMethod::BESTSOURCE
+                    ),
+                "--whole-clip-first-pass requires a VapourSynth chunk method (lsmash, ffms2, \
+                 dgdecnv or bestsource) or a VapourSynth input"
+            );
+        }
+
+        if self.target_quality.q_bracket.is_some() {
+            ensure!(
+                self.encoder != Encoder::svt_av1,
+                "--q-bracket is not supported by svt-av1, which does not limit the quantizers of \
+                 its CRF mode"
+            );
+        }
+
+        if let Some(source) = &self.hdr10plus {
+            ensure!(
+                matches!(self.encoder, Encoder::x265 | Encoder::svt_av1),
+                "--hdr10plus is only supported by x265 and svt-av1"
+            );
+            match source {
+                Hdr10PlusSource::Extract => ensure!(
+                    self.input.is_video(),
+                    "--hdr10plus extract requires a video input, pass the metadata of a \
+                     VapourSynth input as a JSON file instead"
+                ),
+                Hdr10PlusSource::Json(path) => ensure!(
+                    path.exists(),
+                    "HDR10+ metadata file {} does not exist",
+                    path.display()
+                ),
+            }
+        }
+
+        if self.device_target.is_some() {
+            ensure!(
+                matches!(self.encoder, Encoder::svt_av1 | Encoder::aom),
+                "--device-target is only supported by svt-av1 and aom"
+            );
+        }
+
+        if self.target_quality.nvenc_probes > 0 {
+            validate_hevc_nvenc()?;
+        }
+
+        if self.verify_bitstream {
+            ensure!(
+                matches!(self.encoder.format(), "av1" | "h265"),
+                "--verify-bitstream only supports AV1 and HEVC encoders"
+            );
+        }
+
+        if self.collapse_duplicates {
+            ensure!(
+                self.input.is_video(),
+                "--collapse-duplicates requires a video input"
+            );
+            ensure!(
+                self.concat == ConcatMethod::MKVMerge,
+                "--collapse-duplicates requires mkvmerge concatenation to keep the timing of \
+                 dropped frames"
+            );
+            ensure!(
+                self.ffmpeg_filter_args.is_empty(),
+                "--collapse-duplicates cannot be combined with --ffmpeg filters"
+            );
+        }
+
+        if self.keep_vfr {
+            ensure!(self.input.is_video(), "--keep-vfr requires a video input");
+            ensure!(
+                self.concat == ConcatMethod::MKVMerge,
+                "--keep-vfr requires mkvmerge concatenation to set the timing of the frames"
+            );
+            ensure!(
+                self.ffmpeg_filter_args.is_empty(),
+                "--keep-vfr cannot be combined with --ffmpeg filters"
+            );
+        }
+
         if !self.force {
             self.validate_encoder_params()?;
             self.check_rate_control();
@@ -363,28 +712,55 @@ impl EncodeArgs {
         Ok(())
     }
 
-    fn validate_encoder_params(&self) -> anyhow::Result<()> {
-        let video_params: Vec<&str> = self
-            .video_params
-            .iter()
-            .filter_map(|param| {
-                if param.starts_with('-') && [Encoder::aom, Encoder::vpx].contains(&self.encoder) {
-                    // These encoders require args to be passed using an equal sign,
-                    // e.g. `--cq-level=30`
-                    param.split('=').next()
-                } else {
-                    // The other encoders use a space, so we don't need to do extra splitting,
-                    // e.g. `--crf 30`
-                    None
-                }
-            })
-            .collect();
+    /// Matches the scene lengths to the keyframe intervals of the encoder, as
+    /// every scene starts with a keyframe
+    fn check_scene_lengths(&mut self) {
+        let max_keyint = self.encoder.max_keyint(&self.video_params);
+        if let Some(keyint) = max_keyint
+            && self.extra_splits_len.is_none_or(|len| len > keyint)
+        {
+            if self.default_extra_splits {
+                info!(
+                    "lowering the maximum scene length to the keyframe interval of {keyint} frames"
+                );
+                self.extra_splits_len = Some(keyint);
+            } else {
+                warn!(
+                    "The keyframe interval of {keyint} frames is shorter than the maximum scene \
+                     length{}, so the encoder places keyframes within scenes. Set the keyframe \
+                     interval to 0 or lower the maximum scene length (--extra-split).",
+                    self.extra_splits_len
+                        .map_or_else(String::new, |len| format!(" of {len} frames"))
+                );
+            }
+        }
 
-        let help_text = {
-            let [cmd, arg] = self.encoder.help_command();
-            String::from_utf8_lossy(&Command::new(cmd).arg(arg).output()?.stdout).to_string()
-        };
+        if let Some(len) = self.extra_splits_len
+            && self.min_scene_len > len
+        {
+            warn!(
+                "The minimum scene length of {} frames is longer than the maximum scene length of \
+                 {len} frames, so some scenes will be shorter than the minimum.",
+                self.min_scene_len
+            );
+        }
+
+        if let Some(min_keyint) = self.encoder.min_keyint(&self.video_params)
+            && self.min_scene_len < min_keyint
+        {
+            warn!(
+                "The minimum scene length of {} frames is shorter than the minimum keyframe \
+                 interval of {min_keyint} frames, so keyframes can be closer together than the \
+                 encoder allows. Raise --min-scene-len to at least {min_keyint}.",
+                self.min_scene_len
+            );
+        }
+    }
+
+    fn validate_encoder_params(&self) -> anyhow::Result<()> {
+        let help_text = self.encoder.help_text()?;
         let valid_params = valid_params(&help_text, self.encoder);
+        let video_params = option_names(self.encoder, &self.video_params);
         let invalid_params = invalid_params(&video_params, &valid_params);
 
         for wrong_param in &invalid_params {
@@ -447,6 +823,21 @@ impl EncodeArgs {
         }
     }
 
+    /// Chroma grain of the photon noise tables, where `chroma_noise` is
+    /// whether the zone of the chunk or the CLI enabled chroma grain. A chroma
+    /// noise strength applies to every chunk.
+    pub(crate) fn chroma_noise(&self, chroma_noise: bool) -> ChromaNoise {
+        match self.chroma_noise_strength {
+            Some(strength) => ChromaNoise::Strength {
+                strength,
+                cb: self.chroma_noise_coeffs.0,
+                cr: self.chroma_noise_coeffs.1,
+            },
+            None if chroma_noise => ChromaNoise::FromLuma,
+            None => ChromaNoise::None,
+        }
+    }
+
     #[inline]
     pub fn validate_ssimulacra2(&self) -> anyhow::Result<()> {
         ensure!(
@@ -534,6 +925,26 @@ impl EncodeArgs {
     }
 }
 
+/// Returns the option names in `video_params` that can be checked against the
+/// encoder's help text
+#[must_use]
+pub(crate) fn option_names(encoder: Encoder, video_params: &[String]) -> Vec<&str> {
+    video_params
+        .iter()
+        .filter_map(|param| {
+            if param.starts_with('-') && [Encoder::aom, Encoder::vpx].contains(&encoder) {
+                // These encoders require args to be passed using an equal sign,
+                // e.g. `--cq-level=30`
+                param.split('=').next()
+            } else {
+                // The other encoders use a space, so we don't need to do extra splitting,
+                // e.g. `--crf 30`
+                None
+            }
+        })
+        .collect()
+}
+
 #[must_use]
 pub(crate) fn invalid_params<'a>(
     params: &'a [&'a str],
@@ -562,6 +973,57 @@ pub(crate) fn suggest_fix<'a>(
         .and_then(|(suggestion, score)| (score > MIN_THRESHOLD).then(|| suggestion.borrow()))
 }
 
+/// Appends `overrides` to `video_params`, removing any parameter (and its
+/// value) from `video_params` that is set again in `overrides`
+#[inline]
+pub fn merge_video_params(
+    encoder: Encoder,
+    video_params: &mut Vec<String>,
+    overrides: impl IntoIterator<Item = String>,
+) {
+    for arg in overrides {
+        if arg.starts_with("--")
+            || (arg.starts_with('-') && arg.chars().nth(1).is_some_and(char::is_alphabetic))
+        {
+            let key = arg.split_once('=').map_or(arg.as_str(), |split| split.0);
+            if let Some(pos) = video_params
+                .iter()
+                .position(|param| param == key || param.starts_with(&format!("{key}=")))
+            {
+                video_params.remove(pos);
+                if let Some(next) = video_params.get(pos)
+                    && !([Encoder::aom, Encoder::vpx].contains(&encoder)
+                        || next.starts_with("--")
+                        || (next.starts_with('-')
+                            && next.chars().nth(1).is_some_and(char::is_alphabetic)))
+                {
+                    video_params.remove(pos);
+                }
+            }
+        }
+        video_params.push(arg);
+    }
+}
+
+/// Passes the HDR10+ metadata in `json` to the encoder
+pub(crate) fn insert_hdr10plus_params(
+    encoder: Encoder,
+    video_params: &mut Vec<String>,
+    json: &Path,
+) -> anyhow::Result<()> {
+    let key = match encoder {
+        Encoder::x265 => "--dhdr10-info",
+        Encoder::svt_av1 => "--hdr10plus-json",
+        _ => bail!("This encoder does not support HDR10+ metadata through av1an"),
+    };
+    merge_video_params(encoder, video_params, [
+        key.to_string(),
+        json.to_string_lossy().to_string(),
+    ]);
+
+    Ok(())
+}
+
 pub(crate) fn insert_noise_table_params(
     encoder: Encoder,
     video_params: &mut Vec<String>,
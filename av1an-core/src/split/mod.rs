@@ -11,7 +11,7 @@ use std::{
 
 use av_scenechange::ScenecutResult;
 
-use crate::scenes::Scene;
+use crate::{children, scenes::Scene};
 
 pub fn segment(
     input: impl AsRef<Path>,
@@ -22,6 +22,7 @@ pub fn segment(
     let temp = temp.as_ref();
     let mut cmd = Command::new("ffmpeg");
 
+    cmd.stdin(Stdio::null());
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
 
@@ -31,8 +32,7 @@ pub fn segment(
 
     if segments.is_empty() {
         let split_path = Path::new(temp).join("split").join("0.mkv");
-        let split_str = split_path.to_string_lossy();
-        cmd.arg(split_str.as_ref());
+        cmd.arg(split_path);
     } else {
         let segments_to_string = segments.iter().map(ToString::to_string).collect::<Vec<String>>();
         let segments_joined = segments_to_string.join(",");
@@ -41,7 +41,7 @@ pub fn segment(
         let split_path = Path::new(temp).join("split").join("%05d.mkv");
         cmd.arg(split_path);
     }
-    let out = cmd.output()?;
+    let out = children::output(&mut cmd)?;
     anyhow::ensure!(out.status.success(), "FFmpeg failed to segment: {out:#?}");
 
     Ok(())
@@ -184,3 +184,38 @@ fn enhanced_extra_splits(
 
     new_scenes
 }
+
+/// Splits the longest scenes in half until there are at least `workers`
+/// scenes, so that every worker has a chunk to encode. Scenes shorter than
+/// twice `min_scene_len` and zones with extra splits disabled are kept whole.
+///
+/// This function assumes that `scenes` is a contiguous and sorted list
+pub fn split_for_workers(scenes: &[Scene], workers: usize, min_scene_len: usize) -> Vec<Scene> {
+    let mut scenes = scenes.to_vec();
+    while scenes.len() < workers {
+        let Some((index, longest)) = scenes
+            .iter()
+            .enumerate()
+            .filter(|(_, scene)| {
+                scene.end_frame - scene.start_frame >= 2 * min_scene_len.max(1)
+                    && scene
+                        .zone_overrides
+                        .as_ref()
+                        .is_none_or(|ovr| ovr.extra_splits_len.is_some())
+            })
+            .max_by_key(|(_, scene)| scene.end_frame - scene.start_frame)
+        else {
+            break;
+        };
+
+        let middle = longest.start_frame + (longest.end_frame - longest.start_frame) / 2;
+        let second_half = Scene {
+            start_frame: middle,
+            ..longest.clone()
+        };
+        scenes[index].end_frame = middle;
+        scenes.insert(index + 1, second_half);
+    }
+
+    scenes
+}
@@ -202,3 +202,23 @@ fn extra_split_preserves_zone_overrides() {
         }
     }
 }
+
+#[test]
+fn split_for_workers_halves_longest_scenes() {
+    let scene = |start_frame, end_frame| Scene {
+        start_frame,
+        end_frame,
+        zone_overrides: None,
+    };
+    let done = split_for_workers(&[scene(0, 100), scene(100, 400)], 4, 24);
+    assert_eq!(
+        done.iter()
+            .map(|scene| (scene.start_frame, scene.end_frame))
+            .collect::<Vec<_>>(),
+        [(0, 100), (100, 250), (250, 325), (325, 400)]
+    );
+
+    // Halves would be shorter than the minimum scene length
+    let done = split_for_workers(&[scene(0, 40)], 8, 24);
+    assert_eq!(done.len(), 1);
+}
@@ -0,0 +1,144 @@
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString, IntoStaticStr};
+
+/// File format of `--export-stats`
+#[derive(
+    PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug, Display, EnumString, IntoStaticStr,
+)]
+pub enum StatsFormat {
+    #[strum(serialize = "csv")]
+    Csv,
+    #[strum(serialize = "json")]
+    Json,
+}
+
+impl StatsFormat {
+    /// Extension of the exported file, after the file stem of the output
+    #[inline]
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "stats.csv",
+            Self::Json => "stats.json",
+        }
+    }
+}
+
+/// Encoding results of a single scene
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SceneStats {
+    pub index:       usize,
+    pub start_frame: usize,
+    pub frames:      usize,
+    pub size_bytes:  u64,
+    pub kbps:        f64,
+    /// Quantizer chosen by target quality, or the average QP reported by the
+    /// encoder
+    pub quantizer:   Option<f64>,
+    /// VMAF of the output, or the score of the target quality probes, or the
+    /// PSNR reported by the encoder
+    pub score:       Option<f64>,
+    /// Frames per second at which the scene was encoded
+    pub encode_fps:  Option<f64>,
+    /// Times the encoder failed on the scene before it succeeded
+    pub retries:     Option<u32>,
+}
+
+/// Writes `scenes` in `format`, with the score named `score_name`
+pub(crate) fn export_stats(scenes: &[SceneStats], score_name: &str, format: StatsFormat) -> String {
+    match format {
+        StatsFormat::Csv => stats_csv(scenes, score_name),
+        StatsFormat::Json => {
+            #[derive(Serialize)]
+            struct Export<'a> {
+                score_metric: &'a str,
+                scenes:       &'a [SceneStats],
+            }
+
+            serde_json::to_string_pretty(&Export {
+                score_metric: score_name,
+                scenes,
+            })
+            .expect("serialize should not fail")
+        },
+    }
+}
+
+/// One row per scene, with empty cells for the values that are not known
+fn stats_csv(scenes: &[SceneStats], score_name: &str) -> String {
+    let mut csv = format!(
+        "index,start_frame,frames,size_bytes,kbps,quantizer,{score},encode_fps,retries\n",
+        score = score_name.to_lowercase()
+    );
+    let cell = |value: Option<String>| value.unwrap_or_default();
+    for scene in scenes {
+        writeln!(
+            csv,
+            "{index},{start},{frames},{size},{kbps:.3},{quantizer},{score},{fps},{retries}",
+            index = scene.index,
+            start = scene.start_frame,
+            frames = scene.frames,
+            size = scene.size_bytes,
+            kbps = scene.kbps,
+            quantizer = cell(scene.quantizer.map(|q| q.to_string())),
+            score = cell(scene.score.map(|score| format!("{score:.4}"))),
+            fps = cell(scene.encode_fps.map(|fps| format!("{fps:.3}"))),
+            retries = cell(scene.retries.map(|retries| retries.to_string())),
+        )
+        .expect("write to string should work");
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_stats, SceneStats, StatsFormat};
+
+    fn scenes() -> [SceneStats; 2] {
+        [
+            SceneStats {
+                index:       0,
+                start_frame: 0,
+                frames:      48,
+                size_bytes:  12000,
+                kbps:        48.0,
+                quantizer:   Some(28.0),
+                score:       Some(95.125),
+                encode_fps:  Some(12.5),
+                retries:     Some(1),
+            },
+            SceneStats {
+                index:       1,
+                start_frame: 48,
+                frames:      24,
+                size_bytes:  3000,
+                kbps:        24.0,
+                quantizer:   None,
+                score:       None,
+                encode_fps:  None,
+                retries:     None,
+            },
+        ]
+    }
+
+    #[test]
+    fn csv_has_a_row_per_scene() {
+        assert_eq!(
+            export_stats(&scenes(), "VMAF", StatsFormat::Csv),
+            "index,start_frame,frames,size_bytes,kbps,quantizer,vmaf,encode_fps,retries\n0,0,48,\
+             12000,48.000,28,95.1250,12.500,1\n1,48,24,3000,24.000,,,,\n"
+        );
+    }
+
+    #[test]
+    fn json_names_the_score() {
+        let json: serde_json::Value =
+            serde_json::from_str(&export_stats(&scenes(), "PSNR", StatsFormat::Json))
+                .expect("export should be valid JSON");
+        assert_eq!(json["score_metric"], "PSNR");
+        assert_eq!(json["scenes"][0]["retries"], 1);
+        assert!(json["scenes"][1]["score"].is_null());
+    }
+}
@@ -2,6 +2,7 @@ use std::{
     borrow::Cow,
     cmp::{self, Ordering},
     collections::HashSet,
+    fs,
     io::Read,
     path::{Path, PathBuf},
     process::{Child, Stdio},
@@ -15,7 +16,9 @@ use tracing::{debug, trace};
 
 use crate::{
     broker::EncoderCrash,
+    children::ChildGuard,
     chunk::Chunk,
+    encoder::{format_q, probe_file_name},
     ffmpeg::FFPixelFormat,
     interpol::{
         akima_interpolate,
@@ -28,11 +31,14 @@ use crate::{
     },
     metrics::{
         butteraugli::ButteraugliSubMetric,
+        plugin::{measure_plugin, scorer},
         statistics::MetricStatistics,
         vmaf::{get_vmaf_model_version, read_vmaf_file, run_vmaf, run_vmaf_weighted},
         xpsnr::{read_xpsnr_file, run_xpsnr, XPSNRSubMetric},
     },
+    priority::ProcessPriority,
     progress_bar::update_mp_msg,
+    util::{partial_path, write_atomic},
     vapoursynth::{measure_butteraugli, measure_ssimulacra2, measure_xpsnr, VapoursynthPlugins},
     Encoder,
     ProbingStatistic,
@@ -41,6 +47,86 @@ use crate::{
     VmafFeature,
 };
 
+/// Probe results of a chunk, saved after every probe so that they can be
+/// reused when the encode is resumed, even with a different target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ProbeCheckpoint {
+    metric:            TargetMetric,
+    #[serde(default)]
+    metric_plugin:     Option<String>,
+    encoder:           Encoder,
+    /// Frame range of the chunk, which changes when the scenes are detected
+    /// again
+    frames:            (usize, usize),
+    /// Quantizer range the probes were limited to
+    quantizers:        (u32, u32),
+    probing_rate:      usize,
+    probe_res:         Option<(u32, u32)>,
+    probing_statistic: String,
+    video_params:      Vec<String>,
+    /// Target of the last run that probed this chunk
+    target:            (f64, f64),
+    /// Quantizer-score pairs, with scores as reported by the metric
+    probes:            Vec<(f32, f64)>,
+    /// VapourSynth plugin that measured the last probe, for metrics that can
+    /// run on the GPU
+    #[serde(default)]
+    metric_backend:    Option<String>,
+    /// Quantizer-score pairs of the NVENC probes, which only narrowed the
+    /// quantizers probed with the encoder
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    nvenc_probes:      Vec<(f32, f64)>,
+}
+
+impl ProbeCheckpoint {
+    fn new(tq: &TargetQuality, frames: (usize, usize), target: (f64, f64)) -> Self {
+        Self {
+            metric: tq.metric,
+            metric_plugin: tq.metric_plugin.clone(),
+            encoder: tq.encoder,
+            frames,
+            quantizers: (tq.min_q, tq.max_q),
+            probing_rate: tq.probing_rate,
+            probe_res: tq.probe_res,
+            probing_statistic: format!(
+                "{name}{value:?}",
+                name = tq.probing_statistic.name,
+                value = tq.probing_statistic.value
+            ),
+            video_params: tq.video_params.clone().unwrap_or_default(),
+            target,
+            probes: Vec::new(),
+            metric_backend: None,
+            nvenc_probes: Vec::new(),
+        }
+    }
+
+    /// Whether the probes of `other` were measured the same way as the probes
+    /// of `self` would be. The target is ignored, as it does not change the
+    /// score of a probe.
+    fn is_compatible(&self, other: &Self) -> bool {
+        self.metric == other.metric
+            && self.metric_plugin == other.metric_plugin
+            && self.encoder == other.encoder
+            && self.frames == other.frames
+            && self.quantizers == other.quantizers
+            && self.probing_rate == other.probing_rate
+            && self.probe_res == other.probe_res
+            && self.probing_statistic == other.probing_statistic
+            && self.video_params == other.video_params
+    }
+
+    fn load(path: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        write_atomic(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum InterpolationMethod {
     Linear,
@@ -80,10 +166,21 @@ pub struct TargetQuality {
     pub model:                 Option<PathBuf>,
     pub probing_rate:          usize,
     pub probes:                u32,
+    /// Number of probes that chunks with hard to predict scores may use
+    pub max_probes:            u32,
     pub target:                Option<(f64, f64)>,
     pub metric:                TargetMetric,
+    /// Name of the registered [`QualityScorer`](crate::QualityScorer) used
+    /// by [`TargetMetric::Plugin`]
+    pub metric_plugin:         Option<String>,
     pub min_q:                 u32,
     pub max_q:                 u32,
+    /// Limits the rate control of the final encode to within this many
+    /// quantizer steps of the chosen quantizer
+    pub q_bracket:             Option<u32>,
+    /// Number of probes encoded with NVENC to narrow the quantizer range
+    /// before probing with the encoder
+    pub nvenc_probes:          u32,
     pub interp_method:         Option<(InterpolationMethod, InterpolationMethod)>,
     pub encoder:               Encoder,
     pub pix_format:            FFPixelFormat,
@@ -91,9 +188,11 @@ pub struct TargetQuality {
     pub workers:               usize,
     pub video_params:          Option<Vec<String>>,
     pub params_copied:         bool,
-    pub vspipe_args:           Vec<String>,
     pub probing_vmaf_features: Vec<VmafFeature>,
     pub probing_statistic:     ProbingStatistic,
+    /// Maximum number of vship streams used at once by all workers
+    pub gpu_streams:           Option<usize>,
+    pub priority:              ProcessPriority,
 }
 
 impl TargetQuality {
@@ -110,10 +209,14 @@ impl TargetQuality {
             model: None,
             probing_rate: 1,
             probes: 4,
+            max_probes: 4,
             target: None,
             metric: TargetMetric::VMAF,
+            metric_plugin: None,
             min_q: encoder.get_default_cq_range().0 as u32,
             max_q: encoder.get_default_cq_range().1 as u32,
+            q_bracket: None,
+            nvenc_probes: 0,
             interp_method: None,
             encoder,
             pix_format: FFPixelFormat::YUV420P10LE,
@@ -121,26 +224,51 @@ impl TargetQuality {
             workers: 1,
             video_params: None,
             params_copied: false,
-            vspipe_args: vec![],
             probing_vmaf_features: vec![VmafFeature::Default],
             probing_statistic: ProbingStatistic {
                 name:  ProbingStatisticName::Automatic,
                 value: None,
             },
+            gpu_streams: None,
+            priority: ProcessPriority::Normal,
         }
     }
 
+    /// Returns the chosen quantizer and its probed or predicted score
     #[inline]
     pub fn per_shot_target_quality(
         &self,
         chunk: &Chunk,
         worker_id: Option<usize>,
         plugins: Option<VapoursynthPlugins>,
-    ) -> anyhow::Result<f32> {
+    ) -> anyhow::Result<(f32, f64)> {
         anyhow::ensure!(self.target.is_some(), "Target must be some");
         let target = self.target.expect("target is some");
+        let is_inverse_metric = matches!(
+            self.metric,
+            TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3
+        );
+
+        // Reuse the probes of a previous run on this chunk if they were measured
+        // the same way, regardless of the target they were measured for
+        let checkpoint_path = Path::new(&chunk.temp)
+            .join("split")
+            .join(format!("{}_probes.json", chunk.name()));
+        let mut checkpoint =
+            ProbeCheckpoint::new(self, (chunk.start_frame, chunk.end_frame), target);
+        if let Some(previous) = ProbeCheckpoint::load(&checkpoint_path)
+            && previous.is_compatible(&checkpoint)
+        {
+            checkpoint.probes = previous.probes;
+        }
+
         // History of probe results as quantizer-score pairs
-        let mut quantizer_score_history: Vec<(f32, f64)> = vec![];
+        let mut quantizer_score_history: Vec<(f32, f64)> = checkpoint
+            .probes
+            .iter()
+            .map(|&(quantizer, score)| (quantizer, if is_inverse_metric { -score } else { score }))
+            .collect();
+        let mut probes_run = 0;
 
         let update_progress_bar = |next_quantizer: f32| {
             if let Some(worker_id) = worker_id {
@@ -166,9 +294,100 @@ impl TargetQuality {
         let mut lower_quantizer_limit = self.min_q as f32;
         let mut upper_quantizer_limit = self.max_q as f32;
 
+        let target_range = if is_inverse_metric {
+            (-target.1, -target.0)
+        } else {
+            target
+        };
+
+        // Narrow the limits with the reused probes as if they were just run
+        for &(quantizer, score) in &quantizer_score_history {
+            if score > target_range.1 {
+                lower_quantizer_limit =
+                    (quantizer + step).max(lower_quantizer_limit).min(upper_quantizer_limit);
+            } else if score < target_range.0 {
+                upper_quantizer_limit =
+                    (quantizer - step).min(upper_quantizer_limit).max(lower_quantizer_limit);
+            }
+        }
+
         let skip_reason;
+        // Absolute difference between the score of the last probe and the score
+        // interpolated from the probes before it
+        let mut prediction_error: Option<f64> = None;
+        let mut predicted_quantizer_score = None;
+        let tolerance = target_range.1 - target_range.0;
+
+        // Narrow the limits with fast NVENC probes. Their scores are offset from
+        // the scores of the encoder, so they only bracket the quantizers and are
+        // never chosen as the score of the chunk.
+        if self.nvenc_probes > 0 && quantizer_score_history.is_empty() {
+            let mut nvenc_history: Vec<(f32, f64)> = Vec::new();
+            let (mut lower, mut upper) = (lower_quantizer_limit, upper_quantizer_limit);
+            while nvenc_history.len() < self.nvenc_probes as usize {
+                let quantizer = predict_quantizer(
+                    lower,
+                    upper,
+                    &nvenc_history,
+                    target_range,
+                    self.interp_method,
+                    step,
+                )?;
+                if nvenc_history.iter().any(|&(probed, _)| probed == quantizer) {
+                    break;
+                }
+
+                update_progress_bar(quantizer);
+                let probe_path = self.encode_nvenc_probe(chunk, quantizer)?;
+                let measured = self.measure_probe(chunk, &probe_path, quantizer, plugins);
+                let _ = fs::remove_file(&probe_path);
+                let (value, _) = measured?;
+                let score = if is_inverse_metric { -value } else { value };
+                nvenc_history.push((quantizer, score));
+
+                if score > target_range.1 {
+                    lower = (quantizer + step).min(upper);
+                } else if score < target_range.0 {
+                    upper = (quantizer - step).max(lower);
+                } else {
+                    break;
+                }
+            }
+
+            (lower_quantizer_limit, upper_quantizer_limit) = nvenc_bracket(
+                &nvenc_history,
+                (lower_quantizer_limit, upper_quantizer_limit),
+                target_range,
+                step,
+            );
+            checkpoint.nvenc_probes = nvenc_history
+                .iter()
+                .map(|&(quantizer, score)| {
+                    (quantizer, if is_inverse_metric { -score } else { score })
+                })
+                .collect();
+            checkpoint.save(&checkpoint_path)?;
+            debug!(
+                "chunk {name}: NVENC probes {probes:.2?}, probing {lower}-{upper} with {encoder}",
+                name = chunk.name(),
+                probes = checkpoint.nvenc_probes,
+                lower = lower_quantizer_limit,
+                upper = upper_quantizer_limit,
+                encoder = self.encoder
+            );
+        }
 
         loop {
+            if probes_run == 0
+                && quantizer_score_history
+                    .iter()
+                    .any(|&(_, score)| within_range(score, target_range))
+            {
+                // A reused probe already satisfies the target
+                skip_reason = SkipProbingReason::WithinTolerance;
+                break;
+            }
+
             let next_quantizer = predict_quantizer(
                 lower_quantizer_limit,
                 upper_quantizer_limit,
@@ -194,10 +413,25 @@ impl TargetQuality {
                 break;
             }
 
+            // Skip probing a quantizer whose interpolated score is within the target if
+            // the last prediction was accurate
+            let predicted_score = interpolate_score(&quantizer_score_history, next_quantizer);
+            if let Some(predicted_score) = predicted_score
+                && prediction_error.is_some_and(|error| error <= tolerance / 4.0)
+                && within_range(predicted_score, target_range)
+            {
+                predicted_quantizer_score = Some((next_quantizer, predicted_score));
+                skip_reason = SkipProbingReason::Predicted;
+                break;
+            }
+
             update_progress_bar(next_quantizer);
 
             let score = {
-                let value = self.probe(chunk, next_quantizer, plugins)?;
+                let (value, backend) = self.probe(chunk, next_quantizer, plugins)?;
+                if let Some(backend) = backend {
+                    checkpoint.metric_backend = Some(backend.to_owned());
+                }
 
                 // Butteraugli is an inverse metric, invert score for comparisons
                 match self.metric {
@@ -214,8 +448,21 @@ impl TargetQuality {
             );
 
             quantizer_score_history.push((next_quantizer, score));
+            probes_run += 1;
+            prediction_error = predicted_score.map(|predicted| (score - predicted).abs());
+            checkpoint.probes.push((
+                next_quantizer,
+                if is_inverse_metric { -score } else { score },
+            ));
+            checkpoint.save(&checkpoint_path)?;
 
-            if score_within_range || quantizer_score_history.len() >= self.probes as usize {
+            // Chunks whose scores were mispredicted may use more probes
+            let probe_limit = if prediction_error.is_some_and(|error| error > tolerance) {
+                self.max_probes.max(self.probes)
+            } else {
+                self.probes
+            };
+            if score_within_range || probes_run >= probe_limit as usize {
                 skip_reason = if score_within_range {
                     SkipProbingReason::WithinTolerance
                 } else {
@@ -224,11 +471,6 @@ impl TargetQuality {
                 break;
             }
 
-            let target_range = match self.metric {
-                TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => (-target.1, -target.0),
-                _ => target,
-            };
-
             if score > target_range.1 {
                 lower_quantizer_limit = ((next_quantizer) + step).min(upper_quantizer_limit);
             } else if score < target_range.0 {
@@ -247,38 +489,50 @@ impl TargetQuality {
         }
 
         // Calculate final quantizer and score BEFORE logging
-        let final_quantizer_score = quantizer_score_history
-            .iter()
-            .filter(|(_, score)| {
-                within_range(
-                    match self.metric {
-                        TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => -score,
-                        _ => *score,
-                    },
-                    target,
-                )
-            })
-            .max_by(|(q1, _), (q2, _)| q1.partial_cmp(q2).unwrap_or(std::cmp::Ordering::Equal))
-            .unwrap_or_else(|| {
-                // No quantizers within tolerance, choose the quantizer closest to target
-                let target_midpoint = f64::midpoint(target.0, target.1);
-                quantizer_score_history
-                    .iter()
-                    .min_by(|(_, score1), (_, score2)| {
-                        let score_1 = match self.metric {
-                            TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => -score1,
-                            _ => *score1,
-                        };
-                        let score_2 = match self.metric {
-                            TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => -score2,
-                            _ => *score2,
-                        };
-                        let difference1 = (score_1 - target_midpoint).abs();
-                        let difference2 = (score_2 - target_midpoint).abs();
-                        difference1.partial_cmp(&difference2).unwrap_or(Ordering::Equal)
-                    })
-                    .expect("quantizer_score_history is not empty")
-            });
+        let final_quantizer_score = predicted_quantizer_score.unwrap_or_else(|| {
+            *quantizer_score_history
+                .iter()
+                .filter(|(_, score)| {
+                    within_range(
+                        match self.metric {
+                            TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => -score,
+                            _ => *score,
+                        },
+                        target,
+                    )
+                })
+                .max_by(|(q1, _), (q2, _)| q1.partial_cmp(q2).unwrap_or(std::cmp::Ordering::Equal))
+                .unwrap_or_else(|| {
+                    // No quantizers within tolerance, choose the quantizer closest to target
+                    let target_midpoint = f64::midpoint(target.0, target.1);
+                    quantizer_score_history
+                        .iter()
+                        .min_by(|(_, score1), (_, score2)| {
+                            let score_1 = match self.metric {
+                                TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
+                                    -score1
+                                },
+                                _ => *score1,
+                            };
+                            let score_2 = match self.metric {
+                                TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
+                                    -score2
+                                },
+                                _ => *score2,
+                            };
+                            let difference1 = (score_1 - target_midpoint).abs();
+                            let difference2 = (score_2 - target_midpoint).abs();
+                            difference1.partial_cmp(&difference2).unwrap_or(Ordering::Equal)
+                        })
+                        .expect("quantizer_score_history is not empty")
+                })
+        });
+
+        // Inverse reverse metrics
+        let final_score = match self.metric {
+            TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => -final_quantizer_score.1,
+            _ => final_quantizer_score.1,
+        };
 
         log_probes(
             &quantizer_score_history,
@@ -289,17 +543,70 @@ impl TargetQuality {
             self.video_params.as_ref(),
             &chunk.name(),
             final_quantizer_score.0,
-            // Inverse reverse metrics
-            match self.metric {
-                TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
-                    -final_quantizer_score.1
-                },
-                _ => final_quantizer_score.1,
-            },
+            final_score,
             skip_reason,
         );
 
-        Ok(final_quantizer_score.0)
+        Ok((final_quantizer_score.0, final_score))
+    }
+
+    /// Aggregates the per-frame scores of a probe at `quantizer` into the
+    /// score that is compared to the target
+    pub(crate) fn aggregate_frame_scores(
+        &self,
+        scores: Vec<f64>,
+        quantizer: f32,
+    ) -> anyhow::Result<f64> {
+        let mut statistics = MetricStatistics::new(scores);
+
+        let aggregate = match self.probing_statistic.name {
+            ProbingStatisticName::Automatic => {
+                if self.metric == TargetMetric::VMAF {
+                    // Preserve legacy VMAF aggregation
+                    return Ok(statistics.percentile(1));
+                }
+
+                let sigma_1 = {
+                    let sigma_distance = statistics.standard_deviation();
+                    let statistic = statistics.mean() - sigma_distance;
+                    statistic.clamp(statistics.minimum(), statistics.maximum())
+                };
+
+                // Based on quantizer - lower quantizer leads to more accurate scores (lower
+                // variance) (citation needed)
+                if self.encoder.get_cq_relative_percentage(quantizer as usize) > 0.25 {
+                    // Liberal: Use mean to determine aggregate
+                    statistics.mean()
+                } else {
+                    // Less liberal: Use -1 sigma to determine aggregate
+                    sigma_1
+                }
+            },
+            ProbingStatisticName::Mean => statistics.mean(),
+            ProbingStatisticName::RootMeanSquare => statistics.root_mean_square(),
+            ProbingStatisticName::Median => statistics.median(),
+            ProbingStatisticName::Harmonic => statistics.harmonic_mean(),
+            ProbingStatisticName::Percentile => {
+                let value = self
+                    .probing_statistic
+                    .value
+                    .ok_or_else(|| anyhow::anyhow!("Percentile statistic requires a value"))?;
+                statistics.percentile(value as usize)
+            },
+            ProbingStatisticName::StandardDeviation => {
+                let value = self.probing_statistic.value.ok_or_else(|| {
+                    anyhow::anyhow!("Standard deviation statistic requires a value")
+                })?;
+                let sigma_distance = value * statistics.standard_deviation();
+                let statistic = statistics.mean() + sigma_distance;
+                statistic.clamp(statistics.minimum(), statistics.maximum())
+            },
+            ProbingStatisticName::Mode => statistics.mode(),
+            ProbingStatisticName::Minimum => statistics.minimum(),
+            ProbingStatisticName::Maximum => statistics.maximum(),
+        };
+
+        Ok(aggregate)
     }
 
     fn probe(
@@ -307,67 +614,34 @@ impl TargetQuality {
         chunk: &Chunk,
         quantizer: f32,
         plugins: Option<VapoursynthPlugins>,
-    ) -> anyhow::Result<f64> {
+    ) -> anyhow::Result<(f64, Option<&'static str>)> {
         let probe_name = self.encode_probe(chunk, quantizer)?;
+        self.measure_probe(chunk, &probe_name, quantizer, plugins)
+    }
+
+    /// Measures the score of the probe at `probe_name`, returning the plugin
+    /// that measured it for metrics that can run on the GPU
+    fn measure_probe(
+        &self,
+        chunk: &Chunk,
+        probe_name: &Path,
+        quantizer: f32,
+        plugins: Option<VapoursynthPlugins>,
+    ) -> anyhow::Result<(f64, Option<&'static str>)> {
         let reference_pipe_cmd =
             chunk.proxy_cmd.as_ref().map_or(chunk.source_cmd.as_slice(), |proxy_cmd| {
                 proxy_cmd.as_slice()
             });
+        let reference_vspipe_args =
+            chunk.proxy.as_ref().unwrap_or(&chunk.input).as_vspipe_args_vec()?;
 
-        let aggregate_frame_scores = |scores: Vec<f64>| -> anyhow::Result<f64> {
-            let mut statistics = MetricStatistics::new(scores);
+        let aggregate_frame_scores =
+            |scores: Vec<f64>| self.aggregate_frame_scores(scores, quantizer);
 
-            let aggregate = match self.probing_statistic.name {
-                ProbingStatisticName::Automatic => {
-                    if self.metric == TargetMetric::VMAF {
-                        // Preserve legacy VMAF aggregation
-                        return Ok(statistics.percentile(1));
-                    }
-
-                    let sigma_1 = {
-                        let sigma_distance = statistics.standard_deviation();
-                        let statistic = statistics.mean() - sigma_distance;
-                        statistic.clamp(statistics.minimum(), statistics.maximum())
-                    };
+        // Plugin used for metrics that can run on the GPU
+        let mut backend = None;
 
-                    // Based on quantizer - lower quantizer leads to more accurate scores (lower
-                    // variance) (citation needed)
-                    if self.encoder.get_cq_relative_percentage(quantizer as usize) > 0.25 {
-                        // Liberal: Use mean to determine aggregate
-                        statistics.mean()
-                    } else {
-                        // Less liberal: Use -1 sigma to determine aggregate
-                        sigma_1
-                    }
-                },
-                ProbingStatisticName::Mean => statistics.mean(),
-                ProbingStatisticName::RootMeanSquare => statistics.root_mean_square(),
-                ProbingStatisticName::Median => statistics.median(),
-                ProbingStatisticName::Harmonic => statistics.harmonic_mean(),
-                ProbingStatisticName::Percentile => {
-                    let value = self
-                        .probing_statistic
-                        .value
-                        .ok_or_else(|| anyhow::anyhow!("Percentile statistic requires a value"))?;
-                    statistics.percentile(value as usize)
-                },
-                ProbingStatisticName::StandardDeviation => {
-                    let value = self.probing_statistic.value.ok_or_else(|| {
-                        anyhow::anyhow!("Standard deviation statistic requires a value")
-                    })?;
-                    let sigma_distance = value * statistics.standard_deviation();
-                    let statistic = statistics.mean() + sigma_distance;
-                    statistic.clamp(statistics.minimum(), statistics.maximum())
-                },
-                ProbingStatisticName::Mode => statistics.mode(),
-                ProbingStatisticName::Minimum => statistics.minimum(),
-                ProbingStatisticName::Maximum => statistics.maximum(),
-            };
-
-            Ok(aggregate)
-        };
-
-        match self.metric {
+        let score = match self.metric {
             TargetMetric::VMAF => {
                 let features: HashSet<_> = self.probing_vmaf_features.iter().copied().collect();
                 let use_weighted = features.contains(&VmafFeature::Weighted);
@@ -396,9 +670,9 @@ impl TargetQuality {
 
                 let vmaf_scores = if use_weighted {
                     run_vmaf_weighted(
-                        &probe_name,
+                        probe_name,
                         reference_pipe_cmd,
-                        self.vspipe_args.clone(),
+                        reference_vspipe_args,
                         model,
                         self.vmaf_threads,
                         chunk.frame_rate,
@@ -415,14 +689,18 @@ impl TargetQuality {
                         })
                     })?
                 } else {
-                    let fl_path = std::path::Path::new(&chunk.temp)
-                        .join("split")
-                        .join(format!("{index}.json", index = chunk.index));
+                    // Unique to this probe, so runs sharing the temp folder never read each
+                    // other's scores
+                    let fl_path = partial_path(
+                        &Path::new(&chunk.temp)
+                            .join("split")
+                            .join(format!("{index}.json", index = chunk.index)),
+                    );
 
                     run_vmaf(
-                        &probe_name,
+                        probe_name,
                         reference_pipe_cmd,
-                        self.vspipe_args.clone(),
+                        reference_vspipe_args,
                         &fl_path,
                         model,
                         &self.probe_res.map_or_else(
@@ -431,6 +709,7 @@ impl TargetQuality {
                         ),
                         &self.vmaf_scaler,
                         self.probing_rate,
+                        1,
                         self.vmaf_filter.as_deref(),
                         self.vmaf_threads,
                         chunk.frame_rate,
@@ -438,21 +717,26 @@ impl TargetQuality {
                         &self.probing_vmaf_features,
                     )?;
 
-                    read_vmaf_file(&fl_path)?
+                    let scores = read_vmaf_file(&fl_path);
+                    let _ = fs::remove_file(&fl_path);
+                    scores?
                 };
 
                 aggregate_frame_scores(vmaf_scores)
             },
             TargetMetric::SSIMULACRA2 => {
                 let scores = if let Some(plugins) = plugins {
-                    measure_ssimulacra2(
+                    let (scores, plugin) = measure_ssimulacra2(
                         chunk.proxy.as_ref().unwrap_or(&chunk.input),
-                        &probe_name,
+                        probe_name,
                         (chunk.start_frame as u32, chunk.end_frame as u32),
                         self.probe_res,
                         self.probing_rate,
                         plugins,
-                    )?
+                        self.gpu_streams,
+                    )?;
+                    backend = Some(plugin);
+                    scores
                 } else {
                     bail!("SSIMULACRA2 requires Vapoursynth to be installed");
                 };
@@ -461,19 +745,22 @@ impl TargetQuality {
             },
             TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
                 let scores = if let Some(plugins) = plugins {
-                    measure_butteraugli(
+                    let (scores, plugin) = measure_butteraugli(
                         match self.metric {
                             TargetMetric::ButteraugliINF => ButteraugliSubMetric::InfiniteNorm,
                             TargetMetric::Butteraugli3 => ButteraugliSubMetric::ThreeNorm,
                             _ => unreachable!(),
                         },
                         chunk.proxy.as_ref().unwrap_or(&chunk.input),
-                        &probe_name,
+                        probe_name,
                         (chunk.start_frame as u32, chunk.end_frame as u32),
                         self.probe_res,
                         self.probing_rate,
                         plugins,
-                    )?
+                        self.gpu_streams,
+                    )?;
+                    backend = Some(plugin);
+                    scores
                 } else {
                     bail!("Butteraugli requires Vapoursynth to be installed");
                 };
@@ -491,7 +778,7 @@ impl TargetQuality {
                         measure_xpsnr(
                             submetric,
                             chunk.proxy.as_ref().unwrap_or(&chunk.input),
-                            &probe_name,
+                            probe_name,
                             (chunk.start_frame as u32, chunk.end_frame as u32),
                             self.probe_res,
                             self.probing_rate,
@@ -503,13 +790,14 @@ impl TargetQuality {
 
                     aggregate_frame_scores(scores)
                 } else {
-                    let fl_path =
-                        Path::new(&chunk.temp).join("split").join(format!("{}.json", chunk.index));
+                    let fl_path = partial_path(
+                        &Path::new(&chunk.temp).join("split").join(format!("{}.json", chunk.index)),
+                    );
 
                     run_xpsnr(
-                        &probe_name,
+                        probe_name,
                         reference_pipe_cmd,
-                        self.vspipe_args.clone(),
+                        reference_vspipe_args,
                         &fl_path,
                         &self.probe_res.map_or_else(
                             || self.vmaf_res.clone(),
@@ -520,7 +808,9 @@ impl TargetQuality {
                         chunk.frame_rate,
                     )?;
 
-                    let (aggregate, scores) = read_xpsnr_file(fl_path, submetric)?;
+                    let xpsnr = read_xpsnr_file(&fl_path, submetric);
+                    let _ = fs::remove_file(&fl_path);
+                    let (aggregate, scores) = xpsnr?;
 
                     match self.probing_statistic.name {
                         ProbingStatisticName::Automatic => Ok(aggregate),
@@ -528,7 +818,24 @@ impl TargetQuality {
                     }
                 }
             },
-        }
+            TargetMetric::Plugin => {
+                let name = self.metric_plugin.as_deref().unwrap_or_default();
+                let Some(scorer) = scorer(name) else {
+                    bail!("No quality scorer is registered as {name:?}");
+                };
+                let scores = measure_plugin(
+                    scorer.as_ref(),
+                    probe_name,
+                    reference_pipe_cmd,
+                    reference_vspipe_args,
+                    self.probing_rate,
+                )?;
+
+                aggregate_frame_scores(scores)
+            },
+        }?;
+
+        Ok((score, backend))
     }
 
     fn encode_probe(&self, chunk: &Chunk, q: f32) -> Result<PathBuf, Box<EncoderCrash>> {
@@ -538,22 +845,60 @@ impl TargetQuality {
             self.vmaf_threads
         };
 
-        let cmd = self.encoder.probe_cmd(
-            self.temp.clone(),
+        let probe_path = Path::new(&chunk.temp).join("split").join(probe_file_name(
+            self.encoder,
             chunk.index,
             q,
+        ));
+        let partial_probe_path = partial_path(&probe_path);
+        let cmd = self.encoder.probe_cmd(
+            &partial_probe_path,
+            q,
             self.pix_format,
             self.probing_rate,
             vmaf_threads,
             self.video_params.clone(),
         );
 
+        Self::run_probe_encode(chunk, probe_path, &partial_probe_path, cmd, self.priority)
+    }
+
+    /// Encodes a probe of the chunk with NVENC at the quantizer that matches
+    /// `q` of the encoder
+    fn encode_nvenc_probe(&self, chunk: &Chunk, q: f32) -> Result<PathBuf, Box<EncoderCrash>> {
+        let probe_path = Path::new(&chunk.temp).join("split").join(format!(
+            "v_{index:05}_{q}_nvenc.mkv",
+            index = chunk.index,
+            q = format_q(q)
+        ));
+        let partial_probe_path = partial_path(&probe_path);
+        let cmd = self.encoder.nvenc_probe_cmd(
+            &partial_probe_path,
+            q,
+            self.pix_format,
+            self.probing_rate,
+        );
+
+        Self::run_probe_encode(chunk, probe_path, &partial_probe_path, cmd, self.priority)
+    }
+
+    /// Runs the encode of a probe, which writes to `partial_probe_path` and is
+    /// only renamed to `probe_path` once complete, so an interrupted or
+    /// concurrent probe is never mistaken for a finished one
+    fn run_probe_encode(
+        chunk: &Chunk,
+        probe_path: PathBuf,
+        partial_probe_path: &Path,
+        cmd: (Option<Vec<String>>, Vec<Cow<'static, str>>),
+        priority: ProcessPriority,
+    ) -> Result<PathBuf, Box<EncoderCrash>> {
         let source_cmd = chunk.proxy_cmd.clone().unwrap_or_else(|| chunk.source_cmd.clone());
-        let (ff_cmd, output) = cmd.clone();
+        let (ff_cmd, output) = cmd;
 
         thread::scope(move |scope| -> Result<(), Box<EncoderCrash>> {
             let mut source = if let [pipe_cmd, args @ ..] = &*source_cmd {
-                std::process::Command::new(pipe_cmd)
+                priority
+                    .apply(&mut std::process::Command::new(pipe_cmd))
                     .args(args)
                     .stderr(std::process::Stdio::piped())
                     .stdout(std::process::Stdio::piped())
@@ -574,7 +919,8 @@ impl TargetQuality {
             let (mut source_pipe, mut enc_pipe) = {
                 if let Some(ff_cmd) = ff_cmd.as_deref() {
                     let (ffmpeg, args) = ff_cmd.split_first().expect("not empty");
-                    let mut source_pipe = std::process::Command::new(ffmpeg)
+                    let mut source_pipe = priority
+                        .apply(&mut std::process::Command::new(ffmpeg))
                         .args(args)
                         .stdin(source_stdout)
                         .stdout(std::process::Stdio::piped())
@@ -592,7 +938,7 @@ impl TargetQuality {
                         source_pipe.stdout.take().expect("source_pipe stdout should exist");
 
                     let enc_pipe = if let [cmd, args @ ..] = &*output {
-                        build_encoder_pipe(cmd, args, source_pipe_stdout)?
+                        build_encoder_pipe(cmd, args, source_pipe_stdout, priority)?
                     } else {
                         unreachable!()
                     };
@@ -601,13 +947,18 @@ impl TargetQuality {
                     // We unfortunately have to duplicate the code like this
                     // in order to satisfy the borrow checker for `source_stdout`
                     let enc_pipe = if let [cmd, args @ ..] = &*output {
-                        build_encoder_pipe(cmd, args, source_stdout)?
+                        build_encoder_pipe(cmd, args, source_stdout, priority)?
                     } else {
                         unreachable!()
                     };
                     (None, enc_pipe)
                 }
             };
+            let _guards = [Some(&source), source_pipe.as_ref(), Some(&enc_pipe)]
+                .into_iter()
+                .flatten()
+                .map(ChildGuard::new)
+                .collect::<Vec<_>>();
 
             // Drop stdout to prevent buffer deadlock
             drop(enc_pipe.stdout.take());
@@ -672,18 +1023,20 @@ impl TargetQuality {
             }
 
             Ok(())
+        })
+        .inspect_err(|_| {
+            let _ = fs::remove_file(partial_probe_path);
         })?;
 
-        let extension = match self.encoder {
-            crate::encoder::Encoder::x264 => "264",
-            crate::encoder::Encoder::x265 => "hevc",
-            _ => "ivf",
-        };
-
-        let q_str = crate::encoder::format_q(q);
-        let probe_name = format!("v_{index:05}_{q_str}.{extension}", index = chunk.index);
+        fs::rename(partial_probe_path, &probe_path).map_err(|e| EncoderCrash {
+            exit_status:        std::process::ExitStatus::default(),
+            source_pipe_stderr: String::new().into(),
+            ffmpeg_pipe_stderr: None,
+            stderr:             format!("Failed to rename probe: {e}").into(),
+            stdout:             String::new().into(),
+        })?;
 
-        Ok(std::path::Path::new(&chunk.temp).join("split").join(&probe_name))
+        Ok(probe_path)
     }
 
     #[inline]
@@ -888,8 +1241,10 @@ fn build_encoder_pipe(
     cmd: &str,
     args: &[Cow<'_, str>],
     in_pipe: impl Into<Stdio>,
+    priority: ProcessPriority,
 ) -> Result<Child, EncoderCrash> {
-    std::process::Command::new(cmd)
+    priority
+        .apply(&mut std::process::Command::new(cmd))
         .args(args.iter().map(AsRef::as_ref))
         .stdin(in_pipe)
         .stdout(std::process::Stdio::piped())
@@ -997,6 +1352,96 @@ fn predict_quantizer(
     )
 }
 
+/// Linearly interpolates the score of `quantizer` between the closest probed
+/// quantizers below and above it. Returns `None` if it is not between two
+/// probes.
+fn interpolate_score(quantizer_score_history: &[(f32, f64)], quantizer: f32) -> Option<f64> {
+    let below = quantizer_score_history
+        .iter()
+        .filter(|(q, _)| *q <= quantizer)
+        .max_by(|(q1, _), (q2, _)| q1.total_cmp(q2))?;
+    let above = quantizer_score_history
+        .iter()
+        .filter(|(q, _)| *q >= quantizer)
+        .min_by(|(q1, _), (q2, _)| q1.total_cmp(q2))?;
+    if below.0 == above.0 {
+        return Some(below.1);
+    }
+
+    let t = f64::from((quantizer - below.0) / (above.0 - below.0));
+    Some(t.mul_add(above.1 - below.1, below.1))
+}
+
+/// Score that target quality chose for a chunk next to the score measured on
+/// the final output
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct PredictionError {
+    pub chunk:     String,
+    pub quantizer: f32,
+    pub predicted: f64,
+    pub achieved:  f64,
+}
+
+impl PredictionError {
+    pub(crate) fn error(&self) -> f64 {
+        self.achieved - self.predicted
+    }
+
+    /// Target for encoding the chunk again, which is `target` shifted by the
+    /// error of the prediction so that the achieved score lands in `target`.
+    /// Returns `None` if the achieved score is already within `target`.
+    pub(crate) fn corrected_target(&self, target: (f64, f64)) -> Option<(f64, f64)> {
+        let (min, max) = target;
+        if (min..=max).contains(&self.achieved) {
+            return None;
+        }
+        Some((min - self.error(), max - self.error()))
+    }
+}
+
+/// Returns the scores of the frames in `start_frame..end_frame` out of
+/// `scores`, which were measured on every `sample_rate`th frame
+pub(crate) fn sampled_scene_scores(
+    scores: &[f64],
+    sample_rate: usize,
+    start_frame: usize,
+    end_frame: usize,
+) -> &[f64] {
+    let sample_rate = sample_rate.max(1);
+    let start = start_frame.div_ceil(sample_rate).min(scores.len());
+    let end = end_frame.div_ceil(sample_rate).clamp(start, scores.len());
+    scores.get(start..end).unwrap_or_default()
+}
+
+/// Limits of the quantizers to probe with the encoder after the NVENC probes
+/// in `nvenc_history`, which are bracketed by the closest quantizers scoring
+/// above and below the target. The bracket is widened by a tenth of `limits`
+/// on each side, as NVENC only roughly ranks the quantizers of the encoder.
+fn nvenc_bracket(
+    nvenc_history: &[(f32, f64)],
+    limits: (f32, f32),
+    target_range: (f64, f64),
+    step: f32,
+) -> (f32, f32) {
+    let (mut lower, mut upper) = limits;
+    for &(quantizer, score) in nvenc_history {
+        if within_range(score, target_range) {
+            (lower, upper) = (quantizer, quantizer);
+            break;
+        } else if score > target_range.1 {
+            lower = lower.max(quantizer);
+        } else {
+            upper = upper.min(quantizer);
+        }
+    }
+
+    let margin = (((limits.1 - limits.0) / 10.0 / step).round() * step).max(step);
+    (
+        (lower - margin).max(limits.0),
+        (upper + margin).min(limits.1),
+    )
+}
+
 fn within_range(score: f64, target_range: (f64, f64)) -> bool {
     score >= target_range.0 && score <= target_range.1
 }
@@ -1020,6 +1465,8 @@ pub enum SkipProbingReason {
     QuantizerTooLow,
     WithinTolerance,
     ProbeLimitReached,
+    /// The interpolated score of the final quantizer is within the target
+    Predicted,
     None,
 }
 
@@ -1075,6 +1522,7 @@ pub fn log_probes(
             SkipProbingReason::QuantizerTooLow => " Early Skip Low Quantizer",
             SkipProbingReason::WithinTolerance => " Early Skip Within Tolerance",
             SkipProbingReason::ProbeLimitReached => " Early Skip Probe Limit Reached",
+            SkipProbingReason::Predicted => " Early Skip Predicted Within Tolerance",
         },
         target_quantizer = target_quantizer,
         target_score = target_score
@@ -1172,4 +1620,93 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn nvenc_bracket_widens_closest_probes() {
+        // Above the target at 20 and 30, below it at 40
+        let history = [(30.0, 82.0), (40.0, 76.0), (20.0, 88.0)];
+        assert_eq!(
+            nvenc_bracket(&history, (10.0, 60.0), (79.0, 81.0), 1.0),
+            (25.0, 45.0)
+        );
+        // A probe within the target is bracketed on both sides
+        let history = [(35.0, 80.0)];
+        assert_eq!(
+            nvenc_bracket(&history, (10.0, 60.0), (79.0, 81.0), 1.0),
+            (30.0, 40.0)
+        );
+        assert_eq!(
+            nvenc_bracket(&[], (10.0, 60.0), (79.0, 81.0), 1.0),
+            (10.0, 60.0)
+        );
+    }
+
+    #[test]
+    fn interpolate_score_between_probes() {
+        let history = [(20.0, 90.0), (40.0, 70.0), (30.0, 84.0)];
+
+        assert_eq!(interpolate_score(&history, 25.0), Some(87.0));
+        assert_eq!(interpolate_score(&history, 35.0), Some(77.0));
+        assert_eq!(interpolate_score(&history, 30.0), Some(84.0));
+        assert_eq!(interpolate_score(&history, 10.0), None);
+        assert_eq!(interpolate_score(&history, 45.0), None);
+    }
+
+    #[test]
+    fn sampled_scene_scores_selects_scene_frames() {
+        let scores = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0];
+
+        assert_eq!(sampled_scene_scores(&scores, 1, 2, 4), &[2.0, 3.0]);
+        // Frames 0, 4, 8, ... were measured
+        assert_eq!(sampled_scene_scores(&scores, 4, 3, 9), &[1.0, 2.0]);
+        assert_eq!(sampled_scene_scores(&scores, 4, 5, 8), &[] as &[f64]);
+        assert_eq!(sampled_scene_scores(&scores, 4, 20, 40), &[5.0]);
+        assert_eq!(sampled_scene_scores(&scores, 4, 30, 40), &[] as &[f64]);
+    }
+
+    #[test]
+    fn corrected_target_offsets_prediction_error() {
+        let error = |predicted, achieved| PredictionError {
+            chunk: "00000".to_string(),
+            quantizer: 30.0,
+            predicted,
+            achieved,
+        };
+
+        assert_eq!(error(95.5, 95.2).corrected_target((95.0, 96.0)), None);
+        // Scored 2 lower than predicted, so aim 2 higher
+        assert_eq!(
+            error(95.5, 93.5).corrected_target((95.0, 96.0)),
+            Some((97.0, 98.0))
+        );
+        assert_eq!(
+            error(95.5, 97.0).corrected_target((95.0, 96.0)),
+            Some((93.5, 94.5))
+        );
+    }
+
+    #[test]
+    fn probe_checkpoint_ignores_target() {
+        let mut tq = TargetQuality::default("", Encoder::svt_av1);
+        let frames = (0, 100);
+        let mut previous = ProbeCheckpoint::new(&tq, frames, (95.0, 96.0));
+        previous.probes.push((30.0, 95.5));
+
+        assert!(previous.is_compatible(&ProbeCheckpoint::new(&tq, frames, (93.0, 94.0))));
+        assert!(!previous.is_compatible(&ProbeCheckpoint::new(&tq, (0, 120), (95.0, 96.0))));
+
+        tq.probing_rate = 2;
+        assert!(!previous.is_compatible(&ProbeCheckpoint::new(&tq, frames, (95.0, 96.0))));
+
+        tq.probing_rate = 1;
+        tq.max_q = 40;
+        assert!(!previous.is_compatible(&ProbeCheckpoint::new(&tq, frames, (95.0, 96.0))));
+
+        tq = TargetQuality::default("", Encoder::aom);
+        assert!(!previous.is_compatible(&ProbeCheckpoint::new(&tq, frames, (95.0, 96.0))));
+
+        tq = TargetQuality::default("", Encoder::svt_av1);
+        tq.metric = TargetMetric::SSIMULACRA2;
+        assert!(!previous.is_compatible(&ProbeCheckpoint::new(&tq, frames, (95.0, 96.0))));
+    }
 }
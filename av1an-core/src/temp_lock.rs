@@ -0,0 +1,111 @@
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::Write,
+    path::Path,
+    process,
+};
+
+use anyhow::bail;
+use tracing::warn;
+
+/// Name of the lock file in the temporary folder
+const LOCK_FILE: &str = "av1an.lock";
+
+/// Advisory lock on a temporary folder, held while an encode uses it so that
+/// two encodes cannot share a temporary folder. The operating system releases
+/// the lock when the process exits, so a crashed encode never leaves a stale
+/// lock behind.
+#[derive(Debug)]
+pub(crate) struct TempLock {
+    /// Contains the process ID of the encode holding the lock
+    _file: File,
+}
+
+impl TempLock {
+    /// Locks `temp`, which must exist. Fails if another encode holds the lock,
+    /// unless `force` is set, in which case the encode continues without the
+    /// lock.
+    pub(crate) fn acquire(temp: &Path, force: bool) -> anyhow::Result<Option<Self>> {
+        let path = temp.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {},
+            Err(TryLockError::WouldBlock) => {
+                let holder = holder(&path);
+                if force {
+                    warn!(
+                        "temporary directory {temp} is in use by another instance ({holder}), \
+                         continuing because of --force-lock",
+                        temp = temp.display()
+                    );
+                    return Ok(None);
+                }
+                bail!(
+                    "Another instance ({holder}) is using the temporary directory {temp}. Wait \
+                     for it to finish, choose another directory with --temp, or use --force-lock \
+                     if it is not running",
+                    temp = temp.display()
+                );
+            },
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        file.set_len(0)?;
+        write!(file, "{}", process::id())?;
+        file.flush()?;
+
+        Ok(Some(Self {
+            _file: file
+        }))
+    }
+
+    /// Checks that no other encode holds the lock on `temp` before it is
+    /// deleted. Does nothing if `temp` has no lock file.
+    pub(crate) fn check_unlocked(temp: &Path, force: bool) -> anyhow::Result<()> {
+        if temp.join(LOCK_FILE).exists() {
+            drop(Self::acquire(temp, force)?);
+        }
+        Ok(())
+    }
+}
+
+/// Describes the process holding the lock in `path`, which cannot always be
+/// read while it is locked
+fn holder(path: &Path) -> String {
+    match fs::read_to_string(path) {
+        Ok(pid) if !pid.trim().is_empty() => format!("pid {pid}", pid = pid.trim()),
+        _ => "unknown pid".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{TempLock, LOCK_FILE};
+
+    #[test]
+    fn second_lock_is_refused() {
+        let temp = tempfile::tempdir().expect("temp dir should be created");
+        let lock = TempLock::acquire(temp.path(), false).expect("first lock should succeed");
+        assert!(lock.is_some());
+        assert_eq!(
+            fs::read_to_string(temp.path().join(LOCK_FILE)).expect("lock file should exist"),
+            std::process::id().to_string()
+        );
+
+        let err = TempLock::acquire(temp.path(), false).expect_err("second lock should fail");
+        assert!(err.to_string().contains(&format!("pid {}", std::process::id())));
+        assert!(TempLock::acquire(temp.path(), true)
+            .expect("forced lock should continue")
+            .is_none());
+
+        drop(lock);
+        assert!(TempLock::acquire(temp.path(), false).expect("lock should be free").is_some());
+    }
+}
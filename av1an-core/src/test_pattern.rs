@@ -0,0 +1,197 @@
+use std::{fmt::Write, str::FromStr};
+
+use anyhow::{bail, ensure, Context};
+use strum::{Display, EnumString, IntoStaticStr};
+
+/// Frame rate of the generated test patterns
+const FRAME_RATE: (u32, u32) = (24, 1);
+
+/// Kind of test signal generated by a [`TestPattern`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString, IntoStaticStr)]
+pub enum PatternKind {
+    /// 75% color bars
+    #[strum(serialize = "bars")]
+    Bars,
+    /// Luma noise that changes every frame
+    #[strum(serialize = "noise")]
+    Noise,
+    /// Horizontal luma gradient that scrolls to the left
+    #[strum(serialize = "gradient")]
+    Gradient,
+    /// White box that moves across a gray background
+    #[strum(serialize = "moving-box")]
+    MovingBox,
+}
+
+/// Synthetic input that is generated by VapourSynth instead of decoded from a
+/// file, so that the whole pipeline can be run without sample media
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestPattern {
+    pub kind:   PatternKind,
+    pub width:  u32,
+    pub height: u32,
+    pub frames: usize,
+}
+
+impl FromStr for TestPattern {
+    type Err = anyhow::Error;
+
+    /// Parses `KIND[:WIDTHxHEIGHT[:FRAMES]]`, defaulting to 1280x720 and 240
+    /// frames
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap_or_default();
+        let kind = PatternKind::from_str(kind).with_context(|| {
+            format!("unknown test pattern {kind:?}, expected bars, noise, gradient or moving-box")
+        })?;
+        let (width, height) = match parts.next() {
+            Some(size) => {
+                let (width, height) = size
+                    .split_once('x')
+                    .with_context(|| format!("test pattern size {size:?} is not WIDTHxHEIGHT"))?;
+                (width.parse()?, height.parse()?)
+            },
+            None => (1280, 720),
+        };
+        let frames = parts.next().map(str::parse).transpose()?.unwrap_or(240);
+        if parts.next().is_some() {
+            bail!("test pattern {s:?} is not KIND[:WIDTHxHEIGHT[:FRAMES]]");
+        }
+
+        ensure!(
+            width >= 16 && height >= 16 && width % 2 == 0 && height % 2 == 0,
+            "test pattern size must be even and at least 16x16, got {width}x{height}"
+        );
+        ensure!(frames > 0, "test pattern must have at least one frame");
+
+        Ok(Self {
+            kind,
+            width,
+            height,
+            frames,
+        })
+    }
+}
+
+impl TestPattern {
+    /// File name of the generated script, which is also used as the input name
+    /// for the default output and temp folder
+    #[inline]
+    pub fn file_name(&self) -> String {
+        format!(
+            "{kind}-{width}x{height}-{frames}.vpy",
+            kind = self.kind,
+            width = self.width,
+            height = self.height,
+            frames = self.frames
+        )
+    }
+
+    /// VapourSynth script that outputs the pattern as 10-bit 4:2:0 video
+    pub(crate) fn script_text(&self) -> String {
+        let (width, height, frames) = (self.width, self.height, self.frames);
+        let (fps_num, fps_den) = FRAME_RATE;
+        let mut script = format!(
+            "import vapoursynth as vs\ncore = vs.core\n\nbase = core.std.BlankClip(width={width}, \
+             height={height}, format=vs.YUV420P10, length={frames}, fpsnum={fps_num}, \
+             fpsden={fps_den}, color=[512, 512, 512])\n"
+        );
+
+        let clip = match self.kind {
+            PatternKind::Bars => {
+                // White, yellow, cyan, green, magenta, red and blue at 75% intensity
+                let colors = [
+                    [191, 191, 191],
+                    [191, 191, 0],
+                    [0, 191, 191],
+                    [0, 191, 0],
+                    [191, 0, 191],
+                    [191, 0, 0],
+                    [0, 0, 191],
+                ];
+                let bar_width = (width / colors.len() as u32) / 2 * 2;
+                script.push_str("bars = [\n");
+                for (i, color) in colors.iter().enumerate() {
+                    let bar_width = if i == colors.len() - 1 {
+                        width - bar_width * (colors.len() as u32 - 1)
+                    } else {
+                        bar_width
+                    };
+                    writeln!(
+                        script,
+                        "    core.std.BlankClip(base, width={bar_width}, format=vs.RGB24, \
+                         color={color:?}),"
+                    )
+                    .expect("write to string should work");
+                }
+                script.push_str("]\n");
+                "core.resize.Bicubic(core.std.StackHorizontal(bars), format=vs.YUV420P10, \
+                 matrix_s=\"709\")"
+                    .to_string()
+            },
+            PatternKind::Noise => {
+                // Hash of the position and frame number, kept below 2^24 where possible
+                // since Expr calculates with 32-bit floats
+                "core.std.Expr(base, [\"X 37 * Y 59 * + N 97 * + 1031 % dup * X + 1024 % 64 + 876 \
+                 * 1024 /\", \"\"])"
+                    .to_string()
+            },
+            PatternKind::Gradient => {
+                "core.std.Expr(base, [\"X N 8 * + width % width / 876 * 64 +\", \"\"])".to_string()
+            },
+            PatternKind::MovingBox => {
+                let size = (width.min(height) / 4).max(2);
+                format!(
+                    "core.std.Expr(base, [\"X N 8 * width {size} - % - dup 0 >= swap {size} < and \
+                     Y height {size} - 2 / - dup 0 >= swap {size} < and and 940 512 ?\", \"\"])"
+                )
+            },
+        };
+        writeln!(script, "clip = {clip}\nclip.set_output()").expect("write to string should work");
+
+        script
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PatternKind, TestPattern};
+
+    #[test]
+    fn parse_test_pattern() {
+        assert_eq!(
+            "moving-box:640x360:48".parse::<TestPattern>().expect("pattern should parse"),
+            TestPattern {
+                kind:   PatternKind::MovingBox,
+                width:  640,
+                height: 360,
+                frames: 48,
+            }
+        );
+        assert_eq!(
+            "bars".parse::<TestPattern>().expect("pattern should parse"),
+            TestPattern {
+                kind:   PatternKind::Bars,
+                width:  1280,
+                height: 720,
+                frames: 240,
+            }
+        );
+        assert!("stripes".parse::<TestPattern>().is_err());
+        assert!("noise:641x360".parse::<TestPattern>().is_err());
+        assert!("noise:640x360:0".parse::<TestPattern>().is_err());
+        assert!("noise:640x360:10:1".parse::<TestPattern>().is_err());
+    }
+
+    #[test]
+    fn bars_cover_the_width() {
+        let pattern = "bars:1000x200:10".parse::<TestPattern>().expect("pattern should parse");
+        let script = pattern.script_text();
+        // Six bars of 142 pixels and a last bar of 148 pixels
+        assert_eq!(script.matches("width=142,").count(), 6);
+        assert!(script.contains("width=148,"));
+        assert!(script.contains("length=10,"));
+        assert_eq!(pattern.file_name(), "bars-1000x200-10.vpy");
+    }
+}
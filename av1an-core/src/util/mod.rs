@@ -1,7 +1,12 @@
 #[cfg(test)]
 mod tests;
 
-use std::path::{Path, PathBuf};
+use std::{
+    env,
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 /// Count the number of elements passed to this macro.
 ///
@@ -132,3 +137,187 @@ pub fn read_in_dir(path: &Path) -> anyhow::Result<impl Iterator<Item = PathBuf>>
         d.file_type().map_or(None, |file_type| (!file_type.is_dir()).then(|| d.path()))
     }))
 }
+
+/// Common per-OS install locations of encoders and tools that are often
+/// missing from `PATH`
+fn common_install_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if cfg!(windows) {
+        for program_files in ["ProgramFiles", "ProgramFiles(x86)"] {
+            if let Some(root) = env::var_os(program_files) {
+                let root = PathBuf::from(root);
+                dirs.extend(
+                    ["SVT-AV1", "ffmpeg\\bin", "MKVToolNix", "VapourSynth", "x264", "x265"]
+                        .iter()
+                        .map(|dir| root.join(dir)),
+                );
+            }
+        }
+    } else {
+        if cfg!(target_os = "macos") {
+            dirs.push(PathBuf::from("/opt/homebrew/bin"));
+        }
+        dirs.push(PathBuf::from("/usr/local/bin"));
+        if let Some(home) = env::var_os("HOME") {
+            let home = PathBuf::from(home);
+            dirs.push(home.join(".local/bin"));
+            dirs.push(home.join(".cargo/bin"));
+        }
+    }
+    dirs
+}
+
+/// Builds the search path used to find executables: `dirs` first, then the
+/// current `PATH`, then any common install locations that exist. Duplicate
+/// entries are removed.
+#[inline]
+pub fn executable_search_path(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let path = env::var_os("PATH").map(|path| env::split_paths(&path).collect::<Vec<_>>());
+    let mut search_path: Vec<PathBuf> = Vec::new();
+    for dir in dirs
+        .iter()
+        .cloned()
+        .chain(path.into_iter().flatten())
+        .chain(common_install_dirs().into_iter().filter(|dir| dir.is_dir()))
+    {
+        if !search_path.contains(&dir) {
+            search_path.push(dir);
+        }
+    }
+    search_path
+}
+
+/// Returns a path next to `path` that is unique to this process and call, to
+/// write a file to before renaming it to `path`. The extension of `path` is
+/// kept last, as encoders and muxers pick their container from it.
+#[inline]
+pub(crate) fn partial_path(path: &Path) -> PathBuf {
+    static PARTIAL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(format!(
+        ".{pid}-{n}",
+        pid = std::process::id(),
+        n = PARTIAL_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+/// Appends `suffix` to the file name of `path`, keeping file names that are not
+/// valid UTF-8 intact
+fn with_file_name_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+/// 64-bit FNV-1a hash of `bytes`. Unlike `DefaultHasher`, it is the same in
+/// every build, so it can name files that outlive the process.
+#[inline]
+pub(crate) fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Moves `from` to `to`, copying the file if it cannot be renamed, e.g. because
+/// `to` is on a different file system
+#[inline]
+pub(crate) fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Writes `contents` to `path` through a uniquely named partial file, so
+/// readers and concurrent writers never see a partially written file
+#[inline]
+pub(crate) fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let partial = partial_path(path);
+    std::fs::write(&partial, contents)?;
+    std::fs::rename(partial, path)
+}
+
+/// Returns the total size in bytes of the files in `path` and all of its
+/// subdirectories, skipping anything that cannot be read
+#[inline]
+pub(crate) fn dir_size(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path).map_or(0, |entries| {
+        entries.flatten().map(|entry| dir_size(&entry.path())).sum()
+    })
+}
+
+/// Returns the first of `<path>.bak`, `<path>.1.bak`, `<path>.2.bak`, ... that
+/// does not exist yet
+#[inline]
+pub(crate) fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = with_file_name_suffix(path, ".bak");
+    let mut n = 1;
+    while backup.exists() {
+        backup = with_file_name_suffix(path, &format!(".{n}.bak"));
+        n += 1;
+    }
+    backup
+}
+
+/// Replaces every occurrence of the path `from` in `text` with `to`, returning
+/// `None` if there is none. Occurrences that are only part of a longer path,
+/// like `/a/job` in `/b/a/job` or `/a/job2`, are kept.
+#[inline]
+pub(crate) fn remap_path_prefix(text: &str, from: &str, to: &str) -> Option<String> {
+    // Characters that can surround a path in arguments and scripts
+    let is_delimiter = |c: char| matches!(c, '"' | '\'' | '=' | ' ' | ',' | '\n');
+    if from.is_empty() || from == to {
+        return None;
+    }
+
+    let mut remapped = String::with_capacity(text.len());
+    let mut changed = false;
+    let mut rest = text;
+    while let Some((before, after)) = rest.split_once(from) {
+        let starts_path = before
+            .chars()
+            .next_back()
+            .or_else(|| remapped.chars().next_back())
+            .is_none_or(is_delimiter);
+        let ends_path =
+            after.chars().next().is_none_or(|c| is_delimiter(c) || c == '/' || c == '\\');
+
+        remapped.push_str(before);
+        remapped.push_str(if starts_path && ends_path { to } else { from });
+        changed |= starts_path && ends_path;
+        rest = after;
+    }
+    remapped.push_str(rest);
+
+    changed.then_some(remapped)
+}
+
+/// Applies [`remap_path_prefix`] with each `(from, to)` pair of `remaps` in
+/// order, returning `None` if none of them changed `text`
+#[inline]
+pub(crate) fn remap_paths(text: &str, remaps: &[(String, String)]) -> Option<String> {
+    remaps.iter().fold(None, |remapped: Option<String>, (from, to)| {
+        remap_path_prefix(remapped.as_deref().unwrap_or(text), from, to).or(remapped)
+    })
+}
+
+/// Applies [`remap_paths`] to `path` if it is valid UTF-8
+#[inline]
+pub(crate) fn remap_path_buf(path: &mut PathBuf, remaps: &[(String, String)]) {
+    if let Some(remapped) = path.to_str().and_then(|text| remap_paths(text, remaps)) {
+        *path = remapped.into();
+    }
+}
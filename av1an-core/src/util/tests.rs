@@ -1,4 +1,4 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, path::PathBuf};
 
 #[test]
 fn count_macro() {
@@ -33,3 +33,118 @@ fn inplace_vec_is_sound() {
 
     assert_eq!(v1, v2);
 }
+
+#[test]
+fn executable_search_path_prefers_given_dirs() {
+    let first = PathBuf::from("/opt/av1an/first");
+    let second = PathBuf::from("/opt/av1an/second");
+    let search_path =
+        super::executable_search_path(&[first.clone(), second.clone(), first.clone()]);
+
+    assert_eq!(search_path[..2], [first.clone(), second]);
+    assert_eq!(search_path.iter().filter(|dir| **dir == first).count(), 1);
+}
+
+#[test]
+fn backup_path_skips_existing_backups() {
+    let dir = tempfile::tempdir().expect("temp dir should be created");
+    let output = dir.path().join("output.mkv");
+    assert_eq!(
+        super::backup_path(&output),
+        dir.path().join("output.mkv.bak")
+    );
+
+    std::fs::write(dir.path().join("output.mkv.bak"), []).expect("file should be written");
+    std::fs::write(dir.path().join("output.mkv.1.bak"), []).expect("file should be written");
+    assert_eq!(
+        super::backup_path(&output),
+        dir.path().join("output.mkv.2.bak")
+    );
+}
+
+#[test]
+fn dir_size_counts_nested_files() {
+    let dir = tempfile::tempdir().expect("temp dir should be created");
+    std::fs::create_dir_all(dir.path().join("split")).expect("dir should be created");
+    std::fs::write(dir.path().join("done.json"), [0; 10]).expect("file should be written");
+    std::fs::write(dir.path().join("split").join("00000.mkv"), [0; 32])
+        .expect("file should be written");
+    assert_eq!(super::dir_size(dir.path()), 42);
+    assert_eq!(super::dir_size(&dir.path().join("missing")), 0);
+}
+
+#[test]
+fn write_atomic_replaces_file() {
+    let dir = tempfile::tempdir().expect("temp dir should be created");
+    let path = dir.path().join("done.json");
+    assert_ne!(super::partial_path(&path), super::partial_path(&path));
+    assert_eq!(
+        super::partial_path(&path).extension().and_then(|ext| ext.to_str()),
+        Some("json")
+    );
+
+    super::write_atomic(&path, "{}").expect("file should be written");
+    super::write_atomic(&path, "[]").expect("file should be written");
+    assert_eq!(std::fs::read_to_string(&path).ok().as_deref(), Some("[]"));
+    assert_eq!(
+        std::fs::read_dir(dir.path()).map(Iterator::count).ok(),
+        Some(1)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_file_names_are_kept() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let dir = tempfile::tempdir().expect("temp dir should be created");
+    let output = dir.path().join(OsStr::from_bytes(b"output\xff.mkv"));
+    assert_eq!(
+        super::backup_path(&output),
+        dir.path().join(OsStr::from_bytes(b"output\xff.mkv.bak"))
+    );
+
+    let merged = dir.path().join("output.mkv");
+    std::fs::write(&merged, [0; 16]).expect("file should be written");
+    super::move_file(&merged, &output).expect("file should be moved");
+    assert!(!merged.exists());
+    assert_eq!(std::fs::metadata(&output).map(|m| m.len()).ok(), Some(16));
+}
+
+#[test]
+fn remap_path_prefix_keeps_longer_paths() {
+    use super::remap_path_prefix;
+
+    assert_eq!(
+        remap_path_prefix("/mnt/a/job/split/00001_fpf", "/mnt/a/job", "/data/job").as_deref(),
+        Some("/data/job/split/00001_fpf")
+    );
+    assert_eq!(
+        remap_path_prefix("--fpf=/mnt/a/job/split/x", "/mnt/a/job", "/data/job").as_deref(),
+        Some("--fpf=/data/job/split/x")
+    );
+    assert_eq!(
+        remap_path_prefix(
+            "source = r\"/mnt/a/in.mkv\"\ncache_file = r\"/mnt/a/job/split/cache\"",
+            "/mnt/a/job",
+            "/data/job"
+        )
+        .as_deref(),
+        Some("source = r\"/mnt/a/in.mkv\"\ncache_file = r\"/data/job/split/cache\"")
+    );
+    assert_eq!(
+        remap_path_prefix("/mnt/a/job2/x", "/mnt/a/job", "/data/job"),
+        None
+    );
+    assert_eq!(
+        remap_path_prefix("/b/mnt/a/job/x", "/mnt/a/job", "/data/job"),
+        None
+    );
+    assert_eq!(remap_path_prefix(".abc/encode", ".abc", ".abc"), None);
+}
+
+#[test]
+fn stable_hash_is_fnv1a() {
+    assert_eq!(super::stable_hash(b""), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(super::stable_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+}
@@ -1,9 +1,12 @@
 use std::{
     fmt::Display,
     fs::{create_dir_all, File},
+    hash::{DefaultHasher, Hasher},
     io::Write,
     path::{absolute, Path, PathBuf},
     process::Command,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, bail, Context};
@@ -11,7 +14,7 @@ use av_format::rational::Rational64;
 use path_abs::{PathAbs, PathInfo};
 use serde::{Deserialize, Serialize};
 use strum::{EnumString, IntoStaticStr};
-use tracing::info;
+use tracing::{info, warn};
 use vapoursynth::{
     core::CoreRef,
     prelude::*,
@@ -20,6 +23,7 @@ use vapoursynth::{
 
 use super::ChunkMethod;
 use crate::{
+    children,
     metrics::{
         butteraugli::ButteraugliSubMetric,
         xpsnr::{weight_xpsnr, XPSNRSubMetric},
@@ -74,6 +78,20 @@ impl VapoursynthPlugins {
             ChunkMethod::Hybrid
         }
     }
+
+    /// All installed VapourSynth source plugins usable as a chunk method
+    #[inline]
+    pub fn available_chunk_methods(&self) -> Vec<ChunkMethod> {
+        [
+            (self.bestsource, ChunkMethod::BESTSOURCE),
+            (self.lsmash, ChunkMethod::LSMASH),
+            (self.ffms2, ChunkMethod::FFMS2),
+            (self.dgdecnv, ChunkMethod::DGDECNV),
+        ]
+        .into_iter()
+        .filter_map(|(available, method)| available.then_some(method))
+        .collect()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -145,6 +163,7 @@ pub fn get_clip_info(source: &Input, vspipe_args_map: &OwnedMap) -> anyhow::Resu
         frame_rate:               get_frame_rate(&info)?,
         resolution:               get_resolution(&info)?,
         color_range:              get_color_range(&environment)?,
+        sample_aspect_ratio:      get_sample_aspect_ratio(&environment)?,
         transfer_characteristics: match get_transfer(&environment)? {
             16 => av1_grain::TransferFunction::SMPTE2084,
             _ => av1_grain::TransferFunction::BT1886,
@@ -234,6 +253,25 @@ fn get_color_range(env: &Environment) -> anyhow::Result<Option<ColorRange>> {
     Ok(color_range)
 }
 
+/// Get the sample aspect ratio from an environment that has already been
+/// evaluated on a script.
+fn get_sample_aspect_ratio(env: &Environment) -> anyhow::Result<Option<(u32, u32)>> {
+    // Get the output node.
+    const OUTPUT_INDEX: i32 = 0;
+
+    let (node, _) = env.get_output(OUTPUT_INDEX)?;
+    let frame = node.get_frame(0).context("get_sample_aspect_ratio")?;
+    let props = frame.props();
+    let sar = props
+        .get::<i64>("_SARNum")
+        .ok()
+        .zip(props.get::<i64>("_SARDen").ok())
+        .and_then(|(num, den)| Some((u32::try_from(num).ok()?, u32::try_from(den).ok()?)))
+        .filter(|&(num, den)| num != 0 && den != 0);
+
+    Ok(sar)
+}
+
 #[inline]
 const fn map_vapoursynth_color_range(color_range: i64) -> Option<ColorRange> {
     match color_range {
@@ -465,6 +503,49 @@ pub fn resize_node<'core>(
         .map_err(|_| anyhow::anyhow!(error_message.clone()))
 }
 
+/// Keeps only the luma plane of `node` as a GRAY clip with the same bit depth
+#[inline]
+pub fn luma_node<'core>(core: CoreRef<'core>, node: &Node<'core>) -> anyhow::Result<Node<'core>> {
+    let api = API::get().ok_or_else(|| anyhow::anyhow!("Failed to get VapourSynth API"))?;
+    let std = get_plugin(core, PluginId::Std)?;
+
+    let mut arguments = vapoursynth::map::OwnedMap::new(api);
+    arguments.set("clip", node)?;
+    arguments.set_int_array("planes", &[0])?;
+    arguments.set_int("colorfamily", vapoursynth::format::ColorFamily::Gray as i64)?;
+
+    let error_message = "Failed to extract the luma plane";
+
+    std.invoke("ShufflePlanes", &arguments)
+        .map_err(|_| anyhow::anyhow!(error_message))?
+        .get_video_node("clip")
+        .map_err(|_| anyhow::anyhow!(error_message))
+}
+
+/// Maps the values of the luma plane of `node` through `lut`, which has an
+/// entry for every value of the clip's bit depth
+#[inline]
+pub fn luma_lut_node<'core>(
+    core: CoreRef<'core>,
+    node: &Node<'core>,
+    lut: &[i64],
+) -> anyhow::Result<Node<'core>> {
+    let api = API::get().ok_or_else(|| anyhow::anyhow!("Failed to get VapourSynth API"))?;
+    let std = get_plugin(core, PluginId::Std)?;
+
+    let mut arguments = vapoursynth::map::OwnedMap::new(api);
+    arguments.set("clip", node)?;
+    arguments.set_int_array("planes", &[0])?;
+    arguments.set_int_array("lut", lut)?;
+
+    let error_message = "Failed to map the luma plane";
+
+    std.invoke("Lut", &arguments)
+        .map_err(|_| anyhow::anyhow!(error_message))?
+        .get_video_node("clip")
+        .map_err(|_| anyhow::anyhow!(error_message))
+}
+
 fn select_every<'core>(
     core: CoreRef<'core>,
     node: &Node<'core>,
@@ -491,6 +572,7 @@ fn compare_ssimulacra2<'core>(
     source: &Node<'core>,
     encoded: &Node<'core>,
     plugins: VapoursynthPlugins,
+    vship_streams: usize,
 ) -> anyhow::Result<(Node<'core>, &'static str)> {
     if !plugins.vship && plugins.vszip == VSZipVersion::None {
         return Err(anyhow::anyhow!("SSIMULACRA2 not available"));
@@ -520,7 +602,7 @@ fn compare_ssimulacra2<'core>(
     arguments.set("distorted", encoded)?;
 
     if plugins.vship {
-        arguments.set_int("numStream", 4)?;
+        arguments.set_int("numStream", vship_streams as i64)?;
     } else if plugins.vszip == VSZipVersion::Legacy {
         // Handle older vszip API
         arguments.set_int("mode", 0)?;
@@ -557,6 +639,7 @@ fn compare_butteraugli<'core>(
     encoded: &Node<'core>,
     submetric: ButteraugliSubMetric,
     plugins: VapoursynthPlugins,
+    vship_streams: usize,
 ) -> anyhow::Result<(Node<'core>, &'static str)> {
     if !plugins.vship && !plugins.julek {
         return Err(anyhow::anyhow!("butteraugli not available"));
@@ -589,7 +672,7 @@ fn compare_butteraugli<'core>(
         arguments.set("reference", source)?;
         arguments.set("distorted", encoded)?;
         arguments.set_float("intensity_multiplier", INTENSITY)?;
-        arguments.set_int("numStream", 4)?;
+        arguments.set_int("numStream", vship_streams as i64)?;
     } else if plugins.julek {
         // Inputs must be in RGBS format
         let formatted_source = resize_node(
@@ -716,13 +799,14 @@ pub fn create_vs_file(loadscript_args: &LoadscriptArgs) -> anyhow::Result<(PathB
             info!("Indexing input with DGDecNV");
 
             // Run dgindexnv to generate the .dgi index file
-            Command::new("dgindexnv")
-                .arg("-h")
-                .arg("-i")
-                .arg(&absolute_source)
-                .arg("-o")
-                .arg(&dgindexnv_output)
-                .output()?;
+            children::output(
+                Command::new("dgindexnv")
+                    .arg("-h")
+                    .arg("-i")
+                    .arg(&absolute_source)
+                    .arg("-o")
+                    .arg(&dgindexnv_output),
+            )?;
         }
     }
 
@@ -877,7 +961,84 @@ pub fn get_comparands<'core>(
     Ok((chunk_node, resized_encoded_node))
 }
 
+/// Number of streams vship uses for a single measurement, unless limited by
+/// `--gpu-streams`
+const VSHIP_STREAMS: usize = 4;
+
+/// Number of vship streams in use by all workers
+#[expect(
+    clippy::mutex_atomic,
+    reason = "the counter is waited on with a condvar"
+)]
+static VSHIP_STREAMS_IN_USE: Mutex<usize> = Mutex::new(0);
+static VSHIP_STREAMS_RELEASED: Condvar = Condvar::new();
+
+/// vship streams reserved for a single measurement, released when dropped
+struct VshipStreams(usize);
+
+impl VshipStreams {
+    /// Waits until a measurement can run without the total number of vship
+    /// streams exceeding `max_streams`
+    fn acquire(max_streams: Option<usize>) -> Self {
+        let max_streams = max_streams.unwrap_or(usize::MAX);
+        let streams = VSHIP_STREAMS.min(max_streams).max(1);
+        let mut in_use = VSHIP_STREAMS_IN_USE.lock().expect("mutex should acquire lock");
+        while *in_use > 0 && *in_use + streams > max_streams {
+            in_use = VSHIP_STREAMS_RELEASED.wait(in_use).expect("mutex should acquire lock");
+        }
+        *in_use += streams;
+        Self(streams)
+    }
+}
+
+impl Drop for VshipStreams {
+    fn drop(&mut self) {
+        *VSHIP_STREAMS_IN_USE.lock().expect("mutex should acquire lock") -= self.0;
+        VSHIP_STREAMS_RELEASED.notify_all();
+    }
+}
+
+/// Runs `measure` on the GPU if vship is installed, falling back to
+/// `cpu_plugin` if vship fails, e.g. because it ran out of VRAM.
+///
+/// `measure` is given the plugins to use and the number of vship streams.
+/// Returns the scores along with the ID of the plugin that measured them.
+fn measure_with_cpu_fallback(
+    metric: &str,
+    plugins: VapoursynthPlugins,
+    cpu_plugin: Option<PluginId>,
+    gpu_streams: Option<usize>,
+    measure: impl Fn(VapoursynthPlugins, usize) -> anyhow::Result<Vec<f64>>,
+) -> anyhow::Result<(Vec<f64>, &'static str)> {
+    if plugins.vship {
+        let result = {
+            let streams = VshipStreams::acquire(gpu_streams);
+            measure(plugins, streams.0)
+        };
+        match (result, cpu_plugin) {
+            (Ok(scores), _) => return Ok((scores, PluginId::Vship.as_str())),
+            (Err(e), None) => return Err(e),
+            (Err(e), Some(cpu_plugin)) => warn!(
+                "{metric} failed with {vship}, falling back to {cpu}: {e}",
+                vship = PluginId::Vship.as_str(),
+                cpu = cpu_plugin.as_str()
+            ),
+        }
+    }
+
+    let cpu_plugin = cpu_plugin.ok_or_else(|| anyhow!("{metric} not available"))?;
+    let scores = measure(
+        VapoursynthPlugins {
+            vship: false,
+            ..plugins
+        },
+        0,
+    )?;
+    Ok((scores, cpu_plugin.as_str()))
+}
+
 #[inline]
+#[expect(clippy::too_many_arguments)]
 pub fn measure_butteraugli(
     submetric: ButteraugliSubMetric,
     source: &Input,
@@ -886,35 +1047,52 @@ pub fn measure_butteraugli(
     probe_res: Option<(u32, u32)>,
     sample_rate: usize,
     plugins: VapoursynthPlugins,
-) -> anyhow::Result<Vec<f64>> {
-    let mut environment = Environment::new()?;
-    let args = source.as_vspipe_args_map()?;
-    environment.set_variables(&args)?;
-    // Cannot use eval_file because it causes file system access errors during
-    // Target Quality probing
-    // Consider using eval_file only when source is not in CWD
-    environment.eval_script(&source.as_script_text()?)?;
-    let core = environment.get_core()?;
-
-    let source_node = environment.get_output(0)?.0;
-    let (chunk_node, encoded_node) = get_comparands(
-        core,
-        &source_node,
-        encoded,
-        frame_range,
-        probe_res,
-        sample_rate,
-    )?;
-    let (compared_node, butteraugli_key) =
-        compare_butteraugli(core, &chunk_node, &encoded_node, submetric, plugins)?;
-
-    let mut scores = Vec::new();
-    for frame_index in 0..compared_node.info().num_frames {
-        let score = compared_node.get_frame(frame_index)?.props().get_float(butteraugli_key)?;
-        scores.push(score);
-    }
+    gpu_streams: Option<usize>,
+) -> anyhow::Result<(Vec<f64>, &'static str)> {
+    let cpu_plugin = plugins.julek.then_some(PluginId::Julek);
+    measure_with_cpu_fallback(
+        "butteraugli",
+        plugins,
+        cpu_plugin,
+        gpu_streams,
+        |plugins, vship_streams| {
+            let mut environment = Environment::new()?;
+            let args = source.as_vspipe_args_map()?;
+            environment.set_variables(&args)?;
+            // Cannot use eval_file because it causes file system access errors during
+            // Target Quality probing
+            // Consider using eval_file only when source is not in CWD
+            environment.eval_script(&source.as_script_text()?)?;
+            let core = environment.get_core()?;
+
+            let source_node = environment.get_output(0)?.0;
+            let (chunk_node, encoded_node) = get_comparands(
+                core,
+                &source_node,
+                encoded,
+                frame_range,
+                probe_res,
+                sample_rate,
+            )?;
+            let (compared_node, butteraugli_key) = compare_butteraugli(
+                core,
+                &chunk_node,
+                &encoded_node,
+                submetric,
+                plugins,
+                vship_streams,
+            )?;
+
+            let mut scores = Vec::new();
+            for frame_index in 0..compared_node.info().num_frames {
+                let score =
+                    compared_node.get_frame(frame_index)?.props().get_float(butteraugli_key)?;
+                scores.push(score);
+            }
 
-    Ok(scores)
+            Ok(scores)
+        },
+    )
 }
 
 #[inline]
@@ -925,34 +1103,45 @@ pub fn measure_ssimulacra2(
     probe_res: Option<(u32, u32)>,
     sample_rate: usize,
     plugins: VapoursynthPlugins,
-) -> anyhow::Result<Vec<f64>> {
-    let mut environment = Environment::new()?;
-    let args = source.as_vspipe_args_map()?;
-    environment.set_variables(&args)?;
-    // Cannot use eval_file because it causes file system access errors during
-    // Target Quality probing
-    environment.eval_script(&source.as_script_text()?)?;
-    let core = environment.get_core()?;
-
-    let source_node = environment.get_output(0)?.0;
-    let (chunk_node, encoded_node) = get_comparands(
-        core,
-        &source_node,
-        encoded,
-        frame_range,
-        probe_res,
-        sample_rate,
-    )?;
-    let (compared_node, ssimulacra_key) =
-        compare_ssimulacra2(core, &chunk_node, &encoded_node, plugins)?;
-
-    let mut scores = Vec::new();
-    for frame_index in 0..compared_node.info().num_frames {
-        let score = compared_node.get_frame(frame_index)?.props().get_float(ssimulacra_key)?;
-        scores.push(score);
-    }
+    gpu_streams: Option<usize>,
+) -> anyhow::Result<(Vec<f64>, &'static str)> {
+    let cpu_plugin = (plugins.vszip != VSZipVersion::None).then_some(PluginId::Vszip);
+    measure_with_cpu_fallback(
+        "SSIMULACRA2",
+        plugins,
+        cpu_plugin,
+        gpu_streams,
+        |plugins, vship_streams| {
+            let mut environment = Environment::new()?;
+            let args = source.as_vspipe_args_map()?;
+            environment.set_variables(&args)?;
+            // Cannot use eval_file because it causes file system access errors during
+            // Target Quality probing
+            environment.eval_script(&source.as_script_text()?)?;
+            let core = environment.get_core()?;
+
+            let source_node = environment.get_output(0)?.0;
+            let (chunk_node, encoded_node) = get_comparands(
+                core,
+                &source_node,
+                encoded,
+                frame_range,
+                probe_res,
+                sample_rate,
+            )?;
+            let (compared_node, ssimulacra_key) =
+                compare_ssimulacra2(core, &chunk_node, &encoded_node, plugins, vship_streams)?;
+
+            let mut scores = Vec::new();
+            for frame_index in 0..compared_node.info().num_frames {
+                let score =
+                    compared_node.get_frame(frame_index)?.props().get_float(ssimulacra_key)?;
+                scores.push(score);
+            }
 
-    Ok(scores)
+            Ok(scores)
+        },
+    )
 }
 
 #[inline]
@@ -1015,6 +1204,118 @@ pub fn measure_xpsnr(
     Ok(scores)
 }
 
+/// Finds the index of the frame that vspipe failed to retrieve in its error
+/// output, if any
+pub(crate) fn parse_failed_frame(output: &str) -> Option<usize> {
+    const PREFIX: &str = "Failed to retrieve frame ";
+
+    let (_, rest) = output.split_once(PREFIX)?;
+    let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    rest.get(..digits)?.parse().ok()
+}
+
+/// Splits a vspipe argument of the form `key=value`. The value may contain
+/// further `=` characters.
+pub(crate) fn parse_vspipe_arg(arg: &str) -> anyhow::Result<(&str, &str)> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key, value)),
+        _ => bail!("Invalid vspipe argument {arg:?}, expected the form key=value"),
+    }
+}
+
+/// Result of decoding a sample of the source with one chunk method
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkMethodBenchmark {
+    pub chunk_method:  ChunkMethod,
+    /// Time to index the source and load the script
+    pub index_time:    Duration,
+    /// Frames decoded per second when reading the sample sequentially
+    pub decode_fps:    f64,
+    pub frames:        usize,
+    /// Whether seeking to frames out of order returned the same frames as
+    /// sequential decoding
+    pub seek_accurate: bool,
+}
+
+/// Decodes `sample_frames` frames from the middle of `source` with
+/// `chunk_method`, then fetches a few of them again out of order from a fresh
+/// decoder to check that seeking lands on the right frames.
+#[inline]
+pub fn benchmark_chunk_method(
+    temp: &str,
+    source: &Path,
+    chunk_method: ChunkMethod,
+    sample_frames: usize,
+) -> anyhow::Result<ChunkMethodBenchmark> {
+    const SEEK_POINTS: usize = 8;
+
+    fn hash_frame(frame: &Frame) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for row in 0..frame.height(0) {
+            hasher.write(frame.data_row(0, row));
+        }
+        hasher.finish()
+    }
+
+    let index_start = Instant::now();
+    let (script, _) = create_vs_file(&LoadscriptArgs {
+        temp,
+        source,
+        chunk_method,
+        is_proxy: false,
+        cache_mode: CacheSource::TEMP,
+    })?;
+    let load = || -> anyhow::Result<Environment> {
+        let mut environment = Environment::new()?;
+        environment.eval_file(&script, EvalFlags::SetWorkingDir)?;
+        Ok(environment)
+    };
+
+    let environment = load()?;
+    let (node, _) = environment.get_output(0)?;
+    let num_frames = get_num_frames(&node.info())?;
+    let index_time = index_start.elapsed();
+
+    let start = num_frames.saturating_sub(sample_frames) / 2;
+    let end = (start + sample_frames).min(num_frames);
+    let decode_start = Instant::now();
+    let hashes = (start..end)
+        .map(|n| Ok(hash_frame(&*node.get_frame(n)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let decode_fps = hashes.len() as f64 / decode_start.elapsed().as_secs_f64();
+    drop(node);
+    drop(environment);
+
+    let environment = load()?;
+    let (node, _) = environment.get_output(0)?;
+    let step = (hashes.len() / SEEK_POINTS).max(1);
+    let mut seek_accurate = true;
+    for (offset, hash) in hashes.iter().enumerate().step_by(step).rev() {
+        if hash_frame(&*node.get_frame(start + offset)?) != *hash {
+            seek_accurate = false;
+            break;
+        }
+    }
+
+    Ok(ChunkMethodBenchmark {
+        chunk_method,
+        index_time,
+        decode_fps,
+        frames: hashes.len(),
+        seek_accurate,
+    })
+}
+
+/// Picks the fastest chunk method that seeked accurately
+#[inline]
+pub fn recommend_chunk_method(results: &[ChunkMethodBenchmark]) -> Option<ChunkMethod> {
+    results
+        .iter()
+        .filter(|result| result.seek_accurate)
+        .max_by(|a, b| a.decode_fps.total_cmp(&b.decode_fps))
+        .map(|result| result.chunk_method)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1025,4 +1326,68 @@ mod tests {
         assert_eq!(map_vapoursynth_color_range(1), Some(ColorRange::Limited));
         assert_eq!(map_vapoursynth_color_range(2), None);
     }
+
+    #[test]
+    fn parse_vspipe_arg_key_value() {
+        assert_eq!(parse_vspipe_arg("blur=10").ok(), Some(("blur", "10")));
+        assert_eq!(parse_vspipe_arg("expr=x=y").ok(), Some(("expr", "x=y")));
+        assert_eq!(parse_vspipe_arg("empty=").ok(), Some(("empty", "")));
+        assert!(parse_vspipe_arg("blur").is_err());
+        assert!(parse_vspipe_arg("=10").is_err());
+    }
+
+    #[test]
+    fn vship_streams_are_limited() {
+        let limited = VshipStreams::acquire(Some(2));
+        assert_eq!(limited.0, 2);
+        drop(limited);
+
+        let unlimited = VshipStreams::acquire(None);
+        assert_eq!(unlimited.0, VSHIP_STREAMS);
+        drop(unlimited);
+        assert_eq!(
+            *VSHIP_STREAMS_IN_USE.lock().expect("mutex should acquire lock"),
+            0
+        );
+    }
+
+    #[test]
+    fn parse_failed_frame_from_vspipe_error() {
+        assert_eq!(
+            parse_failed_frame(
+                "Script evaluation done in 0.25 seconds\nError: Failed to retrieve frame 1234 \
+                 with error: Decoding failed"
+            ),
+            Some(1234)
+        );
+        assert_eq!(
+            parse_failed_frame("Output 240 frames in 2.10 seconds"),
+            None
+        );
+        assert_eq!(parse_failed_frame("Failed to retrieve frame "), None);
+    }
+
+    #[test]
+    fn recommend_fastest_accurate_chunk_method() {
+        let result = |chunk_method, decode_fps, seek_accurate| ChunkMethodBenchmark {
+            chunk_method,
+            index_time: Duration::ZERO,
+            decode_fps,
+            frames: 100,
+            seek_accurate,
+        };
+
+        assert_eq!(
+            recommend_chunk_method(&[
+                result(ChunkMethod::BESTSOURCE, 80.0, true),
+                result(ChunkMethod::FFMS2, 200.0, false),
+                result(ChunkMethod::LSMASH, 120.0, true),
+            ]),
+            Some(ChunkMethod::LSMASH)
+        );
+        assert_eq!(
+            recommend_chunk_method(&[result(ChunkMethod::FFMS2, 200.0, false)]),
+            None
+        );
+    }
 }
@@ -0,0 +1,148 @@
+//! Rewriting of the y4m stream header sent to the encoder. vspipe always
+//! writes `Ip A0:0` and no color range, so the aspect ratio, interlacing and
+//! range of the source are lost unless they are put back into the header.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::{settings::EncodeArgs, ClipInfo, ColorRange, Interlacing};
+
+/// Values written to the y4m header instead of the ones of the source pipe
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct HeaderOverrides {
+    pub sample_aspect_ratio: Option<(u32, u32)>,
+    pub interlacing:         Option<Interlacing>,
+    pub color_range:         Option<ColorRange>,
+}
+
+impl HeaderOverrides {
+    /// Takes the explicit overrides of `args`, falling back to the aspect
+    /// ratio and range of the source when they differ from the y4m defaults
+    pub(crate) fn new(args: &EncodeArgs, clip_info: &ClipInfo) -> Self {
+        Self {
+            sample_aspect_ratio: args
+                .sar
+                .or_else(|| clip_info.sample_aspect_ratio.filter(|&sar| sar != (1, 1))),
+            interlacing:         args.interlacing,
+            color_range:         args
+                .color_range
+                .or_else(|| clip_info.color_range.filter(|&range| range == ColorRange::Full)),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Replaces or appends the overridden tags of a y4m stream header
+    pub(crate) fn apply(&self, header: &str) -> String {
+        let sar = self.sample_aspect_ratio.map(|(num, den)| format!("A{num}:{den}"));
+        let interlacing = self.interlacing.map(|interlacing| {
+            match interlacing {
+                Interlacing::Progressive => "Ip",
+                Interlacing::TopFieldFirst => "It",
+                Interlacing::BottomFieldFirst => "Ib",
+                Interlacing::Mixed => "Im",
+            }
+            .to_string()
+        });
+        let color_range = self.color_range.map(|range| {
+            match range {
+                ColorRange::Full => "XCOLORRANGE=FULL",
+                ColorRange::Limited => "XCOLORRANGE=LIMITED",
+            }
+            .to_string()
+        });
+
+        let mut tags = header.split(' ').map(str::to_string).collect::<Vec<_>>();
+        for (prefix, tag) in [("A", sar), ("I", interlacing), ("XCOLORRANGE=", color_range)] {
+            let Some(tag) = tag else {
+                continue;
+            };
+            // The first tag is the stream magic
+            match tags.iter_mut().skip(1).find(|existing| existing.starts_with(prefix)) {
+                Some(existing) => *existing = tag,
+                None => tags.push(tag),
+            }
+        }
+
+        tags.join(" ")
+    }
+}
+
+/// Copies a y4m stream from `source` to `sink`, rewriting its header with
+/// `overrides`
+pub(crate) fn copy_with_header(
+    source: impl Read,
+    mut sink: impl Write,
+    overrides: &HeaderOverrides,
+) -> io::Result<u64> {
+    let mut reader = BufReader::new(source);
+    let mut header = Vec::with_capacity(128);
+    if reader.read_until(b'\n', &mut header)? == 0 {
+        return Ok(0);
+    }
+
+    let header = String::from_utf8_lossy(&header);
+    let header = overrides.apply(header.trim_end_matches('\n'));
+    writeln!(sink, "{header}")?;
+
+    io::copy(&mut reader, &mut sink)
+}
+
+/// Display dimensions of a `resolution` with non-square samples, used to
+/// signal the aspect ratio in the merged output
+pub(crate) fn display_dimensions(
+    (width, height): (u32, u32),
+    sample_aspect_ratio: Option<(u32, u32)>,
+) -> Option<(u32, u32)> {
+    let (num, den) = sample_aspect_ratio.filter(|&(num, den)| num != den && den != 0)?;
+    let width = (u64::from(width) * u64::from(num) + u64::from(den) / 2) / u64::from(den);
+    Some((width as u32, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy_with_header, display_dimensions, HeaderOverrides};
+    use crate::{ColorRange, Interlacing};
+
+    #[test]
+    fn header_overrides_replace_and_append_tags() {
+        let overrides = HeaderOverrides {
+            sample_aspect_ratio: Some((32, 27)),
+            interlacing:         Some(Interlacing::TopFieldFirst),
+            color_range:         Some(ColorRange::Full),
+        };
+        assert_eq!(
+            overrides.apply("YUV4MPEG2 C420p10 W720 H480 F30000:1001 Ip A0:0 XLENGTH=100"),
+            "YUV4MPEG2 C420p10 W720 H480 F30000:1001 It A32:27 XLENGTH=100 XCOLORRANGE=FULL"
+        );
+        assert_eq!(
+            overrides.apply("YUV4MPEG2 W720 H480 F25:1 XCOLORRANGE=LIMITED"),
+            "YUV4MPEG2 W720 H480 F25:1 XCOLORRANGE=FULL A32:27 It"
+        );
+        assert!(HeaderOverrides::default().is_empty());
+
+        let mut output = Vec::new();
+        let copied = copy_with_header(
+            &b"YUV4MPEG2 W2 H2 Ip A0:0\nFRAME\n\x10\x10\x10\x10\x80\x80"[..],
+            &mut output,
+            &overrides,
+        )
+        .expect("copy should succeed");
+        assert_eq!(copied, 12);
+        assert_eq!(
+            output,
+            b"YUV4MPEG2 W2 H2 It A32:27 XCOLORRANGE=FULL\nFRAME\n\x10\x10\x10\x10\x80\x80"
+        );
+    }
+
+    #[test]
+    fn display_dimensions_stretch_width() {
+        assert_eq!(
+            display_dimensions((720, 480), Some((32, 27))),
+            Some((853, 480))
+        );
+        assert_eq!(display_dimensions((1920, 1080), Some((1, 1))), None);
+        assert_eq!(display_dimensions((1920, 1080), None), None);
+    }
+}
@@ -3,9 +3,10 @@ use std::fs;
 use anyhow::bail;
 
 use crate::{
-    metrics::vmaf::validate_libvmaf,
+    metrics::{plugin::validate_scorer, vmaf::validate_libvmaf},
     scenes::Scene,
     EncodeArgs,
+    Encoder,
     TargetMetric,
     TargetQuality,
 };
@@ -82,5 +83,36 @@ pub(crate) fn validate_zones(args: &EncodeArgs, zones: &[Scene]) -> anyhow::Resu
         args.validate_xpsnr(TargetMetric::XPSNR, 1)?;
     }
 
+    for zone in zones {
+        if let Some(tq) = zone.zone_overrides.as_ref().and_then(|ovr| ovr.target_quality.as_ref())
+            && tq.target.is_some()
+            && tq.metric == TargetMetric::Plugin
+        {
+            validate_scorer(tq.metric_plugin.as_deref())?;
+        }
+    }
+
+    if zones.iter().filter_map(|zone| zone.zone_overrides.as_ref()).any(|ovr| {
+        ovr.encoder == Encoder::svt_av1
+            && ovr.target_quality.as_ref().is_some_and(|tq| tq.q_bracket.is_some())
+    }) {
+        bail!(
+            "--q-bracket is not supported by svt-av1, which does not limit the quantizers of its \
+             CRF mode"
+        );
+    }
+
+    if args.hdr10plus.is_some()
+        && let Some(ovr) = zones
+            .iter()
+            .filter_map(|zone| zone.zone_overrides.as_ref())
+            .find(|ovr| !matches!(ovr.encoder, Encoder::x265 | Encoder::svt_av1))
+    {
+        bail!(
+            "--hdr10plus is only supported by x265 and svt-av1, but a zone uses {encoder}",
+            encoder = ovr.encoder
+        );
+    }
+
     Ok(())
 }
@@ -0,0 +1,107 @@
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+
+/// Environment variable with the path of the defaults file, overriding the
+/// default location
+const DEFAULTS_ENV: &str = "AV1AN_DEFAULTS";
+
+/// Flag that skips the defaults file, which has to be found before the
+/// arguments are parsed
+const NO_DEFAULTS_FILE_FLAG: &str = "--no-defaults-file";
+
+/// Returns the command line arguments with the arguments from the user's
+/// defaults file inserted before them, so that the command line overrides
+/// them. The defaults file is skipped with `--no-defaults-file`.
+pub fn args_with_defaults() -> anyhow::Result<Vec<OsString>> {
+    let mut args = env::args_os().collect::<Vec<_>>();
+    if args.iter().skip(1).any(|arg| arg == NO_DEFAULTS_FILE_FLAG) {
+        return Ok(args);
+    }
+    let Some(path) = defaults_path() else {
+        return Ok(args);
+    };
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        // Only a file given with the environment variable has to exist
+        Err(e) if e.kind() == io::ErrorKind::NotFound && env::var_os(DEFAULTS_ENV).is_none() => {
+            return Ok(args);
+        },
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read the defaults file {}", path.display()));
+        },
+    };
+    let defaults = parse_defaults(&contents)
+        .with_context(|| format!("Invalid defaults file {}", path.display()))?;
+
+    let program = args.remove(0);
+    Ok(std::iter::once(program)
+        .chain(defaults.into_iter().map(OsString::from))
+        .chain(args)
+        .collect())
+}
+
+/// Location of the defaults file, either from `AV1AN_DEFAULTS` or in the
+/// user's config directory
+fn defaults_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(DEFAULTS_ENV) {
+        return Some(PathBuf::from(path));
+    }
+
+    let config_dir = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| Some(Path::new(&env::var_os("HOME")?).join(".config")))?
+    };
+    Some(config_dir.join("av1an").join("defaults"))
+}
+
+/// Splits the defaults file into arguments like a shell would, where a word
+/// starting with `#` starts a comment
+fn parse_defaults(contents: &str) -> anyhow::Result<Vec<String>> {
+    let mut defaults = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        defaults
+            .extend(shlex::split(line).ok_or_else(|| anyhow!("Unbalanced quotes in {line:?}"))?);
+    }
+
+    Ok(defaults)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_defaults;
+
+    #[test]
+    fn defaults_are_split_like_a_shell() {
+        let defaults = parse_defaults(
+            "# encoder defaults\n-e svt-av1\n  --video-params \"--preset 4 --crf \
+             30\"\n\n--workers 4 # trailing\n",
+        )
+        .expect("defaults should parse");
+
+        assert_eq!(defaults, [
+            "-e",
+            "svt-av1",
+            "--video-params",
+            "--preset 4 --crf 30",
+            "--workers",
+            "4"
+        ]);
+        assert!(parse_defaults("--video-params \"--preset 4").is_err());
+    }
+}
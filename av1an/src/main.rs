@@ -1,36 +1,65 @@
 use std::{
+    collections::HashSet,
+    env,
     fmt::Write as FmtWrite,
+    fs,
     io::{self, Write as IoWrite},
     panic,
     path::{Path, PathBuf},
     process::{self, exit},
-    thread::available_parallelism,
+    thread::{self, available_parallelism},
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, bail, ensure, Context};
 use av1an_core::{
-    ffmpeg::FFPixelFormat,
+    determine_workers,
+    executable_search_path,
+    ffmpeg::{FFPixelFormat, Passthrough},
     hash_path,
     into_vec,
+    kill_children,
+    merge_video_params,
     read_in_dir,
-    vapoursynth::{get_vapoursynth_plugins, CacheSource, VSZipVersion},
+    read_vmaf_file,
+    vapoursynth::{
+        benchmark_chunk_method,
+        get_vapoursynth_plugins,
+        recommend_chunk_method,
+        CacheSource,
+        VSZipVersion,
+    },
     Av1anContext,
     ChunkMethod,
     ChunkOrdering,
+    ColorRange,
     ConcatMethod,
+    DecodeErrorPolicy,
+    DeviceTarget,
     EncodeArgs,
     Encoder,
+    Hdr10PlusSource,
+    IgnoreRange,
     Input,
     InputPixelFormat,
+    Interlacing,
     InterpolationMethod,
+    KeepPolicy,
+    OverwritePolicy,
     PixelFormat,
     PixelFormatConverter,
+    ProcessPriority,
     ScenecutMethod,
+    ScenecutPqMode,
+    ScenesFormat,
     SplitMethod,
+    StatsFormat,
     TargetMetric,
     TargetQuality,
+    TestPattern,
     Verbosity,
     VmafFeature,
+    PROGRESS_SCHEMA,
 };
 use clap::{value_parser, CommandFactory, Parser};
 use clap_complete::generate;
@@ -39,8 +68,12 @@ use once_cell::sync::OnceCell;
 use path_abs::{PathAbs, PathInfo};
 use tracing::{instrument, level_filters::LevelFilter, warn};
 
-use crate::logging::{init_logging, DEFAULT_LOG_LEVEL};
+use crate::{
+    defaults::args_with_defaults,
+    logging::{init_logging, DEFAULT_LOG_LEVEL},
+};
 
+mod defaults;
 mod logging;
 
 fn main() -> anyhow::Result<()> {
@@ -48,6 +81,8 @@ fn main() -> anyhow::Result<()> {
     // Catch panics in child threads
     panic::set_hook(Box::new(move |panic_info| {
         orig_hook(panic_info);
+        // Exiting skips the destructors that would wait for the encoders
+        kill_children();
         process::exit(1);
     }));
     run()
@@ -99,13 +134,21 @@ fn version() -> &'static str {
   rav1e   : {}
   x264    : {}
   x265    : {}
-  vpxenc  : {}",
+  vpxenc  : {}
+  vvencapp: {}
+  NVENC   : {}
+  QSV     : {}
+  VA-API  : {}",
             Encoder::aom.version_text().as_deref().unwrap_or("Not found"),
             Encoder::svt_av1.version_text().as_deref().unwrap_or("Not found"),
             Encoder::rav1e.version_text().as_deref().unwrap_or("Not found"),
             Encoder::x264.version_text().as_deref().unwrap_or("Not found"),
             Encoder::x265.version_text().as_deref().unwrap_or("Not found"),
-            Encoder::vpx.version_text().as_deref().unwrap_or("Not found")
+            Encoder::vpx.version_text().as_deref().unwrap_or("Not found"),
+            Encoder::vvenc.version_text().as_deref().unwrap_or("Not found"),
+            Encoder::nvenc.version_text().as_deref().unwrap_or("Not found"),
+            Encoder::qsv.version_text().as_deref().unwrap_or("Not found"),
+            Encoder::vaapi.version_text().as_deref().unwrap_or("Not found")
         )
     }
 
@@ -176,7 +219,7 @@ fn version() -> &'static str {
 /// Cross-platform command-line AV1 / VP9 / HEVC / H264 encoding framework with
 /// per-scene quality encoding
 #[derive(Parser, Debug)]
-#[clap(name = "av1an", version = version())]
+#[clap(name = "av1an", version = version(), args_override_self = true)]
 pub struct CliOpts {
     /// Input file to encode
     ///
@@ -194,6 +237,15 @@ pub struct CliOpts {
     #[clap(long)]
     pub proxy: Vec<PathBuf>,
 
+    /// Encode a generated test pattern instead of an input file
+    ///
+    /// Format is PATTERN[:WIDTHxHEIGHT[:FRAMES]], where PATTERN is one of
+    /// bars, noise, gradient or moving-box. The pattern is generated by
+    /// VapourSynth as 10-bit 4:2:0 video at 24 fps, by default 1280x720 with
+    /// 240 frames.
+    #[clap(long, conflicts_with = "input", value_name = "PATTERN")]
+    pub test_pattern: Option<TestPattern>,
+
     /// Video output file
     #[clap(short)]
     pub output_file: Option<PathBuf>,
@@ -213,6 +265,13 @@ pub struct CliOpts {
     #[clap(long)]
     pub verbose: bool,
 
+    /// Minimum time in milliseconds between progress updates from each worker
+    ///
+    /// Encoder progress is coalesced per worker so fast encodes do not flood
+    /// the progress display. The final update of each pass is always sent.
+    #[clap(long, default_value_t = 100)]
+    pub progress_interval: u64,
+
     /// Log file location
     ///
     /// If not specified, the log file location will be `./logs/av1an.log` and
@@ -240,19 +299,60 @@ pub struct CliOpts {
     #[clap(long, conflicts_with = "input", value_name = "SHELL")]
     pub completions: Option<clap_complete::Shell>,
 
+    /// Print the JSON schema of the progress.json file written to the
+    /// temporary folder and exit
+    #[clap(long, conflicts_with = "input")]
+    pub progress_schema: bool,
+
+    /// Benchmark each available chunk method on the input and exit
+    ///
+    /// Decodes a sample of frames through every installed VapourSynth source
+    /// plugin (bestsource, lsmash, ffms2, dgdecnv), reports the indexing time,
+    /// decode speed and whether seeking returns the correct frames, and
+    /// recommends the fastest accurate method for this input.
+    #[clap(long)]
+    pub bench_input: bool,
+
     /// Resume previous session from temporary directory
     #[clap(short, long)]
     pub resume: bool,
 
     /// Do not delete the temporary folder after encoding has finished
-    #[clap(short, long)]
+    ///
+    /// Same as --keep-temp always.
+    #[clap(short, long, conflicts_with = "keep_temp")]
     pub keep: bool,
 
+    /// When to keep the temporary folder, with the split scenes, probes and
+    /// encoded chunks
+    ///
+    /// always - Never delete the temporary folder.
+    ///
+    /// on-failure - Delete the temporary folder after a successful encode, and
+    /// keep it if the encode fails so it can be resumed.
+    ///
+    /// never - Delete the temporary folder even if the encode fails.
+    ///
+    /// The size of the temporary folder is logged before it is deleted.
+    #[clap(long, default_value_t = KeepPolicy::OnFailure)]
+    pub keep_temp: KeepPolicy,
+
     /// Do not check if the encoder arguments specified by -v/--video-params are
     /// valid.
     #[clap(long)]
     pub force: bool,
 
+    /// Use the temporary folder even if another instance of Av1an holds its
+    /// lock
+    ///
+    /// Av1an locks the temporary folder while encoding, and refuses to start
+    /// if another instance is using it, as two encodes sharing a temporary
+    /// folder overwrite each other's chunks. The lock is released when Av1an
+    /// exits, even after a crash, so this is only needed on file systems
+    /// where locks outlive the process, such as some network shares.
+    #[clap(long)]
+    pub force_lock: bool,
+
     /// Do not include Av1an's default set of encoder parameters.
     #[clap(long)]
     pub no_defaults: bool,
@@ -265,10 +365,29 @@ pub struct CliOpts {
     #[clap(short = 'n', conflicts_with = "overwrite")]
     pub never_overwrite: bool,
 
+    /// Rename an existing output file to a backup instead of overwriting it,
+    /// without confirmation
+    ///
+    /// The existing file is renamed to `<output>.bak`, or `<output>.N.bak` if
+    /// that exists too, right before the new output is written.
+    #[clap(long, conflicts_with_all = ["overwrite", "never_overwrite"])]
+    pub backup: bool,
+
     /// Maximum number of chunk restarts for an encode
     #[clap(long, default_value_t = 3, value_parser = value_parser!(u32).range(1..))]
     pub max_tries: u32,
 
+    /// Stop starting new chunks once this much time has passed since the
+    /// encode started
+    ///
+    /// Takes a time in seconds or with an `s`, `m` or `h` suffix, e.g. "8h".
+    /// Chunks that are already encoding are allowed to finish, after which
+    /// Av1an exits with status code 3 if chunks remain. The temporary folder is
+    /// kept so the encode can be continued later with --resume. Useful for
+    /// encoding in fixed time windows or on preemptible machines.
+    #[clap(long, value_parser = parse_time_budget)]
+    pub time_budget: Option<Duration>,
+
     /// Number of workers to spawn [0 = automatic]
     #[clap(short, long, default_value_t = 0)]
     pub workers: usize,
@@ -282,6 +401,21 @@ pub struct CliOpts {
     #[clap(long)]
     pub set_thread_affinity: Option<usize>,
 
+    /// Scheduling priority of the encoders and source pipes of chunks and
+    /// target quality probes
+    ///
+    /// normal - Same priority as Av1an.
+    ///
+    /// low - Nice 10 on Unix, below normal priority class on Windows.
+    ///
+    /// idle - Nice 19 on Unix, idle priority class on Windows. Only uses CPU
+    /// time that nothing else needs.
+    ///
+    /// Lowering the priority keeps the machine responsive while encoding.
+    /// Processes never get a higher priority than Av1an itself.
+    #[clap(long, default_value_t = ProcessPriority::Normal)]
+    pub priority: ProcessPriority,
+
     /// Scaler used for scene detection (if --sc-downscale-height XXXX is used)
     /// and VMAF calculation
     ///
@@ -295,6 +429,28 @@ pub struct CliOpts {
     #[clap(long, num_args(0..))]
     pub vspipe_args: Vec<String>,
 
+    /// Pass python argument(s) to the script environment of the proxy, instead
+    /// of the ones given with --vspipe-args
+    #[clap(long, num_args(0..))]
+    pub proxy_vspipe_args: Option<Vec<String>>,
+
+    /// Additional directories to search for encoders and tools, before PATH
+    ///
+    /// After PATH, common install locations such as `C:\Program
+    /// Files\SVT-AV1` or `/usr/local/bin` are also searched. Can be given
+    /// multiple times.
+    #[clap(long)]
+    pub bin_path: Vec<PathBuf>,
+
+    /// Ignores the user's defaults file
+    ///
+    /// Arguments in `$XDG_CONFIG_HOME/av1an/defaults` (`~/.config` if unset,
+    /// `%APPDATA%` on Windows), or in the file set with the `AV1AN_DEFAULTS`
+    /// environment variable, are read before the command line, which
+    /// overrides them.
+    #[clap(long)]
+    pub no_defaults_file: bool,
+
     /// File location for scenes
     #[clap(short, long, help_heading = "Scene Detection")]
     pub scenes: Option<PathBuf>,
@@ -311,6 +467,15 @@ pub struct CliOpts {
     /// are the start of new scenes, while "none" disables scene detection
     /// entirely (and only relies on -x/--extra-split to
     /// add extra scenecuts).
+    ///
+    /// "ffmpeg" cuts where the scene score of FFmpeg's select filter exceeds
+    /// --sc-threshold, and "pyscenedetect" imports the scenes detected by
+    /// PySceneDetect from --sc-import.
+    ///
+    /// "keyframes" cuts at the existing keyframes of an already encoded input,
+    /// so that scenes can be trimmed from it without reencoding.
+    ///
+    /// "import" reads the scenes from --sc-import in --scenes-format.
     #[clap(long, default_value_t = SplitMethod::AvScenechange, help_heading = "Scene Detection")]
     pub split_method: SplitMethod,
 
@@ -339,26 +504,113 @@ pub struct CliOpts {
     #[clap(long, help_heading = "Scene Detection")]
     pub sc_pix_format: Option<FFPixelFormat>,
 
+    /// Only decode the luma plane for scene detection
+    ///
+    /// Scene detection only looks at the luma plane, so skipping the chroma
+    /// planes reduces the amount of data decoded and copied, which is
+    /// noticeably faster for high bit depth sources. With VapourSynth inputs,
+    /// the luma plane is kept at its original bit depth. With FFmpeg, high bit
+    /// depth inputs are converted to 12-bit grayscale.
+    #[clap(
+        long,
+        conflicts_with = "sc_pix_format",
+        help_heading = "Scene Detection"
+    )]
+    pub sc_luma_only: bool,
+
+    /// How scene detection handles HDR inputs with the PQ transfer function
+    ///
+    /// The scene detection thresholds are tuned for SDR content, so they miss
+    /// cuts between dark scenes and place extra cuts in bright scenes of PQ
+    /// content.
+    ///
+    /// tonemap - Tone map the luma plane of PQ inputs to SDR before detection.
+    /// Only affects scene detection, not the encoded frames.
+    ///
+    /// none - Detect on the PQ signal as it is.
+    #[clap(long, default_value_t = ScenecutPqMode::Tonemap, help_heading = "Scene Detection")]
+    pub sc_pq_mode: ScenecutPqMode,
+
+    /// Scene score above which the ffmpeg split method places a scenecut
+    ///
+    /// FFmpeg scores each frame from 0 (identical to the previous frame) to 1
+    /// (entirely different). Lower values place more scenecuts.
+    #[clap(long, default_value_t = 0.4, help_heading = "Scene Detection")]
+    pub sc_threshold: f64,
+
+    /// Scene list to read the scenes from
+    ///
+    /// Used by the pyscenedetect split method, with the CSV of "list-scenes"
+    /// or the EDL of "save-edl" written by PySceneDetect, and by the import
+    /// split method, in --scenes-format.
+    #[clap(long, help_heading = "Scene Detection")]
+    pub sc_import: Option<PathBuf>,
+
+    /// Write the scenes to this file after scene detection, in --scenes-format
+    ///
+    /// Unlike --scenes, the file is only written, so it can be in a format
+    /// read by other tools.
+    #[clap(long, help_heading = "Scene Detection")]
+    pub export_scenes: Option<PathBuf>,
+
+    /// Format of --export-scenes and of --sc-import with the import split
+    /// method
+    ///
+    /// json - Av1an's scenes file, as written to --scenes.
+    ///
+    /// qpfile - x264 and x265 --qpfile, with a keyframe line for each scene.
+    ///
+    /// keyframes - Comma-separated first frames of the scenes, as taken by
+    /// --force-keyframes.
+    ///
+    /// chapters - Matroska XML chapters with a chapter for each scene, for
+    /// chapter-based editors and mkvmerge --chapters.
+    #[clap(long, default_value_t = ScenesFormat::Json, help_heading = "Scene Detection")]
+    pub scenes_format: ScenesFormat,
+
     /// Maximum scene length
     ///
     /// When a scenecut is found whose distance to the previous scenecut is
     /// greater than the value specified by this option, one or more extra
     /// splits (scenecuts) are added. Set this option to 0 to disable adding
     /// extra splits.
+    ///
+    /// If neither this option nor --extra-split-sec is specified, the maximum
+    /// scene length is 10 seconds, lowered to the keyframe interval set in the
+    /// video params if that is shorter.
     #[clap(short = 'x', long, help_heading = "Scene Detection")]
     pub extra_split: Option<usize>,
 
-    /// Maximum scene length, in seconds
+    /// Maximum scene length, in seconds (Default: 10)
     ///
     /// If both frames and seconds are specified, then the number of frames will
     /// take priority.
-    #[clap(long, default_value_t = 10.0, help_heading = "Scene Detection")]
-    pub extra_split_sec: f64,
+    #[clap(long, help_heading = "Scene Detection")]
+    pub extra_split_sec: Option<f64>,
 
     /// Minimum number of frames for a scenecut
     #[clap(long, default_value_t = 24, help_heading = "Scene Detection")]
     pub min_scene_len: usize,
 
+    /// Split the longest scenes in half until there is a scene for every
+    /// worker
+    ///
+    /// Without this option, an input with fewer scenes than workers is
+    /// encoded with fewer workers. Scenes are not split below twice the
+    /// minimum scene length, and zones that disable extra splits are kept
+    /// whole.
+    #[clap(long, help_heading = "Scene Detection")]
+    pub split_for_workers: bool,
+
+    /// Folder in which detected scenes are kept for later encodes
+    ///
+    /// The scenes are stored under a name derived from the path, size and
+    /// modification time of the input, the zones file, and every setting
+    /// that affects the scenes. Later encodes of the same input with the same
+    /// settings reuse the scenes instead of running scene detection again.
+    #[clap(long, help_heading = "Scene Detection")]
+    pub sc_cache: Option<PathBuf>,
+
     /// Comma-separated list of frames to force as keyframes
     ///
     /// Can be useful for improving seeking with chapters, etc.
@@ -367,6 +619,48 @@ pub struct CliOpts {
     #[clap(long, help_heading = "Scene Detection")]
     pub force_keyframes: Option<String>,
 
+    /// Comma-separated ranges in which no scene cuts are placed
+    ///
+    /// Each range is START-END, with END exclusive. Positions are frame
+    /// numbers, or times if they end in "s", "m" or "h", e.g. "90s-3m".
+    /// Useful for openings and credits with flashing that would otherwise be
+    /// split into many short scenes.
+    #[clap(long, help_heading = "Scene Detection")]
+    pub sc_ignore_ranges: Option<String>,
+
+    /// Comma-separated ranges that are each encoded as a single scene
+    ///
+    /// Same format as --sc-ignore-ranges, but scenes are also cut at the start
+    /// and end of each range.
+    #[clap(long, help_heading = "Scene Detection")]
+    pub sc_single_scene_ranges: Option<String>,
+
+    /// Detect black and frozen sections of the input
+    ///
+    /// Runs an extra FFmpeg pass over the input after scene detection. The
+    /// sections are stored in the scenes file, and scenes that are entirely
+    /// black or frozen are logged. Not supported with VapourSynth scripts.
+    #[clap(long, help_heading = "Scene Detection")]
+    pub sc_annotate: bool,
+
+    /// Detect fades and place scene cuts at their ends
+    ///
+    /// Runs an extra FFmpeg pass over the input that finds fades from their
+    /// gradual change of luma. Cuts inside a fade are moved to its end, and the
+    /// fade is cut at its start if that does not leave a scene shorter than
+    /// --min-scene-len. The fades are stored in the scenes file. Not supported
+    /// with VapourSynth scripts.
+    #[clap(long, help_heading = "Scene Detection")]
+    pub sc_fades: bool,
+
+    /// Zone applied to every scene that contains a fade
+    ///
+    /// Uses the syntax of a line of the zones file without the start and end
+    /// frames, e.g. "svt-av1 --enable-tf 0". Scenes that are already in a zone
+    /// keep their zone.
+    #[clap(long, requires("sc_fades"), help_heading = "Scene Detection")]
+    pub sc_fade_zone: Option<String>,
+
     /// Video encoder to use
     #[clap(short, long, default_value_t = Encoder::svt_av1, help_heading = "Encoding")]
     pub encoder: Encoder,
@@ -382,6 +676,35 @@ pub struct CliOpts {
     #[clap(short, long, allow_hyphen_values = true, help_heading = "Encoding")]
     pub video_params: Option<String>,
 
+    /// File with encoder parameters that can be edited while encoding
+    ///
+    /// Whenever the file changes, its parameters are validated and merged into
+    /// the video parameters of every chunk that has not started yet, replacing
+    /// any parameter that is set again. The contents of the file when encoding
+    /// starts are not applied. The parameter generation each chunk was
+    /// encoded with is recorded in the log file. Not supported with
+    /// --target-quality.
+    #[clap(long, help_heading = "Encoding")]
+    pub live_params: Option<PathBuf>,
+
+    /// Pause encoding while this file exists
+    ///
+    /// No new chunks are started while the file exists, and encoding continues
+    /// once it is removed. Chunks that are already encoding are finished.
+    #[clap(long, help_heading = "Encoding")]
+    pub pause_file: Option<PathBuf>,
+
+    /// Percentage of the system memory in use above which no new chunks are
+    /// started
+    ///
+    /// Before a chunk starts, the memory in use by the system plus the memory
+    /// of the largest chunk seen so far must stay below this percentage of
+    /// the total memory, otherwise the worker waits until enough memory is
+    /// free. Useful with VapourSynth filtering, whose memory use is hard to
+    /// predict. A chunk always starts when no other chunk is encoding.
+    #[clap(long, help_heading = "Encoding", value_parser = value_parser!(u8).range(1..=100))]
+    pub memory_ceiling: Option<u8>,
+
     /// Number of encoder passes
     ///
     /// Since aom and vpx benefit from two-pass mode even with constant quality
@@ -392,9 +715,51 @@ pub struct CliOpts {
     /// When using aom or vpx with RT mode (--rt), one-pass mode is always used
     /// regardless of the value specified by this flag (as RT mode in aom
     /// and vpx only supports one-pass encoding).
-    #[clap(short, long, value_parser = value_parser!(u8).range(1..=2), help_heading = "Encoding")]
+    ///
+    /// svt-av1 also supports three-pass encoding, in which the first two
+    /// passes only write stats for the last one.
+    #[clap(short, long, value_parser = value_parser!(u8).range(1..=3), help_heading = "Encoding")]
     pub passes: Option<u8>,
 
+    /// Encoder parameters for a single pass of multi-pass encoding
+    ///
+    /// Takes PASS=PARAMS, and can be given multiple times. PARAMS override the
+    /// matching --video-params in pass PASS only, e.g. `--pass-params
+    /// "1=--preset 8"` runs a faster first pass. Chunks that a zone switches to
+    /// another encoder or to one pass do not use these parameters.
+    #[clap(long, help_heading = "Encoding", value_parser = parse_pass_params)]
+    pub pass_params: Vec<(u8, Vec<String>)>,
+
+    /// Run the first pass over the whole clip instead of each chunk
+    /// (experimental)
+    ///
+    /// The first pass stats of the whole clip are sliced per chunk and used
+    /// for the second pass of every chunk, so that rate control can take the
+    /// rest of the clip into account. Requires two-pass encoding with aom and
+    /// a VapourSynth chunk method or input.
+    #[clap(long, help_heading = "Encoding")]
+    pub whole_clip_first_pass: bool,
+
+    /// Encode only the first frame of every run of identical frames
+    ///
+    /// Duplicate frames are detected by hashing every frame of the input with
+    /// ffmpeg and are dropped before encoding. Their timing is kept with a
+    /// timestamps file when the chunks are merged, so the output plays back
+    /// with variable frame rate. Requires mkvmerge concatenation and a video
+    /// input.
+    #[clap(long, help_heading = "Encoding", conflicts_with = "sample")]
+    pub collapse_duplicates: bool,
+
+    /// Keep the frame timing of variable frame rate inputs
+    ///
+    /// Reads the timestamp of every frame of the input and passes them to
+    /// mkvmerge when the chunks are merged, so that the output keeps the
+    /// timing of the input and stays in sync with its audio. Without this, the
+    /// output has a constant frame rate. Inputs with a constant frame rate are
+    /// left as is. Requires mkvmerge concatenation and a video input.
+    #[clap(long, help_heading = "Encoding", conflicts_with = "sample")]
+    pub keep_vfr: bool,
+
     /// Estimate tile count from source
     ///
     /// Worker estimation will consider tile count accordingly.
@@ -417,7 +782,8 @@ pub struct CliOpts {
     /// Do not use ffmpeg's -map syntax with this option. Instead, use the colon
     /// syntax with each parameter you specify.
     ///
-    /// Subtitles are always copied by default.
+    /// Subtitles, attachments and chapters are copied by default, see
+    /// --subtitle-tracks, --no-attachments and --no-chapters.
     ///
     /// Example to encode all audio tracks with libopus at 128k:
     ///
@@ -431,6 +797,22 @@ pub struct CliOpts {
     #[clap(short, long, allow_hyphen_values = true, help_heading = "Encoding")]
     pub audio_params: Option<String>,
 
+    /// Subtitle tracks of the input to copy to the output
+    ///
+    /// "all" copies every subtitle track, "none" drops them, and a comma
+    /// separated list such as "0,2" only copies those tracks, counted among
+    /// the subtitle tracks of the input from 0. (Default: all)
+    #[clap(long, help_heading = "Encoding")]
+    pub subtitle_tracks: Option<String>,
+
+    /// Do not copy the chapters of the input to the output
+    #[clap(long, help_heading = "Encoding")]
+    pub no_chapters: bool,
+
+    /// Do not copy the attachments of the input, such as fonts, to the output
+    #[clap(long, help_heading = "Encoding")]
+    pub no_attachments: bool,
+
     /// Ignore any detected mismatch between scene frame count and encoder frame
     /// count
     #[clap(long, help_heading = "Encoding")]
@@ -505,6 +887,106 @@ pub struct CliOpts {
     #[clap(long, default_value_t = ChunkOrdering::LongestFirst, help_heading = "Encoding")]
     pub chunk_order: ChunkOrdering,
 
+    /// Only encode a sample of N scenes spread evenly across the video
+    ///
+    /// Scene detection runs on the whole video as usual, but only N evenly
+    /// spaced scenes are encoded and concatenated into the output file, without
+    /// audio. After encoding, the bitrate of the sample and the estimated size
+    /// of a full encode are reported. Useful for quickly comparing encoder
+    /// settings on a representative part of a long video. Not supported with
+    /// the "segment" chunk method.
+    #[clap(long, help_heading = "Encoding", value_parser = value_parser!(u64).range(1..))]
+    pub sample: Option<u64>,
+
+    /// Encode multiple renditions of the input from a single scene detection
+    /// run
+    ///
+    /// Takes NAME=PARAMS, and can be given multiple times. Each rendition is
+    /// encoded with PARAMS overriding the matching --video-params, e.g.
+    /// `--ladder "crf24=--crf 24" --ladder "crf30=--crf 30"`. The output file
+    /// of each rendition gets `_NAME` appended to its file stem, and each
+    /// rendition uses its own subfolder of the temporary folder. Scene
+    /// detection runs once, and its scenes are stored in the --scenes file or
+    /// the temporary folder and reused by all renditions.
+    #[clap(long, help_heading = "Encoding", value_parser = parse_rendition)]
+    pub ladder: Vec<(String, Vec<String>)>,
+
+    /// Encode the renditions of --ladder at the same time and compare them
+    ///
+    /// After scene detection, each rendition is encoded by its own av1an
+    /// process, and the workers (--workers, or the automatic count if 0) are
+    /// split evenly between them. Once all renditions are done, their size,
+    /// bitrate, encoding speed and VMAF score (with --vmaf) are reported side
+    /// by side. The time of each rendition includes its VMAF calculation. The
+    /// renditions run quietly, without progress bars. Only supports a single
+    /// input.
+    #[clap(
+        long,
+        requires = "ladder",
+        conflicts_with = "sc_only",
+        help_heading = "Encoding"
+    )]
+    pub ladder_parallel: bool,
+
+    /// Encode only this rendition of --ladder, used by --ladder-parallel
+    #[clap(long, requires = "ladder", hide = true)]
+    pub ladder_rendition: Option<String>,
+
+    /// Encode only the scene with this index and exit
+    ///
+    /// The scene is encoded with its zone overrides, photon noise and target
+    /// quality even if it is already done, which is useful to debug a scene
+    /// that crashes the encoder. The scenes and chunks of an existing
+    /// temporary folder are reused, and the encoded chunk is left in its
+    /// "encode" folder.
+    #[clap(long, conflicts_with_all = ["sc_only", "sample", "ladder"], help_heading = "Encoding")]
+    pub encode_scene: Option<usize>,
+
+    /// Warn about chunks whose bitrate is more than X times the median
+    ///
+    /// After encoding, the bitrate of every chunk is compared to the median
+    /// chunk bitrate, and chunks above the threshold are listed together with
+    /// their frame count, bitrate and ratio to the median. Useful for finding
+    /// scenes that take up a disproportionate amount of the file size. Must be
+    /// greater than 1.
+    #[clap(long, help_heading = "Encoding")]
+    pub bitrate_spike_threshold: Option<f64>,
+
+    /// Write a heatmap of the scenes next to the output file
+    ///
+    /// After encoding, a timeline of the scenes is written to a .heatmap.svg
+    /// file, with one row colored by bitrate and one by quality, so that the
+    /// scenes with the highest bitrate or the lowest quality stand out in red.
+    /// The quality is the VMAF of the output with --vmaf, or otherwise the
+    /// target quality score or the PSNR reported by the encoder. Hovering a
+    /// scene shows its frames, size, bitrate, quantizer and score.
+    #[clap(long, help_heading = "Encoding")]
+    pub heatmap: bool,
+
+    /// Write the statistics of every scene next to the output file
+    ///
+    /// After encoding, one row per scene with its index, first frame, frame
+    /// count, size, bitrate, quantizer, score, encoding speed and encoder
+    /// retries is written to a .stats.csv or .stats.json file, for
+    /// spreadsheets and plotting. The score is the same as in --heatmap.
+    #[clap(long, help_heading = "Encoding")]
+    pub export_stats: Option<StatsFormat>,
+
+    /// How to handle source frames that fail to decode
+    ///
+    /// fail - The chunk fails and is retried like any other error, aborting
+    /// the encode after --max-tries.
+    ///
+    /// duplicate - The frame is replaced with the closest previous frame that
+    /// decodes.
+    ///
+    /// black - The frame is replaced with a black frame.
+    ///
+    /// Every replaced frame is reported with a warning. Only supported with
+    /// the VapourSynth chunk methods on video inputs.
+    #[clap(long, default_value_t = DecodeErrorPolicy::Fail, help_heading = "Encoding")]
+    pub decode_error: DecodeErrorPolicy,
+
     /// Generates a photon noise table and applies it using grain synthesis
     /// [strength: 0-64] (disabled by default)
     ///
@@ -518,22 +1000,113 @@ pub struct CliOpts {
     /// by specifying the correct parameter to the encoder. However, the two
     /// should not be used together, and specifying this option will disable
     /// the encoder's internal grain synthesis.
-    #[clap(long, help_heading = "Encoding")]
+    #[clap(long, help_heading = "Encoding", group = "photon_noise_source")]
     pub photon_noise: Option<u8>,
 
+    /// Photon noise strengths at specific timestamps, interpolated for each
+    /// chunk in between
+    ///
+    /// Takes a comma separated list of TIME=STRENGTH anchors, where TIME is in
+    /// seconds or has an `s`, `m` or `h` suffix, e.g. "0=4,45m=16". Each chunk
+    /// uses the strength linearly interpolated at its first frame, and the
+    /// first and last anchors apply before and after them respectively. Useful
+    /// for sources whose grain changes over time, such as films with
+    /// differently grained reels. Cannot be used with `--photon-noise`, and
+    /// zones that set their own photon noise take precedence.
+    #[clap(long, help_heading = "Encoding", group = "photon_noise_source")]
+    pub photon_noise_anchors: Option<String>,
+
+    /// Picks the photon noise strength of each scene from the noise measured
+    /// in the input
+    ///
+    /// Runs an extra FFmpeg pass after scene detection that compares each
+    /// frame with a denoised version of it. Scenes with more noise get
+    /// stronger grain, and clean scenes get none. The strengths are stored in
+    /// the scenes file. Cannot be used with `--photon-noise` or
+    /// `--photon-noise-anchors`, and zones that set their own photon noise take
+    /// precedence. Not supported with VapourSynth scripts.
+    #[clap(long, help_heading = "Encoding", group = "photon_noise_source")]
+    pub photon_noise_auto: bool,
+
     /// Adds chroma grain synthesis to the grain table generated by
-    /// `--photon-noise`. (Default: false)
-    #[clap(long, help_heading = "Encoding", requires = "photon_noise")]
+    /// `--photon-noise`, `--photon-noise-anchors` or `--photon-noise-auto`.
+    /// (Default: false)
+    #[clap(long, help_heading = "Encoding", requires = "photon_noise_source")]
     pub chroma_noise: bool,
 
+    /// Photon noise strength of the chroma planes, from 0-64 like
+    /// `--photon-noise`
+    ///
+    /// Instead of scaling the chroma grain like the luma grain, gives the
+    /// chroma planes their own grain table entries at this strength, which is
+    /// usually lower than the luma strength as most film grain is in the
+    /// luma. Applies to every chunk, including zones. 0 disables it. Cannot be
+    /// used with `--chroma-noise`.
+    #[clap(
+        long,
+        help_heading = "Encoding",
+        requires = "photon_noise_source",
+        conflicts_with = "chroma_noise"
+    )]
+    pub chroma_noise_strength: Option<u8>,
+
+    /// Multipliers of the chroma grain strength of the Cb and Cr planes, as
+    /// CB,CR
+    ///
+    /// Each is between 0.0 and 4.0, e.g. "1.0,0.5" halves the grain of the Cr
+    /// plane. Requires `--chroma-noise-strength`. (Default: 1.0,1.0)
+    #[clap(
+        long,
+        help_heading = "Encoding",
+        requires = "chroma_noise_strength",
+        value_parser = parse_chroma_noise_coeffs
+    )]
+    pub chroma_noise_coeffs: Option<(f64, f64)>,
+
+    /// Seed of the photon noise grain
+    ///
+    /// Each chunk's grain is seeded from this seed and the chunk's index, so
+    /// that every scene gets different grain and a run with the same seed
+    /// produces the same grain. Without a seed, every chunk uses the same
+    /// default seed.
+    #[clap(long, help_heading = "Encoding", requires = "photon_noise_source")]
+    pub photon_noise_seed: Option<u16>,
+
     /// Manually set the width for the photon noise table.
-    #[clap(long, help_heading = "Encoding")]
+    #[clap(long, help_heading = "Encoding", value_parser = value_parser!(u32).range(1..))]
     pub photon_noise_width: Option<u32>,
 
     /// Manually set the height for the photon noise table.
-    #[clap(long, help_heading = "Encoding")]
+    #[clap(long, help_heading = "Encoding", value_parser = value_parser!(u32).range(1..))]
     pub photon_noise_height: Option<u32>,
 
+    /// Passes HDR10+ dynamic metadata to the encoder of each chunk
+    ///
+    /// Takes either "extract", to extract the metadata from the HEVC video
+    /// stream of the input with hdr10plus_tool, or the path of a JSON file in
+    /// the format written by `hdr10plus_tool extract`. The metadata is split
+    /// at the chunk boundaries so that each chunk gets the metadata of its own
+    /// frames. Only supported by x265 and svt-av1.
+    #[clap(long, help_heading = "Encoding")]
+    pub hdr10plus: Option<Hdr10PlusSource>,
+
+    /// Class of hardware decoders that the output has to play on
+    ///
+    /// tv-2020 - Televisions and streaming devices from 2020 on: AV1 level
+    /// 5.1, up to 3840x2160 at 60 fps, fast decode and at least 2 tile
+    /// columns.
+    ///
+    /// mobile - Phones and tablets: AV1 level 4.1, up to 1920x1080 at 60 fps
+    /// and fast decode.
+    ///
+    /// The level, fast decode and tile parameters are added to every chunk,
+    /// including the chunks of zones, unless they are already set. Av1an
+    /// fails if they are set to values the decoders cannot handle, or if the
+    /// resolution or frame rate of the input is too high. Only supported by
+    /// svt-av1 and aom, which has no fast decode option.
+    #[clap(long, help_heading = "Encoding")]
+    pub device_target: Option<DeviceTarget>,
+
     /// Determines method used for concatenating encoded chunks and audio into
     /// output file
     ///
@@ -555,10 +1128,43 @@ pub struct CliOpts {
     #[clap(short, long, default_value_t = ConcatMethod::MKVMerge, help_heading = "Encoding")]
     pub concat: ConcatMethod,
 
+    /// Check the stream headers of the output after concatenation
+    ///
+    /// The sequence headers (AV1) or sequence parameter sets (HEVC) of the
+    /// whole output are extracted with ffmpeg and compared. Av1an fails if
+    /// their profile, level, bit depth, chroma subsampling or maximum frame
+    /// size change within the output, which happens when chunks encoded with
+    /// different parameters are merged and plays back incorrectly on many
+    /// hardware decoders.
+    #[clap(long, help_heading = "Encoding")]
+    pub verify_bitstream: bool,
+
     /// FFmpeg pixel format
     #[clap(long, default_value = "yuv420p10le", help_heading = "Encoding")]
     pub pix_format: FFPixelFormat,
 
+    /// Sample aspect ratio signaled to the encoder, as NUM:DEN (e.g. 32:27)
+    ///
+    /// By default, the sample aspect ratio of the source is used. It is also
+    /// used to set the display dimensions of the output when concatenating
+    /// with mkvmerge.
+    #[clap(long, value_parser = parse_sample_aspect_ratio, help_heading = "Encoding")]
+    pub sar: Option<(u32, u32)>,
+
+    /// Field order signaled to the encoder
+    ///
+    /// progressive, tff (top field first), bff (bottom field first) or mixed.
+    /// vspipe always signals progressive frames, so this must be set to encode
+    /// interlaced content as interlaced.
+    #[clap(long, help_heading = "Encoding")]
+    pub interlacing: Option<Interlacing>,
+
+    /// Color range signaled to the encoder (full or limited)
+    ///
+    /// By default, full range is signaled for full range sources.
+    #[clap(long, help_heading = "Encoding")]
+    pub color_range: Option<ColorRange>,
+
     /// Path to a file specifying zones within the video with differing encoder
     /// settings.
     ///
@@ -632,6 +1238,11 @@ pub struct CliOpts {
     /// This option is independent of --target-quality, i.e. it can be used with
     /// or without it. The SVG plot is created in the same directory as the
     /// output file.
+    ///
+    /// When used with --target-quality and the VMAF target metric, the score
+    /// target quality chose for each chunk is compared with the score of its
+    /// frames in the final output. The differences are summarized in the log
+    /// and written to a .tq.json file next to the output file.
     #[clap(long, help_heading = "VMAF")]
     pub vmaf: bool,
 
@@ -666,6 +1277,36 @@ pub struct CliOpts {
     #[clap(long, help_heading = "VMAF")]
     pub vmaf_filter: Option<String>,
 
+    /// Only score every nth frame of the output when plotting VMAF with --vmaf
+    ///
+    /// Scoring every frame of a long encode is expensive. When n is greater
+    /// than 1, the plot and its percentiles are labeled as sampled.
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), help_heading = "VMAF")]
+    pub vmaf_sample_rate: u32,
+
+    /// Minimum number of frames scored per scene when using --vmaf-sample-rate
+    ///
+    /// The sample rate is lowered as needed so that every scene gets at least
+    /// this many scored frames.
+    #[clap(long, default_value_t = 0, help_heading = "VMAF")]
+    pub vmaf_min_scene_samples: usize,
+
+    /// Encode the chunks that missed their target quality range again
+    ///
+    /// After the VMAF of the output is calculated with --vmaf, every chunk that
+    /// used target quality with the VMAF metric and scored outside of its
+    /// target range is encoded again once. Target quality then aims for the
+    /// range shifted by how far its prediction was off, e.g. 2 points higher
+    /// for a chunk that scored 2 points lower than predicted. The output is
+    /// concatenated and scored again afterwards.
+    #[clap(
+        long,
+        requires = "vmaf",
+        conflicts_with = "sample",
+        help_heading = "VMAF"
+    )]
+    pub reencode_outliers: bool,
+
     /// Target a metric score range for encoding (disabled by default)
     ///
     /// For each chunk, target quality uses an algorithm to find the
@@ -699,6 +1340,30 @@ pub struct CliOpts {
     #[clap(long, help_heading = "Target Quality", value_parser = TargetQuality::parse_qp_range)]
     pub qp_range: Option<(u32, u32)>,
 
+    /// Limit the rate control of each chunk to this many quantizer steps
+    /// around the quantizer chosen by target quality
+    ///
+    /// Besides setting the CRF/cq-level to the chosen quantizer, the minimum
+    /// and maximum quantizers of the encoder are set around it (--min-q and
+    /// --max-q for aom and vpx, --min-quantizer for rav1e, --qpmin and --qpmax
+    /// for x264 and x265), which keeps the rate control of the scene close to
+    /// the probed quality. Not supported by svt-av1, which only limits the
+    /// quantizers of its bitrate modes.
+    #[clap(long, help_heading = "Target Quality", requires = "target_quality")]
+    pub q_bracket: Option<u32>,
+
+    /// Experimental: number of probes encoded with NVENC before the probes
+    /// with the encoder
+    ///
+    /// The NVENC probes are much faster and rank the quantizers roughly like
+    /// the encoder, so they narrow the quantizer range that the regular
+    /// --probes search afterwards. Their scores are never used as the score of
+    /// a chunk. Works well with --probes 2. Requires FFmpeg with hevc_nvenc,
+    /// and consumer GPUs limit the number of NVENC sessions at once, which may
+    /// require fewer workers.
+    #[clap(long, help_heading = "Target Quality", requires = "target_quality")]
+    pub nvenc_probes: Option<u32>,
+
     #[rustfmt::skip]
     /// Interpolation methods for target quality probing
     ///
@@ -762,11 +1427,26 @@ pub struct CliOpts {
     /// the VapourSynth-Zig Image Process plugin version R7 or newer is required
     /// and the Chunk method must be set to "lsmash", "ffms2", "bestsource", or
     /// "dgdecnv".
-    #[clap(long, default_value_t = TargetMetric::VMAF, help_heading = "Target Quality")]
+    #[clap(
+        long,
+        default_value_t = TargetMetric::VMAF,
+        value_parser = parse_target_metric,
+        help_heading = "Target Quality"
+    )]
     pub target_metric: TargetMetric,
     /// Maximum number of probes allowed for target quality
+    ///
+    /// Probing stops earlier when the score of the next quantizer can be
+    /// predicted accurately from the previous probes.
     #[clap(long, default_value_t = 4, help_heading = "Target Quality")]
     pub probes:        u32,
+    /// Maximum number of probes allowed for chunks whose scores are hard to
+    /// predict
+    ///
+    /// Chunks whose last probe scored far from its predicted score may keep
+    /// probing past --probes up to this number. Defaults to --probes.
+    #[clap(long, help_heading = "Target Quality")]
+    pub max_probes:    Option<u32>,
 
     /// Only use every nth frame for VMAF calculation, while probing.
     ///
@@ -835,6 +1515,17 @@ pub struct CliOpts {
     ///   "harmonic" works as expected when there are no negative scores. Use with caution with target metrics such as "ssimulacra2".
     #[clap(long, default_value_t = String::from("auto"), help_heading = "Target Quality", verbatim_doc_comment)]
     pub probing_stat: String,
+    /// Maximum number of GPU streams used at once by all workers when
+    /// measuring SSIMULACRA2 or butteraugli with vship
+    ///
+    /// Each measurement uses up to 4 streams, so limiting the total keeps
+    /// multiple workers from running out of VRAM. If vship fails, the
+    /// measurement falls back to the CPU with vszip or julek. The plugin used
+    /// for each chunk is recorded in its probe file in the temporary folder.
+    ///
+    /// By default, the number of streams is not limited.
+    #[clap(long, value_parser = value_parser!(u32).range(1..), help_heading = "Target Quality")]
+    pub gpu_streams: Option<u32>,
 }
 
 impl CliOpts {
@@ -873,18 +1564,22 @@ impl CliOpts {
             }),
             model: self.vmaf_path.clone(),
             probes: self.probes,
+            max_probes: self.max_probes.unwrap_or(self.probes),
             target: self.target_quality,
             interp_method: self.interp_method,
             min_q,
             max_q,
+            q_bracket: self.q_bracket,
+            nvenc_probes: self.nvenc_probes.unwrap_or(0),
             metric: self.target_metric,
+            metric_plugin: None,
             encoder: self.encoder,
             pix_format: output_pix_format,
             temp: temp_dir,
             workers: self.workers,
             video_params: probe_video_params,
             params_copied,
-            vspipe_args: self.vspipe_args.clone(),
+            gpu_streams: self.gpu_streams.map(|streams| streams as usize),
             probing_rate: self.probing_rate as usize,
             probing_vmaf_features: if self.probing_vmaf_features.is_empty() {
                 vec![VmafFeature::Default]
@@ -892,6 +1587,7 @@ impl CliOpts {
                 self.probing_vmaf_features.clone()
             },
             probing_statistic,
+            priority: self.priority,
         })
     }
 }
@@ -917,6 +1613,30 @@ fn confirm(prompt: &str) -> io::Result<bool> {
     }
 }
 
+/// Decides what happens to an existing output file at `path`, asking the user
+/// with `prompt` unless -y, -n or --backup was given. Exits if the file exists
+/// and may not be overwritten.
+fn overwrite_policy(args: &CliOpts, path: &Path, prompt: &str) -> io::Result<OverwritePolicy> {
+    if args.overwrite {
+        return Ok(OverwritePolicy::Overwrite);
+    }
+    if args.backup {
+        return Ok(OverwritePolicy::Backup);
+    }
+    if !path.exists() {
+        return Ok(OverwritePolicy::Fail);
+    }
+    if args.ladder_rendition.is_some() {
+        // Already confirmed by the process that started the rendition
+        return Ok(OverwritePolicy::Overwrite);
+    }
+    if args.never_overwrite || !confirm(prompt)? {
+        println!("Not overwriting, aborting.");
+        exit(0);
+    }
+    Ok(OverwritePolicy::Overwrite)
+}
+
 /// Given Folder and File path as inputs
 /// Converts them all to file paths
 /// Converting only depth 1 of Folder paths
@@ -944,10 +1664,17 @@ pub(crate) fn resolve_file_paths(
 /// Returns vector of Encode args ready to be fed to encoder
 #[tracing::instrument(level = "debug")]
 pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
+    check_ladder_names(&args.ladder)?;
+
     let input_paths = &*args.input;
     let proxy_paths = &*args.proxy;
 
     let mut inputs = Vec::new();
+    if let Some(pattern) = &args.test_pattern {
+        // Only names the default output and temp folder, the script is written
+        // by `Input::test_pattern`
+        inputs.push(PathBuf::from(pattern.file_name()));
+    }
     for path in input_paths {
         inputs.extend(resolve_file_paths(path)?);
     }
@@ -963,7 +1690,7 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
     let vapoursynth_plugins = get_vapoursynth_plugins().ok();
 
     for (index, input) in inputs.into_iter().enumerate() {
-        let output_file = {
+        let (output_file, prompt) = {
             if let Some(path) = args.output_file.as_ref() {
                 let path = PathAbs::new(path)?;
 
@@ -973,50 +1700,38 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
                     bail!("Failed to get parent directory of path: {:?}", path);
                 }
 
-                if !args.overwrite
-                    && path.exists()
-                    && (args.never_overwrite
-                        || !confirm(&format!(
-                            "Output file {} exists. Do you want to overwrite it? [y/N]: ",
-                            path.file_name().expect("file name should exist").display()
-                        ))?)
-                {
-                    println!("Not overwriting, aborting.");
-                    exit(0);
-                }
+                let prompt = format!(
+                    "Output file {} exists. Do you want to overwrite it? [y/N]: ",
+                    path.file_name().expect("file name should exist").display()
+                );
 
-                path.to_string_lossy().to_string()
+                (path.as_path().to_path_buf(), prompt)
             } else {
-                let output_file = format!(
-                    "{}_{}.mkv",
-                    input
-                        .as_path()
-                        .file_stem()
-                        .unwrap_or_else(|| input.as_path().as_ref())
-                        .to_string_lossy(),
-                    args.encoder
+                let mut output_file = input
+                    .as_path()
+                    .file_stem()
+                    .unwrap_or_else(|| input.as_path().as_ref())
+                    .to_os_string();
+                output_file.push(format!("_{}.mkv", args.encoder));
+                let output_file = PathBuf::from(output_file);
+
+                let prompt = format!(
+                    "Default output file {} exists. Do you want to overwrite it? [y/N]: ",
+                    output_file.display()
                 );
 
-                if !args.overwrite
-                    && Path::new(&output_file).exists()
-                    && (args.never_overwrite
-                        || !confirm(&format!(
-                            "Default output file {} exists. Do you want to overwrite it? [y/N]: ",
-                            output_file
-                        ))?)
-                {
-                    println!("Not overwriting, aborting.");
-                    exit(0);
-                }
-
-                output_file
+                (output_file, prompt)
             }
         };
+        // With --ladder, only the outputs of the renditions are written, and
+        // `ladder_renditions` checks those instead
+        let overwrite_policy = if args.ladder.is_empty() {
+            self::overwrite_policy(args, &output_file, &prompt)?
+        } else {
+            OverwritePolicy::Fail
+        };
 
-        let temp = args.temp.as_ref().map_or_else(
-            || format!(".{}", hash_path(input.as_path())),
-            |path| path.to_string_lossy().to_string(),
-        );
+        let temp = temp_dir(args, input.as_path())?;
 
         let chunk_method = args.chunk_method.unwrap_or_else(|| {
             vapoursynth_plugins.map_or(ChunkMethod::Hybrid, |p| p.best_available_chunk_method())
@@ -1038,14 +1753,18 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
             scaler
         };
 
-        let input = Input::new(
-            input,
-            args.vspipe_args.clone(),
-            temp.as_str(),
-            chunk_method,
-            false,
-            args.cache_mode,
-        )?;
+        let input = if let Some(pattern) = args.test_pattern {
+            Input::test_pattern(pattern, temp.as_str())?
+        } else {
+            Input::new(
+                input,
+                args.vspipe_args.clone(),
+                temp.as_str(),
+                chunk_method,
+                false,
+                args.cache_mode,
+            )?
+        };
 
         // Assumes proxies supplied are the same number as inputs. Otherwise gets the
         // first proxy if available
@@ -1053,7 +1772,7 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
         let proxy = if let Some(path) = proxy_path {
             Some(Input::new(
                 path,
-                args.vspipe_args.clone(),
+                args.proxy_vspipe_args.as_ref().unwrap_or(&args.vspipe_args).clone(),
                 temp.as_str(),
                 chunk_method,
                 true,
@@ -1063,7 +1782,8 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
             None
         };
 
-        let verbosity = if args.quiet {
+        // The renditions of --ladder-parallel share the terminal
+        let verbosity = if args.quiet || args.ladder_rendition.is_some() {
             Verbosity::Quiet
         } else if args.verbose {
             Verbosity::Verbose
@@ -1114,36 +1834,82 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
             },
             temp: temp.clone(),
             force: args.force,
+            force_lock: args.force_lock,
             no_defaults: args.no_defaults,
             passes: args.passes.unwrap_or_else(|| args.encoder.get_default_pass()),
+            pass_params: args.pass_params.clone(),
+            whole_clip_first_pass: args.whole_clip_first_pass,
+            collapse_duplicates: args.collapse_duplicates,
+            keep_vfr: args.keep_vfr,
             video_params: video_params.clone(),
+            live_params: args.live_params.clone(),
+            pause_file: args.pause_file.clone(),
+            memory_ceiling: args.memory_ceiling,
             output_file,
+            overwrite_policy,
             audio_params: if let Some(args) = args.audio_params.as_ref() {
                 shlex::split(args)
                     .ok_or_else(|| anyhow!("Failed to split ffmpeg audio encoder arguments"))?
             } else {
                 into_vec!["-c:a", "copy"]
             },
+            passthrough: Passthrough {
+                subtitle_tracks: args
+                    .subtitle_tracks
+                    .as_deref()
+                    .map(parse_subtitle_tracks)
+                    .transpose()?
+                    .flatten(),
+                chapters:        !args.no_chapters,
+                attachments:     !args.no_attachments,
+            },
             chunk_method,
             chunk_order: args.chunk_order,
+            sample: args.sample.map(|sample| sample as usize),
+            bitrate_spike_threshold: args.bitrate_spike_threshold,
+            heatmap: args.heatmap,
+            export_stats: args.export_stats,
+            decode_error: args.decode_error,
             concat: args.concat,
+            verify_bitstream: args.verify_bitstream,
             encoder: args.encoder,
             extra_splits_len: match args.extra_split {
                 Some(0) => None,
                 Some(x) => Some(x),
                 // Make sure it's at least 10 seconds, unless specified by user
                 None => Some(
-                    (clip_info.frame_rate.to_f64().unwrap() * args.extra_split_sec).round()
-                        as usize,
+                    (clip_info.frame_rate.to_f64().unwrap() * args.extra_split_sec.unwrap_or(10.0))
+                        .round() as usize,
                 ),
             },
+            default_extra_splits: args.extra_split.is_none() && args.extra_split_sec.is_none(),
             photon_noise: args.photon_noise.and_then(|arg| if arg == 0 { None } else { Some(arg) }),
+            photon_noise_anchors: args
+                .photon_noise_anchors
+                .as_deref()
+                .map(parse_photon_noise_anchors)
+                .transpose()?
+                .unwrap_or_default(),
+            photon_noise_auto: args.photon_noise_auto,
             photon_noise_size: (args.photon_noise_width, args.photon_noise_height),
             chroma_noise: args.chroma_noise,
+            chroma_noise_strength: args.chroma_noise_strength.filter(|&strength| strength > 0),
+            chroma_noise_coeffs: args.chroma_noise_coeffs.unwrap_or((1.0, 1.0)),
+            photon_noise_seed: args.photon_noise_seed,
+            hdr10plus: args.hdr10plus.clone(),
+            device_target: args.device_target,
             sc_pix_format: args.sc_pix_format,
-            keep: args.keep,
+            sc_luma_only: args.sc_luma_only,
+            keep: if args.keep {
+                KeepPolicy::Always
+            } else {
+                args.keep_temp
+            },
             max_tries: args.max_tries as usize,
+            time_budget: args.time_budget,
             min_scene_len: args.min_scene_len,
+            split_for_workers: args.split_for_workers,
+            sc_cache: args.sc_cache.clone(),
             cache_mode: args.cache_mode,
             pix_format_converter: args.pix_format_converter,
             input_pix_format: {
@@ -1176,15 +1942,43 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
             input,
             proxy,
             output_pix_format,
-            resume: args.resume,
+            sar: args.sar,
+            interlacing: args.interlacing,
+            color_range: args.color_range,
+            resume: args.resume || args.encode_scene.is_some(),
             scenes: args.scenes.clone(),
             split_method: args.split_method.clone(),
             sc_method: args.sc_method,
             sc_only: args.sc_only,
             sc_downscale_height: args.sc_downscale_height,
+            sc_pq_mode: args.sc_pq_mode,
+            sc_threshold: args.sc_threshold,
+            sc_import: args.sc_import.clone(),
+            export_scenes: args.export_scenes.clone(),
+            scenes_format: args.scenes_format,
             force_keyframes: parse_comma_separated_numbers(
                 args.force_keyframes.as_deref().unwrap_or(""),
             )?,
+            sc_annotate: args.sc_annotate,
+            sc_fades: args.sc_fades,
+            sc_fade_zone: args.sc_fade_zone.clone(),
+            sc_ignore_ranges: {
+                let mut ranges = Vec::new();
+                for (list, single_scene) in
+                    [(&args.sc_ignore_ranges, false), (&args.sc_single_scene_ranges, true)]
+                {
+                    if let Some(list) = list {
+                        let frame_rate = clip_info.frame_rate.to_f64().ok_or_else(|| {
+                            anyhow!(
+                                "Frame rate {} of the input cannot convert ranges to frames",
+                                clip_info.frame_rate
+                            )
+                        })?;
+                        ranges.extend(parse_ignore_ranges(list, frame_rate, single_scene)?);
+                    }
+                }
+                ranges
+            },
             target_quality,
             vmaf: args.vmaf,
             vmaf_path: args.vmaf_path.clone(),
@@ -1192,37 +1986,161 @@ pub fn parse_cli(args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
             probe_res: args.probe_res.clone(),
             vmaf_threads: args.vmaf_threads,
             vmaf_filter: args.vmaf_filter.clone(),
+            vmaf_sample_rate: args.vmaf_sample_rate as usize,
+            vmaf_min_scene_samples: args.vmaf_min_scene_samples,
+            reencode_outliers: args.reencode_outliers,
             verbosity,
+            progress_interval: Duration::from_millis(args.progress_interval),
             workers: args.workers,
             tiles: (1, 1), // default value; will be adjusted if tile_auto set
             tile_auto: args.tile_auto,
             set_thread_affinity: args.set_thread_affinity,
+            priority: args.priority,
             zones: args.zones.clone(),
             scaler,
             ignore_frame_mismatch: args.ignore_frame_mismatch,
             vapoursynth_plugins,
         };
 
-        valid_args.push(arg);
+        if args.ladder.is_empty() {
+            valid_args.push(arg);
+        } else {
+            valid_args.extend(ladder_renditions(&arg, args)?);
+        }
     }
 
     Ok(valid_args)
 }
 
+/// Fails if two renditions of `--ladder` share a name, as they would share
+/// their temporary folder and output file
+fn check_ladder_names(ladder: &[(String, Vec<String>)]) -> anyhow::Result<()> {
+    let mut names = HashSet::new();
+    for (name, _) in ladder {
+        ensure!(
+            names.insert(name.as_str()),
+            "--ladder has more than one rendition named {name:?}, rendition names must be unique"
+        );
+    }
+    Ok(())
+}
+
+/// Creates the encode args of every rendition of `--ladder` from the args of
+/// a single encode
+fn ladder_renditions(base: &EncodeArgs, args: &CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
+    let scenes = base.scenes.clone().unwrap_or_else(|| Path::new(&base.temp).join("scenes.json"));
+
+    let workers = if args.ladder_parallel {
+        let workers = if base.workers == 0 {
+            determine_workers(base)? as usize
+        } else {
+            base.workers
+        };
+        (workers / args.ladder.len()).max(1)
+    } else {
+        base.workers
+    };
+
+    let mut renditions = Vec::with_capacity(args.ladder.len());
+    for (name, params) in &args.ladder {
+        if args.ladder_rendition.as_ref().is_some_and(|rendition| rendition != name) {
+            continue;
+        }
+        let temp = Path::new(&base.temp).join(name).to_string_lossy().to_string();
+        let input = Input::new(
+            base.input.as_path(),
+            base.input.as_vspipe_args_vec()?,
+            &temp,
+            base.chunk_method,
+            false,
+            args.cache_mode,
+        )?;
+        let proxy = base
+            .proxy
+            .as_ref()
+            .map(|proxy| {
+                Input::new(
+                    proxy.as_path(),
+                    proxy.as_vspipe_args_vec()?,
+                    &temp,
+                    base.chunk_method,
+                    true,
+                    args.cache_mode,
+                )
+            })
+            .transpose()?;
+
+        let (output_file, overwrite_policy) = {
+            let path = base.output_file.as_path();
+            let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+            file_name.push(format!("_{name}"));
+            if let Some(ext) = path.extension() {
+                file_name.push(".");
+                file_name.push(ext);
+            }
+            let path = path.with_file_name(file_name);
+
+            let policy = overwrite_policy(
+                args,
+                &path,
+                &format!(
+                    "Output file {} exists. Do you want to overwrite it? [y/N]: ",
+                    path.file_name().expect("file name should exist").display()
+                ),
+            )?;
+
+            (path, policy)
+        };
+
+        let mut video_params = base.video_params.clone();
+        merge_video_params(base.encoder, &mut video_params, params.iter().cloned());
+
+        let mut target_quality = base.target_quality.clone();
+        target_quality.temp.clone_from(&temp);
+        if target_quality.params_copied {
+            target_quality.video_params = Some(video_params.clone());
+        }
+
+        renditions.push(EncodeArgs {
+            input,
+            proxy,
+            temp,
+            output_file,
+            overwrite_policy,
+            video_params,
+            target_quality,
+            scenes: Some(scenes.clone()),
+            workers,
+            ..base.clone()
+        });
+    }
+
+    Ok(renditions)
+}
+
 #[instrument]
 pub fn run() -> anyhow::Result<()> {
-    let cli_options = CliOpts::parse();
+    let cli_options = CliOpts::parse_from(args_with_defaults()?);
 
     let completions = cli_options.completions;
     if let Some(shell) = completions {
         generate(shell, &mut CliOpts::command(), "av1an", &mut io::stdout());
         return Ok(());
     }
+    if cli_options.progress_schema {
+        println!("{PROGRESS_SCHEMA}");
+        return Ok(());
+    }
+
+    let search_path = env::join_paths(executable_search_path(&cli_options.bin_path))?;
+    // SAFETY: no other threads have been spawned yet, so nothing can be reading
+    // the environment concurrently
+    unsafe { env::set_var("PATH", search_path) };
 
     let log_file = cli_options.log_file.as_ref().map(PathAbs::new).transpose()?;
     let log_level = cli_options.log_level;
     let verbosity = {
-        if cli_options.quiet {
+        if cli_options.quiet || cli_options.ladder_rendition.is_some() {
             Verbosity::Quiet
         } else if cli_options.verbose {
             Verbosity::Verbose
@@ -1242,14 +2160,222 @@ pub fn run() -> anyhow::Result<()> {
         log_level,
     )?;
 
+    if cli_options.bench_input {
+        return bench_input(&cli_options);
+    }
+
     let args = parse_cli(&cli_options)?;
+    if cli_options.ladder_parallel && cli_options.ladder_rendition.is_none() {
+        return encode_ladder_parallel(&cli_options, &args);
+    }
     for arg in args {
-        Av1anContext::new(arg)?.encode_file()?;
+        let mut context = Av1anContext::new(arg)?;
+        if let Some(index) = cli_options.encode_scene {
+            let output = context.encode_scene(index)?;
+            println!("{}", output.display());
+        } else {
+            context.encode_file()?;
+        }
     }
 
     Ok(())
 }
 
+/// Runs scene detection once, encodes every rendition of `--ladder` in its
+/// own av1an process at the same time, and reports them side by side
+fn encode_ladder_parallel(cli_options: &CliOpts, renditions: &[EncodeArgs]) -> anyhow::Result<()> {
+    ensure!(
+        renditions.len() == cli_options.ladder.len(),
+        "--ladder-parallel only supports a single input"
+    );
+
+    let (frames, fps) = {
+        let mut context = Av1anContext::new(renditions[0].clone())?;
+        context.detect_scenes()?;
+        let fps = context.args.input.clip_info()?.frame_rate.to_f64().unwrap_or(1.0);
+        (context.frames, fps)
+    };
+
+    let exe = env::current_exe()?;
+    let results = thread::scope(|scope| {
+        // All renditions are started before waiting for any of them
+        let mut handles = Vec::with_capacity(cli_options.ladder.len());
+        for (name, _) in &cli_options.ladder {
+            let mut command = process::Command::new(&exe);
+            command.args(env::args_os().skip(1)).arg("--ladder-rendition").arg(name);
+            handles.push(scope.spawn(move || {
+                let start = Instant::now();
+                let status = command.status()?;
+                ensure!(status.success(), "Rendition {name} failed with {status}");
+                Ok(start.elapsed())
+            }));
+        }
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("thread should join successfully"))
+            .collect::<Vec<anyhow::Result<Duration>>>()
+    });
+
+    let duration = frames as f64 / fps;
+    println!(
+        "{:<16} {:>12} {:>10} {:>10} {:>9} {:>8}",
+        "rendition", "size", "kbps", "time", "fps", "VMAF"
+    );
+    let mut failed = 0;
+    for ((name, _), (rendition, result)) in
+        cli_options.ladder.iter().zip(renditions.iter().zip(results))
+    {
+        let elapsed = match result {
+            Ok(elapsed) => elapsed,
+            Err(e) => {
+                println!("{name:<16} failed: {e:#}");
+                failed += 1;
+                continue;
+            },
+        };
+        let size = fs::metadata(&rendition.output_file)?.len();
+        let vmaf = if rendition.vmaf {
+            read_vmaf_file(rendition.output_file.with_extension("json"))
+                .ok()
+                .filter(|scores| !scores.is_empty())
+                .map_or_else(
+                    || "-".to_string(),
+                    |scores| format!("{:.2}", scores.iter().sum::<f64>() / scores.len() as f64),
+                )
+        } else {
+            "-".to_string()
+        };
+        println!(
+            "{name:<16} {:>9.2} MiB {:>10.1} {:>9.1}s {:>9.2} {vmaf:>8}",
+            size as f64 / (1024.0 * 1024.0),
+            size as f64 * 8.0 / duration / 1000.0,
+            elapsed.as_secs_f64(),
+            frames as f64 / elapsed.as_secs_f64()
+        );
+    }
+    ensure!(
+        failed == 0,
+        "{failed} of {} renditions failed",
+        cli_options.ladder.len()
+    );
+
+    Ok(())
+}
+
+/// Returns the temporary directory for `input`. It has to be valid UTF-8, as
+/// it is written into VapourSynth scripts and encoder parameters.
+fn temp_dir(args: &CliOpts, input: &Path) -> anyhow::Result<String> {
+    args.temp.as_ref().map_or_else(
+        || Ok(format!(".{}", hash_path(input))),
+        |path| {
+            path.to_str()
+                .map(ToString::to_string)
+                .ok_or_else(|| anyhow!("Temporary directory {} is not valid UTF-8", path.display()))
+        },
+    )
+}
+
+/// Benchmarks every available chunk method on each input for `--bench-input`
+fn bench_input(args: &CliOpts) -> anyhow::Result<()> {
+    const SAMPLE_FRAMES: usize = 500;
+
+    let chunk_methods = get_vapoursynth_plugins()?.available_chunk_methods();
+    ensure!(
+        !chunk_methods.is_empty(),
+        "No VapourSynth source plugins are installed, nothing to benchmark"
+    );
+
+    for input in &args.input {
+        if input.extension().is_some_and(|ext| ext == "py" || ext == "vpy") {
+            warn!("Skipping VapourSynth script {}", input.display());
+            continue;
+        }
+
+        // The benchmark only writes to a subdirectory of its own, as the temporary
+        // directory may belong to an encode or hold other files of the user
+        let base_temp = temp_dir(args, input)?;
+        let base_temp_existed = Path::new(&base_temp).exists();
+        let temp = Path::new(&base_temp)
+            .join("bench")
+            .to_str()
+            .expect("temporary directory is valid UTF-8")
+            .to_string();
+        println!("{}", input.display());
+        let mut results = Vec::new();
+        for chunk_method in chunk_methods.iter().copied() {
+            match benchmark_chunk_method(&temp, input, chunk_method, SAMPLE_FRAMES) {
+                Ok(result) => {
+                    println!(
+                        "  {chunk_method:<11} index {:>7.2}s  decode {:>8.2} fps  seek {}",
+                        result.index_time.as_secs_f64(),
+                        result.decode_fps,
+                        if result.seek_accurate {
+                            "accurate"
+                        } else {
+                            "INACCURATE"
+                        }
+                    );
+                    results.push(result);
+                },
+                Err(e) => println!("  {chunk_method:<11} failed: {e:#}"),
+            }
+        }
+
+        match recommend_chunk_method(&results) {
+            Some(chunk_method) => println!("Recommended: --chunk-method {chunk_method}"),
+            None => println!("No chunk method decoded this input accurately"),
+        }
+
+        if !args.keep && args.keep_temp != KeepPolicy::Always {
+            if Path::new(&temp).exists() {
+                std::fs::remove_dir_all(&temp)?;
+            }
+            if !base_temp_existed && Path::new(&base_temp).exists() {
+                std::fs::remove_dir(&base_temp)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a `--ladder` rendition of the form NAME=PARAMS
+fn parse_rendition(string: &str) -> anyhow::Result<(String, Vec<String>)> {
+    let (name, params) = string
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Invalid rendition {string:?}, expected NAME=PARAMS"))?;
+    ensure!(
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+        "Rendition name {name:?} must only contain letters, numbers, '-' and '_'"
+    );
+    let params = shlex::split(params)
+        .ok_or_else(|| anyhow!("Failed to split video encoder arguments of rendition {name}"))?;
+
+    Ok((name.to_string(), params))
+}
+
+/// Parses a target metric, except for the plugin metric, whose scorers can
+/// only be registered by programs using av1an-core as a library
+fn parse_target_metric(string: &str) -> anyhow::Result<TargetMetric> {
+    let metric = string.parse()?;
+    ensure!(
+        metric != TargetMetric::Plugin,
+        "The plugin target metric is only available to programs using av1an-core as a library"
+    );
+    Ok(metric)
+}
+
+fn parse_pass_params(string: &str) -> anyhow::Result<(u8, Vec<String>)> {
+    let (pass, params) = string
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Invalid pass parameters {string:?}, expected PASS=PARAMS"))?;
+    let pass = pass.trim().parse().with_context(|| format!("Invalid pass {pass:?}"))?;
+    let params = shlex::split(params)
+        .ok_or_else(|| anyhow!("Failed to split video encoder arguments of pass {pass}"))?;
+
+    Ok((pass, params))
+}
+
 fn parse_comma_separated_numbers(string: &str) -> anyhow::Result<Vec<usize>> {
     let mut result = Vec::new();
 
@@ -1263,3 +2389,144 @@ fn parse_comma_separated_numbers(string: &str) -> anyhow::Result<Vec<usize>> {
     }
     Ok(result)
 }
+
+/// Parses `--sc-ignore-ranges` and `--sc-single-scene-ranges`. Positions are
+/// frames, or times if they have an `s`, `m` or `h` suffix.
+fn parse_ignore_ranges(
+    string: &str,
+    frame_rate: f64,
+    single_scene: bool,
+) -> anyhow::Result<Vec<IgnoreRange>> {
+    let parse_position = |position: &str| -> anyhow::Result<usize> {
+        let position = position.trim();
+        if position.ends_with(['s', 'm', 'h']) {
+            Ok((parse_seconds(position)? * frame_rate).round() as usize)
+        } else {
+            Ok(position.parse()?)
+        }
+    };
+
+    string
+        .split(',')
+        .map(|range| {
+            let (start, end) = range
+                .split_once('-')
+                .ok_or_else(|| anyhow!("Invalid range {range:?}, expected START-END"))?;
+            let start_frame =
+                parse_position(start).with_context(|| format!("Invalid range start {start:?}"))?;
+            let end_frame =
+                parse_position(end).with_context(|| format!("Invalid range end {end:?}"))?;
+            ensure!(
+                start_frame < end_frame,
+                "Range {range:?} must end after it starts"
+            );
+            Ok(IgnoreRange {
+                start_frame,
+                end_frame,
+                single_scene,
+            })
+        })
+        .collect()
+}
+
+fn parse_sample_aspect_ratio(string: &str) -> anyhow::Result<(u32, u32)> {
+    let (num, den) = string
+        .split_once(':')
+        .ok_or_else(|| anyhow!("sample aspect ratio must be NUM:DEN"))?;
+    let (num, den) = (num.trim().parse()?, den.trim().parse()?);
+    ensure!(
+        num > 0 && den > 0,
+        "sample aspect ratio must be greater than 0"
+    );
+    Ok((num, den))
+}
+
+fn parse_time_budget(string: &str) -> anyhow::Result<Duration> {
+    let seconds = parse_seconds(string)?;
+    ensure!(seconds > 0.0, "time budget must be greater than 0");
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parses a non-negative time in seconds, optionally with an `s`, `m` or `h`
+/// suffix, e.g. "90", "45m" or "1.5h"
+fn parse_seconds(time: &str) -> anyhow::Result<f64> {
+    let time = time.trim();
+    let (value, scale) = [('h', 3600.0), ('m', 60.0), ('s', 1.0)]
+        .into_iter()
+        .find_map(|(suffix, scale)| time.strip_suffix(suffix).map(|value| (value, scale)))
+        .unwrap_or((time, 1.0));
+    let seconds = value.parse::<f64>()? * scale;
+    ensure!(
+        seconds.is_finite() && seconds >= 0.0,
+        "time must be a non-negative number of seconds"
+    );
+    Ok(seconds)
+}
+
+/// Parses `--subtitle-tracks`, returning `None` if all tracks are copied
+fn parse_subtitle_tracks(string: &str) -> anyhow::Result<Option<Vec<usize>>> {
+    match string {
+        "all" => Ok(None),
+        "none" => Ok(Some(Vec::new())),
+        tracks => tracks
+            .split(',')
+            .map(|track| {
+                track.trim().parse::<usize>().with_context(|| {
+                    format!("Invalid subtitle track {track:?}, expected all, none or indices")
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map(Some),
+    }
+}
+
+/// Parses `--photon-noise-anchors` into (seconds, strength) pairs sorted by
+/// time
+fn parse_photon_noise_anchors(string: &str) -> anyhow::Result<Vec<(f64, u8)>> {
+    let mut anchors = Vec::new();
+
+    for anchor in string.split(',') {
+        let (time, strength) = anchor.trim().split_once('=').ok_or_else(|| {
+            anyhow!("Invalid photon noise anchor {anchor:?}, expected TIME=STRENGTH")
+        })?;
+        let seconds = parse_seconds(time)
+            .with_context(|| format!("Invalid photon noise anchor time {time:?}"))?;
+        let strength = strength
+            .trim()
+            .parse::<u8>()
+            .with_context(|| format!("Invalid photon noise anchor strength {strength:?}"))?;
+
+        anchors.push((seconds, strength));
+    }
+
+    anchors.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(anchors)
+}
+
+/// Parses `--chroma-noise-coeffs` into the coefficients of the Cb and Cr
+/// planes
+fn parse_chroma_noise_coeffs(string: &str) -> anyhow::Result<(f64, f64)> {
+    let (cb, cr) = string
+        .split_once(',')
+        .ok_or_else(|| anyhow!("chroma noise coefficients must be CB,CR"))?;
+    let (cb, cr): (f64, f64) = (cb.trim().parse()?, cr.trim().parse()?);
+    ensure!(
+        (0.0..=4.0).contains(&cb) && (0.0..=4.0).contains(&cr),
+        "chroma noise coefficients must be between 0.0 and 4.0"
+    );
+    Ok((cb, cr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_ladder_names, parse_rendition};
+
+    #[test]
+    fn duplicate_ladder_names_are_rejected() {
+        let ladder = ["crf24=--crf 24", "crf30=--crf 30", "crf24=--crf 25"]
+            .map(|rendition| parse_rendition(rendition).expect("rendition should parse"));
+        assert!(check_ladder_names(&ladder[..2]).is_ok());
+        let e = check_ladder_names(&ladder).expect_err("duplicate name should be rejected");
+        assert!(e.to_string().contains("\"crf24\""));
+    }
+}
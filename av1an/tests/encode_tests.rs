@@ -1376,3 +1376,70 @@ fn encode_test_sc_pix_format_vpy_input() {
     assert!(output_file.exists());
     assert!(output_file.metadata().unwrap().len() > 0);
 }
+
+#[cfg(feature = "failure-injection")]
+#[test]
+#[serial]
+fn encode_test_recovers_from_injected_failures() {
+    let mut cmd = Command::new(cargo_bin!("av1an"));
+    let input_file = input_path();
+    let output_file = NamedTempFile::with_suffix(".mkv").unwrap().into_temp_path();
+    let temp_dir = TempDir::new().unwrap();
+
+    cmd.env(
+        "AV1AN_INJECT_FAILURES",
+        "encoder-exit:0,corrupt-output:0,start-delay:0:100",
+    )
+    .arg("-i")
+    .arg(&input_file)
+    .arg("-e")
+    .arg("x264")
+    .arg("--pix-format")
+    .arg("yuv420p")
+    .arg("--sc-method")
+    .arg("fast")
+    .arg("-y")
+    .arg("-v")
+    .arg(X26X_FAST_PARAMS)
+    .arg("--max-tries")
+    .arg("3")
+    .arg("--temp")
+    .arg(temp_dir.path())
+    .arg("-o")
+    .arg(&output_file)
+    .assert()
+    .success();
+    assert!(output_file.exists());
+    assert!(output_file.metadata().unwrap().len() > 0);
+}
+
+#[cfg(feature = "failure-injection")]
+#[test]
+#[serial]
+fn encode_test_fails_after_max_tries_of_injected_failures() {
+    let mut cmd = Command::new(cargo_bin!("av1an"));
+    let input_file = input_path();
+    let output_file = NamedTempFile::with_suffix(".mkv").unwrap().into_temp_path();
+    let temp_dir = TempDir::new().unwrap();
+
+    cmd.env("AV1AN_INJECT_FAILURES", "encoder-exit:0:2")
+        .arg("-i")
+        .arg(&input_file)
+        .arg("-e")
+        .arg("x264")
+        .arg("--pix-format")
+        .arg("yuv420p")
+        .arg("--sc-method")
+        .arg("fast")
+        .arg("-y")
+        .arg("-v")
+        .arg(X26X_FAST_PARAMS)
+        .arg("--max-tries")
+        .arg("2")
+        .arg("--temp")
+        .arg(temp_dir.path())
+        .arg("-o")
+        .arg(&output_file)
+        .assert()
+        .failure();
+}